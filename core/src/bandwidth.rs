@@ -0,0 +1,343 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Accounting of the bytes that go through the substreams of a `Swarm`, broken down by peer and
+//! by negotiated protocol.
+//!
+//! Pass a [`ProtocolBandwidth`] to `SwarmBuilder::bandwidth` and keep a clone of it in order to
+//! read the counters. Only the bytes read from and written to substreams after their protocol has
+//! been negotiated are counted, which excludes the overhead of the transport, of the encryption
+//! and of the multiplexing.
+
+use crate::PeerId;
+use fnv::FnvHashMap;
+use lazy_static::lazy_static;
+use multistream_select::TrafficObserver;
+use parking_lot::Mutex;
+use smallvec::{smallvec, SmallVec};
+use std::{cmp, sync::Arc, time::Duration};
+use wasm_timer::Instant;
+
+/// Bandwidth counters of a peer or of a protocol.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BandwidthStats {
+    total_inbound: u64,
+    total_outbound: u64,
+    inbound_per_sec: u64,
+    outbound_per_sec: u64,
+}
+
+impl BandwidthStats {
+    /// Returns the number of bytes that have been downloaded since the counters have been
+    /// created.
+    pub fn total_inbound(&self) -> u64 {
+        self.total_inbound
+    }
+
+    /// Returns the number of bytes that have been uploaded since the counters have been created.
+    pub fn total_outbound(&self) -> u64 {
+        self.total_outbound
+    }
+
+    /// Returns the average number of bytes that have been downloaded per second in the period.
+    pub fn inbound_per_sec(&self) -> u64 {
+        self.inbound_per_sec
+    }
+
+    /// Returns the average number of bytes that have been uploaded per second in the period.
+    pub fn outbound_per_sec(&self) -> u64 {
+        self.outbound_per_sec
+    }
+}
+
+/// Counts the bytes that go through the substreams of a `Swarm`, per peer and per protocol.
+///
+/// Cloning a `ProtocolBandwidth` is cheap, and the clones share the same counters. The counters
+/// of a peer are forgotten once the swarm is no longer connected to it, while the counters of a
+/// protocol are kept forever.
+#[derive(Clone)]
+pub struct ProtocolBandwidth {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    /// Period over which the rates are averaged.
+    period: Duration,
+    peers: Mutex<FnvHashMap<PeerId, Arc<Mutex<BandwidthCounters>>>>,
+    protocols: Mutex<FnvHashMap<Vec<u8>, Arc<Mutex<BandwidthCounters>>>>,
+}
+
+impl ProtocolBandwidth {
+    /// Creates new counters, whose rates are averaged over the given period.
+    pub fn new(period: Duration) -> Self {
+        ProtocolBandwidth {
+            inner: Arc::new(Shared {
+                period,
+                peers: Mutex::new(FnvHashMap::default()),
+                protocols: Mutex::new(FnvHashMap::default()),
+            }),
+        }
+    }
+
+    /// Returns the counters of the substreams with the given peer.
+    pub fn peer(&self, peer_id: &PeerId) -> Option<BandwidthStats> {
+        self.inner.peers.lock().get(peer_id).map(|c| c.lock().stats())
+    }
+
+    /// Returns the counters of the substreams that use the given protocol.
+    pub fn protocol(&self, protocol: &[u8]) -> Option<BandwidthStats> {
+        self.inner.protocols.lock().get(protocol).map(|c| c.lock().stats())
+    }
+
+    /// Returns the counters of every connected peer.
+    pub fn peers(&self) -> Vec<(PeerId, BandwidthStats)> {
+        self.inner.peers.lock().iter()
+            .map(|(peer_id, c)| (peer_id.clone(), c.lock().stats()))
+            .collect()
+    }
+
+    /// Returns the counters of every protocol that has been negotiated.
+    pub fn protocols(&self) -> Vec<(Vec<u8>, BandwidthStats)> {
+        self.inner.protocols.lock().iter()
+            .map(|(protocol, c)| (protocol.clone(), c.lock().stats()))
+            .collect()
+    }
+
+    /// Returns an observer to attach to a substream with `peer_id` that uses `protocol`.
+    pub(crate) fn substream_traffic(&self, peer_id: &PeerId, protocol: &[u8]) -> SubstreamTraffic {
+        let period = self.inner.period;
+        let peer = self.inner.peers.lock()
+            .entry(peer_id.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(BandwidthCounters::new(period))))
+            .clone();
+        let protocol = self.inner.protocols.lock()
+            .entry(protocol.to_vec())
+            .or_insert_with(|| Arc::new(Mutex::new(BandwidthCounters::new(period))))
+            .clone();
+        SubstreamTraffic { peer, protocol }
+    }
+
+    /// Forgets the counters of a peer.
+    pub(crate) fn remove_peer(&self, peer_id: &PeerId) {
+        self.inner.peers.lock().remove(peer_id);
+    }
+}
+
+/// Total number of bytes downloaded and uploaded, along with their average rates over a period.
+pub struct BandwidthCounters {
+    total_inbound: u64,
+    total_outbound: u64,
+    download: BandwidthSink,
+    upload: BandwidthSink,
+}
+
+impl BandwidthCounters {
+    /// Creates counters whose rates are averaged over the given period, rounded up to a whole
+    /// number of seconds and capped to one day.
+    pub fn new(period: Duration) -> Self {
+        let period_seconds = period_seconds(period);
+        BandwidthCounters {
+            total_inbound: 0,
+            total_outbound: 0,
+            download: BandwidthSink::new(period_seconds),
+            upload: BandwidthSink::new(period_seconds),
+        }
+    }
+
+    /// Returns the number of bytes that have been downloaded since the counters have been
+    /// created.
+    pub fn total_inbound(&self) -> u64 {
+        self.total_inbound
+    }
+
+    /// Returns the number of bytes that have been uploaded since the counters have been created.
+    pub fn total_outbound(&self) -> u64 {
+        self.total_outbound
+    }
+
+    /// Returns the totals and the rates at once.
+    pub fn stats(&mut self) -> BandwidthStats {
+        BandwidthStats {
+            total_inbound: self.total_inbound,
+            total_outbound: self.total_outbound,
+            inbound_per_sec: self.download.get(),
+            outbound_per_sec: self.upload.get(),
+        }
+    }
+
+    /// Records that bytes have been downloaded.
+    pub fn inject_read(&mut self, num_bytes: usize) {
+        self.total_inbound = self.total_inbound.saturating_add(num_bytes as u64);
+        self.download.inject(num_bytes);
+    }
+
+    /// Records that bytes have been uploaded.
+    pub fn inject_write(&mut self, num_bytes: usize) {
+        self.total_outbound = self.total_outbound.saturating_add(num_bytes as u64);
+        self.upload.inject(num_bytes);
+    }
+}
+
+/// Updates the counters of a peer and of a protocol with the traffic of a substream.
+pub(crate) struct SubstreamTraffic {
+    peer: Arc<Mutex<BandwidthCounters>>,
+    protocol: Arc<Mutex<BandwidthCounters>>,
+}
+
+impl TrafficObserver for SubstreamTraffic {
+    fn on_read(&self, num_bytes: usize) {
+        self.peer.lock().inject_read(num_bytes);
+        self.protocol.lock().inject_read(num_bytes);
+    }
+
+    fn on_write(&self, num_bytes: usize) {
+        self.peer.lock().inject_write(num_bytes);
+        self.protocol.lock().inject_write(num_bytes);
+    }
+}
+
+/// Converts a period into a whole number of seconds, rounded up and capped to one day.
+fn period_seconds(period: Duration) -> u32 {
+    let mut period_seconds = cmp::min(period.as_secs(), 86400) as u32;
+    if period.subsec_nanos() > 0 {
+        period_seconds += 1;
+    }
+    period_seconds
+}
+
+/// Returns the number of seconds that have elapsed between an arbitrary EPOCH and now.
+#[inline]
+fn current_second() -> u32 {
+    lazy_static! {
+        static ref EPOCH: Instant = Instant::now();
+    }
+
+    EPOCH.elapsed().as_secs() as u32
+}
+
+/// Structure that calculates the average bandwidth over the last few seconds.
+///
+/// If you want to calculate for example both download and upload bandwidths, create two different
+/// objects.
+struct BandwidthSink {
+    /// Bytes sent over the past seconds. Contains `rolling_seconds + 1` elements, where
+    /// `rolling_seconds` is the value passed to `new`. Only the first `rolling_seconds` elements
+    /// are taken into account for the average, while the last element is the element to be
+    /// inserted later.
+    bytes: SmallVec<[u64; 8]>,
+    /// Number of seconds between `EPOCH` and the moment we have last updated `bytes`.
+    latest_update: u32,
+}
+
+impl BandwidthSink {
+    /// Initializes a `BandwidthSink`.
+    fn new(seconds: u32) -> Self {
+        BandwidthSink {
+            bytes: smallvec![0; seconds as usize + 1],
+            latest_update: current_second(),
+        }
+    }
+
+    /// Returns the number of bytes over the last few seconds. The number of seconds is the value
+    /// configured at initialization.
+    fn get(&mut self) -> u64 {
+        self.update();
+        let seconds = self.bytes.len() - 1;
+        self.bytes.iter()
+            .take(seconds)
+            .fold(0u64, |a, &b| a.saturating_add(b)) / seconds as u64
+    }
+
+    /// Notifies the `BandwidthSink` that a certain number of bytes have been transmitted at this
+    /// moment.
+    fn inject(&mut self, bytes: usize) {
+        self.update();
+        if let Some(last) = self.bytes.last_mut() {
+            *last = last.saturating_add(bytes as u64);
+        }
+    }
+
+    /// Updates the state of the `BandwidthSink` so that the last element of `bytes` contains the
+    /// current second.
+    fn update(&mut self) {
+        let current_second = current_second();
+        debug_assert!(current_second >= self.latest_update);
+        let num_iter = cmp::min(current_second - self.latest_update, self.bytes.len() as u32);
+        for _ in 0..num_iter {
+            self.bytes.remove(0);
+            self.bytes.push(0);
+        }
+
+        self.latest_update = current_second;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+    use super::*;
+
+    #[test]
+    fn sink_works() {
+        let mut sink = BandwidthSink::new(5);
+        sink.inject(100);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(sink.get(), 20);
+        sink.inject(100);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(sink.get(), 40);
+        sink.inject(100);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(sink.get(), 60);
+        sink.inject(100);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(sink.get(), 80);
+        sink.inject(100);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(sink.get(), 100);
+        thread::sleep(Duration::from_millis(1000));
+        assert_eq!(sink.get(), 80);
+    }
+
+    #[test]
+    fn traffic_is_counted_per_peer_and_protocol() {
+        let bandwidth = ProtocolBandwidth::new(Duration::from_secs(1));
+        let peer1 = PeerId::random();
+        let peer2 = PeerId::random();
+
+        let traffic = bandwidth.substream_traffic(&peer1, b"/kad/1");
+        traffic.on_read(10);
+        traffic.on_write(5);
+        let traffic = bandwidth.substream_traffic(&peer2, b"/kad/1");
+        traffic.on_read(20);
+        let traffic = bandwidth.substream_traffic(&peer2, b"/meshsub/1");
+        traffic.on_write(7);
+
+        let kad = bandwidth.protocol(b"/kad/1").unwrap();
+        assert_eq!((kad.total_inbound(), kad.total_outbound()), (30, 5));
+        let peer2_stats = bandwidth.peer(&peer2).unwrap();
+        assert_eq!((peer2_stats.total_inbound(), peer2_stats.total_outbound()), (20, 7));
+        assert_eq!(bandwidth.protocols().len(), 2);
+
+        bandwidth.remove_peer(&peer2);
+        assert!(bandwidth.peer(&peer2).is_none());
+        assert_eq!(bandwidth.protocol(b"/meshsub/1").unwrap().total_outbound(), 7);
+    }
+}
@@ -18,7 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::{muxing::StreamMuxer, ProtocolName, transport::ListenerEvent};
+use crate::{muxing::StreamMuxer, ProtocolName, resource::ConnectionScope, transport::ListenerEvent};
 use futures::prelude::*;
 use std::{fmt, io::{Error as IoError, Read, Write}};
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -277,6 +277,13 @@ where
             EitherOutput::Second(inner) => inner.flush_all().map_err(|e| e.into()),
         }
     }
+
+    fn set_resource_scope(&self, scope: &ConnectionScope) {
+        match self {
+            EitherOutput::First(inner) => inner.set_resource_scope(scope),
+            EitherOutput::Second(inner) => inner.set_resource_scope(scope),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
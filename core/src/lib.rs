@@ -70,11 +70,13 @@ mod translation;
 #[cfg(test)]
 mod tests;
 
+pub mod bandwidth;
 pub mod either;
 pub mod identity;
 pub mod muxing;
 pub mod nodes;
 pub mod protocols_handler;
+pub mod resource;
 pub mod swarm;
 pub mod transport;
 pub mod upgrade;
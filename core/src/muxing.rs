@@ -51,6 +51,7 @@
 //! The upgrade process will take ownership of the connection, which makes it possible for the
 //! implementation of `StreamMuxer` to control everything that happens on the wire.
 
+use crate::resource::ConnectionScope;
 use fnv::FnvHashMap;
 use futures::{future, prelude::*, try_ready};
 use parking_lot::Mutex;
@@ -215,6 +216,13 @@ pub trait StreamMuxer {
     /// due to `shutdown_substream` or `close`. One may thus shutdown groups of substreams
     /// followed by a final `flush_all` instead of having to do `flush_substream` for each.
     fn flush_all(&self) -> Poll<(), Self::Error>;
+
+    /// Gives the muxer the resource scope of its connection, once it has been opened.
+    ///
+    /// Muxers that buffer data should reserve the memory of their buffers in it from then on,
+    /// through `ConnectionScope::memory`. Does nothing by default.
+    #[inline]
+    fn set_resource_scope(&self, _scope: &ConnectionScope) {}
 }
 
 /// Polls for an inbound from the muxer but wraps the output in an object that
@@ -548,6 +556,11 @@ impl StreamMuxer for StreamMuxerBox {
     fn flush_all(&self) -> Poll<(), Self::Error> {
         self.inner.flush_all()
     }
+
+    #[inline]
+    fn set_resource_scope(&self, scope: &ConnectionScope) {
+        self.inner.set_resource_scope(scope)
+    }
 }
 
 struct Wrap<T> where T: StreamMuxer {
@@ -648,4 +661,9 @@ where
     fn flush_all(&self) -> Poll<(), Self::Error> {
         self.inner.flush_all().map_err(|e| e.into())
     }
+
+    #[inline]
+    fn set_resource_scope(&self, scope: &ConnectionScope) {
+        self.inner.set_resource_scope(scope)
+    }
 }
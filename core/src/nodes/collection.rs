@@ -30,6 +30,7 @@ use crate::{
 };
 use fnv::FnvHashMap;
 use futures::prelude::*;
+use smallvec::SmallVec;
 use std::{error, fmt, hash::Hash, mem};
 
 mod tests;
@@ -42,9 +43,10 @@ pub struct CollectionStream<TInEvent, TOutEvent, THandler, TReachErr, THandlerEr
     /// must be present in `nodes`.
     inner: HandledNodesTasks<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr, TaskState<TConnInfo, TUserData>, TConnInfo>,
 
-    /// List of nodes, with the task id that handles this node. The corresponding entry in `tasks`
-    /// must always be in the `Connected` state.
-    nodes: FnvHashMap<TPeerId, TaskId>,
+    /// List of nodes, with the ids of the tasks that handle the connections to this node, from
+    /// the oldest to the most recent. The corresponding entries in `tasks` must always be in the
+    /// `Connected` state, and an entry is never empty.
+    nodes: FnvHashMap<TPeerId, SmallVec<[TaskId; 2]>>,
 }
 
 impl<TInEvent, TOutEvent, THandler, TReachErr, THandlerErr, TUserData, TConnInfo, TPeerId> fmt::Debug for
@@ -78,6 +80,8 @@ pub enum CollectionEvent<'a, TInEvent, TOutEvent, THandler, TReachErr, THandlerE
     NodeClosed {
         /// Information about the connection.
         conn_info: TConnInfo,
+        /// Identifier of the connection that has been closed.
+        id: ConnectionId,
         /// The error that happened.
         error: HandledNodeError<THandlerErr>,
         /// User data that was passed when accepting.
@@ -118,9 +122,10 @@ where TOutEvent: fmt::Debug,
                 .field(inner)
                 .finish()
             },
-            CollectionEvent::NodeClosed { ref conn_info, ref error, ref user_data } => {
+            CollectionEvent::NodeClosed { ref conn_info, ref id, ref error, ref user_data } => {
                 f.debug_struct("CollectionEvent::NodeClosed")
                 .field("conn_info", conn_info)
+                .field("id", id)
                 .field("user_data", user_data)
                 .field("error", error)
                 .finish()
@@ -134,6 +139,7 @@ where TOutEvent: fmt::Debug,
             CollectionEvent::NodeEvent { ref peer, ref event } => {
                 f.debug_struct("CollectionEvent::NodeEvent")
                 .field("conn_info", peer.info())
+                .field("id", &peer.connection_id())
                 .field("event", event)
                 .finish()
             },
@@ -182,15 +188,17 @@ where
     TConnInfo: ConnectionInfo<PeerId = TPeerId>,
     TPeerId: Eq + Hash,
 {
-    /// Returns `true` if accepting this reached node would replace an existing connection to that
-    /// node.
+    /// Returns `true` if we already have at least one connection to this node.
     #[inline]
-    pub fn would_replace(&self) -> bool {
+    pub fn has_other_connections(&self) -> bool {
         self.parent.nodes.contains_key(self.connection_info().peer_id())
     }
 
     /// Accepts the new node.
-    pub fn accept(mut self, user_data: TUserData) -> (CollectionNodeAccept<TConnInfo, TUserData>, TConnInfo)
+    ///
+    /// Existing connections to the same node are kept open. Returns the identifier of the new
+    /// connection.
+    pub fn accept(mut self, user_data: TUserData) -> (ConnectionId, TConnInfo)
     where
         // TODO: these two clones shouldn't be necessary if we return references
         TConnInfo: Clone,
@@ -200,29 +208,14 @@ where
             .expect("conn_info is always Some when the object is alive; QED");
 
         // Set the state of the task to `Connected`.
-        let former_task_id = self.parent.nodes.insert(self_conn_info.peer_id().clone(), self.id);
+        self.parent.nodes.entry(self_conn_info.peer_id().clone())
+            .or_insert_with(SmallVec::new)
+            .push(self.id);
         *self.parent.inner.task(self.id)
             .expect("A CollectionReachEvent is only ever created from a valid attempt; QED")
             .user_data_mut() = TaskState::Connected(self_conn_info.clone(), user_data);
 
-        // It is possible that we already have a task connected to the same peer. In this
-        // case, we need to emit a `NodeReplaced` event.
-        let tasks = &mut self.parent.inner;
-        let ret_value = if let Some(former_task) = former_task_id.and_then(|i| tasks.task(i)) {
-            debug_assert!(match *former_task.user_data() {
-                TaskState::Connected(ref p, _) if p.peer_id() == self_conn_info.peer_id() => true,
-                _ => false
-            });
-            let (old_info, user_data) = match former_task.close().into_user_data() {
-                TaskState::Connected(old_info, user_data) => (old_info, user_data),
-                _ => panic!("The former task was picked from `nodes`; all the nodes in `nodes` \
-                             are always in the connected state")
-            };
-            (CollectionNodeAccept::ReplacedExisting(old_info, user_data), self_conn_info)
-
-        } else {
-            (CollectionNodeAccept::NewEntry, self_conn_info)
-        };
+        let ret_value = (ConnectionId(self.id), self_conn_info);
 
         // Don't run the destructor.
         mem::forget(self);
@@ -269,20 +262,25 @@ impl<'a, TInEvent, TOutEvent, THandler, TReachErr, THandlerErr, TUserData, TConn
     }
 }
 
-/// Outcome of accepting a node.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum CollectionNodeAccept<TConnInfo, TUserData> {
-    /// We replaced an existing node. Returns the information about the old connection and the
-    /// user data that was assigned to this node.
-    ReplacedExisting(TConnInfo, TUserData),
-    /// We didn't replace anything existing.
-    NewEntry,
-}
-
 /// Identifier for a future that attempts to reach a node.
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ReachAttemptId(TaskId);
 
+/// Identifier of an established connection to a node.
+///
+/// A connection keeps the identifier of the task that reached the node, which means that it is
+/// unique among all the connections and reach attempts of a collection.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConnectionId(TaskId);
+
+#[cfg(test)]
+impl ConnectionId {
+    /// Builds a `ConnectionId` out of thin air, for the tests of other modules.
+    pub(crate) fn from_raw(id: usize) -> Self {
+        ConnectionId(TaskId::from_raw(id))
+    }
+}
+
 /// Information about a connection.
 pub trait ConnectionInfo {
     /// Identity of the node we are connected to.
@@ -365,25 +363,64 @@ where
         self.inner.broadcast_event(event)
     }
 
-    /// Grants access to an object that allows controlling a peer of the collection.
+    /// Asks all the nodes, including the ones we are still trying to reach, to shut down
+    /// gracefully.
+    ///
+    /// See `NodeHandler::close_gracefully`.
+    pub fn close_gracefully(&mut self) {
+        self.inner.close_gracefully()
+    }
+
+    /// Returns true if some tasks are polled by `poll` rather than by an executor and haven't
+    /// finished yet.
+    ///
+    /// See `HandledNodesTasks::has_local_tasks`.
+    pub fn has_local_tasks(&self) -> bool {
+        self.inner.has_local_tasks()
+    }
+
+    /// Grants access to an object that allows controlling the oldest connection to a peer of the
+    /// collection.
     ///
     /// Returns `None` if we don't have a connection to this peer.
     #[inline]
     pub fn peer_mut(&mut self, id: &TPeerId) -> Option<PeerMut<'_, TInEvent, TUserData, TConnInfo, TPeerId>> {
-        let task = match self.nodes.get(id) {
+        let task = match self.nodes.get(id).and_then(|tasks| tasks.first()) {
             Some(&task) => task,
             None => return None,
         };
 
-        match self.inner.task(task) {
-            Some(inner) => Some(PeerMut {
-                inner,
-                nodes: &mut self.nodes,
-            }),
+        self.connection_mut(ConnectionId(task))
+    }
+
+    /// Grants access to an object that allows controlling a specific connection of the
+    /// collection.
+    ///
+    /// Returns `None` if the connection doesn't exist or has been closed.
+    pub fn connection_mut(&mut self, id: ConnectionId) -> Option<PeerMut<'_, TInEvent, TUserData, TConnInfo, TPeerId>> {
+        match self.inner.task(id.0) {
+            Some(inner) => {
+                if let TaskState::Pending = inner.user_data() {
+                    return None;
+                }
+
+                Some(PeerMut {
+                    inner,
+                    nodes: &mut self.nodes,
+                })
+            },
             None => None,
         }
     }
 
+    /// Returns the identifiers of the connections to the given peer, from the oldest to the most
+    /// recent.
+    pub fn peer_connections(&self, id: &TPeerId) -> impl Iterator<Item = ConnectionId> + '_ {
+        self.nodes.get(id)
+            .into_iter()
+            .flat_map(|tasks| tasks.iter().map(|&task| ConnectionId(task)))
+    }
+
     /// Returns true if we are connected to the given peer.
     ///
     /// This will return true only after a `NodeReached` event has been produced by `poll()`.
@@ -392,7 +429,7 @@ where
         self.nodes.contains_key(id)
     }
 
-    /// Returns a list of all the active connections.
+    /// Returns a list of all the peers we have an active connection to.
     ///
     /// Does not include reach attempts that haven't reached any target yet.
     #[inline]
@@ -439,10 +476,11 @@ where
                     },
                     (TaskState::Connected(conn_info, user_data), TaskClosedEvent::Node(err), _handler) => {
                         debug_assert!(_handler.is_none());
-                        let _node_task_id = self.nodes.remove(conn_info.peer_id());
-                        debug_assert_eq!(_node_task_id, Some(id));
+                        let _was_in = remove_task(&mut self.nodes, conn_info.peer_id(), id);
+                        debug_assert!(_was_in);
                         Async::Ready(CollectionEvent::NodeClosed {
                             conn_info,
+                            id: ConnectionId(id),
                             error: err,
                             user_data,
                         })
@@ -463,21 +501,19 @@ where
                 }))
             },
             HandledNodesEvent::NodeEvent { task, event } => {
-                let conn_info = match task.user_data() {
-                    TaskState::Connected(conn_info, _) => conn_info.clone(),
-                    _ => panic!("we can only receive NodeEvent events from a task after we \
-                                 received a corresponding NodeReached event from that same task; \
-                                 when we receive a NodeReached event, we ensure that the entry in \
-                                 self.tasks is switched to the Connected state; QED"),
-                };
+                let id = task.id();
+                debug_assert!(match task.user_data() {
+                    TaskState::Connected(..) => true,
+                    TaskState::Pending => false,
+                });
                 drop(task);
                 Async::Ready(CollectionEvent::NodeEvent {
                     // TODO: normally we'd build a `PeerMut` manually here, but the borrow checker
                     //       doesn't like it
-                    peer: self.peer_mut(&conn_info.peer_id())
+                    peer: self.connection_mut(ConnectionId(id))
                         .expect("we can only receive NodeEvent events from a task after we \
                                  received a corresponding NodeReached event from that same task;\
-                                 when that happens, peer_mut will always return Some; QED"),
+                                 when that happens, connection_mut will always return Some; QED"),
                     event,
                 })
             }
@@ -485,6 +521,29 @@ where
     }
 }
 
+/// Removes `task` from the list of tasks of `peer_id` in `nodes`, and removes the entry
+/// altogether if it becomes empty. Returns `false` if the task wasn't found.
+fn remove_task<TPeerId>(nodes: &mut FnvHashMap<TPeerId, SmallVec<[TaskId; 2]>>, peer_id: &TPeerId, task: TaskId) -> bool
+where
+    TPeerId: Eq + Hash,
+{
+    let tasks = match nodes.get_mut(peer_id) {
+        Some(tasks) => tasks,
+        None => return false,
+    };
+
+    let pos = match tasks.iter().position(|t| *t == task) {
+        Some(pos) => pos,
+        None => return false,
+    };
+
+    tasks.remove(pos);
+    if tasks.is_empty() {
+        nodes.remove(peer_id);
+    }
+    true
+}
+
 /// Reach attempt interrupt errors.
 #[derive(Debug)]
 pub enum InterruptError {
@@ -528,13 +587,18 @@ where
     }
 }
 
-/// Access to a peer in the collection.
+/// Access to a connection to a peer in the collection.
 pub struct PeerMut<'a, TInEvent, TUserData, TConnInfo = PeerId, TPeerId = PeerId> {
     inner: HandledNodesTask<'a, TInEvent, TaskState<TConnInfo, TUserData>>,
-    nodes: &'a mut FnvHashMap<TPeerId, TaskId>,
+    nodes: &'a mut FnvHashMap<TPeerId, SmallVec<[TaskId; 2]>>,
 }
 
 impl<'a, TInEvent, TUserData, TConnInfo, TPeerId> PeerMut<'a, TInEvent, TUserData, TConnInfo, TPeerId> {
+    /// Returns the identifier of the connection.
+    pub fn connection_id(&self) -> ConnectionId {
+        ConnectionId(self.inner.id())
+    }
+
     /// Returns the information of the connection with the peer.
     pub fn info(&self) -> &TConnInfo {
         match self.inner.user_data() {
@@ -579,14 +643,15 @@ where
         self.inner.send_event(event)
     }
 
-    /// Closes the connections to this node. Returns the user data.
+    /// Closes this connection to the node. Returns the user data.
     ///
-    /// No further event will be generated for this node.
+    /// No further event will be generated for this connection. Other connections to the same
+    /// node are not affected.
     pub fn close(self) -> TUserData {
         let task_id = self.inner.id();
         if let TaskState::Connected(conn_info, user_data) = self.inner.close().into_user_data() {
-            let old_task_id = self.nodes.remove(conn_info.peer_id());
-            debug_assert_eq!(old_task_id, Some(task_id));
+            let _was_in = remove_task(self.nodes, conn_info.peer_id(), task_id);
+            debug_assert!(_was_in);
             user_data
         } else {
             panic!("a PeerMut can only be created if an entry is present in nodes; an entry in \
@@ -107,9 +107,8 @@ fn accepting_a_node_yields_new_entry() {
                 }
                 2 => {
                     assert_matches!(event, Async::Ready(CollectionEvent::NodeReached(reach_ev)) => {
-                        let (accept_ev, accepted_peer_id) = reach_ev.accept(());
+                        let (_, accepted_peer_id) = reach_ev.accept(());
                         assert_eq!(accepted_peer_id, peer_id);
-                        assert_matches!(accept_ev, CollectionNodeAccept::NewEntry);
                     });
                 }
                 _ => unreachable!()
@@ -324,3 +323,47 @@ fn interrupting_an_established_connection_is_err() {
 
     assert_matches!(cs.lock().interrupt(reach_id), Err(InterruptError::AlreadyReached));
 }
+
+#[test]
+fn multiple_connections_to_the_same_peer_coexist() {
+    let cs = Arc::new(Mutex::new(TestCollectionStream::new()));
+    let peer_id = PeerId::random();
+    for _ in 0 .. 2 {
+        let fut = future::ok((peer_id.clone(), DummyMuxer::new()));
+        cs.lock().add_reach_attempt(fut, Handler::default());
+    }
+    let mut rt = Builder::new().core_threads(1).build().unwrap();
+
+    // Accept both connections.
+    let cs_fut = cs.clone();
+    let mut num_accepted = 0;
+    rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+        let mut cs = cs_fut.lock();
+        while num_accepted < 2 {
+            match cs.poll() {
+                Async::Ready(CollectionEvent::NodeReached(reach_ev)) => {
+                    reach_ev.accept(());
+                    num_accepted += 1;
+                }
+                Async::Ready(_) => unreachable!(),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        Ok(Async::Ready(()))
+    })).expect("tokio works");
+
+    let mut cs = cs.lock();
+    let connections = cs.peer_connections(&peer_id).collect::<Vec<_>>();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(cs.peer_mut(&peer_id).unwrap().connection_id(), connections[0]);
+    assert_eq!(cs.connections().collect::<Vec<&PeerId>>(), vec![&peer_id]);
+
+    // Closing one connection keeps the other one open.
+    cs.connection_mut(connections[0]).unwrap().close();
+    assert!(cs.connection_mut(connections[0]).is_none());
+    assert!(cs.has_connection(&peer_id));
+    assert_eq!(cs.peer_connections(&peer_id).collect::<Vec<_>>(), vec![connections[1]]);
+
+    cs.connection_mut(connections[1]).unwrap().close();
+    assert!(!cs.has_connection(&peer_id));
+}
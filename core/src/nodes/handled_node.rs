@@ -19,6 +19,7 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::muxing::StreamMuxer;
+use crate::resource::ConnectionScope;
 use crate::nodes::node::{NodeEvent, NodeStream, Substream, Close};
 use futures::prelude::*;
 use std::{error, fmt, io};
@@ -56,6 +57,22 @@ pub trait NodeHandler {
     /// Injects an event coming from the outside into the handler.
     fn inject_event(&mut self, event: Self::InEvent);
 
+    /// Indicates to the handler that the node is being shut down.
+    ///
+    /// The handler should stop accepting new work, finish what is in progress, and then return
+    /// an error from `poll` to close the connection. The default implementation does nothing,
+    /// in which case the connection is only closed when the handler decides to or when it is
+    /// closed from the outside.
+    fn close_gracefully(&mut self) {}
+
+    /// Returns the resource scope of the connection, if the handler has opened one.
+    ///
+    /// The scope is given to the muxer of the connection when the `HandledNode` is created.
+    /// The default implementation returns `None`.
+    fn resource_scope(&self) -> Option<&ConnectionScope> {
+        None
+    }
+
     /// Should behave like `Stream::poll()`.
     ///
     /// Returning an error will close the connection to the remote.
@@ -161,6 +178,9 @@ where
     /// Builds a new `HandledNode`.
     #[inline]
     pub fn new(muxer: TMuxer, handler: THandler) -> Self {
+        if let Some(scope) = handler.resource_scope() {
+            muxer.set_resource_scope(scope);
+        }
         HandledNode {
             node: NodeStream::new(muxer),
             handler,
@@ -183,6 +203,13 @@ where
         self.handler.inject_event(event);
     }
 
+    /// Indicates to the handler that the node is being shut down.
+    ///
+    /// See `NodeHandler::close_gracefully`.
+    pub fn close_gracefully(&mut self) {
+        self.handler.close_gracefully();
+    }
+
     /// Returns `true` if the remote has shown any sign of activity after the muxer has been open.
     ///
     /// See `StreamMuxer::is_remote_acknowledged`.
@@ -186,6 +186,14 @@ pub enum HandledNodesEvent<'a, TInEvent, TOutEvent, TIntoHandler, TReachErr, THa
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(usize);
 
+#[cfg(test)]
+impl TaskId {
+    /// Builds a `TaskId` out of thin air, for the tests of other modules.
+    pub(crate) fn from_raw(id: usize) -> Self {
+        TaskId(id)
+    }
+}
+
 impl<TInEvent, TOutEvent, TIntoHandler, TReachErr, THandlerErr, TUserData, TConnInfo>
     HandledNodesTasks<TInEvent, TOutEvent, TIntoHandler, TReachErr, THandlerErr, TUserData, TConnInfo>
 {
@@ -235,6 +243,7 @@ impl<TInEvent, TOutEvent, TIntoHandler, TReachErr, THandlerErr, TUserData, TConn
                 handler,
                 events_buffer: Vec::new(),
             },
+            closing_gracefully: false,
             events_tx: self.events_tx.clone(),
             in_events_rx: rx.fuse(),
             id: task_id,
@@ -256,6 +265,25 @@ impl<TInEvent, TOutEvent, TIntoHandler, TReachErr, THandlerErr, TUserData, TConn
         }
     }
 
+    /// Asks all the tasks, including the pending ones, to shut their node down gracefully.
+    ///
+    /// See `NodeHandler::close_gracefully`.
+    pub fn close_gracefully(&mut self) {
+        for (sender, _) in self.tasks.values() {
+            // As in `broadcast_event`, sending fails if the task has already finished.
+            let _ = sender.unbounded_send(ExtToInMessage::CloseGracefully);
+        }
+    }
+
+    /// Returns true if some tasks haven't finished yet and are polled by `poll` rather than by an
+    /// executor, because no tokio executor is available.
+    ///
+    /// This includes the tasks that keep running after their node has been reported as closed,
+    /// in order to close its muxer.
+    pub fn has_local_tasks(&self) -> bool {
+        !self.local_spawns.is_empty() || !self.to_spawn.is_empty()
+    }
+
     /// Grants access to an object that allows controlling a task of the collection.
     ///
     /// Returns `None` if the task id is invalid.
@@ -490,6 +518,8 @@ enum ExtToInMessage<TInEvent> {
     /// When received, stores the parameter inside the task and keeps it alive until we have an
     /// acknowledgment that the remote has accepted our handshake.
     TakeOver(mpsc::UnboundedSender<ExtToInMessage<TInEvent>>),
+    /// The node should be shut down gracefully.
+    CloseGracefully,
 }
 
 /// Message to transmit from a task to the public API.
@@ -521,6 +551,8 @@ where
     id: TaskId,
     /// Channels to keep alive for as long as we don't have an acknowledgment from the remote.
     taken_over: SmallVec<[mpsc::UnboundedSender<ExtToInMessage<TInEvent>>; 1]>,
+    /// Whether the node has been asked to shut down gracefully.
+    closing_gracefully: bool,
 }
 
 enum NodeTaskInner<TFut, TMuxer, TIntoHandler, TInEvent, TConnInfo>
@@ -577,6 +609,9 @@ where
                             Ok(Async::Ready(Some(ExtToInMessage::TakeOver(take_over)))) => {
                                 self.taken_over.push(take_over);
                             },
+                            Ok(Async::Ready(Some(ExtToInMessage::CloseGracefully))) => {
+                                self.closing_gracefully = true;
+                            },
                             Ok(Async::NotReady) => break,
                             Err(_) => unreachable!("An UnboundedReceiver never errors"),
                         }
@@ -589,6 +624,9 @@ where
                             for event in events_buffer {
                                 node.inject_event(event);
                             }
+                            if self.closing_gracefully {
+                                node.close_gracefully();
+                            }
                             let _ = self.events_tx.unbounded_send((event, self.id));
                             self.inner = NodeTaskInner::Node(node);
                         }
@@ -618,6 +656,10 @@ where
                                 Ok(Async::Ready(Some(ExtToInMessage::TakeOver(take_over)))) => {
                                     self.taken_over.push(take_over);
                                 },
+                                Ok(Async::Ready(Some(ExtToInMessage::CloseGracefully))) => {
+                                    self.closing_gracefully = true;
+                                    node.close_gracefully();
+                                },
                                 Ok(Async::Ready(None)) => {
                                     // Node closed by the external API; start closing.
                                     self.inner = NodeTaskInner::Closing(node.close());
@@ -644,8 +686,20 @@ where
                                 let _ = self.events_tx.unbounded_send((event, self.id));
                             }
                             Err(err) => {
+                                // If the handler finished its work after being asked to shut down,
+                                // the muxer still works and we close it gracefully before ending
+                                // the task. Otherwise, substreams that outlive the handler may
+                                // still be in use and the muxer is simply dropped.
+                                let close_muxer = match err {
+                                    HandledNodeError::Handler(_) => self.closing_gracefully,
+                                    HandledNodeError::Node(_) => false,
+                                };
                                 let event = InToExtMessage::TaskClosed(TaskClosedEvent::Node(err), None);
                                 let _ = self.events_tx.unbounded_send((event, self.id));
+                                if close_muxer {
+                                    self.inner = NodeTaskInner::Closing(node.close());
+                                    continue 'outer_loop;
+                                }
                                 return Ok(Async::Ready(())); // End the task.
                             }
                         }
@@ -23,8 +23,9 @@
 use crate::{Multiaddr, Transport, transport::{TransportError, ListenerEvent}};
 use futures::prelude::*;
 use smallvec::SmallVec;
-use std::{collections::VecDeque, fmt};
+use std::{cmp, collections::VecDeque, fmt, time::Duration};
 use void::Void;
+use wasm_timer::{Delay, Instant};
 
 /// Implementation of `futures::Stream` that allows listening on multiaddresses.
 ///
@@ -33,10 +34,14 @@ use void::Void;
 /// a `Transport` that supports the protocols you wish you listen on.
 ///
 /// Then, call `ListenerStream::listen_on` for all addresses you want to start listening on.
+/// Each call returns a `ListenerId` that can later be passed to `remove_listener` in order to
+/// stop listening.
 ///
 /// The `ListenersStream` never ends and never produces errors. If a listener errors or closes,
 /// an event is generated on the stream and the listener is then dropped, but the `ListenersStream`
-/// itself continues.
+/// itself continues. If a [`ListenerRestartPolicy`] is set, listeners that fail with an error are
+/// instead started again on the same address after a backoff. Because of that, polling a
+/// `ListenersStream` requires the `Transport` to implement `Clone`.
 ///
 /// # Example
 ///
@@ -53,14 +58,17 @@ use void::Void;
 /// // The `listeners` will now generate events when polled.
 /// let future = listeners.for_each(move |event| {
 ///     match event {
-///         ListenersEvent::NewAddress { listen_addr } => {
+///         ListenersEvent::NewAddress { listen_addr, .. } => {
 ///             println!("Listener is listening at address {}", listen_addr);
 ///         },
-///         ListenersEvent::AddressExpired { listen_addr } => {
+///         ListenersEvent::AddressExpired { listen_addr, .. } => {
 ///             println!("Listener is no longer listening at address {}", listen_addr);
 ///         },
-///         ListenersEvent::Closed { result, .. } => {
-///             println!("Listener has been closed: {:?}", result);
+///         ListenersEvent::Closed { reason, .. } => {
+///             println!("Listener has been closed: {:?}", reason);
+///         },
+///         ListenersEvent::Error { error, .. } => {
+///             println!("Listener has failed and will be restarted: {:?}", error);
 ///         },
 ///         ListenersEvent::Incoming { upgrade, listen_addr, .. } => {
 ///             println!("A connection has arrived on {}", listen_addr);
@@ -83,19 +91,107 @@ where
     /// Transport used to spawn listeners.
     transport: TTrans,
     /// All the active listeners.
-    listeners: VecDeque<Listener<TTrans>>
+    listeners: VecDeque<Listener<TTrans>>,
+    /// Listeners that failed and are waiting for their backoff to elapse before being restarted.
+    restarting: Vec<PendingRestart>,
+    /// If `Some`, listeners that fail with an error are restarted according to this policy.
+    restart_policy: Option<ListenerRestartPolicy>,
+    /// The next listener ID to assign.
+    next_id: ListenerId
 }
 
+/// The ID of a single listener.
+///
+/// It is part of most [`ListenersEvent`]s and can be used to remove
+/// individual listeners from the [`ListenersStream`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ListenerId(u64);
+
 /// A single active listener.
 #[derive(Debug)]
 struct Listener<TTrans>
 where
     TTrans: Transport,
 {
+    /// The ID of this listener.
+    id: ListenerId,
+    /// The address that was passed to `listen_on`. Used to restart the listener.
+    addr: Multiaddr,
     /// The object that actually listens.
     listener: TTrans::Listener,
     /// Addresses it is listening on.
-    addresses: SmallVec<[Multiaddr; 4]>
+    addresses: SmallVec<[Multiaddr; 4]>,
+    /// Number of times in a row the listener has failed without producing an event in-between.
+    failures: u32,
+}
+
+/// A listener that failed and will be restarted once `delay` has elapsed.
+#[derive(Debug)]
+struct PendingRestart {
+    /// The ID of the listener, which is preserved across restarts.
+    id: ListenerId,
+    /// The address to listen on.
+    addr: Multiaddr,
+    /// Number of times in a row the listener has failed.
+    failures: u32,
+    /// Fires when the listener must be restarted.
+    delay: Delay,
+}
+
+/// Policy for restarting listeners that fail with an error.
+///
+/// After each failure, the listener is restarted on the address originally passed to
+/// `listen_on` once a backoff has elapsed. The backoff starts at `initial_backoff` and doubles
+/// with every consecutive failure, up to `max_backoff`. A listener that produces an event again is
+/// no longer considered failing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenerRestartPolicy {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl Default for ListenerRestartPolicy {
+    fn default() -> Self {
+        ListenerRestartPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ListenerRestartPolicy {
+    /// Sets the delay before the first restart of a failed listener. Defaults to one second.
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum delay between two restarts of a failed listener. Defaults to one minute.
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum number of consecutive restarts of a listener, after which it is closed.
+    /// Defaults to `None`, which means that failed listeners are always restarted.
+    pub fn with_max_attempts(mut self, attempts: Option<u32>) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Returns the delay before restarting a listener that failed `failures` times in a row, or
+    /// `None` if the listener shouldn't be restarted.
+    fn backoff(&self, failures: u32) -> Option<Duration> {
+        if self.max_attempts.map_or(false, |max| failures >= max) {
+            return None;
+        }
+        let backoff = self.initial_backoff
+            .checked_mul(2u32.saturating_pow(failures))
+            .map_or(self.max_backoff, |b| cmp::min(b, self.max_backoff));
+        Some(backoff)
+    }
 }
 
 /// Event that can happen on the `ListenersStream`.
@@ -105,11 +201,15 @@ where
 {
     /// A new address is being listened on.
     NewAddress {
+        /// The listener that is listening on the new address.
+        listener_id: ListenerId,
         /// The new address that is being listened on.
         listen_addr: Multiaddr
     },
     /// An address is no longer being listened on.
     AddressExpired {
+        /// The listener that is no longer listening on the address.
+        listener_id: ListenerId,
         /// The new address that is being listened on.
         listen_addr: Multiaddr
     },
@@ -122,12 +222,27 @@ where
         /// Address used to send back data to the incoming client.
         send_back_addr: Multiaddr,
     },
-    /// A listener has closed, either gracefully or with an error.
+    /// A listener has closed, either gracefully or with an error, and will not be restarted.
     Closed {
-        /// The listener that closed.
-        listener: TTrans::Listener,
+        /// The ID of the listener that closed.
+        listener_id: ListenerId,
+        /// The addresses the listener was listening on, which are now expired.
+        addresses: Vec<Multiaddr>,
         /// The error that happened. `Ok` if gracefully closed.
-        result: Result<(), <TTrans::Listener as Stream>::Error>,
+        reason: Result<(), TTrans::Error>,
+    },
+    /// A listener has failed with an error and will be restarted according to the
+    /// [`ListenerRestartPolicy`].
+    ///
+    /// Can also be generated if restarting the listener failed, in which case another attempt
+    /// will be made later.
+    Error {
+        /// The ID of the listener that failed.
+        listener_id: ListenerId,
+        /// The addresses the listener was listening on, which are now expired.
+        addresses: Vec<Multiaddr>,
+        /// The error that happened.
+        error: TTrans::Error,
     },
 }
 
@@ -140,7 +255,10 @@ where
     pub fn new(transport: TTrans) -> Self {
         ListenersStream {
             transport,
-            listeners: VecDeque::new()
+            listeners: VecDeque::new(),
+            restarting: Vec::new(),
+            restart_policy: None,
+            next_id: ListenerId(1)
         }
     }
 
@@ -150,20 +268,50 @@ where
     pub fn with_capacity(transport: TTrans, capacity: usize) -> Self {
         ListenersStream {
             transport,
-            listeners: VecDeque::with_capacity(capacity)
+            listeners: VecDeque::with_capacity(capacity),
+            restarting: Vec::new(),
+            restart_policy: None,
+            next_id: ListenerId(1)
         }
     }
 
     /// Start listening on a multiaddress.
     ///
     /// Returns an error if the transport doesn't support the given multiaddress.
-    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<(), TransportError<TTrans::Error>>
+    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, TransportError<TTrans::Error>>
     where
         TTrans: Clone,
     {
-        let listener = self.transport.clone().listen_on(addr)?;
-        self.listeners.push_back(Listener { listener, addresses: SmallVec::new() });
-        Ok(())
+        let listener = self.transport.clone().listen_on(addr.clone())?;
+        let id = self.next_id;
+        self.next_id = ListenerId(self.next_id.0 + 1);
+        self.listeners.push_back(Listener { id, addr, listener, addresses: SmallVec::new(), failures: 0 });
+        Ok(id)
+    }
+
+    /// Sets the policy for restarting listeners that fail with an error. If `None`, which is the
+    /// default, failed listeners are closed.
+    ///
+    /// Listeners that are already waiting to be restarted are not affected.
+    pub fn set_restart_policy(&mut self, policy: Option<ListenerRestartPolicy>) {
+        self.restart_policy = policy;
+    }
+
+    /// Remove the listener matching the given `ListenerId`.
+    ///
+    /// The listener is dropped immediately and no `Closed` event is generated for it. On success,
+    /// returns the addresses the listener was listening on. Returns `Err(())` if no listener
+    /// with this ID exists.
+    pub fn remove_listener(&mut self, id: ListenerId) -> Result<impl Iterator<Item = Multiaddr>, ()> {
+        if let Some(i) = self.listeners.iter().position(|l| l.id == id) {
+            let listener = self.listeners.remove(i).expect("the index was found above; qed");
+            Ok(listener.addresses.into_iter())
+        } else if let Some(i) = self.restarting.iter().position(|r| r.id == id) {
+            self.restarting.remove(i);
+            Ok(SmallVec::<[Multiaddr; 4]>::new().into_iter())
+        } else {
+            Err(())
+        }
     }
 
     /// Returns the transport passed when building this object.
@@ -176,8 +324,46 @@ where
         self.listeners.iter().flat_map(|l| l.addresses.iter())
     }
 
+    /// Returns the IDs of all the listeners, including the ones waiting to be restarted.
+    pub fn listener_ids(&self) -> impl Iterator<Item = ListenerId> + '_ {
+        self.listeners.iter().map(|l| l.id).chain(self.restarting.iter().map(|r| r.id))
+    }
+
     /// Provides an API similar to `Stream`, except that it cannot error.
-    pub fn poll(&mut self) -> Async<ListenersEvent<TTrans>> {
+    ///
+    /// The transport must implement `Clone`, as failed listeners are restarted by calling
+    /// `listen_on` on a copy of it.
+    pub fn poll(&mut self) -> Async<ListenersEvent<TTrans>>
+    where
+        TTrans: Clone,
+    {
+        // Restart the failed listeners whose backoff has elapsed.
+        for n in (0 .. self.restarting.len()).rev() {
+            match self.restarting[n].delay.poll() {
+                Ok(Async::NotReady) => continue,
+                Ok(Async::Ready(())) | Err(_) => {}
+            }
+            let PendingRestart { id, addr, failures, .. } = self.restarting.swap_remove(n);
+            match self.transport.clone().listen_on(addr.clone()) {
+                Ok(listener) => {
+                    // Pushed at the back so that the new listener is polled below.
+                    self.listeners.push_back(Listener { id, addr, listener, addresses: SmallVec::new(), failures });
+                }
+                Err(TransportError::Other(error)) => {
+                    return Async::Ready(self.on_listener_error(id, addr, failures, Vec::new(), error))
+                }
+                Err(TransportError::MultiaddrNotSupported(_)) => {
+                    // The transport no longer supports the address. There is no error to report
+                    // and no point in trying again.
+                    return Async::Ready(ListenersEvent::Closed {
+                        listener_id: id,
+                        addresses: Vec::new(),
+                        reason: Ok(()),
+                    })
+                }
+            }
+        }
+
         // We remove each element from `listeners` one by one and add them back.
         let mut remaining = self.listeners.len();
         while let Some(mut listener) = self.listeners.pop_back() {
@@ -191,6 +377,7 @@ where
                     debug_assert!(listener.addresses.contains(&listen_addr),
                         "Transport reported listen address {} not in the list: {:?}",
                         listen_addr, listener.addresses);
+                    listener.failures = 0;
                     self.listeners.push_front(listener);
                     return Async::Ready(ListenersEvent::Incoming {
                         upgrade,
@@ -204,25 +391,27 @@ where
                     if !listener.addresses.contains(&a) {
                         listener.addresses.push(a.clone());
                     }
+                    listener.failures = 0;
+                    let id = listener.id;
                     self.listeners.push_front(listener);
-                    return Async::Ready(ListenersEvent::NewAddress { listen_addr: a })
+                    return Async::Ready(ListenersEvent::NewAddress { listener_id: id, listen_addr: a })
                 }
                 Ok(Async::Ready(Some(ListenerEvent::AddressExpired(a)))) => {
                     listener.addresses.retain(|x| x != &a);
+                    let id = listener.id;
                     self.listeners.push_front(listener);
-                    return Async::Ready(ListenersEvent::AddressExpired { listen_addr: a })
+                    return Async::Ready(ListenersEvent::AddressExpired { listener_id: id, listen_addr: a })
                 }
                 Ok(Async::Ready(None)) => {
                     return Async::Ready(ListenersEvent::Closed {
-                        listener: listener.listener,
-                        result: Ok(()),
+                        listener_id: listener.id,
+                        addresses: listener.addresses.into_vec(),
+                        reason: Ok(()),
                     })
                 }
                 Err(err) => {
-                    return Async::Ready(ListenersEvent::Closed {
-                        listener: listener.listener,
-                        result: Err(err),
-                    })
+                    let Listener { id, addr, addresses, failures, .. } = listener;
+                    return Async::Ready(self.on_listener_error(id, addr, failures, addresses.into_vec(), err))
                 }
             }
         }
@@ -230,11 +419,34 @@ where
         // We register the current task to be woken up if a new listener is added.
         Async::NotReady
     }
+
+    /// Handles a listener failing with `error`, either by scheduling a restart or by closing it.
+    fn on_listener_error(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+        failures: u32,
+        addresses: Vec<Multiaddr>,
+        error: TTrans::Error
+    ) -> ListenersEvent<TTrans> {
+        let backoff = self.restart_policy.as_ref().and_then(|p| p.backoff(failures));
+        if let Some(backoff) = backoff {
+            self.restarting.push(PendingRestart {
+                id,
+                addr,
+                failures: failures + 1,
+                delay: Delay::new(Instant::now() + backoff),
+            });
+            ListenersEvent::Error { listener_id: id, addresses, error }
+        } else {
+            ListenersEvent::Closed { listener_id: id, addresses, reason: Err(error) }
+        }
+    }
 }
 
 impl<TTrans> Stream for ListenersStream<TTrans>
 where
-    TTrans: Transport,
+    TTrans: Transport + Clone,
 {
     type Item = ListenersEvent<TTrans>;
     type Error = Void; // TODO: use ! once stable
@@ -260,25 +472,35 @@ where
 impl<TTrans> fmt::Debug for ListenersEvent<TTrans>
 where
     TTrans: Transport,
-    <TTrans::Listener as Stream>::Error: fmt::Debug,
+    TTrans::Error: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
-            ListenersEvent::NewAddress { listen_addr } => f
+            ListenersEvent::NewAddress { listener_id, listen_addr } => f
                 .debug_struct("ListenersEvent::NewAddress")
+                .field("listener_id", listener_id)
                 .field("listen_addr", listen_addr)
                 .finish(),
-            ListenersEvent::AddressExpired { listen_addr } => f
+            ListenersEvent::AddressExpired { listener_id, listen_addr } => f
                 .debug_struct("ListenersEvent::AddressExpired")
+                .field("listener_id", listener_id)
                 .field("listen_addr", listen_addr)
                 .finish(),
             ListenersEvent::Incoming { listen_addr, .. } => f
                 .debug_struct("ListenersEvent::Incoming")
                 .field("listen_addr", listen_addr)
                 .finish(),
-            ListenersEvent::Closed { result, .. } => f
+            ListenersEvent::Closed { listener_id, addresses, reason } => f
                 .debug_struct("ListenersEvent::Closed")
-                .field("result", result)
+                .field("listener_id", listener_id)
+                .field("addresses", addresses)
+                .field("reason", reason)
+                .finish(),
+            ListenersEvent::Error { listener_id, addresses, error } => f
+                .debug_struct("ListenersEvent::Error")
+                .field("listener_id", listener_id)
+                .field("addresses", addresses)
+                .field("error", error)
                 .finish(),
         }
     }
@@ -498,6 +720,83 @@ mod tests {
         assert_eq!(ls.listeners.len(), 0); // it's gone
     }
 
+    #[test]
+    fn listener_stream_remove_listener() {
+        let mut t = DummyTransport::new();
+        let addr1 = tcp4([127, 0, 0, 1], 1234);
+        let addr2 = tcp4([127, 0, 0, 1], 4321);
+        t.set_initial_listener_state(ListenerState::Events(vec![
+            ListenerEvent::NewAddress(addr1.clone()),
+            ListenerEvent::NewAddress(addr2.clone())
+        ]));
+
+        let mut ls = ListenersStream::new(t);
+        let id1 = ls.listen_on(tcp4([0, 0, 0, 0], 0)).expect("listen_on");
+        let id2 = ls.listen_on(tcp4([0, 0, 0, 0], 0)).expect("listen_on");
+        assert_ne!(id1, id2);
+        set_listener_state(&mut ls, 1, ListenerState::Ok(Async::NotReady));
+
+        for expected in &[addr1, addr2] {
+            assert_matches!(ls.poll(), Async::Ready(ListenersEvent::NewAddress { listener_id, listen_addr }) => {
+                assert_eq!(listener_id, id1);
+                assert_eq!(&listen_addr, expected);
+            });
+        }
+        assert_eq!(ls.listen_addrs().count(), 2);
+
+        let removed = ls.remove_listener(id1).expect("listener exists").collect::<Vec<_>>();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(ls.listen_addrs().count(), 0);
+        assert_eq!(ls.listeners.len(), 1);
+        assert!(ls.remove_listener(id1).is_err());
+        assert!(ls.remove_listener(id2).is_ok());
+        assert_matches!(ls.poll(), Async::NotReady);
+    }
+
+    #[test]
+    fn listener_stream_restarts_failed_listener() {
+        let mut t = DummyTransport::new();
+        let addr = tcp4([127, 0, 0, 1], 1234);
+        t.set_initial_listener_state(ListenerState::Events(vec![ListenerEvent::NewAddress(addr.clone())]));
+        let mut ls = ListenersStream::new(t);
+        ls.set_restart_policy(Some(ListenerRestartPolicy::default()
+            .with_initial_backoff(Duration::from_millis(1))));
+        let id = ls.listen_on(tcp4([0, 0, 0, 0], 0)).expect("listen_on");
+
+        assert_matches!(ls.by_ref().wait().next(), Some(Ok(ListenersEvent::NewAddress { listener_id, .. })) => {
+            assert_eq!(listener_id, id)
+        });
+
+        set_listener_state(&mut ls, 0, ListenerState::Error);
+        assert_matches!(ls.by_ref().wait().next(), Some(Ok(ListenersEvent::Error { listener_id, addresses, .. })) => {
+            assert_eq!(listener_id, id);
+            assert_eq!(addresses, vec![addr.clone()]);
+        });
+        assert_eq!(ls.listeners.len(), 0);
+        assert_eq!(ls.listen_addrs().count(), 0);
+
+        // The listener is restarted with the same ID.
+        assert_matches!(ls.by_ref().wait().next(), Some(Ok(ListenersEvent::NewAddress { listener_id, listen_addr })) => {
+            assert_eq!(listener_id, id);
+            assert_eq!(listen_addr, addr);
+        });
+        assert_eq!(ls.listeners.len(), 1);
+    }
+
+    #[test]
+    fn listener_restart_policy_backoff() {
+        let policy = ListenerRestartPolicy::default()
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_backoff(Duration::from_secs(5))
+            .with_max_attempts(Some(4));
+        assert_eq!(policy.backoff(0), Some(Duration::from_secs(1)));
+        assert_eq!(policy.backoff(1), Some(Duration::from_secs(2)));
+        assert_eq!(policy.backoff(2), Some(Duration::from_secs(4)));
+        assert_eq!(policy.backoff(3), Some(Duration::from_secs(5)));
+        assert_eq!(policy.backoff(4), None);
+        assert_eq!(ListenerRestartPolicy::default().backoff(u32::max_value()), Some(Duration::from_secs(60)));
+    }
+
     fn tcp4(ip: [u8; 4], port: u16) -> Multiaddr {
         let protos = std::iter::once(multiaddr::Protocol::Ip4(ip.into()))
             .chain(std::iter::once(multiaddr::Protocol::Tcp(port)));
@@ -33,7 +33,8 @@ pub mod listeners;
 pub mod node;
 pub mod raw_swarm;
 
-pub use self::collection::ConnectionInfo;
+pub use self::collection::{ConnectionId, ConnectionInfo};
 pub use self::node::Substream;
 pub use self::handled_node::{NodeHandlerEvent, NodeHandlerEndpoint};
+pub use self::listeners::{ListenerId, ListenerRestartPolicy};
 pub use self::raw_swarm::{ConnectedPoint, Peer, RawSwarm, RawSwarmEvent};
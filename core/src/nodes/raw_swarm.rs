@@ -24,9 +24,9 @@ use crate::{
     nodes::{
         collection::{
             CollectionEvent,
-            CollectionNodeAccept,
             CollectionReachEvent,
             CollectionStream,
+            ConnectionId,
             ConnectionInfo,
             ReachAttemptId
         },
@@ -37,11 +37,12 @@ use crate::{
         handled_node_tasks::IntoNodeHandler,
         node::Substream
     },
-    nodes::listeners::{ListenersEvent, ListenersStream},
+    nodes::listeners::{ListenerId, ListenerRestartPolicy, ListenersEvent, ListenersStream},
     transport::{Transport, TransportError}
 };
 use fnv::FnvHashMap;
 use futures::{prelude::*, future};
+use smallvec::SmallVec;
 use std::{
     collections::hash_map::{Entry, OccupiedEntry},
     error,
@@ -111,9 +112,9 @@ struct ReachAttempts<TPeerId> {
     /// the peer ID.
     other_reach_attempts: Vec<(ReachAttemptId, ConnectedPoint)>,
 
-    /// For each peer ID we're connected to, contains the endpoint we're connected to.
-    /// Always in sync with `active_nodes`.
-    connected_points: FnvHashMap<TPeerId, ConnectedPoint>,
+    /// For each peer ID we're connected to, contains the identifier and the endpoint of each
+    /// connection, from the oldest to the most recent. Always in sync with `active_nodes`.
+    connected_points: FnvHashMap<TPeerId, SmallVec<[(ConnectionId, ConnectedPoint); 2]>>,
 }
 
 impl<TPeerId> fmt::Debug for ReachAttempts<TPeerId>
@@ -146,22 +147,39 @@ pub enum RawSwarmEvent<'a, TTrans, TInEvent, TOutEvent, THandler, THandlerErr, T
 where
     TTrans: Transport,
 {
-    /// One of the listeners gracefully closed.
+    /// One of the listeners closed, either gracefully or with an error, and will not be restarted.
     ListenerClosed {
-        /// The listener which closed.
-        listener: TTrans::Listener,
+        /// The ID of the listener which closed.
+        listener_id: ListenerId,
+        /// The addresses the listener was listening on, which are now expired.
+        addresses: Vec<Multiaddr>,
         /// The error that happened. `Ok` if gracefully closed.
-        result: Result<(), <TTrans::Listener as Stream>::Error>,
+        reason: Result<(), TTrans::Error>,
+    },
+
+    /// One of the listeners failed with an error and will be restarted according to the
+    /// listener restart policy.
+    ListenerError {
+        /// The ID of the listener which failed.
+        listener_id: ListenerId,
+        /// The addresses the listener was listening on, which are now expired.
+        addresses: Vec<Multiaddr>,
+        /// The error that happened.
+        error: TTrans::Error,
     },
 
     /// One of the listeners is now listening on an additional address.
     NewListenerAddress {
+        /// The listener that is listening on the new address.
+        listener_id: ListenerId,
         /// The new address the listener is now also listening on.
         listen_addr: Multiaddr
     },
 
     /// One of the listeners is no longer listening on some address.
     ExpiredListenerAddress {
+        /// The listener that is no longer listening on the address.
+        listener_id: ListenerId,
         /// The expired address.
         listen_addr: Multiaddr
     },
@@ -183,24 +201,13 @@ where
     },
 
     /// A new connection to a peer has been opened.
+    ///
+    /// Other connections to the same peer, if any, are kept open.
     Connected {
         /// Information about the connection, including the peer ID.
         conn_info: TConnInfo,
-        /// If `Listener`, then we received the connection. If `Dial`, then it's a connection that
-        /// we opened.
-        endpoint: ConnectedPoint,
-    },
-
-    /// A connection to a peer has been replaced with a new one.
-    Replaced {
-        /// Information about the new connection. The `TPeerId` is the same as the one as the one
-        /// in `old_info`.
-        new_info: TConnInfo,
-        /// Information about the old connection. The `TPeerId` is the same as the one as the one
-        /// in `new_info`.
-        old_info: TConnInfo,
-        /// Endpoint we were connected to.
-        closed_endpoint: ConnectedPoint,
+        /// Identifier of the new connection.
+        connection_id: ConnectionId,
         /// If `Listener`, then we received the connection. If `Dial`, then it's a connection that
         /// we opened.
         endpoint: ConnectedPoint,
@@ -210,6 +217,8 @@ where
     NodeClosed {
         /// Information about the connection that has been closed.
         conn_info: TConnInfo,
+        /// Identifier of the connection that has been closed.
+        connection_id: ConnectionId,
         /// Endpoint we were connected to.
         endpoint: ConnectedPoint,
         /// The error that happened.
@@ -247,6 +256,8 @@ where
     NodeEvent {
         /// Connection that produced the event.
         conn_info: TConnInfo,
+        /// Identifier of the connection that produced the event.
+        connection_id: ConnectionId,
         /// Event that was produced by the node.
         event: TOutEvent,
     },
@@ -264,19 +275,30 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match *self {
-            RawSwarmEvent::NewListenerAddress { ref listen_addr } => {
+            RawSwarmEvent::NewListenerAddress { ref listener_id, ref listen_addr } => {
                 f.debug_struct("NewListenerAddress")
+                    .field("listener_id", listener_id)
                     .field("listen_addr", listen_addr)
                     .finish()
             }
-            RawSwarmEvent::ExpiredListenerAddress { ref listen_addr } => {
+            RawSwarmEvent::ExpiredListenerAddress { ref listener_id, ref listen_addr } => {
                 f.debug_struct("ExpiredListenerAddress")
+                    .field("listener_id", listener_id)
                     .field("listen_addr", listen_addr)
                     .finish()
             }
-            RawSwarmEvent::ListenerClosed { ref result, .. } => {
+            RawSwarmEvent::ListenerClosed { ref listener_id, ref addresses, ref reason } => {
                 f.debug_struct("ListenerClosed")
-                    .field("result", result)
+                    .field("listener_id", listener_id)
+                    .field("addresses", addresses)
+                    .field("reason", reason)
+                    .finish()
+            }
+            RawSwarmEvent::ListenerError { ref listener_id, ref addresses, ref error } => {
+                f.debug_struct("ListenerError")
+                    .field("listener_id", listener_id)
+                    .field("addresses", addresses)
+                    .field("error", error)
                     .finish()
             }
             RawSwarmEvent::IncomingConnection(ref event) => {
@@ -296,23 +318,17 @@ where
                     .field("error", error)
                     .finish()
             }
-            RawSwarmEvent::Connected { ref conn_info, ref endpoint } => {
+            RawSwarmEvent::Connected { ref conn_info, ref connection_id, ref endpoint } => {
                 f.debug_struct("Connected")
                     .field("conn_info", conn_info)
+                    .field("connection_id", connection_id)
                     .field("endpoint", endpoint)
                     .finish()
             }
-            RawSwarmEvent::Replaced { ref new_info, ref old_info, ref closed_endpoint, ref endpoint } => {
-                f.debug_struct("Replaced")
-                    .field("new_info", new_info)
-                    .field("old_info", old_info)
-                    .field("closed_endpoint", closed_endpoint)
-                    .field("endpoint", endpoint)
-                    .finish()
-            }
-            RawSwarmEvent::NodeClosed { ref conn_info, ref endpoint, ref error } => {
+            RawSwarmEvent::NodeClosed { ref conn_info, ref connection_id, ref endpoint, ref error } => {
                 f.debug_struct("NodeClosed")
                     .field("conn_info", conn_info)
+                    .field("connection_id", connection_id)
                     .field("endpoint", endpoint)
                     .field("error", error)
                     .finish()
@@ -331,9 +347,10 @@ where
                     .field("error", error)
                     .finish()
             }
-            RawSwarmEvent::NodeEvent { ref conn_info, ref event } => {
+            RawSwarmEvent::NodeEvent { ref conn_info, ref connection_id, ref event } => {
                 f.debug_struct("NodeEvent")
                     .field("conn_info", conn_info)
+                    .field("connection_id", connection_id)
                     .field("event", event)
                     .finish()
             }
@@ -483,9 +500,6 @@ pub enum IncomingError<TTransErr> {
     /// Error in the transport layer.
     // TODO: just TTransError should be enough?
     Transport(TransportError<TTransErr>),
-    /// Denied the incoming connection because we're already connected to this peer as a dialer
-    /// and we have a higher priority than the remote.
-    DeniedLowerPriority,
     /// The negotiated `PeerId` is the same as the local node.
     FoundLocalPeerId,
 }
@@ -496,9 +510,6 @@ where TTransErr: fmt::Display
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             IncomingError::Transport(err) => write!(f, "{}", err),
-            IncomingError::DeniedLowerPriority => {
-                write!(f, "Denied because of lower priority")
-            },
             IncomingError::FoundLocalPeerId => {
                 write!(f, "Incoming connection has same PeerId as us")
             },
@@ -512,7 +523,6 @@ where TTransErr: error::Error + 'static
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             IncomingError::Transport(err) => Some(err),
-            IncomingError::DeniedLowerPriority => None,
             IncomingError::FoundLocalPeerId => None,
         }
     }
@@ -755,15 +765,37 @@ where
 
     /// Start listening on the given multiaddress.
     #[inline]
-    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<(), TransportError<TTrans::Error>> {
+    pub fn listen_on(&mut self, addr: Multiaddr) -> Result<ListenerId, TransportError<TTrans::Error>> {
         self.listeners.listen_on(addr)
     }
 
+    /// Stop the listener matching the given `ListenerId`.
+    ///
+    /// On success, returns the addresses the listener was listening on. No
+    /// `ExpiredListenerAddress` or `ListenerClosed` event is generated for them.
+    /// Returns `Err(())` if no listener with this ID exists.
+    #[inline]
+    pub fn remove_listener(&mut self, id: ListenerId) -> Result<impl Iterator<Item = Multiaddr>, ()> {
+        self.listeners.remove_listener(id)
+    }
+
+    /// Sets the policy for restarting listeners that fail with an error. If `None`, which is the
+    /// default, failed listeners are closed.
+    #[inline]
+    pub fn set_listener_restart_policy(&mut self, policy: Option<ListenerRestartPolicy>) {
+        self.listeners.set_restart_policy(policy)
+    }
+
     /// Returns an iterator that produces the list of addresses we are listening on.
     pub fn listen_addrs(&self) -> impl Iterator<Item = &Multiaddr> {
         self.listeners.listen_addrs()
     }
 
+    /// Returns the IDs of all the listeners.
+    pub fn listener_ids(&self) -> impl Iterator<Item = ListenerId> + '_ {
+        self.listeners.listener_ids()
+    }
+
     /// Returns limit on incoming connections.
     #[inline]
     pub fn incoming_limit(&self) -> Option<u32> {
@@ -870,6 +902,20 @@ where
         self.active_nodes.broadcast_event(event)
     }
 
+    /// Asks all the nodes, including the ones we are still trying to reach, to shut down
+    /// gracefully.
+    ///
+    /// See `NodeHandler::close_gracefully`.
+    pub fn close_gracefully(&mut self) {
+        self.active_nodes.close_gracefully()
+    }
+
+    /// Returns true if some tasks are polled by `poll` rather than by an executor and haven't
+    /// finished yet, for example in order to close the muxer of a connection.
+    pub fn has_local_tasks(&self) -> bool {
+        self.active_nodes.has_local_tasks()
+    }
+
     /// Returns a list of all the peers we are currently connected to.
     ///
     /// Calling `peer()` with each `PeerId` is guaranteed to produce a `PeerConnected`.
@@ -879,6 +925,15 @@ where
         self.active_nodes.connections()
     }
 
+    /// Returns the peers we are currently connected to, with the endpoint of each connection.
+    ///
+    /// A peer appears once for each connection we have to it.
+    pub fn connected_points(&self) -> impl Iterator<Item = (&TPeerId, &ConnectedPoint)> {
+        self.reach_attempts.connected_points
+            .iter()
+            .flat_map(|(peer_id, points)| points.iter().map(move |(_, endpoint)| (peer_id, endpoint)))
+    }
+
     /// Returns a list of all the nodes we are currently trying to reach.
     ///
     /// Calling `peer()` with each `PeerId` is guaranteed to produce a `PeerPendingConnect`
@@ -911,10 +966,7 @@ where
             return Peer::LocalNode;
         }
 
-        // TODO: we do `peer_mut(...).is_some()` followed with `peer_mut(...).unwrap()`, otherwise
-        // the borrow checker yells at us.
-
-        if self.active_nodes.peer_mut(&peer_id).is_some() {
+        if self.active_nodes.has_connection(&peer_id) {
             return Peer::Connected(PeerConnected {
                 active_nodes: &mut self.active_nodes,
                 peer_id,
@@ -1010,7 +1062,7 @@ where
         <THandler::Handler as NodeHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
         THandlerErr: error::Error + Send + 'static,
         TConnInfo: Clone,
-        TPeerId: AsRef<[u8]> + Send + 'static,
+        TPeerId: Send + 'static,
     {
         // Start by polling the listeners for events, but only if the number
         // of incoming connections does not exceed the limit.
@@ -1031,14 +1083,17 @@ where
                         };
                         return Async::Ready(RawSwarmEvent::IncomingConnection(event));
                     }
-                    Async::Ready(ListenersEvent::NewAddress { listen_addr }) => {
-                        return Async::Ready(RawSwarmEvent::NewListenerAddress { listen_addr })
+                    Async::Ready(ListenersEvent::NewAddress { listener_id, listen_addr }) => {
+                        return Async::Ready(RawSwarmEvent::NewListenerAddress { listener_id, listen_addr })
+                    }
+                    Async::Ready(ListenersEvent::AddressExpired { listener_id, listen_addr }) => {
+                        return Async::Ready(RawSwarmEvent::ExpiredListenerAddress { listener_id, listen_addr })
                     }
-                    Async::Ready(ListenersEvent::AddressExpired { listen_addr }) => {
-                        return Async::Ready(RawSwarmEvent::ExpiredListenerAddress { listen_addr })
+                    Async::Ready(ListenersEvent::Closed { listener_id, addresses, reason }) => {
+                        return Async::Ready(RawSwarmEvent::ListenerClosed { listener_id, addresses, reason })
                     }
-                    Async::Ready(ListenersEvent::Closed { listener, result }) => {
-                        return Async::Ready(RawSwarmEvent::ListenerClosed { listener, result })
+                    Async::Ready(ListenersEvent::Error { listener_id, addresses, error }) => {
+                        return Async::Ready(RawSwarmEvent::ListenerError { listener_id, addresses, error })
                     }
                 }
             }
@@ -1060,10 +1115,11 @@ where
             }
             Async::Ready(CollectionEvent::NodeClosed {
                 conn_info,
+                id,
                 error,
                 ..
             }) => {
-                let endpoint = self.reach_attempts.connected_points.remove(conn_info.peer_id())
+                let endpoint = remove_connected_point(&mut self.reach_attempts.connected_points, conn_info.peer_id(), id)
                     .expect("We insert into connected_points whenever a connection is \
                              opened and remove only when a connection is closed; the \
                              underlying API is guaranteed to always deliver a connection \
@@ -1072,13 +1128,18 @@ where
                 action = Default::default();
                 out_event = RawSwarmEvent::NodeClosed {
                     conn_info: conn_info.0,
+                    connection_id: id,
                     endpoint,
                     error,
                 };
             }
             Async::Ready(CollectionEvent::NodeEvent { peer, event }) => {
                 action = Default::default();
-                out_event = RawSwarmEvent::NodeEvent { conn_info: peer.info().0.clone(), event };
+                out_event = RawSwarmEvent::NodeEvent {
+                    conn_info: peer.info().0.clone(),
+                    connection_id: peer.connection_id(),
+                    event,
+                };
             }
         }
 
@@ -1086,38 +1147,40 @@ where
             self.start_dial_out(peer_id, handler, first, rest);
         }
 
-        if let Some((peer_id, interrupt)) = action.take_over {
-            // TODO: improve proof or remove; this is too complicated right now
-            let interrupted = self.active_nodes
-                .interrupt(interrupt)
-                .expect("take_over is guaranteed to be gathered from `out_reach_attempts`;
-                         we insert in out_reach_attempts only when we call \
-                         active_nodes.add_reach_attempt, and we remove only when we call \
-                         interrupt or when a reach attempt succeeds or errors; therefore the \
-                         out_reach_attempts should always be in sync with the actual \
-                         attempts; QED");
-            self.active_nodes.peer_mut(&peer_id).unwrap().take_over(interrupted);
-        }
-
         Async::Ready(out_event)
     }
 }
 
+/// Removes the connection `id` from the connections of `peer_id` in `connected_points`, and
+/// removes the entry altogether if it becomes empty. Returns the endpoint of the connection.
+fn remove_connected_point<TPeerId>(
+    connected_points: &mut FnvHashMap<TPeerId, SmallVec<[(ConnectionId, ConnectedPoint); 2]>>,
+    peer_id: &TPeerId,
+    id: ConnectionId,
+) -> Option<ConnectedPoint>
+where
+    TPeerId: Eq + Hash,
+{
+    let points = connected_points.get_mut(peer_id)?;
+    let pos = points.iter().position(|(i, _)| *i == id)?;
+    let (_, endpoint) = points.remove(pos);
+    if points.is_empty() {
+        connected_points.remove(peer_id);
+    }
+    Some(endpoint)
+}
+
 /// Internal struct indicating an action to perform of the swarm.
 #[derive(Debug)]
 #[must_use]
 struct ActionItem<THandler, TPeerId> {
     start_dial_out: Option<(TPeerId, THandler, Multiaddr, Vec<Multiaddr>)>,
-    /// The `ReachAttemptId` should be interrupted, and the task for the given `PeerId` should take
-    /// over it.
-    take_over: Option<(TPeerId, ReachAttemptId)>,
 }
 
 impl<THandler, TPeerId> Default for ActionItem<THandler, TPeerId> {
     fn default() -> Self {
         ActionItem {
             start_dial_out: None,
-            take_over: None,
         }
     }
 }
@@ -1140,7 +1203,7 @@ where
     TInEvent: Send + 'static,
     TOutEvent: Send + 'static,
     TConnInfo: ConnectionInfo<PeerId = TPeerId> + Clone + Send + 'static,
-    TPeerId: Eq + Hash + AsRef<[u8]> + Clone,
+    TPeerId: Eq + Hash + Clone,
 {
     // We first start looking in the incoming attempts. While this makes the code less optimal,
     // it also makes the logic easier.
@@ -1150,66 +1213,25 @@ where
         .position(|i| i.0 == event.reach_attempt_id())
     {
         let (_, opened_endpoint) = reach_attempts.other_reach_attempts.swap_remove(in_pos);
-        let has_dial_prio = has_dial_prio(&reach_attempts.local_peer_id, event.peer_id());
-
-        // If we already have an active connection to this peer, a priority system comes into play.
-        // If we have a lower peer ID than the incoming one, we drop an incoming connection.
-        if event.would_replace() && has_dial_prio {
-            if let Some(ConnectedPoint::Dialer { .. }) = reach_attempts.connected_points.get(event.peer_id()) {
-                if let ConnectedPoint::Listener { listen_addr, send_back_addr } = opened_endpoint {
-                    return (Default::default(), RawSwarmEvent::IncomingConnectionError {
-                        listen_addr,
-                        send_back_addr,
-                        error: IncomingError::DeniedLowerPriority,
-                    });
-                }
-            }
-        }
 
-        // Set the endpoint for this peer.
-        let closed_endpoint = reach_attempts.connected_points.insert(event.peer_id().clone(), opened_endpoint.clone());
-
-        // If we have dial priority, we keep the current outgoing attempt because it may already
-        // have succeeded without us knowing. It is possible that the remote has already closed
-        // its ougoing attempt because it sees our outgoing attempt as a success.
-        // However we cancel any further multiaddress to attempt in any situation.
-        let action = if has_dial_prio {
-            if let Some(attempt) = reach_attempts.out_reach_attempts.get_mut(&event.peer_id()) {
-                debug_assert_ne!(attempt.id, event.reach_attempt_id());
-                attempt.next_attempts.clear();
-            }
-            ActionItem::default()
-        } else {
-            if let Some(attempt) = reach_attempts.out_reach_attempts.remove(&event.peer_id()) {
-                debug_assert_ne!(attempt.id, event.reach_attempt_id());
-                ActionItem {
-                    take_over: Some((event.peer_id().clone(), attempt.id)),
-                    .. Default::default()
-                }
-            } else {
-                ActionItem::default()
-            }
-        };
-
-        let (outcome, conn_info) = event.accept(());
-        if let CollectionNodeAccept::ReplacedExisting(old_info, ()) = outcome {
-            let closed_endpoint = closed_endpoint
-                .expect("We insert into connected_points whenever a connection is opened and \
-                         remove only when a connection is closed; the underlying API is \
-                         guaranteed to always deliver a connection closed message after it has \
-                         been opened, and no two closed messages; QED");
-            return (action, RawSwarmEvent::Replaced {
-                new_info: conn_info.0,
-                old_info: old_info.0,
-                endpoint: opened_endpoint,
-                closed_endpoint,
-            });
-        } else {
-            return (action, RawSwarmEvent::Connected {
-                conn_info: conn_info.0,
-                endpoint: opened_endpoint
-            });
+        // If we are also dialing this peer, we keep the current outgoing attempt, which may
+        // result in an additional connection. However we cancel any further multiaddress to
+        // attempt.
+        if let Some(attempt) = reach_attempts.out_reach_attempts.get_mut(&event.peer_id()) {
+            debug_assert_ne!(attempt.id, event.reach_attempt_id());
+            attempt.next_attempts.clear();
         }
+
+        let (connection_id, conn_info) = event.accept(());
+        reach_attempts.connected_points
+            .entry(conn_info.peer_id().clone())
+            .or_insert_with(SmallVec::new)
+            .push((connection_id, opened_endpoint.clone()));
+        return (Default::default(), RawSwarmEvent::Connected {
+            conn_info: conn_info.0,
+            connection_id,
+            endpoint: opened_endpoint
+        });
     }
 
     // Otherwise, try for outgoing attempts.
@@ -1230,29 +1252,16 @@ where
             address: attempt.cur_attempted,
         };
 
-        let closed_endpoint = reach_attempts.connected_points
-            .insert(event.peer_id().clone(), opened_endpoint.clone());
-
-        let (outcome, conn_info) = event.accept(());
-        if let CollectionNodeAccept::ReplacedExisting(old_info, ()) = outcome {
-            let closed_endpoint = closed_endpoint
-                .expect("We insert into connected_points whenever a connection is opened and \
-                        remove only when a connection is closed; the underlying API is guaranteed \
-                        to always deliver a connection closed message after it has been opened, \
-                        and no two closed messages; QED");
-            return (Default::default(), RawSwarmEvent::Replaced {
-                new_info: conn_info.0,
-                old_info: old_info.0,
-                endpoint: opened_endpoint,
-                closed_endpoint,
-            });
-
-        } else {
-            return (Default::default(), RawSwarmEvent::Connected {
-                conn_info: conn_info.0,
-                endpoint: opened_endpoint
-            });
-        }
+        let (connection_id, conn_info) = event.accept(());
+        reach_attempts.connected_points
+            .entry(conn_info.peer_id().clone())
+            .or_insert_with(SmallVec::new)
+            .push((connection_id, opened_endpoint.clone()));
+        return (Default::default(), RawSwarmEvent::Connected {
+            conn_info: conn_info.0,
+            connection_id,
+            endpoint: opened_endpoint
+        });
     }
 
     // We didn't find any entry in neither the outgoing connections not ingoing connections.
@@ -1264,18 +1273,6 @@ where
             find back this ID in either of these two sets");
 }
 
-/// Returns true if `local` has dialing priority over `other`.
-///
-/// This means that if `local` and `other` both dial each other, the connection from `local` should
-/// be kept and the one from `other` will be dropped.
-#[inline]
-fn has_dial_prio<TPeerId>(local: &TPeerId, other: &TPeerId) -> bool
-where
-    TPeerId: AsRef<[u8]>,
-{
-    local.as_ref() < other.as_ref()
-}
-
 /// Handles a reach error event from the collection.
 ///
 /// Optionally returns an event to return from the stream.
@@ -1592,7 +1589,7 @@ where TTrans: Transport,
     /// Reference to the `active_nodes` of the parent.
     active_nodes: &'a mut CollectionStream<TInEvent, TOutEvent, THandler, InternalReachErr<TTrans::Error, TConnInfo>, THandlerErr, (), (TConnInfo, ConnectedPoint), TPeerId>,
     /// Reference to the `connected_points` field of the parent.
-    connected_points: &'a mut FnvHashMap<TPeerId, ConnectedPoint>,
+    connected_points: &'a mut FnvHashMap<TPeerId, SmallVec<[(ConnectionId, ConnectedPoint); 2]>>,
     /// Reference to the `out_reach_attempts` field of the parent.
     out_reach_attempts: &'a mut FnvHashMap<TPeerId, OutReachAttempt>,
     peer_id: TPeerId,
@@ -1604,7 +1601,7 @@ where
     TConnInfo: ConnectionInfo<PeerId = TPeerId>,
     TPeerId: Eq + Hash,
 {
-    /// Closes the connection to this node.
+    /// Closes all the connections to this node.
     ///
     /// No `NodeClosed` message will be generated for this node.
     // TODO: consider returning a `PeerNotConnected`; however this makes all the borrows things
@@ -1617,28 +1614,79 @@ where
         }
 
         self.connected_points.remove(&self.peer_id);
-        self.active_nodes.peer_mut(&self.peer_id)
-            .expect("A PeerConnected is always created with a PeerId in active_nodes; QED")
+        while let Some(connection) = self.active_nodes.peer_mut(&self.peer_id) {
+            connection.close();
+        }
+    }
+
+    /// Closes one of the connections to this node. Other connections are kept open.
+    ///
+    /// No `NodeClosed` message will be generated for this connection. Returns `false` if the
+    /// connection doesn't belong to this node or has already been closed.
+    pub fn close_connection(self, connection_id: ConnectionId) -> bool {
+        if !self.has_connection(connection_id) {
+            return false;
+        }
+
+        remove_connected_point(self.connected_points, &self.peer_id, connection_id);
+        self.active_nodes.connection_mut(connection_id)
+            .expect("We checked above that the connection belongs to this node; QED")
             .close();
+        true
+    }
+
+    /// Returns the identifiers and endpoints of the connections to this node, from the oldest
+    /// to the most recent.
+    pub fn connections(&self) -> impl Iterator<Item = (ConnectionId, &ConnectedPoint)> {
+        self.connected_points.get(&self.peer_id)
+            .into_iter()
+            .flat_map(|points| points.iter().map(|(id, endpoint)| (*id, endpoint)))
+    }
+
+    /// Returns the number of connections to this node.
+    #[inline]
+    pub fn num_connections(&self) -> usize {
+        self.connected_points.get(&self.peer_id).map_or(0, |points| points.len())
+    }
+
+    /// Returns true if `connection_id` is one of the connections to this node.
+    #[inline]
+    pub fn has_connection(&self, connection_id: ConnectionId) -> bool {
+        self.connections().any(|(id, _)| id == connection_id)
     }
 
-    /// Returns the endpoint we're connected to.
+    /// Returns the endpoint of the oldest connection to this node.
     #[inline]
     pub fn endpoint(&self) -> &ConnectedPoint {
-        self.connected_points.get(&self.peer_id)
+        self.connections().next()
+            .map(|(_, endpoint)| endpoint)
             .expect("We insert into connected_points whenever a connection is opened and remove \
                      only when a connection is closed; the underlying API is guaranteed to always \
                      deliver a connection closed message after it has been opened, and no two \
                      closed messages; QED")
     }
 
-    /// Sends an event to the node.
+    /// Sends an event to the handler of the oldest connection to this node.
     #[inline]
     pub fn send_event(&mut self, event: TInEvent) {
         self.active_nodes.peer_mut(&self.peer_id)
             .expect("A PeerConnected is always created with a PeerId in active_nodes; QED")
             .send_event(event)
     }
+
+    /// Sends an event to the handler of a specific connection to this node.
+    ///
+    /// Returns back the event if the connection doesn't belong to this node or has been closed.
+    pub fn send_event_to(&mut self, connection_id: ConnectionId, event: TInEvent) -> Result<(), TInEvent> {
+        if !self.has_connection(connection_id) {
+            return Err(event);
+        }
+
+        self.active_nodes.connection_mut(connection_id)
+            .expect("We checked above that the connection belongs to this node; QED")
+            .send_event(event);
+        Ok(())
+    }
 }
 
 /// Access to a peer we are attempting to connect to.
@@ -1668,7 +1716,7 @@ where
             // TODO: improve proof or remove; this is too complicated right now
             panic!("We retreived this attempt.id from out_reach_attempts. We insert in \
                     out_reach_attempts only at the same time as we call add_reach_attempt. \
-                    Whenever we receive a NodeReached, NodeReplaced or ReachError event, which \
+                    Whenever we receive a NodeReached or ReachError event, which \
                     invalidate the attempt.id, we also remove the corresponding entry in \
                     out_reach_attempts.");
         }
@@ -152,7 +152,7 @@ fn broadcasted_events_reach_active_nodes() {
             let mut swarm = swarm_fut.lock();
             match swarm.poll() {
                 Async::Ready(event) => {
-                    assert_matches!(event, RawSwarmEvent::NodeEvent { conn_info: _, event: inner_event } => {
+                    assert_matches!(event, RawSwarmEvent::NodeEvent { event: inner_event, .. } => {
                         // The event we sent reached the node and triggered sending the out event we told it to return
                         assert_matches!(inner_event, OutEvent::Custom("from handler 1"));
                     });
@@ -215,6 +215,46 @@ fn querying_for_connected_peer() {
     assert_matches!(peer, Peer::Connected( PeerConnected { .. } ));
 }
 
+#[test]
+fn multiple_connections_to_the_same_peer() {
+    let peer_id = PeerId::random();
+    let mut transport = DummyTransport::new();
+    transport.set_next_peer_id(&peer_id);
+    let mut swarm = RawSwarm::<_, _, _, Handler, _>::new(transport, PeerId::random());
+
+    // Dial the same node on two different addresses.
+    swarm.dial("/ip4/127.0.0.1/tcp/1234".parse().unwrap(), Handler::default()).expect("dialing works");
+    swarm.dial("/ip4/127.0.0.1/tcp/5678".parse().unwrap(), Handler::default()).expect("dialing works");
+
+    let swarm = Arc::new(Mutex::new(swarm));
+    let mut rt = Runtime::new().unwrap();
+    let mut connections = Vec::new();
+    while connections.len() < 2 {
+        let swarm_fut = swarm.clone();
+        let connection = rt.block_on(future::poll_fn(move || -> Poll<_, ()> {
+            let mut swarm = swarm_fut.lock();
+            match swarm.poll() {
+                Async::Ready(RawSwarmEvent::Connected { connection_id, .. }) => Ok(Async::Ready(Some(connection_id))),
+                _ => Ok(Async::Ready(None))
+            }
+        })).expect("tokio works");
+        connections.extend(connection);
+    }
+
+    // Both connections are kept open.
+    let mut swarm = swarm.lock();
+    assert_eq!(swarm.connected_points().count(), 2);
+    let peer = swarm.peer(peer_id.clone()).into_connected().expect("we are connected");
+    assert_eq!(peer.connections().map(|(id, _)| id).collect::<Vec<_>>(), connections);
+
+    // Closing one of them keeps the other one.
+    assert!(peer.close_connection(connections[0]));
+    let peer = swarm.peer(peer_id.clone()).into_connected().expect("we are still connected");
+    assert_eq!(peer.num_connections(), 1);
+    assert!(peer.has_connection(connections[1]));
+    assert!(!peer.close_connection(connections[0]));
+}
+
 #[test]
 fn poll_with_closed_listener() {
     let mut transport = DummyTransport::new();
@@ -359,15 +399,6 @@ fn yields_node_error_when_there_is_an_error_after_successful_connect() {
     })).expect("tokio works");
 }
 
-#[test]
-fn local_prio_equivalence_relation() {
-    for _ in 0..1000 {
-        let a = PeerId::random();
-        let b = PeerId::random();
-        assert_ne!(has_dial_prio(&a, &b), has_dial_prio(&b, &a));
-    }
-}
-
 #[test]
 fn limit_incoming_connections() {
     let mut transport = DummyTransport::new();
@@ -0,0 +1,376 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Type-erased [`ProtocolsHandler`]s, so that handlers of different protocols can be combined in
+//! a [`MultiHandler`](super::MultiHandler).
+
+use crate::{
+    protocols_handler::{
+        KeepAlive,
+        ProtocolsHandler,
+        ProtocolsHandlerEvent,
+        ProtocolsHandlerUpgrErr,
+        SubstreamProtocol,
+    },
+    upgrade::{InboundUpgrade, Negotiated, OutboundUpgrade, ProtocolName, UpgradeError, UpgradeInfo}
+};
+use futures::prelude::*;
+use log::debug;
+use std::{any::Any, error, fmt, vec};
+
+/// Event, upgrade output or outbound open information of a [`BoxedProtocolsHandler`].
+///
+/// Contains the value of the original handler, which can be recovered with `downcast`.
+pub type BoxedEvent = Box<dyn Any + Send>;
+
+/// Future of a [`BoxedInboundUpgrade`] or a [`BoxedOutboundUpgrade`].
+pub type BoxedUpgradeFuture = Box<dyn Future<Item = BoxedEvent, Error = BoxedError> + Send>;
+
+/// A [`ProtocolsHandler`] whose types have been erased with [`boxed`].
+pub type BoxedProtocolsHandler<TSubstream> = Box<dyn ProtocolsHandler<
+    InEvent = BoxedEvent,
+    OutEvent = BoxedEvent,
+    Error = BoxedError,
+    Substream = TSubstream,
+    InboundProtocol = BoxedInboundUpgrade<TSubstream>,
+    OutboundProtocol = BoxedOutboundUpgrade<TSubstream>,
+    OutboundOpenInfo = BoxedEvent,
+> + Send>;
+
+/// Erases the types of a handler.
+///
+/// The events sent to the returned handler must be `H::InEvent`s, and the events it produces
+/// are `H::OutEvent`s. Events of another type are ignored.
+pub fn boxed<H>(handler: H) -> BoxedProtocolsHandler<H::Substream>
+where
+    H: ProtocolsHandler + Send + 'static,
+    H::InEvent: 'static,
+    H::OutEvent: Send + 'static,
+    H::Error: Send + 'static,
+    H::Substream: 'static,
+    H::InboundProtocol: Send + 'static,
+    <H::InboundProtocol as InboundUpgrade<H::Substream>>::Output: Send + 'static,
+    <H::InboundProtocol as InboundUpgrade<H::Substream>>::Error: fmt::Debug + Send + 'static,
+    <H::InboundProtocol as InboundUpgrade<H::Substream>>::Future: Send + 'static,
+    H::OutboundProtocol: Send + 'static,
+    <H::OutboundProtocol as OutboundUpgrade<H::Substream>>::Output: Send + 'static,
+    <H::OutboundProtocol as OutboundUpgrade<H::Substream>>::Error: fmt::Debug + Send + 'static,
+    <H::OutboundProtocol as OutboundUpgrade<H::Substream>>::Future: Send + 'static,
+    H::OutboundOpenInfo: Send + 'static,
+{
+    Box::new(Erased(handler))
+}
+
+/// Error of a [`BoxedProtocolsHandler`] or of its upgrades.
+///
+/// Contains the error of the original handler or upgrade, which can be recovered with
+/// `downcast`.
+pub struct BoxedError(Box<dyn AnyDebug>);
+
+impl BoxedError {
+    fn new<E>(error: E) -> Self
+    where
+        E: fmt::Debug + Send + 'static,
+    {
+        BoxedError(Box::new(error))
+    }
+
+    /// Returns the original error if it is an `E`, otherwise returns `self`.
+    pub fn downcast<E: 'static>(self) -> Result<E, Self> {
+        if self.0.as_any().is::<E>() {
+            let error = self.0.into_any().downcast::<E>().expect("checked with is; QED");
+            Ok(*error)
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Returns a reference to the original error if it is an `E`.
+    pub fn downcast_ref<E: 'static>(&self) -> Option<&E> {
+        self.0.as_any().downcast_ref()
+    }
+}
+
+impl fmt::Debug for BoxedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for BoxedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl error::Error for BoxedError {}
+
+/// A value that can be debugged and downcast.
+trait AnyDebug: fmt::Debug + Send {
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send>;
+}
+
+impl<T> AnyDebug for T
+where
+    T: fmt::Debug + Send + 'static,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        self
+    }
+}
+
+/// Inbound upgrade of a [`BoxedProtocolsHandler`].
+pub struct BoxedInboundUpgrade<TSubstream> {
+    inner: Box<dyn AbstractInbound<TSubstream> + Send>,
+}
+
+impl<TSubstream> BoxedInboundUpgrade<TSubstream> {
+    fn new<U>(upgrade: U) -> Self
+    where
+        U: InboundUpgrade<TSubstream> + Send + 'static,
+        U::Output: Send + 'static,
+        U::Error: fmt::Debug + Send + 'static,
+        U::Future: Send + 'static,
+    {
+        BoxedInboundUpgrade { inner: Box::new(upgrade) }
+    }
+}
+
+impl<TSubstream> fmt::Debug for BoxedInboundUpgrade<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BoxedInboundUpgrade")
+    }
+}
+
+impl<TSubstream> UpgradeInfo for BoxedInboundUpgrade<TSubstream> {
+    type Info = Vec<u8>;
+    type InfoIter = vec::IntoIter<Vec<u8>>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.inner.protocol_names().into_iter()
+    }
+}
+
+impl<TSubstream> InboundUpgrade<TSubstream> for BoxedInboundUpgrade<TSubstream> {
+    type Output = BoxedEvent;
+    type Error = BoxedError;
+    type Future = BoxedUpgradeFuture;
+
+    fn upgrade_inbound(self, socket: Negotiated<TSubstream>, info: Self::Info) -> Self::Future {
+        self.inner.upgrade_inbound(socket, &info)
+    }
+}
+
+trait AbstractInbound<TSubstream> {
+    fn protocol_names(&self) -> Vec<Vec<u8>>;
+    fn upgrade_inbound(self: Box<Self>, socket: Negotiated<TSubstream>, name: &[u8]) -> BoxedUpgradeFuture;
+}
+
+impl<TSubstream, U> AbstractInbound<TSubstream> for U
+where
+    U: InboundUpgrade<TSubstream>,
+    U::Output: Send + 'static,
+    U::Error: fmt::Debug + Send + 'static,
+    U::Future: Send + 'static,
+{
+    fn protocol_names(&self) -> Vec<Vec<u8>> {
+        self.protocol_info().into_iter().map(|info| info.protocol_name().to_vec()).collect()
+    }
+
+    fn upgrade_inbound(self: Box<Self>, socket: Negotiated<TSubstream>, name: &[u8]) -> BoxedUpgradeFuture {
+        let info = self.protocol_info().into_iter()
+            .find(|info| info.protocol_name() == name)
+            .expect("The name was produced by protocol_names; QED");
+        let future = InboundUpgrade::upgrade_inbound(*self, socket, info)
+            .map(|output| Box::new(output) as BoxedEvent)
+            .map_err(BoxedError::new);
+        Box::new(future)
+    }
+}
+
+/// Outbound upgrade of a [`BoxedProtocolsHandler`].
+pub struct BoxedOutboundUpgrade<TSubstream> {
+    inner: Box<dyn AbstractOutbound<TSubstream> + Send>,
+}
+
+impl<TSubstream> BoxedOutboundUpgrade<TSubstream> {
+    fn new<U>(upgrade: U) -> Self
+    where
+        U: OutboundUpgrade<TSubstream> + Send + 'static,
+        U::Output: Send + 'static,
+        U::Error: fmt::Debug + Send + 'static,
+        U::Future: Send + 'static,
+    {
+        BoxedOutboundUpgrade { inner: Box::new(upgrade) }
+    }
+}
+
+impl<TSubstream> fmt::Debug for BoxedOutboundUpgrade<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BoxedOutboundUpgrade")
+    }
+}
+
+impl<TSubstream> UpgradeInfo for BoxedOutboundUpgrade<TSubstream> {
+    type Info = Vec<u8>;
+    type InfoIter = vec::IntoIter<Vec<u8>>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.inner.protocol_names().into_iter()
+    }
+}
+
+impl<TSubstream> OutboundUpgrade<TSubstream> for BoxedOutboundUpgrade<TSubstream> {
+    type Output = BoxedEvent;
+    type Error = BoxedError;
+    type Future = BoxedUpgradeFuture;
+
+    fn upgrade_outbound(self, socket: Negotiated<TSubstream>, info: Self::Info) -> Self::Future {
+        self.inner.upgrade_outbound(socket, &info)
+    }
+}
+
+trait AbstractOutbound<TSubstream> {
+    fn protocol_names(&self) -> Vec<Vec<u8>>;
+    fn upgrade_outbound(self: Box<Self>, socket: Negotiated<TSubstream>, name: &[u8]) -> BoxedUpgradeFuture;
+}
+
+impl<TSubstream, U> AbstractOutbound<TSubstream> for U
+where
+    U: OutboundUpgrade<TSubstream>,
+    U::Output: Send + 'static,
+    U::Error: fmt::Debug + Send + 'static,
+    U::Future: Send + 'static,
+{
+    fn protocol_names(&self) -> Vec<Vec<u8>> {
+        self.protocol_info().into_iter().map(|info| info.protocol_name().to_vec()).collect()
+    }
+
+    fn upgrade_outbound(self: Box<Self>, socket: Negotiated<TSubstream>, name: &[u8]) -> BoxedUpgradeFuture {
+        let info = self.protocol_info().into_iter()
+            .find(|info| info.protocol_name() == name)
+            .expect("The name was produced by protocol_names; QED");
+        let future = OutboundUpgrade::upgrade_outbound(*self, socket, info)
+            .map(|output| Box::new(output) as BoxedEvent)
+            .map_err(BoxedError::new);
+        Box::new(future)
+    }
+}
+
+/// Wraps around a handler and erases its types. See [`boxed`].
+struct Erased<H>(H);
+
+impl<H> ProtocolsHandler for Erased<H>
+where
+    H: ProtocolsHandler,
+    H::InEvent: 'static,
+    H::OutEvent: Send + 'static,
+    H::Error: Send + 'static,
+    H::InboundProtocol: Send + 'static,
+    <H::InboundProtocol as InboundUpgrade<H::Substream>>::Output: Send + 'static,
+    <H::InboundProtocol as InboundUpgrade<H::Substream>>::Error: fmt::Debug + Send + 'static,
+    <H::InboundProtocol as InboundUpgrade<H::Substream>>::Future: Send + 'static,
+    H::OutboundProtocol: Send + 'static,
+    <H::OutboundProtocol as OutboundUpgrade<H::Substream>>::Output: Send + 'static,
+    <H::OutboundProtocol as OutboundUpgrade<H::Substream>>::Error: fmt::Debug + Send + 'static,
+    <H::OutboundProtocol as OutboundUpgrade<H::Substream>>::Future: Send + 'static,
+    H::OutboundOpenInfo: Send + 'static,
+{
+    type InEvent = BoxedEvent;
+    type OutEvent = BoxedEvent;
+    type Error = BoxedError;
+    type Substream = H::Substream;
+    type InboundProtocol = BoxedInboundUpgrade<H::Substream>;
+    type OutboundProtocol = BoxedOutboundUpgrade<H::Substream>;
+    type OutboundOpenInfo = BoxedEvent;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        self.0.listen_protocol().map_upgrade(BoxedInboundUpgrade::new)
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, protocol: BoxedEvent) {
+        match protocol.downcast() {
+            Ok(protocol) => self.0.inject_fully_negotiated_inbound(*protocol),
+            Err(_) => debug!("Inbound substream of an unexpected type"),
+        }
+    }
+
+    fn inject_fully_negotiated_outbound(&mut self, protocol: BoxedEvent, info: BoxedEvent) {
+        match (protocol.downcast(), info.downcast()) {
+            (Ok(protocol), Ok(info)) => self.0.inject_fully_negotiated_outbound(*protocol, *info),
+            _ => debug!("Outbound substream of an unexpected type"),
+        }
+    }
+
+    fn inject_event(&mut self, event: BoxedEvent) {
+        match event.downcast() {
+            Ok(event) => self.0.inject_event(*event),
+            Err(_) => debug!("Event of an unexpected type"),
+        }
+    }
+
+    fn inject_dial_upgrade_error(&mut self, info: BoxedEvent, error: ProtocolsHandlerUpgrErr<BoxedError>) {
+        let info = match info.downcast() {
+            Ok(info) => *info,
+            Err(_) => {
+                debug!("Outbound substream error of an unexpected type");
+                return;
+            },
+        };
+        let error = match error {
+            ProtocolsHandlerUpgrErr::Timeout => ProtocolsHandlerUpgrErr::Timeout,
+            ProtocolsHandlerUpgrErr::Timer => ProtocolsHandlerUpgrErr::Timer,
+            ProtocolsHandlerUpgrErr::ResourceLimit(err) => ProtocolsHandlerUpgrErr::ResourceLimit(err),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)) =>
+                ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(err)) => match err.downcast() {
+                Ok(err) => ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(err)),
+                Err(_) => {
+                    debug!("Outbound substream error of an unexpected type");
+                    return;
+                },
+            },
+        };
+        self.0.inject_dial_upgrade_error(info, error)
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.0.connection_keep_alive()
+    }
+
+    fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<Self::OutboundProtocol, BoxedEvent, BoxedEvent>, BoxedError> {
+        match self.0.poll() {
+            Ok(Async::Ready(event)) => {
+                let event = event
+                    .map_protocol(BoxedOutboundUpgrade::new)
+                    .map_outbound_open_info(|info| Box::new(info) as BoxedEvent)
+                    .map_custom(|event| Box::new(event) as BoxedEvent);
+                Ok(Async::Ready(event))
+            },
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err(BoxedError::new(err)),
+        }
+    }
+}
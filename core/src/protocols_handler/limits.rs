@@ -0,0 +1,244 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Enforcement of the `InboundSubstreamLimits` on a connection.
+
+use crate::{
+    PeerId,
+    bandwidth::ProtocolBandwidth,
+    resource::StreamScope,
+    swarm::{InboundSubstreamLimits, SubstreamLimitError},
+    upgrade::{InboundUpgrade, Negotiated, ProtocolName, UpgradeInfo},
+};
+use fnv::FnvHashMap;
+use futures::{prelude::*, try_ready};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Number of inbound substreams of a connection, per protocol.
+#[derive(Debug, Default)]
+pub(crate) struct InboundCounters {
+    inner: Mutex<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// Total number of active substreams.
+    active: usize,
+    /// Number of negotiating and active substreams of each protocol.
+    protocols: FnvHashMap<Vec<u8>, (usize, usize)>,
+}
+
+impl InboundCounters {
+    /// Returns the number of active inbound substreams.
+    pub(crate) fn active(&self) -> usize {
+        self.inner.lock().active
+    }
+
+    /// Checks the limits and, if they allow it, counts a new substream for `protocol` both as
+    /// negotiating and as active. The substream stops being counted when the returned guards are
+    /// dropped.
+    fn open(self: &Arc<Self>, limits: &InboundSubstreamLimits, protocol: &[u8])
+        -> Result<(NegotiatingGuard, ActiveGuard), SubstreamLimitError>
+    {
+        let mut counts = self.inner.lock();
+        let (negotiating, active) = counts.protocols.get(protocol).cloned().unwrap_or((0, 0));
+        limits.check_active(|| counts.active)?;
+        limits.check_protocol_negotiating(protocol, || negotiating)?;
+        limits.check_protocol_active(protocol, || active)?;
+
+        counts.active += 1;
+        counts.protocols.insert(protocol.to_vec(), (negotiating + 1, active + 1));
+        let negotiating = NegotiatingGuard { counters: self.clone(), protocol: protocol.to_vec() };
+        let active = ActiveGuard { counters: self.clone(), protocol: protocol.to_vec() };
+        Ok((negotiating, active))
+    }
+
+    /// Applies `f` to the counts of `protocol`, and forgets the protocol once they drop to zero.
+    fn update(&self, protocol: &[u8], f: impl FnOnce(&mut usize, &mut (usize, usize))) {
+        let mut counts = self.inner.lock();
+        let Counts { active, protocols } = &mut *counts;
+        let remove = match protocols.get_mut(protocol) {
+            Some(entry) => {
+                f(active, entry);
+                *entry == (0, 0)
+            }
+            None => false,
+        };
+        if remove {
+            protocols.remove(protocol);
+        }
+    }
+}
+
+/// Counts a substream as negotiating for as long as it is alive.
+pub(crate) struct NegotiatingGuard {
+    counters: Arc<InboundCounters>,
+    protocol: Vec<u8>,
+}
+
+impl Drop for NegotiatingGuard {
+    fn drop(&mut self) {
+        self.counters.update(&self.protocol, |_, (negotiating, _)| *negotiating -= 1);
+    }
+}
+
+/// Counts a substream as active for as long as it is alive.
+pub(crate) struct ActiveGuard {
+    counters: Arc<InboundCounters>,
+    protocol: Vec<u8>,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.counters.update(&self.protocol, |total, (_, active)| {
+            *total -= 1;
+            *active -= 1;
+        });
+    }
+}
+
+/// Wraps around an inbound upgrade, enforces the per-protocol limits once a protocol has been
+/// agreed on, and adds the name of the negotiated protocol to its output.
+///
+/// If the substream has a `StreamScope`, it is attributed to the negotiated protocol and released
+/// when the substream is dropped. If `bandwidth` is `Some`, the traffic of the substream is
+/// counted for the remote and the negotiated protocol.
+///
+/// A substream that would exceed a limit is dropped, and the upgrade produces the corresponding
+/// error as an `Err` output. The protocol has already been accepted at that point, so the remote
+/// only notices if the muxer resets dropped substreams, as mplex does. Otherwise the remote's
+/// upgrade times out.
+pub(crate) struct LimitInbound<TUpgrade> {
+    pub(crate) upgrade: TUpgrade,
+    pub(crate) limits: Arc<InboundSubstreamLimits>,
+    pub(crate) counters: Arc<InboundCounters>,
+    pub(crate) scope: Option<StreamScope>,
+    pub(crate) bandwidth: Option<(PeerId, ProtocolBandwidth)>,
+}
+
+impl<TUpgrade> UpgradeInfo for LimitInbound<TUpgrade>
+where
+    TUpgrade: UpgradeInfo
+{
+    type Info = TUpgrade::Info;
+    type InfoIter = TUpgrade::InfoIter;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.upgrade.protocol_info()
+    }
+}
+
+impl<C, TUpgrade> InboundUpgrade<C> for LimitInbound<TUpgrade>
+where
+    TUpgrade: InboundUpgrade<C>
+{
+    type Output = Result<(Vec<u8>, TUpgrade::Output), SubstreamLimitError>;
+    type Error = TUpgrade::Error;
+    type Future = LimitInboundFuture<TUpgrade::Future>;
+
+    fn upgrade_inbound(self, mut socket: Negotiated<C>, info: Self::Info) -> Self::Future {
+        let protocol = info.protocol_name().to_vec();
+        if let Some(mut scope) = self.scope {
+            if let Err(err) = scope.set_protocol(&protocol) {
+                return LimitInboundFuture::Refused(Some(SubstreamLimitError::Resource(err)));
+            }
+            socket.attach(scope);
+        }
+        match self.counters.open(&self.limits, &protocol) {
+            Ok((negotiating, active)) => {
+                socket.attach(active);
+                if let Some((peer_id, bandwidth)) = &self.bandwidth {
+                    socket.observe(bandwidth.substream_traffic(peer_id, &protocol));
+                }
+                LimitInboundFuture::Upgrading {
+                    protocol: Some(protocol),
+                    negotiating: Some(negotiating),
+                    inner: self.upgrade.upgrade_inbound(socket, info),
+                }
+            }
+            Err(err) => LimitInboundFuture::Refused(Some(err)),
+        }
+    }
+}
+
+/// Future returned by the upgrade of `LimitInbound`.
+pub(crate) enum LimitInboundFuture<TFut> {
+    /// The substream has been refused.
+    Refused(Option<SubstreamLimitError>),
+    /// The handshake is in progress.
+    Upgrading {
+        protocol: Option<Vec<u8>>,
+        negotiating: Option<NegotiatingGuard>,
+        inner: TFut,
+    },
+}
+
+impl<TFut> Future for LimitInboundFuture<TFut>
+where
+    TFut: Future
+{
+    type Item = Result<(Vec<u8>, TFut::Item), SubstreamLimitError>;
+    type Error = TFut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            LimitInboundFuture::Refused(err) => {
+                let err = err.take().expect("Future polled after it finished");
+                Ok(Async::Ready(Err(err)))
+            }
+            LimitInboundFuture::Upgrading { protocol, negotiating, inner } => {
+                let output = try_ready!(inner.poll());
+                negotiating.take();
+                let protocol = protocol.take().expect("Future polled after it finished");
+                Ok(Async::Ready(Ok((protocol, output))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guards_release_their_substream() {
+        let limits = InboundSubstreamLimits::default()
+            .with_max_active(Some(2))
+            .with_max_negotiating_per_protocol(Some(1));
+        let counters = Arc::new(InboundCounters::default());
+
+        let (negotiating, active) = counters.open(&limits, b"/a").unwrap();
+        assert_eq!(
+            counters.open(&limits, b"/a").err(),
+            Some(SubstreamLimitError::ProtocolNegotiating { protocol: b"/a".to_vec(), limit: 1 })
+        );
+
+        // Once the handshake is over, another substream can be negotiated.
+        drop(negotiating);
+        let (_negotiating, _active) = counters.open(&limits, b"/a").unwrap();
+        assert_eq!(counters.active(), 2);
+        assert_eq!(counters.open(&limits, b"/b").err(), Some(SubstreamLimitError::Active { limit: 2 }));
+
+        drop(active);
+        assert_eq!(counters.active(), 1);
+        assert!(counters.open(&limits, b"/b").is_ok());
+    }
+}
@@ -39,6 +39,7 @@
 
 use crate::nodes::raw_swarm::ConnectedPoint;
 use crate::PeerId;
+use crate::resource::ResourceLimitError;
 use crate::upgrade::{
     InboundUpgrade,
     OutboundUpgrade,
@@ -49,19 +50,26 @@ use std::{cmp::Ordering, error, fmt, time::Duration};
 use tokio_io::{AsyncRead, AsyncWrite};
 use wasm_timer::Instant;
 
+pub use self::boxed::{boxed, BoxedError, BoxedEvent, BoxedInboundUpgrade, BoxedOutboundUpgrade, BoxedProtocolsHandler, BoxedUpgradeFuture};
 pub use self::dummy::DummyProtocolsHandler;
 pub use self::map_in::MapInEvent;
 pub use self::map_out::MapOutEvent;
+pub use self::multi::{DuplicateProtonameError, IntoMultiHandler, KeyedFuture, KeyedProtocolName, MultiHandler, MultiUpgrade};
 pub use self::node_handler::{NodeHandlerWrapper, NodeHandlerWrapperBuilder, NodeHandlerWrapperError};
 pub use self::one_shot::OneShotHandler;
 pub use self::select::{IntoProtocolsHandlerSelect, ProtocolsHandlerSelect};
+pub use self::supported::SupportedProtocols;
 
+mod boxed;
 mod dummy;
+pub(crate) mod limits;
 mod map_in;
 mod map_out;
+mod multi;
 mod node_handler;
 mod one_shot;
 mod select;
+pub(crate) mod supported;
 
 /// A handler for a set of protocols used on a connection with a remote.
 ///
@@ -386,6 +394,9 @@ pub enum ProtocolsHandlerUpgrErr<TUpgrErr> {
     Timeout,
     /// There was an error in the timer used.
     Timer,
+    /// The substream couldn't be opened because it would have exceeded the limits of the
+    /// `ResourceManager` of the swarm.
+    ResourceLimit(ResourceLimitError),
     /// Error while upgrading the substream to the protocol we want.
     Upgrade(UpgradeError<TUpgrErr>),
 }
@@ -402,6 +413,7 @@ where
             ProtocolsHandlerUpgrErr::Timer => {
                 write!(f, "Timer error while opening a substream")
             },
+            ProtocolsHandlerUpgrErr::ResourceLimit(err) => write!(f, "{}", err),
             ProtocolsHandlerUpgrErr::Upgrade(err) => write!(f, "{}", err),
         }
     }
@@ -415,6 +427,7 @@ where
         match self {
             ProtocolsHandlerUpgrErr::Timeout => None,
             ProtocolsHandlerUpgrErr::Timer => None,
+            ProtocolsHandlerUpgrErr::ResourceLimit(err) => Some(err),
             ProtocolsHandlerUpgrErr::Upgrade(err) => Some(err),
         }
     }
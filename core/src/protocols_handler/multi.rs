@@ -0,0 +1,730 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A [`ProtocolsHandler`] that combines any number of handlers of the same type, each
+//! identified by a key.
+
+use crate::{
+    PeerId,
+    nodes::raw_swarm::ConnectedPoint,
+    protocols_handler::{
+        IntoProtocolsHandler,
+        KeepAlive,
+        ProtocolsHandler,
+        ProtocolsHandlerEvent,
+        ProtocolsHandlerUpgrErr,
+        SubstreamProtocol,
+    },
+    upgrade::{InboundUpgrade, Negotiated, OutboundUpgrade, ProtocolName, UpgradeInfo}
+};
+use futures::{prelude::*, try_ready};
+use log::debug;
+use std::{cmp, error, fmt, time::Duration, vec};
+
+/// A [`ProtocolsHandler`] for multiple other handlers of the same type, each identified by a
+/// key.
+///
+/// Contrary to [`ProtocolsHandlerSelect`](super::ProtocolsHandlerSelect), the set of handlers
+/// is only known at runtime, and handlers can be added or removed with `try_insert` and
+/// `remove`. Inbound substreams are routed to the handler that supports the negotiated protocol,
+/// and events sent to the handler are routed by key.
+///
+/// All the handlers must have the same type. Handlers of different protocols, whose events and
+/// upgrades differ, can be combined by erasing their types with [`boxed`](super::boxed). Their
+/// events are then `BoxedEvent`s, which contain the events of the original handlers.
+///
+/// See [`IntoMultiHandler`] for the `IntoProtocolsHandler` counterpart, which a
+/// `NetworkBehaviour` can build from the handlers it knows of at runtime.
+///
+/// The connection is kept alive as long as one of the handlers requires it, and closed as
+/// soon as one of them produces an error.
+#[derive(Clone)]
+pub struct MultiHandler<K, H> {
+    /// The handlers, with their key. Keys are unique.
+    handlers: Vec<(K, H)>,
+    /// Index of the handler to poll first, so that every handler gets polled in turn.
+    next_poll: usize,
+}
+
+impl<K, H> fmt::Debug for MultiHandler<K, H>
+where
+    K: fmt::Debug,
+    H: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiHandler")
+            .field("handlers", &self.handlers)
+            .finish()
+    }
+}
+
+impl<K, H> MultiHandler<K, H>
+where
+    K: PartialEq,
+    H: ProtocolsHandler,
+{
+    /// Builds a `MultiHandler` from the given handlers.
+    ///
+    /// If a key appears several times, the last handler with that key is kept. Returns an
+    /// error if several handlers accept the same inbound protocol.
+    pub fn try_from_iter<I>(iter: I) -> Result<Self, DuplicateProtonameError>
+    where
+        I: IntoIterator<Item = (K, H)>,
+    {
+        let mut multi = MultiHandler { handlers: Vec::new(), next_poll: 0 };
+        for (key, handler) in iter {
+            multi.try_insert(key, handler)?;
+        }
+        Ok(multi)
+    }
+
+    /// Adds a handler, replacing and returning the handler with the same key, if any.
+    ///
+    /// Returns an error, and leaves the `MultiHandler` untouched, if the handler accepts an
+    /// inbound protocol that a handler with another key accepts as well.
+    pub fn try_insert(&mut self, key: K, handler: H) -> Result<Option<H>, DuplicateProtonameError> {
+        let others = self.handlers.iter()
+            .filter(|(k, _)| *k != key)
+            .map(|(_, h)| h.listen_protocol().into_upgrade());
+        check_protocol_names(&handler.listen_protocol().into_upgrade(), others)?;
+        let previous = self.remove(&key);
+        self.handlers.push((key, handler));
+        Ok(previous)
+    }
+
+    /// Removes and returns the handler with the given key.
+    pub fn remove(&mut self, key: &K) -> Option<H> {
+        let pos = self.handlers.iter().position(|(k, _)| k == key)?;
+        Some(self.handlers.remove(pos).1)
+    }
+
+    /// Returns the handler with the given key.
+    pub fn handler(&self, key: &K) -> Option<&H> {
+        self.handlers.iter().find(|(k, _)| k == key).map(|(_, h)| h)
+    }
+
+    /// Returns the handler with the given key.
+    pub fn handler_mut(&mut self, key: &K) -> Option<&mut H> {
+        self.handlers.iter_mut().find(|(k, _)| k == key).map(|(_, h)| h)
+    }
+
+    /// Returns the keys of the handlers.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.handlers.iter().map(|(k, _)| k)
+    }
+}
+
+impl<K, H> ProtocolsHandler for MultiHandler<K, H>
+where
+    K: Clone + PartialEq,
+    H: ProtocolsHandler,
+{
+    type InEvent = (K, H::InEvent);
+    type OutEvent = (K, H::OutEvent);
+    type Error = H::Error;
+    type Substream = H::Substream;
+    type InboundProtocol = MultiUpgrade<K, H::InboundProtocol>;
+    type OutboundProtocol = H::OutboundProtocol;
+    type OutboundOpenInfo = (K, H::OutboundOpenInfo);
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        let mut timeout = Duration::from_secs(0);
+        let upgrades = self.handlers.iter()
+            .map(|(key, handler)| {
+                let protocol = handler.listen_protocol();
+                timeout = cmp::max(timeout, *protocol.timeout());
+                (key.clone(), protocol.into_upgrade())
+            })
+            .collect();
+        SubstreamProtocol::new(MultiUpgrade { upgrades }).with_timeout(timeout)
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        (key, protocol): <Self::InboundProtocol as InboundUpgrade<Self::Substream>>::Output
+    ) {
+        if let Some(handler) = self.handler_mut(&key) {
+            handler.inject_fully_negotiated_inbound(protocol)
+        } else {
+            debug!("Inbound substream for an unknown handler")
+        }
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        protocol: <Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Output,
+        (key, info): Self::OutboundOpenInfo
+    ) {
+        if let Some(handler) = self.handler_mut(&key) {
+            handler.inject_fully_negotiated_outbound(protocol, info)
+        } else {
+            debug!("Outbound substream for an unknown handler")
+        }
+    }
+
+    fn inject_event(&mut self, (key, event): Self::InEvent) {
+        if let Some(handler) = self.handler_mut(&key) {
+            handler.inject_event(event)
+        } else {
+            debug!("Event for an unknown handler")
+        }
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        (key, info): Self::OutboundOpenInfo,
+        error: ProtocolsHandlerUpgrErr<<Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Error>
+    ) {
+        if let Some(handler) = self.handler_mut(&key) {
+            handler.inject_dial_upgrade_error(info, error)
+        } else {
+            debug!("Outbound substream error for an unknown handler")
+        }
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.handlers.iter()
+            .map(|(_, h)| h.connection_keep_alive())
+            .max()
+            .unwrap_or(KeepAlive::No)
+    }
+
+    fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent>, Self::Error> {
+        let len = self.handlers.len();
+        for n in 0 .. len {
+            let index = (self.next_poll + n) % len;
+            let (key, handler) = &mut self.handlers[index];
+            if let Async::Ready(event) = handler.poll()? {
+                self.next_poll = (index + 1) % len;
+                let event = event
+                    .map_outbound_open_info(|info| (key.clone(), info))
+                    .map_custom(|event| (key.clone(), event));
+                return Ok(Async::Ready(event));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+impl<H> ProtocolsHandler for Box<H>
+where
+    H: ProtocolsHandler + ?Sized,
+{
+    type InEvent = H::InEvent;
+    type OutEvent = H::OutEvent;
+    type Error = H::Error;
+    type Substream = H::Substream;
+    type InboundProtocol = H::InboundProtocol;
+    type OutboundProtocol = H::OutboundProtocol;
+    type OutboundOpenInfo = H::OutboundOpenInfo;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        (**self).listen_protocol()
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        protocol: <Self::InboundProtocol as InboundUpgrade<Self::Substream>>::Output
+    ) {
+        (**self).inject_fully_negotiated_inbound(protocol)
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        protocol: <Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Output,
+        info: Self::OutboundOpenInfo
+    ) {
+        (**self).inject_fully_negotiated_outbound(protocol, info)
+    }
+
+    fn inject_event(&mut self, event: Self::InEvent) {
+        (**self).inject_event(event)
+    }
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        info: Self::OutboundOpenInfo,
+        error: ProtocolsHandlerUpgrErr<<Self::OutboundProtocol as OutboundUpgrade<Self::Substream>>::Error>
+    ) {
+        (**self).inject_dial_upgrade_error(info, error)
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        (**self).connection_keep_alive()
+    }
+
+    fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent>, Self::Error> {
+        (**self).poll()
+    }
+}
+
+/// An [`IntoProtocolsHandler`] for multiple other ones, each identified by a key, which builds
+/// a [`MultiHandler`].
+#[derive(Debug, Clone)]
+pub struct IntoMultiHandler<K, H> {
+    /// The handlers, with their key. Keys are unique.
+    handlers: Vec<(K, H)>,
+}
+
+impl<K, H> IntoMultiHandler<K, H>
+where
+    K: PartialEq,
+    H: IntoProtocolsHandler,
+{
+    /// Builds an `IntoMultiHandler` from the given handlers.
+    ///
+    /// If a key appears several times, the last handler with that key is kept. Returns an
+    /// error if several handlers accept the same inbound protocol.
+    pub fn try_from_iter<I>(iter: I) -> Result<Self, DuplicateProtonameError>
+    where
+        I: IntoIterator<Item = (K, H)>,
+    {
+        let mut multi = IntoMultiHandler { handlers: Vec::new() };
+        for (key, handler) in iter {
+            multi.try_insert(key, handler)?;
+        }
+        Ok(multi)
+    }
+
+    /// Adds a handler, replacing and returning the handler with the same key, if any.
+    ///
+    /// Returns an error, and leaves the `IntoMultiHandler` untouched, if the handler accepts an
+    /// inbound protocol that a handler with another key accepts as well.
+    pub fn try_insert(&mut self, key: K, handler: H) -> Result<Option<H>, DuplicateProtonameError> {
+        let others = self.handlers.iter()
+            .filter(|(k, _)| *k != key)
+            .map(|(_, h)| h.inbound_protocol());
+        check_protocol_names(&handler.inbound_protocol(), others)?;
+        let previous = self.remove(&key);
+        self.handlers.push((key, handler));
+        Ok(previous)
+    }
+
+    /// Removes and returns the handler with the given key.
+    pub fn remove(&mut self, key: &K) -> Option<H> {
+        let pos = self.handlers.iter().position(|(k, _)| k == key)?;
+        Some(self.handlers.remove(pos).1)
+    }
+
+    /// Returns the keys of the handlers.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.handlers.iter().map(|(k, _)| k)
+    }
+}
+
+impl<K, H> IntoProtocolsHandler for IntoMultiHandler<K, H>
+where
+    K: Clone + PartialEq,
+    H: IntoProtocolsHandler,
+{
+    type Handler = MultiHandler<K, H::Handler>;
+
+    fn into_handler(self, remote_peer_id: &PeerId, connected_point: &ConnectedPoint) -> Self::Handler {
+        let handlers = self.handlers.into_iter()
+            .map(|(key, handler)| (key, handler.into_handler(remote_peer_id, connected_point)))
+            .collect();
+        MultiHandler { handlers, next_poll: 0 }
+    }
+
+    fn inbound_protocol(&self) -> <Self::Handler as ProtocolsHandler>::InboundProtocol {
+        let upgrades = self.handlers.iter()
+            .map(|(key, handler)| (key.clone(), handler.inbound_protocol()))
+            .collect();
+        MultiUpgrade { upgrades }
+    }
+}
+
+/// Returns an error if `upgrade` accepts one of the protocols accepted by `others`.
+fn check_protocol_names<U, I>(upgrade: &U, others: I) -> Result<(), DuplicateProtonameError>
+where
+    U: UpgradeInfo,
+    I: IntoIterator,
+    I::Item: UpgradeInfo,
+{
+    let names = upgrade.protocol_info().into_iter()
+        .map(|info| info.protocol_name().to_vec())
+        .collect::<Vec<_>>();
+    for other in others {
+        for info in other.protocol_info() {
+            if let Some(name) = names.iter().find(|n| &n[..] == info.protocol_name()) {
+                return Err(DuplicateProtonameError(name.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Inbound upgrade of a [`MultiHandler`], accepting the inbound protocols of all its handlers.
+#[derive(Debug, Clone)]
+pub struct MultiUpgrade<K, U> {
+    upgrades: Vec<(K, U)>,
+}
+
+/// A protocol name of a [`MultiUpgrade`], tagged with the key of the handler supporting it.
+#[derive(Debug, Clone)]
+pub struct KeyedProtocolName<K, N>(K, N);
+
+impl<K, N> ProtocolName for KeyedProtocolName<K, N>
+where
+    N: ProtocolName,
+{
+    fn protocol_name(&self) -> &[u8] {
+        self.1.protocol_name()
+    }
+}
+
+impl<K, U> UpgradeInfo for MultiUpgrade<K, U>
+where
+    K: Clone,
+    U: UpgradeInfo,
+{
+    type Info = KeyedProtocolName<K, U::Info>;
+    type InfoIter = vec::IntoIter<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.upgrades.iter()
+            .flat_map(|(key, upgrade)| {
+                upgrade.protocol_info().into_iter().map(move |info| KeyedProtocolName(key.clone(), info))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl<K, U, C> InboundUpgrade<C> for MultiUpgrade<K, U>
+where
+    K: Clone + PartialEq,
+    U: InboundUpgrade<C>,
+{
+    type Output = (K, U::Output);
+    type Error = U::Error;
+    type Future = KeyedFuture<K, U::Future>;
+
+    fn upgrade_inbound(mut self, socket: Negotiated<C>, KeyedProtocolName(key, info): Self::Info) -> Self::Future {
+        let index = self.upgrades.iter()
+            .position(|(k, _)| *k == key)
+            .expect("The key was produced by protocol_info; QED");
+        let (key, upgrade) = self.upgrades.swap_remove(index);
+        KeyedFuture { key: Some(key), inner: upgrade.upgrade_inbound(socket, info) }
+    }
+}
+
+/// Future returned by [`MultiUpgrade::upgrade_inbound`], tagging the output with the key of
+/// the handler.
+#[must_use = "futures do nothing unless polled"]
+pub struct KeyedFuture<K, F> {
+    key: Option<K>,
+    inner: F,
+}
+
+impl<K, F> Future for KeyedFuture<K, F>
+where
+    F: Future,
+{
+    type Item = (K, F::Item);
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let output = try_ready!(self.inner.poll());
+        let key = self.key.take().expect("Future polled after it finished");
+        Ok(Async::Ready((key, output)))
+    }
+}
+
+/// Error returned when building a [`MultiHandler`] or an [`IntoMultiHandler`] if several handlers
+/// accept the same inbound protocol.
+#[derive(Debug, Clone)]
+pub struct DuplicateProtonameError(Vec<u8>);
+
+impl DuplicateProtonameError {
+    /// Returns the protocol name accepted by several handlers.
+    pub fn protocol_name(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for DuplicateProtonameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Duplicate protocol name: {}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl error::Error for DuplicateProtonameError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols_handler::{boxed, OneShotHandler};
+    use futures::future::{self, FutureResult};
+    use std::iter;
+    use tokio::net::TcpStream;
+    use void::Void;
+    use wasm_timer::Instant;
+
+    #[derive(Debug, Clone)]
+    struct Proto(&'static [u8]);
+
+    impl UpgradeInfo for Proto {
+        type Info = &'static [u8];
+        type InfoIter = iter::Once<&'static [u8]>;
+
+        fn protocol_info(&self) -> Self::InfoIter {
+            iter::once(self.0)
+        }
+    }
+
+    impl<C> InboundUpgrade<C> for Proto {
+        type Output = ();
+        type Error = Void;
+        type Future = FutureResult<(), Void>;
+
+        fn upgrade_inbound(self, _: Negotiated<C>, _: Self::Info) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    impl<C> OutboundUpgrade<C> for Proto {
+        type Output = ();
+        type Error = Void;
+        type Future = FutureResult<(), Void>;
+
+        fn upgrade_outbound(self, _: Negotiated<C>, _: Self::Info) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    type Handler = OneShotHandler<TcpStream, Proto, Proto, ()>;
+
+    /// Handler recording what is injected into it, and producing queued events.
+    struct Recording {
+        proto: &'static [u8],
+        inbound: usize,
+        outbound: Vec<u32>,
+        events: Vec<u32>,
+        keep_alive: KeepAlive,
+        queued: Vec<ProtocolsHandlerEvent<Proto, u32, u32>>,
+    }
+
+    impl Recording {
+        fn new(proto: &'static [u8]) -> Self {
+            Recording {
+                proto,
+                inbound: 0,
+                outbound: Vec::new(),
+                events: Vec::new(),
+                keep_alive: KeepAlive::No,
+                queued: Vec::new(),
+            }
+        }
+    }
+
+    impl ProtocolsHandler for Recording {
+        type InEvent = u32;
+        type OutEvent = u32;
+        type Error = Void;
+        type Substream = TcpStream;
+        type InboundProtocol = Proto;
+        type OutboundProtocol = Proto;
+        type OutboundOpenInfo = u32;
+
+        fn listen_protocol(&self) -> SubstreamProtocol<Proto> {
+            SubstreamProtocol::new(Proto(self.proto))
+        }
+
+        fn inject_fully_negotiated_inbound(&mut self, _: ()) {
+            self.inbound += 1;
+        }
+
+        fn inject_fully_negotiated_outbound(&mut self, _: (), info: u32) {
+            self.outbound.push(info);
+        }
+
+        fn inject_event(&mut self, event: u32) {
+            self.events.push(event);
+        }
+
+        fn inject_dial_upgrade_error(&mut self, _: u32, _: ProtocolsHandlerUpgrErr<Void>) {}
+
+        fn connection_keep_alive(&self) -> KeepAlive {
+            self.keep_alive
+        }
+
+        fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<Proto, u32, u32>, Void> {
+            if self.queued.is_empty() {
+                Ok(Async::NotReady)
+            } else {
+                Ok(Async::Ready(self.queued.remove(0)))
+            }
+        }
+    }
+
+    fn recording(multi: &MultiHandler<u8, Recording>, key: u8) -> &Recording {
+        multi.handler(&key).unwrap()
+    }
+
+    #[test]
+    fn substreams_and_events_are_routed_by_key() {
+        let mut multi = MultiHandler::try_from_iter(vec![(1, Recording::new(b"/a")), (2, Recording::new(b"/b"))]).unwrap();
+
+        multi.inject_fully_negotiated_inbound((2, ()));
+        multi.inject_fully_negotiated_outbound((), (1, 7));
+        multi.inject_event((2, 5));
+        // Unknown keys are ignored.
+        multi.inject_event((3, 6));
+
+        assert_eq!(recording(&multi, 1).inbound, 0);
+        assert_eq!(recording(&multi, 2).inbound, 1);
+        assert_eq!(recording(&multi, 1).outbound, vec![7]);
+        assert!(recording(&multi, 2).outbound.is_empty());
+        assert!(recording(&multi, 1).events.is_empty());
+        assert_eq!(recording(&multi, 2).events, vec![5]);
+    }
+
+    #[test]
+    fn produced_events_are_tagged_with_their_key() {
+        let mut first = Recording::new(b"/a");
+        first.queued.push(ProtocolsHandlerEvent::Custom(3));
+        let mut second = Recording::new(b"/b");
+        second.queued.push(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+            protocol: SubstreamProtocol::new(Proto(b"/b")),
+            info: 9,
+        });
+        let mut multi = MultiHandler::try_from_iter(vec![(1, first), (2, second)]).unwrap();
+
+        match multi.poll() {
+            Ok(Async::Ready(ProtocolsHandlerEvent::Custom((1, 3)))) => {},
+            _ => panic!("expected the event of the first handler"),
+        }
+        match multi.poll() {
+            Ok(Async::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol, info: (2, 9) })) =>
+                assert_eq!(protocol.upgrade().0, b"/b"),
+            _ => panic!("expected the substream request of the second handler"),
+        }
+        match multi.poll() {
+            Ok(Async::NotReady) => {},
+            _ => panic!("expected no more events"),
+        }
+    }
+
+    #[test]
+    fn connection_is_kept_alive_as_long_as_one_handler_requires_it() {
+        let mut multi = MultiHandler::try_from_iter(vec![(1, Recording::new(b"/a")), (2, Recording::new(b"/b"))]).unwrap();
+        assert_eq!(multi.connection_keep_alive(), KeepAlive::No);
+
+        let until = Instant::now() + Duration::from_secs(10);
+        multi.handler_mut(&2).unwrap().keep_alive = KeepAlive::Until(until);
+        assert_eq!(multi.connection_keep_alive(), KeepAlive::Until(until));
+
+        multi.handler_mut(&1).unwrap().keep_alive = KeepAlive::Yes;
+        assert_eq!(multi.connection_keep_alive(), KeepAlive::Yes);
+
+        let empty = MultiHandler::<u8, Recording>::try_from_iter(Vec::new()).unwrap();
+        assert_eq!(empty.connection_keep_alive(), KeepAlive::No);
+    }
+
+    fn handler(name: &'static [u8]) -> Handler {
+        OneShotHandler::new(SubstreamProtocol::new(Proto(name)), Duration::from_secs(10))
+    }
+
+    #[test]
+    fn protocols_are_tagged_with_their_key() {
+        let multi = MultiHandler::try_from_iter(vec![(1, handler(b"/a")), (2, handler(b"/b"))]).unwrap();
+        let infos = multi.listen_protocol().upgrade().protocol_info()
+            .map(|KeyedProtocolName(key, name)| (key, name))
+            .collect::<Vec<_>>();
+        assert_eq!(infos, vec![(1, &b"/a"[..]), (2, &b"/b"[..])]);
+    }
+
+    #[test]
+    fn handlers_of_different_protocols_are_combined_once_boxed() {
+        let mut recording = Recording::new(b"/a");
+        recording.queued.push(ProtocolsHandlerEvent::Custom(3));
+        let mut multi = MultiHandler::try_from_iter(vec![
+            (1, boxed(recording)),
+            (2, boxed(handler(b"/b"))),
+        ]).unwrap();
+
+        let names = multi.listen_protocol().upgrade().protocol_info()
+            .map(|KeyedProtocolName(key, name)| (key, name))
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![(1, b"/a".to_vec()), (2, b"/b".to_vec())]);
+
+        match multi.poll() {
+            Ok(Async::Ready(ProtocolsHandlerEvent::Custom((1, event)))) =>
+                assert_eq!(*event.downcast::<u32>().unwrap(), 3),
+            _ => panic!("expected the event of the first handler"),
+        }
+
+        // The one-shot handler opens a substream when it receives the upgrade to apply.
+        multi.inject_event((2, Box::new(Proto(b"/b"))));
+        match multi.poll() {
+            Ok(Async::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol, info: (2, _) })) =>
+                assert_eq!(protocol.upgrade().protocol_info().collect::<Vec<_>>(), vec![b"/b".to_vec()]),
+            _ => panic!("expected the substream request of the second handler"),
+        }
+    }
+
+    #[test]
+    fn handlers_are_inserted_and_removed_at_runtime() {
+        let mut multi = MultiHandler::try_from_iter(vec![(1, handler(b"/a"))]).unwrap();
+        match multi.try_insert(2, handler(b"/a")) {
+            Err(err) => assert_eq!(err.protocol_name(), b"/a"),
+            Ok(_) => panic!("Duplicate protocol accepted"),
+        }
+        assert!(multi.try_insert(2, handler(b"/b")).unwrap().is_none());
+        assert!(multi.try_insert(2, handler(b"/c")).unwrap().is_some());
+        assert!(multi.remove(&1).is_some());
+        assert!(multi.remove(&1).is_none());
+
+        let infos = multi.listen_protocol().upgrade().protocol_info()
+            .map(|KeyedProtocolName(key, name)| (key, name))
+            .collect::<Vec<_>>();
+        assert_eq!(infos, vec![(2, &b"/c"[..])]);
+    }
+
+    #[test]
+    fn into_multi_handler_builds_keyed_handlers() {
+        let mut into_multi = IntoMultiHandler::try_from_iter(vec![(1, handler(b"/a"))]).unwrap();
+        assert!(into_multi.try_insert(2, handler(b"/a")).is_err());
+        into_multi.try_insert(2, handler(b"/b")).unwrap();
+
+        let infos = into_multi.inbound_protocol().protocol_info()
+            .map(|KeyedProtocolName(key, name)| (key, name))
+            .collect::<Vec<_>>();
+        assert_eq!(infos, vec![(1, &b"/a"[..]), (2, &b"/b"[..])]);
+
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+        let multi = into_multi.into_handler(&PeerId::random(), &endpoint);
+        assert_eq!(multi.keys().collect::<Vec<_>>(), vec![&1, &2]);
+    }
+
+    #[test]
+    fn duplicate_protocol_is_rejected() {
+        match MultiHandler::try_from_iter(vec![(1, handler(b"/a")), (2, handler(b"/a"))]) {
+            Err(err) => assert_eq!(err.protocol_name(), b"/a"),
+            Ok(_) => panic!("Duplicate protocol accepted"),
+        }
+
+        // A handler replacing another one with the same key doesn't conflict with it.
+        let multi = MultiHandler::try_from_iter(vec![(1, handler(b"/a")), (1, handler(b"/a"))]).unwrap();
+        assert_eq!(multi.keys().collect::<Vec<_>>(), vec![&1]);
+    }
+}
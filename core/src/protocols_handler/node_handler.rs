@@ -19,26 +19,52 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::{
+    Endpoint,
     PeerId,
+    bandwidth::ProtocolBandwidth,
     nodes::handled_node::{NodeHandler, NodeHandlerEndpoint, NodeHandlerEvent},
     nodes::handled_node_tasks::IntoNodeHandler,
     nodes::raw_swarm::ConnectedPoint,
     protocols_handler::{KeepAlive, ProtocolsHandler, IntoProtocolsHandler, ProtocolsHandlerEvent, ProtocolsHandlerUpgrErr},
+    protocols_handler::limits::{InboundCounters, LimitInbound},
+    protocols_handler::supported::{PeersProtocols, RecordNegotiated, SupportedProtocols},
+    resource::{ConnectionScope, ResourceLimitError, ResourceManager},
+    swarm::{InboundSubstreamLimits, SubstreamLimitError},
     upgrade::{
         self,
         OutboundUpgrade,
         InboundUpgradeApply,
         OutboundUpgradeApply,
+        ProtocolChoiceError,
+        ProtocolName,
+        UpgradeError,
+        UpgradeInfo,
     }
 };
-use futures::prelude::*;
-use std::{error, fmt, time::Duration};
+use futures::{prelude::*, sync::mpsc};
+use log::debug;
+use smallvec::SmallVec;
+use std::{error, fmt, sync::Arc, time::Duration};
 use wasm_timer::{Delay, Timeout};
 
+/// Maximum number of outbound substream requests for protocols the remote is known not to
+/// support that are refused in a single call to `poll`.
+const MAX_REFUSED_REQUESTS_PER_POLL: usize = 16;
+
 /// Prototype for a `NodeHandlerWrapper`.
 pub struct NodeHandlerWrapperBuilder<TIntoProtoHandler> {
     /// The underlying handler.
     handler: TIntoProtoHandler,
+    /// If `Some`, where to report the protocols supported by the remote.
+    peers_protocols: Option<PeersProtocols>,
+    /// Limits on the inbound substreams.
+    substream_limits: Arc<InboundSubstreamLimits>,
+    /// If `Some`, where to report the inbound substreams refused because of `substream_limits`.
+    refused_substreams: Option<mpsc::UnboundedSender<(PeerId, SubstreamLimitError)>>,
+    /// If `Some`, where to reserve the connection and its substreams.
+    resource_manager: Option<ResourceManager>,
+    /// If `Some`, where to count the traffic of the substreams.
+    bandwidth: Option<ProtocolBandwidth>,
 }
 
 impl<TIntoProtoHandler> NodeHandlerWrapperBuilder<TIntoProtoHandler>
@@ -50,9 +76,48 @@ where
     pub(crate) fn new(handler: TIntoProtoHandler) -> Self {
         NodeHandlerWrapperBuilder {
             handler,
+            peers_protocols: None,
+            substream_limits: Arc::new(InboundSubstreamLimits::default()),
+            refused_substreams: None,
+            resource_manager: None,
+            bandwidth: None,
         }
     }
 
+    /// Reports the protocols that the remote supports to `peers_protocols`, in addition to the
+    /// cache of the connection.
+    #[inline]
+    pub(crate) fn with_peers_protocols(mut self, peers_protocols: PeersProtocols) -> Self {
+        self.peers_protocols = Some(peers_protocols);
+        self
+    }
+
+    /// Resets the inbound substreams that would exceed `limits`, and reports them to `refused`.
+    #[inline]
+    pub(crate) fn with_substream_limits(
+        mut self,
+        limits: Arc<InboundSubstreamLimits>,
+        refused: mpsc::UnboundedSender<(PeerId, SubstreamLimitError)>
+    ) -> Self {
+        self.substream_limits = limits;
+        self.refused_substreams = Some(refused);
+        self
+    }
+
+    /// Reserves the connection and its substreams in `resource_manager`.
+    #[inline]
+    pub(crate) fn with_resource_manager(mut self, resource_manager: ResourceManager) -> Self {
+        self.resource_manager = Some(resource_manager);
+        self
+    }
+
+    /// Counts the traffic of the substreams in `bandwidth`, per protocol and for the remote.
+    #[inline]
+    pub(crate) fn with_bandwidth(mut self, bandwidth: ProtocolBandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
     /// Builds the `NodeHandlerWrapper`.
     #[deprecated(note = "Pass the NodeHandlerWrapperBuilder directly")]
     #[inline]
@@ -61,11 +126,20 @@ where
     {
         NodeHandlerWrapper {
             handler: self.handler,
+            supported_protocols: SupportedProtocols::new(),
+            peers_protocols: None,
+            substream_limits: self.substream_limits,
+            inbound_counters: Arc::new(InboundCounters::default()),
+            refused_substreams: None,
+            resource_scope: None,
+            resource_error: None,
+            bandwidth: None,
             negotiating_in: Vec::new(),
             negotiating_out: Vec::new(),
             queued_dial_upgrades: Vec::new(),
             unique_dial_upgrade_id: 0,
             shutdown: Shutdown::None,
+            closing: false,
         }
     }
 }
@@ -81,41 +155,151 @@ where
     type Handler = NodeHandlerWrapper<TIntoProtoHandler::Handler>;
 
     fn into_handler(self, remote_info: &(PeerId, ConnectedPoint)) -> Self::Handler {
+        let (resource_scope, resource_error) = match self.resource_manager {
+            Some(manager) => {
+                let scope = manager.open_connection(remote_info.1.to_endpoint())
+                    .and_then(|mut scope| scope.set_peer(&remote_info.0).map(|()| scope));
+                match scope {
+                    Ok(scope) => (Some(scope), None),
+                    Err(err) => (None, Some(err)),
+                }
+            }
+            None => (None, None),
+        };
+
         NodeHandlerWrapper {
             handler: self.handler.into_handler(&remote_info.0, &remote_info.1),
+            supported_protocols: SupportedProtocols::new(),
+            peers_protocols: self.peers_protocols.map(|p| (remote_info.0.clone(), p)),
+            substream_limits: self.substream_limits,
+            inbound_counters: Arc::new(InboundCounters::default()),
+            refused_substreams: self.refused_substreams.map(|r| (remote_info.0.clone(), r)),
+            resource_scope,
+            resource_error,
+            bandwidth: self.bandwidth.map(|b| (remote_info.0.clone(), b)),
             negotiating_in: Vec::new(),
             negotiating_out: Vec::new(),
             queued_dial_upgrades: Vec::new(),
             unique_dial_upgrade_id: 0,
             shutdown: Shutdown::None,
+            closing: false,
         }
     }
 }
 
 /// Wraps around an implementation of `ProtocolsHandler`, and implements `NodeHandler`.
-// TODO: add a caching system for protocols that are supported or not
+///
+/// The wrapper caches which protocols the remote supports. Outbound substream requests whose
+/// protocols have all been refused by the remote on this connection fail immediately with
+/// `ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(ProtocolChoiceError::NoProtocolFound))`
+/// instead of being negotiated again.
+///
+/// Inbound substreams that would exceed the `InboundSubstreamLimits` of the swarm are reset.
+///
+/// If the swarm has a `ResourceManager`, the connection and its substreams are reserved in it.
+/// The connection is closed with `NodeHandlerWrapperError::ResourceLimit` if it can't be
+/// reserved, inbound substreams that can't be reserved are reset, and outbound substreams that
+/// can't be reserved fail with `ProtocolsHandlerUpgrErr::ResourceLimit`.
 pub struct NodeHandlerWrapper<TProtoHandler>
 where
     TProtoHandler: ProtocolsHandler,
 {
     /// The underlying handler.
     handler: TProtoHandler,
+    /// Protocols that the remote supports or not, as seen on this connection.
+    supported_protocols: SupportedProtocols,
+    /// If `Some`, the remote and where to report the updates of `supported_protocols`.
+    peers_protocols: Option<(PeerId, PeersProtocols)>,
+    /// Limits on the inbound substreams.
+    substream_limits: Arc<InboundSubstreamLimits>,
+    /// Number of inbound substreams, shared with the substreams themselves.
+    inbound_counters: Arc<InboundCounters>,
+    /// If `Some`, the remote and where to report the inbound substreams that have been refused.
+    refused_substreams: Option<(PeerId, mpsc::UnboundedSender<(PeerId, SubstreamLimitError)>)>,
+    /// If `Some`, the reservation of the connection in the `ResourceManager` of the swarm.
+    resource_scope: Option<ConnectionScope>,
+    /// If `Some`, the connection couldn't be reserved in the `ResourceManager` and must be closed.
+    resource_error: Option<ResourceLimitError>,
+    /// If `Some`, the remote and where to count the traffic of the substreams.
+    bandwidth: Option<(PeerId, ProtocolBandwidth)>,
     /// Futures that upgrade incoming substreams.
-    negotiating_in:
-        Vec<Timeout<InboundUpgradeApply<TProtoHandler::Substream, TProtoHandler::InboundProtocol>>>,
+    negotiating_in: Vec<Timeout<InboundUpgradeApply<
+        TProtoHandler::Substream,
+        LimitInbound<TProtoHandler::InboundProtocol>
+    >>>,
     /// Futures that upgrade outgoing substreams. The first element of the tuple is the userdata
-    /// to pass back once successfully opened.
+    /// to pass back once successfully opened, and the second the names of the protocols offered
+    /// to the remote.
     negotiating_out: Vec<(
         TProtoHandler::OutboundOpenInfo,
-        Timeout<OutboundUpgradeApply<TProtoHandler::Substream, TProtoHandler::OutboundProtocol>>,
+        ProtocolNames,
+        Timeout<OutboundUpgradeApply<
+            TProtoHandler::Substream,
+            RecordNegotiated<TProtoHandler::OutboundProtocol>
+        >>,
     )>,
     /// For each outbound substream request, how to upgrade it. The first element of the tuple
     /// is the unique identifier (see `unique_dial_upgrade_id`).
-    queued_dial_upgrades: Vec<(u64, TProtoHandler::OutboundProtocol)>,
+    queued_dial_upgrades: Vec<(u64, ProtocolNames, TProtoHandler::OutboundProtocol)>,
     /// Unique identifier assigned to each queued dial upgrade.
     unique_dial_upgrade_id: u64,
     /// The currently planned connection & handler shutdown.
     shutdown: Shutdown,
+    /// If true, the node is being shut down and the connection is closed as soon as the handler
+    /// no longer needs it to be kept alive.
+    closing: bool,
+}
+
+/// Names of the protocols offered by an outbound upgrade.
+type ProtocolNames = SmallVec<[Vec<u8>; 4]>;
+
+impl<TProtoHandler> NodeHandlerWrapper<TProtoHandler>
+where
+    TProtoHandler: ProtocolsHandler,
+{
+    /// Returns the protocols that the remote supports or not, as seen on this connection.
+    pub fn supported_protocols(&self) -> &SupportedProtocols {
+        &self.supported_protocols
+    }
+
+    /// Records that the remote supports the given protocol.
+    fn add_supported(&mut self, protocol: Vec<u8>) {
+        if self.supported_protocols.is_supported(&protocol) == Some(true) {
+            return;
+        }
+        if let Some((peer_id, peers)) = &self.peers_protocols {
+            peers.update(peer_id, |p| p.add_supported(protocol.clone()));
+        }
+        self.supported_protocols.add_supported(protocol);
+    }
+
+    /// Records that the remote refused all the given protocols.
+    fn add_unsupported(&mut self, protocols: ProtocolNames) {
+        if let Some((peer_id, peers)) = &self.peers_protocols {
+            peers.update(peer_id, |p| {
+                for protocol in &protocols {
+                    p.add_unsupported(protocol.clone());
+                }
+            });
+        }
+        for protocol in protocols {
+            self.supported_protocols.add_unsupported(protocol);
+        }
+    }
+
+    /// Reports that an inbound substream has been refused.
+    fn refuse_substream(&mut self, err: SubstreamLimitError) {
+        debug!("Refused inbound substream: {}", err);
+        if let Some((peer_id, refused)) = &self.refused_substreams {
+            let _ = refused.unbounded_send((peer_id.clone(), err));
+        }
+    }
+
+    /// Returns true if the remote is known to refuse all the given protocols.
+    fn all_unsupported(&self, protocols: &ProtocolNames) -> bool {
+        !protocols.is_empty() && protocols.iter()
+            .all(|p| self.supported_protocols.is_supported(p) == Some(false))
+    }
 }
 
 /// The options for a planned connection & handler shutdown.
@@ -143,6 +327,11 @@ pub enum NodeHandlerWrapperError<TErr> {
     Handler(TErr),
     /// The connection has been deemed useless and has been closed.
     UselessTimeout,
+    /// The connection has been closed because the node is shutting down.
+    Closed,
+    /// The connection has been closed because it would have exceeded the limits of the
+    /// `ResourceManager` of the swarm.
+    ResourceLimit(ResourceLimitError),
 }
 
 impl<TErr> From<TErr> for NodeHandlerWrapperError<TErr> {
@@ -160,6 +349,9 @@ where
             NodeHandlerWrapperError::Handler(err) => write!(f, "{}", err),
             NodeHandlerWrapperError::UselessTimeout =>
                 write!(f, "Node has been closed due to inactivity"),
+            NodeHandlerWrapperError::Closed =>
+                write!(f, "Node has been closed because the local node is shutting down"),
+            NodeHandlerWrapperError::ResourceLimit(err) => write!(f, "{}", err),
         }
     }
 }
@@ -172,6 +364,8 @@ where
         match self {
             NodeHandlerWrapperError::Handler(err) => Some(err),
             NodeHandlerWrapperError::UselessTimeout => None,
+            NodeHandlerWrapperError::Closed => None,
+            NodeHandlerWrapperError::ResourceLimit(err) => Some(err),
         }
     }
 }
@@ -196,10 +390,41 @@ where
         endpoint: NodeHandlerEndpoint<Self::OutboundOpenInfo>,
     ) {
         match endpoint {
+            // Substreams opened by the remote while we are shutting down are refused.
+            NodeHandlerEndpoint::Listener if self.closing => {}
             NodeHandlerEndpoint::Listener => {
+                let negotiating = self.negotiating_in.len();
+                let active = &self.inbound_counters;
+                let limits_check = self.substream_limits.check_negotiating(|| negotiating)
+                    .and_then(|()| self.substream_limits.check_active(|| active.active()));
+                let scope = match (limits_check, &self.resource_scope) {
+                    (Err(err), _) => Err(err),
+                    (Ok(()), Some(scope)) => scope.open_stream(Endpoint::Listener)
+                        .map(Some)
+                        .map_err(SubstreamLimitError::Resource),
+                    (Ok(()), None) => Ok(None),
+                };
+                let scope = match scope {
+                    Ok(scope) => scope,
+                    Err(err) => {
+                        // Dropping the substream makes muxers that support it, such as mplex, reset
+                        // it, and the remote fails to negotiate. Otherwise the remote's upgrade
+                        // times out.
+                        self.refuse_substream(err);
+                        return;
+                    }
+                };
+
                 let protocol = self.handler.listen_protocol();
                 let timeout = protocol.timeout().clone();
-                let upgrade = upgrade::apply_inbound(substream, protocol.into_upgrade());
+                let upgrade = LimitInbound {
+                    upgrade: protocol.into_upgrade(),
+                    limits: self.substream_limits.clone(),
+                    counters: self.inbound_counters.clone(),
+                    scope,
+                    bandwidth: self.bandwidth.clone(),
+                };
+                let upgrade = upgrade::apply_inbound(substream, upgrade);
                 let with_timeout = Timeout::new(upgrade, timeout);
                 self.negotiating_in.push(with_timeout);
             }
@@ -207,7 +432,7 @@ where
                 let pos = match self
                     .queued_dial_upgrades
                     .iter()
-                    .position(|(id, _)| id == &upgrade_id)
+                    .position(|(id, _, _)| id == &upgrade_id)
                 {
                     Some(p) => p,
                     None => {
@@ -216,10 +441,24 @@ where
                     }
                 };
 
-                let (_, proto_upgrade) = self.queued_dial_upgrades.remove(pos);
-                let upgrade = upgrade::apply_outbound(substream, proto_upgrade);
+                let (_, names, proto_upgrade) = self.queued_dial_upgrades.remove(pos);
+                let scope = match self.resource_scope.as_ref().map(|s| s.open_stream(Endpoint::Dialer)) {
+                    Some(Ok(scope)) => Some(scope),
+                    Some(Err(err)) => {
+                        let err = ProtocolsHandlerUpgrErr::ResourceLimit(err);
+                        self.handler.inject_dial_upgrade_error(user_data, err);
+                        return;
+                    }
+                    None => None,
+                };
+                let upgrade = RecordNegotiated {
+                    upgrade: proto_upgrade,
+                    scope,
+                    bandwidth: self.bandwidth.clone(),
+                };
+                let upgrade = upgrade::apply_outbound(substream, upgrade);
                 let with_timeout = Timeout::new(upgrade, timeout);
-                self.negotiating_out.push((user_data, with_timeout));
+                self.negotiating_out.push((user_data, names, with_timeout));
             }
         }
     }
@@ -229,14 +468,29 @@ where
         self.handler.inject_event(event);
     }
 
+    fn close_gracefully(&mut self) {
+        self.closing = true;
+    }
+
+    fn resource_scope(&self) -> Option<&ConnectionScope> {
+        self.resource_scope.as_ref()
+    }
+
     fn poll(&mut self) -> Poll<NodeHandlerEvent<Self::OutboundOpenInfo, Self::OutEvent>, Self::Error> {
+        if let Some(err) = self.resource_error.take() {
+            return Err(NodeHandlerWrapperError::ResourceLimit(err));
+        }
+
         // Continue negotiation of newly-opened substreams on the listening side.
         // We remove each element from `negotiating_in` one by one and add them back if not ready.
         for n in (0..self.negotiating_in.len()).rev() {
             let mut in_progress = self.negotiating_in.swap_remove(n);
             match in_progress.poll() {
-                Ok(Async::Ready(upgrade)) =>
-                    self.handler.inject_fully_negotiated_inbound(upgrade),
+                Ok(Async::Ready(Ok((protocol, upgrade)))) => {
+                    self.add_supported(protocol);
+                    self.handler.inject_fully_negotiated_inbound(upgrade)
+                },
+                Ok(Async::Ready(Err(err))) => self.refuse_substream(err),
                 Ok(Async::NotReady) => self.negotiating_in.push(in_progress),
                 // TODO: return a diagnostic event?
                 Err(_err) => {}
@@ -246,13 +500,18 @@ where
         // Continue negotiation of newly-opened substreams.
         // We remove each element from `negotiating_out` one by one and add them back if not ready.
         for n in (0..self.negotiating_out.len()).rev() {
-            let (upgr_info, mut in_progress) = self.negotiating_out.swap_remove(n);
+            let (upgr_info, names, mut in_progress) = self.negotiating_out.swap_remove(n);
             match in_progress.poll() {
-                Ok(Async::Ready(upgrade)) => {
+                Ok(Async::Ready(Ok((protocol, upgrade)))) => {
+                    self.add_supported(protocol);
                     self.handler.inject_fully_negotiated_outbound(upgrade, upgr_info);
                 }
+                Ok(Async::Ready(Err(err))) => {
+                    let err = ProtocolsHandlerUpgrErr::ResourceLimit(err);
+                    self.handler.inject_dial_upgrade_error(upgr_info, err);
+                }
                 Ok(Async::NotReady) => {
-                    self.negotiating_out.push((upgr_info, in_progress));
+                    self.negotiating_out.push((upgr_info, names, in_progress));
                 }
                 Err(err) => {
                     let err = if err.is_elapsed() {
@@ -264,6 +523,9 @@ where
                         let err = err.into_inner().expect("Timeout error is one of {elapsed, \
                             timer, inner}; is_elapsed and is_timer are both false; error is \
                             inner; QED");
+                        if let UpgradeError::Select(ProtocolChoiceError::NoProtocolFound) = err {
+                            self.add_unsupported(names);
+                        }
                         ProtocolsHandlerUpgrErr::Upgrade(err)
                     };
 
@@ -273,8 +535,33 @@ where
         }
 
         // Poll the handler at the end so that we see the consequences of the method
-        // calls on `self.handler`.
-        let poll_result = self.handler.poll()?;
+        // calls on `self.handler`. Requests for substreams whose protocols the remote is known
+        // to refuse are answered immediately, after which the handler is polled again, up to
+        // `MAX_REFUSED_REQUESTS_PER_POLL` times before yielding to the executor.
+        let mut refused = 0;
+        let (poll_result, names) = loop {
+            if refused == MAX_REFUSED_REQUESTS_PER_POLL {
+                futures::task::current().notify();
+                break (Async::NotReady, ProtocolNames::new());
+            }
+            match self.handler.poll()? {
+                Async::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol, info }) => {
+                    let names = protocol.upgrade().protocol_info().into_iter()
+                        .map(|p| p.protocol_name().to_vec())
+                        .collect::<ProtocolNames>();
+                    if self.all_unsupported(&names) {
+                        debug!("Remote is known not to support any of the requested protocols");
+                        let err = UpgradeError::Select(ProtocolChoiceError::NoProtocolFound);
+                        self.handler.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::Upgrade(err));
+                        refused += 1;
+                        continue;
+                    }
+                    let event = ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol, info };
+                    break (Async::Ready(event), names);
+                }
+                poll_result => break (poll_result, ProtocolNames::new()),
+            }
+        };
 
         // Ask the handler whether it wants the connection (and the handler itself)
         // to be kept alive, which determines the planned shutdown, if any.
@@ -299,7 +586,7 @@ where
                 let id = self.unique_dial_upgrade_id;
                 let timeout = protocol.timeout().clone();
                 self.unique_dial_upgrade_id += 1;
-                self.queued_dial_upgrades.push((id, protocol.into_upgrade()));
+                self.queued_dial_upgrades.push((id, names, protocol.into_upgrade()));
                 return Ok(Async::Ready(
                     NodeHandlerEvent::OutboundSubstreamRequest((id, info, timeout)),
                 ));
@@ -310,6 +597,14 @@ where
         // Check if the connection (and handler) should be shut down.
         // As long as we're still negotiating substreams, shutdown is always postponed.
         if self.negotiating_in.is_empty() && self.negotiating_out.is_empty() {
+            // When shutting down, the connection is closed as soon as the handler has no pending
+            // outbound substream and doesn't explicitly ask for the connection to be kept alive.
+            if self.closing && self.queued_dial_upgrades.is_empty()
+                && !self.handler.connection_keep_alive().is_yes()
+            {
+                return Err(NodeHandlerWrapperError::Closed);
+            }
+
             match self.shutdown {
                 Shutdown::None => {},
                 Shutdown::Asap => return Err(NodeHandlerWrapperError::UselessTimeout),
@@ -324,3 +619,135 @@ where
         Ok(Async::NotReady)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols_handler::{OneShotHandler, SubstreamProtocol};
+    use crate::upgrade::{InboundUpgrade, Negotiated};
+    use futures::future::{self, FutureResult};
+    use std::iter;
+    use tokio::net::TcpStream;
+    use void::Void;
+
+    #[derive(Debug, Clone)]
+    struct Proto(&'static [u8]);
+
+    impl UpgradeInfo for Proto {
+        type Info = &'static [u8];
+        type InfoIter = iter::Once<&'static [u8]>;
+
+        fn protocol_info(&self) -> Self::InfoIter {
+            iter::once(self.0)
+        }
+    }
+
+    impl<C> InboundUpgrade<C> for Proto {
+        type Output = ();
+        type Error = Void;
+        type Future = FutureResult<(), Void>;
+
+        fn upgrade_inbound(self, _: Negotiated<C>, _: Self::Info) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    impl<C> OutboundUpgrade<C> for Proto {
+        type Output = ();
+        type Error = Void;
+        type Future = FutureResult<(), Void>;
+
+        fn upgrade_outbound(self, _: Negotiated<C>, _: Self::Info) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    #[test]
+    fn known_unsupported_protocols_fail_immediately() {
+        let handler: OneShotHandler<TcpStream, Proto, Proto, ()> =
+            OneShotHandler::new(SubstreamProtocol::new(Proto(b"/a")), Duration::from_secs(10));
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+        let mut wrapper = NodeHandlerWrapperBuilder::new(handler)
+            .into_handler(&(PeerId::random(), endpoint));
+        wrapper.add_unsupported(iter::once(b"/a".to_vec()).collect());
+
+        future::lazy(move || {
+            // A protocol the remote has never refused is negotiated as usual.
+            wrapper.inject_event(Proto(b"/b"));
+            match wrapper.poll() {
+                Ok(Async::Ready(NodeHandlerEvent::OutboundSubstreamRequest(_))) => {}
+                _ => panic!("Expected an outbound substream request"),
+            }
+
+            wrapper.inject_event(Proto(b"/a"));
+            match wrapper.poll() {
+                Err(NodeHandlerWrapperError::Handler(ProtocolsHandlerUpgrErr::Upgrade(
+                    UpgradeError::Select(ProtocolChoiceError::NoProtocolFound)
+                ))) => {}
+                _ => panic!("Expected the request to fail"),
+            }
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+
+    /// Handler that keeps asking for a substream with the same protocol.
+    struct Insistent {
+        failures: usize,
+    }
+
+    impl ProtocolsHandler for Insistent {
+        type InEvent = Void;
+        type OutEvent = Void;
+        type Error = Void;
+        type Substream = TcpStream;
+        type InboundProtocol = Proto;
+        type OutboundProtocol = Proto;
+        type OutboundOpenInfo = ();
+
+        fn listen_protocol(&self) -> SubstreamProtocol<Proto> {
+            SubstreamProtocol::new(Proto(b"/a"))
+        }
+
+        fn inject_fully_negotiated_inbound(&mut self, _: ()) {}
+
+        fn inject_fully_negotiated_outbound(&mut self, _: (), _: ()) {}
+
+        fn inject_event(&mut self, event: Void) {
+            void::unreachable(event)
+        }
+
+        fn inject_dial_upgrade_error(&mut self, _: (), _: ProtocolsHandlerUpgrErr<Void>) {
+            self.failures += 1;
+        }
+
+        fn connection_keep_alive(&self) -> KeepAlive {
+            KeepAlive::Yes
+        }
+
+        fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<Proto, (), Void>, Void> {
+            Ok(Async::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(Proto(b"/a")),
+                info: (),
+            }))
+        }
+    }
+
+    #[test]
+    fn refused_requests_are_bounded_per_poll() {
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+        let mut wrapper = NodeHandlerWrapperBuilder::new(Insistent { failures: 0 })
+            .into_handler(&(PeerId::random(), endpoint));
+        wrapper.add_unsupported(iter::once(b"/a".to_vec()).collect());
+
+        future::lazy(move || {
+            for round in 1 ..= 3 {
+                match wrapper.poll() {
+                    Ok(Async::NotReady) => {}
+                    _ => panic!("Expected the wrapper to yield"),
+                }
+                assert_eq!(wrapper.handler.failures, round * MAX_REFUSED_REQUESTS_PER_POLL);
+            }
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
@@ -176,6 +176,9 @@ where
             (EitherOutput::First(info), ProtocolsHandlerUpgrErr::Timeout) => {
                 self.proto1.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::Timeout)
             },
+            (EitherOutput::First(info), ProtocolsHandlerUpgrErr::ResourceLimit(err)) => {
+                self.proto1.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::ResourceLimit(err))
+            },
             (EitherOutput::First(info), ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err))) => {
                 self.proto1.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)))
             },
@@ -191,6 +194,9 @@ where
             (EitherOutput::Second(info), ProtocolsHandlerUpgrErr::Timer) => {
                 self.proto2.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::Timer)
             },
+            (EitherOutput::Second(info), ProtocolsHandlerUpgrErr::ResourceLimit(err)) => {
+                self.proto2.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::ResourceLimit(err))
+            },
             (EitherOutput::Second(info), ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err))) => {
                 self.proto2.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)))
             },
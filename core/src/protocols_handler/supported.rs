@@ -0,0 +1,237 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Cache of the protocols that a remote is known to support or not.
+
+use crate::{
+    PeerId,
+    bandwidth::ProtocolBandwidth,
+    resource::{ResourceLimitError, StreamScope},
+    upgrade::{Negotiated, OutboundUpgrade, ProtocolName, UpgradeInfo},
+};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{prelude::*, try_ready};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Protocols that a remote has been seen to support or to reject.
+///
+/// A protocol is supported if it has been successfully negotiated on a substream, either opened
+/// by us or by the remote. It is unsupported if the remote has refused all the protocols we
+/// offered on a substream that included it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SupportedProtocols {
+    supported: FnvHashSet<Vec<u8>>,
+    unsupported: FnvHashSet<Vec<u8>>,
+}
+
+impl SupportedProtocols {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        SupportedProtocols::default()
+    }
+
+    /// Returns `Some(true)` if the protocol is known to be supported, `Some(false)` if it is known
+    /// not to be, and `None` if it has never been negotiated.
+    pub fn is_supported(&self, protocol: &[u8]) -> Option<bool> {
+        if self.supported.contains(protocol) {
+            Some(true)
+        } else if self.unsupported.contains(protocol) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the names of the protocols known to be supported.
+    pub fn supported(&self) -> impl Iterator<Item = &[u8]> {
+        self.supported.iter().map(AsRef::as_ref)
+    }
+
+    /// Returns the names of the protocols known not to be supported.
+    pub fn unsupported(&self) -> impl Iterator<Item = &[u8]> {
+        self.unsupported.iter().map(AsRef::as_ref)
+    }
+
+    /// Records that the protocol has been successfully negotiated.
+    pub(crate) fn add_supported(&mut self, protocol: Vec<u8>) {
+        self.unsupported.remove(&protocol);
+        self.supported.insert(protocol);
+    }
+
+    /// Records that the remote has refused the protocol.
+    ///
+    /// Has no effect if the protocol has already been negotiated successfully.
+    pub(crate) fn add_unsupported(&mut self, protocol: Vec<u8>) {
+        if !self.supported.contains(&protocol) {
+            self.unsupported.insert(protocol);
+        }
+    }
+}
+
+/// The `SupportedProtocols` of every connected peer, shared between the swarm and the handlers
+/// of its connections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PeersProtocols {
+    inner: Arc<Mutex<FnvHashMap<PeerId, SupportedProtocols>>>,
+}
+
+impl PeersProtocols {
+    /// Returns a copy of what is known about the protocols of the given peer.
+    pub(crate) fn get(&self, peer_id: &PeerId) -> Option<SupportedProtocols> {
+        self.inner.lock().get(peer_id).cloned()
+    }
+
+    /// Updates the entry of the given peer, creating it if necessary.
+    pub(crate) fn update(&self, peer_id: &PeerId, f: impl FnOnce(&mut SupportedProtocols)) {
+        let mut inner = self.inner.lock();
+        if let Some(protocols) = inner.get_mut(peer_id) {
+            f(protocols);
+        } else {
+            let mut protocols = SupportedProtocols::new();
+            f(&mut protocols);
+            inner.insert(peer_id.clone(), protocols);
+        }
+    }
+
+    /// Forgets about the given peer.
+    pub(crate) fn remove(&self, peer_id: &PeerId) {
+        self.inner.lock().remove(peer_id);
+    }
+}
+
+/// Wraps around an outbound upgrade and adds the name of the negotiated protocol to its output.
+///
+/// If the substream has a `StreamScope`, it is attributed to the negotiated protocol and released
+/// when the substream is dropped. The upgrade produces an `Err` output if the protocol can't take
+/// the substream. If `bandwidth` is `Some`, the traffic of the substream is counted for the
+/// remote and the negotiated protocol.
+pub(crate) struct RecordNegotiated<TUpgrade> {
+    pub(crate) upgrade: TUpgrade,
+    pub(crate) scope: Option<StreamScope>,
+    pub(crate) bandwidth: Option<(PeerId, ProtocolBandwidth)>,
+}
+
+impl<TUpgrade> UpgradeInfo for RecordNegotiated<TUpgrade>
+where
+    TUpgrade: UpgradeInfo
+{
+    type Info = TUpgrade::Info;
+    type InfoIter = TUpgrade::InfoIter;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.upgrade.protocol_info()
+    }
+}
+
+impl<C, TUpgrade> OutboundUpgrade<C> for RecordNegotiated<TUpgrade>
+where
+    TUpgrade: OutboundUpgrade<C>
+{
+    type Output = Result<(Vec<u8>, TUpgrade::Output), ResourceLimitError>;
+    type Error = TUpgrade::Error;
+    type Future = RecordNegotiatedFuture<TUpgrade::Future>;
+
+    fn upgrade_outbound(self, mut socket: Negotiated<C>, info: Self::Info) -> Self::Future {
+        let protocol = info.protocol_name().to_vec();
+        if let Some(mut scope) = self.scope {
+            if let Err(err) = scope.set_protocol(&protocol) {
+                return RecordNegotiatedFuture::Refused(Some(err));
+            }
+            socket.attach(scope);
+        }
+        if let Some((peer_id, bandwidth)) = &self.bandwidth {
+            socket.observe(bandwidth.substream_traffic(peer_id, &protocol));
+        }
+        RecordNegotiatedFuture::Upgrading {
+            protocol: Some(protocol),
+            inner: self.upgrade.upgrade_outbound(socket, info),
+        }
+    }
+}
+
+/// Future returned by the upgrade of `RecordNegotiated`.
+pub(crate) enum RecordNegotiatedFuture<TFut> {
+    /// The protocol can't take the substream.
+    Refused(Option<ResourceLimitError>),
+    /// The handshake is in progress.
+    Upgrading {
+        protocol: Option<Vec<u8>>,
+        inner: TFut,
+    },
+}
+
+impl<TFut> Future for RecordNegotiatedFuture<TFut>
+where
+    TFut: Future
+{
+    type Item = Result<(Vec<u8>, TFut::Item), ResourceLimitError>;
+    type Error = TFut::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            RecordNegotiatedFuture::Refused(err) => {
+                let err = err.take().expect("Future polled after it finished");
+                Ok(Async::Ready(Err(err)))
+            }
+            RecordNegotiatedFuture::Upgrading { protocol, inner } => {
+                let output = try_ready!(inner.poll());
+                let protocol = protocol.take().expect("Future polled after it finished");
+                Ok(Async::Ready(Ok((protocol, output))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiated_protocol_overrides_rejection() {
+        let mut protocols = SupportedProtocols::new();
+        assert_eq!(protocols.is_supported(b"/foo/1"), None);
+
+        protocols.add_unsupported(b"/foo/1".to_vec());
+        assert_eq!(protocols.is_supported(b"/foo/1"), Some(false));
+
+        protocols.add_supported(b"/foo/1".to_vec());
+        assert_eq!(protocols.is_supported(b"/foo/1"), Some(true));
+
+        protocols.add_unsupported(b"/foo/1".to_vec());
+        assert_eq!(protocols.is_supported(b"/foo/1"), Some(true));
+        assert_eq!(protocols.unsupported().count(), 0);
+    }
+
+    #[test]
+    fn peers_are_tracked_separately() {
+        let peers = PeersProtocols::default();
+        let peer1 = PeerId::random();
+        let peer2 = PeerId::random();
+
+        peers.update(&peer1, |p| p.add_supported(b"/foo/1".to_vec()));
+        peers.update(&peer2, |p| p.add_unsupported(b"/foo/1".to_vec()));
+        assert_eq!(peers.get(&peer1).unwrap().is_supported(b"/foo/1"), Some(true));
+        assert_eq!(peers.get(&peer2).unwrap().is_supported(b"/foo/1"), Some(false));
+
+        peers.remove(&peer1);
+        assert!(peers.get(&peer1).is_none());
+    }
+}
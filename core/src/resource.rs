@@ -0,0 +1,939 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Accounting of the memory, streams and connections used by a node.
+//!
+//! A [`ResourceManager`] keeps track of the resources in use in a hierarchy of scopes:
+//!
+//! - The system scope contains everything.
+//! - The transient scope contains the connections whose remote isn't known yet, and the memory
+//!   that isn't attributed to a connection.
+//! - Each peer has a scope containing its connections.
+//! - Each protocol has a scope containing the streams that use it.
+//! - Each connection has a scope containing its streams and the memory held on its behalf, for
+//!   example by its muxer.
+//! - Each stream has its own scope.
+//!
+//! Reserving a resource in a scope also reserves it in all the scopes that contain it, and fails
+//! with a [`ResourceLimitError`] if any of them would exceed its [`ResourceLimits`]. Resources are
+//! released when the handle of the scope that reserved them is dropped.
+
+use crate::{Endpoint, PeerId};
+use fnv::FnvHashMap;
+use parking_lot::Mutex;
+use smallvec::SmallVec;
+use std::{error, fmt, sync::Arc};
+
+/// Limits on the resources of a scope.
+///
+/// Every limit defaults to `None`, which means that there is no limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    max_memory: Option<usize>,
+    max_inbound_streams: Option<u32>,
+    max_outbound_streams: Option<u32>,
+    max_inbound_connections: Option<u32>,
+    max_outbound_connections: Option<u32>,
+}
+
+impl ResourceLimits {
+    /// Sets the maximum number of bytes of memory.
+    pub fn with_max_memory(mut self, limit: Option<usize>) -> Self {
+        self.max_memory = limit;
+        self
+    }
+
+    /// Sets the maximum number of streams opened by remotes.
+    pub fn with_max_inbound_streams(mut self, limit: Option<u32>) -> Self {
+        self.max_inbound_streams = limit;
+        self
+    }
+
+    /// Sets the maximum number of streams opened by us.
+    pub fn with_max_outbound_streams(mut self, limit: Option<u32>) -> Self {
+        self.max_outbound_streams = limit;
+        self
+    }
+
+    /// Sets the maximum number of connections opened by remotes.
+    pub fn with_max_inbound_connections(mut self, limit: Option<u32>) -> Self {
+        self.max_inbound_connections = limit;
+        self
+    }
+
+    /// Sets the maximum number of connections opened by us.
+    pub fn with_max_outbound_connections(mut self, limit: Option<u32>) -> Self {
+        self.max_outbound_connections = limit;
+        self
+    }
+
+    /// Checks whether `delta` can be added to `usage`.
+    fn check(&self, scope: ScopeKind, usage: &ResourceUsage, delta: &ResourceUsage) -> Result<(), ResourceLimitError> {
+        let checks = [
+            (Resource::Memory, self.max_memory, usage.memory, delta.memory),
+            (Resource::InboundStreams, self.max_inbound_streams.map(|l| l as usize), usage.inbound_streams, delta.inbound_streams),
+            (Resource::OutboundStreams, self.max_outbound_streams.map(|l| l as usize), usage.outbound_streams, delta.outbound_streams),
+            (Resource::InboundConnections, self.max_inbound_connections.map(|l| l as usize), usage.inbound_connections, delta.inbound_connections),
+            (Resource::OutboundConnections, self.max_outbound_connections.map(|l| l as usize), usage.outbound_connections, delta.outbound_connections),
+        ];
+        for &(resource, limit, current, additional) in checks.iter() {
+            match limit {
+                Some(limit) if additional > 0 && current.saturating_add(additional) > limit =>
+                    return Err(ResourceLimitError { scope, resource, limit }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resources in use in a scope.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    memory: usize,
+    inbound_streams: usize,
+    outbound_streams: usize,
+    inbound_connections: usize,
+    outbound_connections: usize,
+}
+
+impl ResourceUsage {
+    /// Returns the number of bytes of memory reserved.
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Returns the number of streams opened by remotes.
+    pub fn inbound_streams(&self) -> usize {
+        self.inbound_streams
+    }
+
+    /// Returns the number of streams opened by us.
+    pub fn outbound_streams(&self) -> usize {
+        self.outbound_streams
+    }
+
+    /// Returns the number of connections opened by remotes.
+    pub fn inbound_connections(&self) -> usize {
+        self.inbound_connections
+    }
+
+    /// Returns the number of connections opened by us.
+    pub fn outbound_connections(&self) -> usize {
+        self.outbound_connections
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == ResourceUsage::default()
+    }
+
+    fn stream(endpoint: Endpoint) -> Self {
+        match endpoint {
+            Endpoint::Dialer => ResourceUsage { outbound_streams: 1, ..ResourceUsage::default() },
+            Endpoint::Listener => ResourceUsage { inbound_streams: 1, ..ResourceUsage::default() },
+        }
+    }
+
+    fn connection(endpoint: Endpoint) -> Self {
+        match endpoint {
+            Endpoint::Dialer => ResourceUsage { outbound_connections: 1, ..ResourceUsage::default() },
+            Endpoint::Listener => ResourceUsage { inbound_connections: 1, ..ResourceUsage::default() },
+        }
+    }
+
+    fn memory_only(memory: usize) -> Self {
+        ResourceUsage { memory, ..ResourceUsage::default() }
+    }
+
+    fn add(&mut self, other: &ResourceUsage) {
+        self.memory += other.memory;
+        self.inbound_streams += other.inbound_streams;
+        self.outbound_streams += other.outbound_streams;
+        self.inbound_connections += other.inbound_connections;
+        self.outbound_connections += other.outbound_connections;
+    }
+
+    fn sub(&mut self, other: &ResourceUsage) {
+        self.memory = self.memory.saturating_sub(other.memory);
+        self.inbound_streams = self.inbound_streams.saturating_sub(other.inbound_streams);
+        self.outbound_streams = self.outbound_streams.saturating_sub(other.outbound_streams);
+        self.inbound_connections = self.inbound_connections.saturating_sub(other.inbound_connections);
+        self.outbound_connections = self.outbound_connections.saturating_sub(other.outbound_connections);
+    }
+}
+
+/// Configuration of a [`ResourceManager`], in other words the limits of each scope.
+///
+/// By default, nothing is limited.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceManagerConfig {
+    system: ResourceLimits,
+    transient: ResourceLimits,
+    peer: ResourceLimits,
+    peers: FnvHashMap<PeerId, ResourceLimits>,
+    protocol: ResourceLimits,
+    protocols: FnvHashMap<Vec<u8>, ResourceLimits>,
+    connection: ResourceLimits,
+    stream: ResourceLimits,
+}
+
+impl ResourceManagerConfig {
+    /// Creates a configuration without any limit.
+    pub fn new() -> Self {
+        ResourceManagerConfig::default()
+    }
+
+    /// Sets the limits of the whole node.
+    pub fn with_system_limits(mut self, limits: ResourceLimits) -> Self {
+        self.system = limits;
+        self
+    }
+
+    /// Sets the limits of the resources that aren't attributed to a peer yet.
+    pub fn with_transient_limits(mut self, limits: ResourceLimits) -> Self {
+        self.transient = limits;
+        self
+    }
+
+    /// Sets the limits of each peer.
+    pub fn with_peer_limits(mut self, limits: ResourceLimits) -> Self {
+        self.peer = limits;
+        self
+    }
+
+    /// Sets the limits of the given peer, instead of the ones set with `with_peer_limits`.
+    pub fn with_limits_for_peer(mut self, peer_id: PeerId, limits: ResourceLimits) -> Self {
+        self.peers.insert(peer_id, limits);
+        self
+    }
+
+    /// Sets the limits of each protocol.
+    pub fn with_protocol_limits(mut self, limits: ResourceLimits) -> Self {
+        self.protocol = limits;
+        self
+    }
+
+    /// Sets the limits of the given protocol, instead of the ones set with
+    /// `with_protocol_limits`.
+    pub fn with_limits_for_protocol(mut self, protocol: impl Into<Vec<u8>>, limits: ResourceLimits) -> Self {
+        self.protocols.insert(protocol.into(), limits);
+        self
+    }
+
+    /// Sets the limits of each connection.
+    pub fn with_connection_limits(mut self, limits: ResourceLimits) -> Self {
+        self.connection = limits;
+        self
+    }
+
+    /// Sets the limits of each stream.
+    pub fn with_stream_limits(mut self, limits: ResourceLimits) -> Self {
+        self.stream = limits;
+        self
+    }
+}
+
+/// Kind of scope whose limit would have been exceeded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ScopeKind {
+    /// The whole node.
+    System,
+    /// The resources that aren't attributed to a peer.
+    Transient,
+    /// A peer.
+    Peer,
+    /// A protocol.
+    Protocol,
+    /// A connection.
+    Connection,
+    /// A stream.
+    Stream,
+}
+
+impl fmt::Display for ScopeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScopeKind::System => write!(f, "system"),
+            ScopeKind::Transient => write!(f, "transient"),
+            ScopeKind::Peer => write!(f, "peer"),
+            ScopeKind::Protocol => write!(f, "protocol"),
+            ScopeKind::Connection => write!(f, "connection"),
+            ScopeKind::Stream => write!(f, "stream"),
+        }
+    }
+}
+
+/// Kind of resource whose limit would have been exceeded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    /// Bytes of memory.
+    Memory,
+    /// Streams opened by remotes.
+    InboundStreams,
+    /// Streams opened by us.
+    OutboundStreams,
+    /// Connections opened by remotes.
+    InboundConnections,
+    /// Connections opened by us.
+    OutboundConnections,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Memory => write!(f, "bytes of memory"),
+            Resource::InboundStreams => write!(f, "inbound streams"),
+            Resource::OutboundStreams => write!(f, "outbound streams"),
+            Resource::InboundConnections => write!(f, "inbound connections"),
+            Resource::OutboundConnections => write!(f, "outbound connections"),
+        }
+    }
+}
+
+/// A resource couldn't be reserved because it would have exceeded the limit of a scope.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResourceLimitError {
+    /// The scope whose limit would have been exceeded.
+    pub scope: ScopeKind,
+    /// The resource whose limit would have been exceeded.
+    pub resource: Resource,
+    /// The limit.
+    pub limit: usize,
+}
+
+impl fmt::Display for ResourceLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Reached the limit of {} {} in the {} scope", self.limit, self.resource, self.scope)
+    }
+}
+
+impl error::Error for ResourceLimitError {}
+
+/// Keeps track of the resources used by the node. See the module-level documentation.
+///
+/// Cloning a `ResourceManager` is cheap, and the clones share the same state.
+#[derive(Clone)]
+pub struct ResourceManager {
+    inner: Arc<Mutex<State>>,
+}
+
+impl ResourceManager {
+    /// Creates a new `ResourceManager` enforcing the given limits.
+    pub fn new(config: ResourceManagerConfig) -> Self {
+        ResourceManager {
+            inner: Arc::new(Mutex::new(State {
+                config,
+                system: ResourceUsage::default(),
+                transient: ResourceUsage::default(),
+                peers: FnvHashMap::default(),
+                protocols: FnvHashMap::default(),
+                connections: FnvHashMap::default(),
+                streams: FnvHashMap::default(),
+                spans: FnvHashMap::default(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Reserves a connection, opened by us if `endpoint` is `Dialer` or by the remote if it is
+    /// `Listener`, in the transient scope.
+    ///
+    /// The connection is released when the returned scope is dropped.
+    pub fn open_connection(&self, endpoint: Endpoint) -> Result<ConnectionScope, ResourceLimitError> {
+        let mut state = self.inner.lock();
+        let id = state.next_id();
+        state.connections.insert(id, ConnectionState {
+            usage: ResourceUsage::default(),
+            own: ResourceUsage::default(),
+            peer: None,
+            scopes: 0,
+            closed: false,
+        });
+        let delta = ResourceUsage::connection(endpoint);
+        if let Err(err) = state.reserve(Scope::Connection(id), &delta) {
+            state.connections.remove(&id);
+            return Err(err);
+        }
+        state.connections.get_mut(&id).expect("inserted above; QED").own = delta;
+        Ok(ConnectionScope { manager: self.clone(), id })
+    }
+
+    /// Returns a scope to reserve memory in, which is part of the transient scope.
+    ///
+    /// This is meant for components that need memory before it can be attributed to a
+    /// connection. The memory is released when the returned scope is dropped.
+    pub fn transient_memory(&self) -> MemoryScope {
+        let mut state = self.inner.lock();
+        let id = state.next_id();
+        state.spans.insert(id, SpanState { usage: ResourceUsage::default(), connection: None });
+        MemoryScope { manager: self.clone(), id }
+    }
+
+    /// Returns the resources used by the whole node.
+    pub fn system_usage(&self) -> ResourceUsage {
+        self.inner.lock().system
+    }
+
+    /// Returns the resources that aren't attributed to a peer.
+    pub fn transient_usage(&self) -> ResourceUsage {
+        self.inner.lock().transient
+    }
+
+    /// Returns the resources used by the given peer.
+    pub fn peer_usage(&self, peer_id: &PeerId) -> ResourceUsage {
+        self.inner.lock().peers.get(peer_id).cloned().unwrap_or_default()
+    }
+
+    /// Returns the resources used by the streams of the given protocol.
+    pub fn protocol_usage(&self, protocol: &[u8]) -> ResourceUsage {
+        self.inner.lock().protocols.get(protocol).cloned().unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for ResourceManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceManager")
+            .field("system", &self.system_usage())
+            .finish()
+    }
+}
+
+/// Scope of a connection, as returned by `ResourceManager::open_connection`.
+///
+/// Releases the connection and the memory reserved in it when dropped. The streams of the
+/// connection remain accounted for in the scopes containing it until they are dropped as well.
+pub struct ConnectionScope {
+    manager: ResourceManager,
+    id: u64,
+}
+
+impl ConnectionScope {
+    /// Attributes the connection to the given peer, moving all its resources from the transient
+    /// scope to the scope of the peer.
+    ///
+    /// Fails if the peer can't take the resources of the connection, in which case the
+    /// connection remains in the transient scope.
+    pub fn set_peer(&mut self, peer_id: &PeerId) -> Result<(), ResourceLimitError> {
+        let mut state = self.manager.inner.lock();
+        let connection = state.connections.get(&self.id).expect("connection removed while its scope is alive");
+        if connection.peer.is_some() {
+            return Ok(());
+        }
+        let usage = connection.usage;
+        let peer_usage = state.peers.get(peer_id).cloned().unwrap_or_default();
+        state.config.peer_limits(peer_id).check(ScopeKind::Peer, &peer_usage, &usage)?;
+        state.transient.sub(&usage);
+        state.peers.entry(peer_id.clone()).or_default().add(&usage);
+        state.connections.get_mut(&self.id).expect("checked above; QED").peer = Some(peer_id.clone());
+        Ok(())
+    }
+
+    /// Reserves a stream, opened by us if `endpoint` is `Dialer` or by the remote if it is
+    /// `Listener`, in this connection.
+    ///
+    /// The stream is released when the returned scope is dropped.
+    pub fn open_stream(&self, endpoint: Endpoint) -> Result<StreamScope, ResourceLimitError> {
+        let mut state = self.manager.inner.lock();
+        let id = state.next_id();
+        state.streams.insert(id, StreamState {
+            usage: ResourceUsage::default(),
+            connection: self.id,
+            protocol: None,
+        });
+        if let Err(err) = state.reserve(Scope::Stream(id), &ResourceUsage::stream(endpoint)) {
+            state.streams.remove(&id);
+            return Err(err);
+        }
+        state.connections.get_mut(&self.id).expect("connection removed while its scope is alive").scopes += 1;
+        Ok(StreamScope { manager: self.manager.clone(), id })
+    }
+
+    /// Returns a scope to reserve memory in, which is part of this connection.
+    ///
+    /// This is meant for components that hold memory on behalf of the whole connection, such as
+    /// the buffers of a muxer. The memory is released when the returned scope is dropped.
+    pub fn memory(&self) -> MemoryScope {
+        let mut state = self.manager.inner.lock();
+        let id = state.next_id();
+        state.spans.insert(id, SpanState { usage: ResourceUsage::default(), connection: Some(self.id) });
+        state.connections.get_mut(&self.id).expect("connection removed while its scope is alive").scopes += 1;
+        MemoryScope { manager: self.manager.clone(), id }
+    }
+
+    /// Reserves `size` bytes of memory in this connection.
+    pub fn reserve_memory(&self, size: usize) -> Result<(), ResourceLimitError> {
+        let mut state = self.manager.inner.lock();
+        let delta = ResourceUsage::memory_only(size);
+        state.reserve(Scope::Connection(self.id), &delta)?;
+        if let Some(connection) = state.connections.get_mut(&self.id) {
+            connection.own.add(&delta);
+        }
+        Ok(())
+    }
+
+    /// Releases `size` bytes of memory previously reserved with `reserve_memory`.
+    pub fn release_memory(&self, size: usize) {
+        let mut state = self.manager.inner.lock();
+        let delta = match state.connections.get_mut(&self.id) {
+            Some(connection) => {
+                let delta = ResourceUsage::memory_only(size.min(connection.own.memory));
+                connection.own.sub(&delta);
+                delta
+            }
+            None => return,
+        };
+        state.release(Scope::Connection(self.id), &delta);
+    }
+
+    /// Returns the resources used by the connection, including its streams.
+    pub fn usage(&self) -> ResourceUsage {
+        let state = self.manager.inner.lock();
+        state.connections.get(&self.id).map(|c| c.usage).unwrap_or_default()
+    }
+}
+
+impl Drop for ConnectionScope {
+    fn drop(&mut self) {
+        let mut state = self.manager.inner.lock();
+        let own = match state.connections.get_mut(&self.id) {
+            Some(connection) => {
+                connection.closed = true;
+                connection.own
+            }
+            None => return,
+        };
+        state.release(Scope::Connection(self.id), &own);
+    }
+}
+
+impl fmt::Debug for ConnectionScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionScope").field("usage", &self.usage()).finish()
+    }
+}
+
+/// Scope of a stream, as returned by `ConnectionScope::open_stream`.
+///
+/// Releases the stream and the memory reserved in it when dropped.
+pub struct StreamScope {
+    manager: ResourceManager,
+    id: u64,
+}
+
+impl StreamScope {
+    /// Attributes the stream to the given protocol, adding its resources to the scope of the
+    /// protocol.
+    ///
+    /// Fails if the protocol can't take the resources of the stream.
+    pub fn set_protocol(&mut self, protocol: &[u8]) -> Result<(), ResourceLimitError> {
+        let mut state = self.manager.inner.lock();
+        let stream = state.streams.get(&self.id).expect("stream removed while its scope is alive");
+        if stream.protocol.is_some() {
+            return Ok(());
+        }
+        let usage = stream.usage;
+        let protocol_usage = state.protocols.get(protocol).cloned().unwrap_or_default();
+        state.config.protocol_limits(protocol).check(ScopeKind::Protocol, &protocol_usage, &usage)?;
+        state.protocols.entry(protocol.to_vec()).or_default().add(&usage);
+        state.streams.get_mut(&self.id).expect("checked above; QED").protocol = Some(protocol.to_vec());
+        Ok(())
+    }
+
+    /// Reserves `size` bytes of memory in this stream.
+    pub fn reserve_memory(&self, size: usize) -> Result<(), ResourceLimitError> {
+        self.manager.inner.lock().reserve(Scope::Stream(self.id), &ResourceUsage::memory_only(size))
+    }
+
+    /// Releases `size` bytes of memory previously reserved with `reserve_memory`.
+    pub fn release_memory(&self, size: usize) {
+        let mut state = self.manager.inner.lock();
+        let reserved = state.streams.get(&self.id).map_or(0, |s| s.usage.memory);
+        state.release(Scope::Stream(self.id), &ResourceUsage::memory_only(size.min(reserved)));
+    }
+
+    /// Returns the resources used by the stream.
+    pub fn usage(&self) -> ResourceUsage {
+        let state = self.manager.inner.lock();
+        state.streams.get(&self.id).map(|s| s.usage).unwrap_or_default()
+    }
+}
+
+impl Drop for StreamScope {
+    fn drop(&mut self) {
+        let mut state = self.manager.inner.lock();
+        let usage = match state.streams.get(&self.id) {
+            Some(stream) => stream.usage,
+            None => return,
+        };
+        state.release(Scope::Stream(self.id), &usage);
+        if let Some(stream) = state.streams.remove(&self.id) {
+            state.remove_scope_from_connection(stream.connection);
+        }
+    }
+}
+
+impl fmt::Debug for StreamScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamScope").field("usage", &self.usage()).finish()
+    }
+}
+
+/// Scope to reserve memory in, as returned by `ResourceManager::transient_memory` or
+/// `ConnectionScope::memory`.
+///
+/// Releases the memory reserved in it when dropped.
+pub struct MemoryScope {
+    manager: ResourceManager,
+    id: u64,
+}
+
+impl MemoryScope {
+    /// Reserves `size` bytes of memory.
+    pub fn reserve_memory(&self, size: usize) -> Result<(), ResourceLimitError> {
+        self.manager.inner.lock().reserve(Scope::Span(self.id), &ResourceUsage::memory_only(size))
+    }
+
+    /// Releases `size` bytes of memory previously reserved with `reserve_memory`.
+    pub fn release_memory(&self, size: usize) {
+        let mut state = self.manager.inner.lock();
+        let reserved = state.spans.get(&self.id).map_or(0, |s| s.usage.memory);
+        state.release(Scope::Span(self.id), &ResourceUsage::memory_only(size.min(reserved)));
+    }
+
+    /// Returns the number of bytes of memory reserved.
+    pub fn memory(&self) -> usize {
+        self.manager.inner.lock().spans.get(&self.id).map_or(0, |s| s.usage.memory)
+    }
+}
+
+impl Drop for MemoryScope {
+    fn drop(&mut self) {
+        let mut state = self.manager.inner.lock();
+        let usage = match state.spans.get(&self.id) {
+            Some(span) => span.usage,
+            None => return,
+        };
+        state.release(Scope::Span(self.id), &usage);
+        if let Some(SpanState { connection: Some(connection), .. }) = state.spans.remove(&self.id) {
+            state.remove_scope_from_connection(connection);
+        }
+    }
+}
+
+impl fmt::Debug for MemoryScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryScope").field("memory", &self.memory()).finish()
+    }
+}
+
+/// Shared state of a `ResourceManager`.
+struct State {
+    config: ResourceManagerConfig,
+    system: ResourceUsage,
+    transient: ResourceUsage,
+    peers: FnvHashMap<PeerId, ResourceUsage>,
+    protocols: FnvHashMap<Vec<u8>, ResourceUsage>,
+    connections: FnvHashMap<u64, ConnectionState>,
+    streams: FnvHashMap<u64, StreamState>,
+    /// State of the `MemoryScope`s.
+    spans: FnvHashMap<u64, SpanState>,
+    next_id: u64,
+}
+
+struct ConnectionState {
+    /// Resources of the connection, including its streams.
+    usage: ResourceUsage,
+    /// Resources reserved directly in the connection, excluding its streams.
+    own: ResourceUsage,
+    /// The remote, once known.
+    peer: Option<PeerId>,
+    /// Number of `StreamScope`s and `MemoryScope`s opened in the connection and not dropped yet.
+    scopes: usize,
+    /// Whether the `ConnectionScope` has been dropped.
+    closed: bool,
+}
+
+struct StreamState {
+    usage: ResourceUsage,
+    connection: u64,
+    protocol: Option<Vec<u8>>,
+}
+
+struct SpanState {
+    usage: ResourceUsage,
+    /// The connection the memory is part of, if any. Otherwise it is part of the transient scope.
+    connection: Option<u64>,
+}
+
+/// Identifies a scope in the `State`.
+#[derive(Clone)]
+enum Scope {
+    System,
+    Transient,
+    Peer(PeerId),
+    Protocol(Vec<u8>),
+    Connection(u64),
+    Stream(u64),
+    Span(u64),
+}
+
+impl ResourceManagerConfig {
+    fn peer_limits(&self, peer_id: &PeerId) -> &ResourceLimits {
+        self.peers.get(peer_id).unwrap_or(&self.peer)
+    }
+
+    fn protocol_limits(&self, protocol: &[u8]) -> &ResourceLimits {
+        self.protocols.get(protocol).unwrap_or(&self.protocol)
+    }
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Returns `scope` followed by all the scopes that contain it.
+    fn path(&self, scope: Scope) -> SmallVec<[Scope; 6]> {
+        let mut path = SmallVec::new();
+        let mut connection = None;
+        match &scope {
+            Scope::Stream(id) => if let Some(stream) = self.streams.get(id) {
+                path.push(scope.clone());
+                if let Some(protocol) = &stream.protocol {
+                    path.push(Scope::Protocol(protocol.clone()));
+                }
+                connection = Some(stream.connection);
+            },
+            Scope::Connection(id) => connection = Some(*id),
+            Scope::Span(id) => {
+                path.push(scope.clone());
+                match self.spans.get(id).and_then(|s| s.connection) {
+                    Some(id) => connection = Some(id),
+                    None => path.push(Scope::Transient),
+                }
+            },
+            _ => path.push(scope.clone()),
+        }
+        if let Some(id) = connection {
+            if let Some(state) = self.connections.get(&id) {
+                path.push(Scope::Connection(id));
+                match &state.peer {
+                    Some(peer_id) => path.push(Scope::Peer(peer_id.clone())),
+                    None => path.push(Scope::Transient),
+                }
+            }
+        }
+        path.push(Scope::System);
+        path
+    }
+
+    fn usage(&self, scope: &Scope) -> ResourceUsage {
+        match scope {
+            Scope::System => self.system,
+            Scope::Transient => self.transient,
+            Scope::Peer(peer_id) => self.peers.get(peer_id).cloned().unwrap_or_default(),
+            Scope::Protocol(protocol) => self.protocols.get(protocol).cloned().unwrap_or_default(),
+            Scope::Connection(id) => self.connections.get(id).map(|c| c.usage).unwrap_or_default(),
+            Scope::Stream(id) => self.streams.get(id).map(|s| s.usage).unwrap_or_default(),
+            Scope::Span(id) => self.spans.get(id).map(|s| s.usage).unwrap_or_default(),
+        }
+    }
+
+    fn usage_mut(&mut self, scope: &Scope) -> Option<&mut ResourceUsage> {
+        match scope {
+            Scope::System => Some(&mut self.system),
+            Scope::Transient => Some(&mut self.transient),
+            Scope::Peer(peer_id) => Some(self.peers.entry(peer_id.clone()).or_default()),
+            Scope::Protocol(protocol) => Some(self.protocols.entry(protocol.clone()).or_default()),
+            Scope::Connection(id) => self.connections.get_mut(id).map(|c| &mut c.usage),
+            Scope::Stream(id) => self.streams.get_mut(id).map(|s| &mut s.usage),
+            Scope::Span(id) => self.spans.get_mut(id).map(|s| &mut s.usage),
+        }
+    }
+
+    fn limits(&self, scope: &Scope) -> (ScopeKind, &ResourceLimits) {
+        static UNLIMITED: ResourceLimits = ResourceLimits {
+            max_memory: None,
+            max_inbound_streams: None,
+            max_outbound_streams: None,
+            max_inbound_connections: None,
+            max_outbound_connections: None,
+        };
+        match scope {
+            Scope::System => (ScopeKind::System, &self.config.system),
+            Scope::Transient => (ScopeKind::Transient, &self.config.transient),
+            Scope::Peer(peer_id) => (ScopeKind::Peer, self.config.peer_limits(peer_id)),
+            Scope::Protocol(protocol) => (ScopeKind::Protocol, self.config.protocol_limits(protocol)),
+            Scope::Connection(_) => (ScopeKind::Connection, &self.config.connection),
+            Scope::Stream(_) => (ScopeKind::Stream, &self.config.stream),
+            Scope::Span(_) => (ScopeKind::Transient, &UNLIMITED),
+        }
+    }
+
+    /// Reserves `delta` in `scope` and all the scopes containing it, or in none of them if one
+    /// would exceed its limits.
+    fn reserve(&mut self, scope: Scope, delta: &ResourceUsage) -> Result<(), ResourceLimitError> {
+        let path = self.path(scope);
+        for scope in &path {
+            let (kind, limits) = self.limits(scope);
+            limits.check(kind, &self.usage(scope), delta)?;
+        }
+        for scope in &path {
+            if let Some(usage) = self.usage_mut(scope) {
+                usage.add(delta);
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases `delta` from `scope` and all the scopes containing it.
+    fn release(&mut self, scope: Scope, delta: &ResourceUsage) {
+        let path = self.path(scope);
+        for scope in &path {
+            if let Some(usage) = self.usage_mut(scope) {
+                usage.sub(delta);
+            }
+            match scope {
+                Scope::Peer(peer_id) if self.peers.get(peer_id).map_or(false, ResourceUsage::is_empty) => {
+                    self.peers.remove(peer_id);
+                }
+                Scope::Protocol(protocol) if self.protocols.get(protocol).map_or(false, ResourceUsage::is_empty) => {
+                    self.protocols.remove(protocol);
+                }
+                Scope::Connection(id) => self.remove_connection_if_unused(*id),
+                _ => {}
+            }
+        }
+    }
+
+    /// Accounts for a stream or memory scope of a connection having been dropped.
+    fn remove_scope_from_connection(&mut self, id: u64) {
+        if let Some(connection) = self.connections.get_mut(&id) {
+            connection.scopes -= 1;
+        }
+        self.remove_connection_if_unused(id);
+    }
+
+    /// Forgets about a connection whose scope has been dropped once nothing else refers to it.
+    fn remove_connection_if_unused(&mut self, id: u64) {
+        let unused = self.connections.get(&id)
+            .map_or(false, |c| c.closed && c.scopes == 0 && c.usage.is_empty());
+        if unused {
+            self.connections.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resources_are_reserved_in_the_parent_scopes() {
+        let manager = ResourceManager::new(ResourceManagerConfig::new()
+            .with_peer_limits(ResourceLimits::default().with_max_memory(Some(100))));
+        let peer_id = PeerId::random();
+
+        let mut connection = manager.open_connection(Endpoint::Listener).unwrap();
+        connection.reserve_memory(60).unwrap();
+        assert_eq!(manager.transient_usage().inbound_connections(), 1);
+        assert_eq!(manager.transient_usage().memory(), 60);
+
+        connection.set_peer(&peer_id).unwrap();
+        assert_eq!(manager.transient_usage(), ResourceUsage::default());
+        assert_eq!(manager.peer_usage(&peer_id).memory(), 60);
+
+        let mut stream = connection.open_stream(Endpoint::Dialer).unwrap();
+        stream.set_protocol(b"/foo/1").unwrap();
+        stream.reserve_memory(30).unwrap();
+        assert_eq!(stream.reserve_memory(20), Err(ResourceLimitError {
+            scope: ScopeKind::Peer,
+            resource: Resource::Memory,
+            limit: 100,
+        }));
+        assert_eq!(manager.protocol_usage(b"/foo/1").outbound_streams(), 1);
+        assert_eq!(manager.system_usage().memory(), 90);
+        assert_eq!(connection.usage().memory(), 90);
+
+        // The stream remains accounted for after the connection scope is dropped.
+        drop(connection);
+        assert_eq!(manager.system_usage().memory(), 30);
+        assert_eq!(manager.peer_usage(&peer_id).outbound_streams(), 1);
+
+        drop(stream);
+        assert_eq!(manager.system_usage(), ResourceUsage::default());
+        assert_eq!(manager.peer_usage(&peer_id), ResourceUsage::default());
+        assert_eq!(manager.protocol_usage(b"/foo/1"), ResourceUsage::default());
+    }
+
+    #[test]
+    fn failed_reservations_have_no_effect() {
+        let manager = ResourceManager::new(ResourceManagerConfig::new()
+            .with_system_limits(ResourceLimits::default().with_max_inbound_connections(Some(1)))
+            .with_limits_for_protocol(&b"/foo/1"[..], ResourceLimits::default().with_max_inbound_streams(Some(0))));
+
+        let connection = manager.open_connection(Endpoint::Listener).unwrap();
+        assert_eq!(manager.open_connection(Endpoint::Listener).unwrap_err(), ResourceLimitError {
+            scope: ScopeKind::System,
+            resource: Resource::InboundConnections,
+            limit: 1,
+        });
+        assert!(manager.open_connection(Endpoint::Dialer).is_ok());
+
+        let mut stream = connection.open_stream(Endpoint::Listener).unwrap();
+        assert_eq!(stream.set_protocol(b"/foo/1").unwrap_err().scope, ScopeKind::Protocol);
+        assert!(stream.set_protocol(b"/bar/1").is_ok());
+        assert_eq!(manager.protocol_usage(b"/foo/1"), ResourceUsage::default());
+
+        let memory = manager.transient_memory();
+        memory.reserve_memory(10).unwrap();
+        memory.release_memory(20);
+        assert_eq!(memory.memory(), 0);
+        assert_eq!(manager.transient_usage().inbound_connections(), 1);
+        assert_eq!(manager.transient_usage().inbound_streams(), 1);
+    }
+
+    #[test]
+    fn memory_of_a_connection_is_part_of_its_scopes() {
+        let manager = ResourceManager::new(ResourceManagerConfig::new());
+        let peer_id = PeerId::random();
+
+        let mut connection = manager.open_connection(Endpoint::Dialer).unwrap();
+        connection.set_peer(&peer_id).unwrap();
+        let memory = connection.memory();
+        memory.reserve_memory(10).unwrap();
+        let stream = connection.open_stream(Endpoint::Dialer).unwrap();
+        assert_eq!(connection.usage().memory(), 10);
+        assert_eq!(manager.peer_usage(&peer_id).memory(), 10);
+        assert_eq!(manager.transient_usage(), ResourceUsage::default());
+
+        // The connection is forgotten once its scope and everything opened in it are dropped.
+        drop(connection);
+        drop(stream);
+        assert_eq!(manager.peer_usage(&peer_id).memory(), 10);
+        assert_eq!(manager.inner.lock().connections.len(), 1);
+        drop(memory);
+        assert_eq!(manager.system_usage(), ResourceUsage::default());
+        assert_eq!(manager.peer_usage(&peer_id), ResourceUsage::default());
+        assert!(manager.inner.lock().connections.is_empty());
+    }
+}
@@ -39,13 +39,40 @@
 //! The `NetworkBehaviour` trait is implemented on types that indicate to the swarm how it should
 //! behave. This includes which protocols are supported and which nodes to try to connect to.
 //!
+//! # Peer Store
+//!
+//! The `Swarm` owns a [`PeerStore`] that gathers what is known about the peers of the network,
+//! such as their addresses, public keys and supported protocols. Behaviours can access it through
+//! the [`PollParameters`], and the `Swarm` uses the addresses it contains when dialing a peer.
+//! The store can be persisted across restarts by passing a store created with
+//! [`PeerStore::with_backend`] to [`SwarmBuilder::peer_store`].
+//!
+//! # Connection Manager
+//!
+//! The [`ConnectionManager`] behaviour keeps the number of connections between a low and a high
+//! watermark, closing the connections to the peers that behaviours and the application have
+//! given the lowest value. See the [`connection_manager`] module.
+//!
 
+mod backoff;
 mod behaviour;
+mod gater;
+mod limits;
 mod swarm;
 mod registry;
 
+pub mod connection_manager;
+pub mod peer_store;
+
 pub mod toggle;
 
-pub use crate::nodes::raw_swarm::ConnectedPoint;
-pub use self::behaviour::{NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess};
-pub use self::swarm::{PollParameters, ExpandedSwarm, Swarm, SwarmBuilder};
+pub use crate::nodes::{collection::ConnectionId, listeners::{ListenerId, ListenerRestartPolicy}, raw_swarm::ConnectedPoint};
+pub use self::backoff::DialBackoff;
+pub use self::connection_manager::{ConnectionManager, ConnectionManagerConfig, ConnectionManagerHandle};
+pub use self::behaviour::{DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess};
+pub use self::gater::{ConnectionDenied, ConnectionGater};
+pub use self::limits::{ConnectionLimitError, ConnectionLimits, InboundSubstreamLimits, SubstreamLimitError};
+pub use self::peer_store::{AddressRecord, AddressSource, PeerRecord, PeerStore, PeerStoreBackend};
+#[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
+pub use self::peer_store::FilePeerStoreBackend;
+pub use self::swarm::{ConnectionClosedReason, DialError, PollParameters, ExpandedSwarm, Swarm, SwarmBuilder, SwarmEvent};
@@ -0,0 +1,203 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Backoff of the dialing attempts of a `Swarm`.
+
+use crate::{Multiaddr, PeerId};
+use fnv::FnvHashMap;
+use std::{cmp, hash::Hash, time::Duration};
+use wasm_timer::Instant;
+
+/// Backoff applied by a `Swarm` to the peers and addresses it failed to dial.
+///
+/// After a peer or an address failed to be dialed, it is not dialed again until its backoff has
+/// elapsed. The backoff starts at `initial_backoff` and doubles with every consecutive failure,
+/// up to `max_backoff`. A random extra delay of up to `jitter` times the backoff is added, so
+/// that nodes which failed at the same time don't retry at the same time. Successfully
+/// connecting resets the backoff.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DialBackoff {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: f64,
+}
+
+impl Default for DialBackoff {
+    fn default() -> Self {
+        DialBackoff {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5 * 60),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl DialBackoff {
+    /// Sets the backoff after the first failure. Defaults to one second.
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum backoff, not counting the jitter. Defaults to five minutes.
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the maximum random extra delay, as a fraction of the backoff. The value is clamped
+    /// between `0.0` and `1.0`. Defaults to `0.2`.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.max(0.0).min(1.0);
+        self
+    }
+
+    /// Returns the backoff after `failures` consecutive failures, without the jitter.
+    fn base_backoff(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1);
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(exponent))
+            .map_or(self.max_backoff, |b| cmp::min(b, self.max_backoff))
+    }
+
+    /// Returns the backoff after `failures` consecutive failures, including a random jitter.
+    fn backoff(&self, failures: u32) -> Duration {
+        let base = self.base_backoff(failures);
+        let extra = base.as_millis() as f64 * self.jitter * rand::random::<f64>();
+        base + Duration::from_millis(extra as u64)
+    }
+}
+
+/// Backoff state of the peers and addresses dialed by a `Swarm`.
+#[derive(Debug, Clone)]
+pub(crate) struct DialBackoffState {
+    config: DialBackoff,
+    peers: FnvHashMap<PeerId, Entry>,
+    addresses: FnvHashMap<Multiaddr, Entry>,
+}
+
+/// Backoff of a single peer or address.
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    /// Number of consecutive failures.
+    failures: u32,
+    /// Dialing is refused until then.
+    until: Instant,
+}
+
+impl DialBackoffState {
+    pub(crate) fn new(config: DialBackoff) -> Self {
+        DialBackoffState {
+            config,
+            peers: Default::default(),
+            addresses: Default::default(),
+        }
+    }
+
+    /// Returns the time until which dialing the peer is refused, if it is in backoff.
+    pub(crate) fn peer_backoff(&self, peer_id: &PeerId, now: Instant) -> Option<Instant> {
+        active(&self.peers, peer_id, now)
+    }
+
+    /// Returns the time until which dialing the address is refused, if it is in backoff.
+    pub(crate) fn address_backoff(&self, addr: &Multiaddr, now: Instant) -> Option<Instant> {
+        active(&self.addresses, addr, now)
+    }
+
+    /// Records that all the addresses of a peer failed to be dialed.
+    pub(crate) fn peer_failed(&mut self, peer_id: &PeerId, now: Instant) {
+        failed(&self.config, &mut self.peers, peer_id, now)
+    }
+
+    /// Records that an address failed to be dialed.
+    pub(crate) fn address_failed(&mut self, addr: &Multiaddr, now: Instant) {
+        failed(&self.config, &mut self.addresses, addr, now)
+    }
+
+    /// Records that we are connected to a peer, through the given address if we dialed it.
+    pub(crate) fn connected(&mut self, peer_id: &PeerId, addr: Option<&Multiaddr>) {
+        self.peers.remove(peer_id);
+        if let Some(addr) = addr {
+            self.addresses.remove(addr);
+        }
+    }
+}
+
+fn active<K: Hash + Eq>(entries: &FnvHashMap<K, Entry>, key: &K, now: Instant) -> Option<Instant> {
+    entries.get(key).map(|e| e.until).filter(|until| *until > now)
+}
+
+fn failed<K: Hash + Eq + Clone>(config: &DialBackoff, entries: &mut FnvHashMap<K, Entry>, key: &K, now: Instant) {
+    // Forget the entries whose backoff elapsed long ago, so that the map doesn't grow forever
+    // and so that peers that recovered start again from the initial backoff.
+    entries.retain(|_, e| e.until + config.max_backoff > now);
+
+    let failures = entries.get(key).map_or(0, |e| e.failures).saturating_add(1);
+    let until = now + config.backoff(failures);
+    entries.insert(key.clone(), Entry { failures, until });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DialBackoff, DialBackoffState};
+    use crate::PeerId;
+    use std::time::Duration;
+    use wasm_timer::Instant;
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        let config = DialBackoff::default()
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_backoff(Duration::from_secs(5));
+        assert_eq!(config.base_backoff(1), Duration::from_secs(1));
+        assert_eq!(config.base_backoff(2), Duration::from_secs(2));
+        assert_eq!(config.base_backoff(3), Duration::from_secs(4));
+        assert_eq!(config.base_backoff(4), Duration::from_secs(5));
+        assert_eq!(config.base_backoff(u32::max_value()), Duration::from_secs(5));
+
+        let backoff = config.backoff(2);
+        assert!(backoff >= Duration::from_secs(2) && backoff <= Duration::from_millis(2400));
+    }
+
+    #[test]
+    fn failures_and_success_update_the_state() {
+        let config = DialBackoff::default()
+            .with_initial_backoff(Duration::from_secs(10))
+            .with_jitter(0.0);
+        let mut state = DialBackoffState::new(config);
+        let peer_id = PeerId::random();
+        let addr = "/memory/1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(state.peer_backoff(&peer_id, now).is_none());
+        state.peer_failed(&peer_id, now);
+        state.address_failed(&addr, now);
+        assert_eq!(state.peer_backoff(&peer_id, now), Some(now + Duration::from_secs(10)));
+        assert_eq!(state.address_backoff(&addr, now), Some(now + Duration::from_secs(10)));
+        assert!(state.peer_backoff(&peer_id, now + Duration::from_secs(10)).is_none());
+
+        state.peer_failed(&peer_id, now);
+        assert_eq!(state.peer_backoff(&peer_id, now), Some(now + Duration::from_secs(20)));
+
+        state.connected(&peer_id, Some(&addr));
+        assert!(state.peer_backoff(&peer_id, now).is_none());
+        assert!(state.address_backoff(&addr, now).is_none());
+    }
+}
@@ -20,12 +20,13 @@
 
 use crate::{
     Multiaddr, PeerId,
-    nodes::raw_swarm::ConnectedPoint,
+    nodes::{collection::ConnectionId, listeners::ListenerId, raw_swarm::ConnectedPoint},
     protocols_handler::{IntoProtocolsHandler, ProtocolsHandler},
-    swarm::PollParameters,
+    resource::ResourceLimitError,
+    swarm::{ConnectionLimitError, PollParameters, SubstreamLimitError},
 };
 use futures::prelude::*;
-use std::error;
+use std::{error, io};
 
 /// A behaviour for the network. Allows customizing the swarm.
 ///
@@ -44,27 +45,28 @@ pub trait NetworkBehaviour {
     /// reaching the peer.
     fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr>;
 
-    /// Indicates the behaviour that we connected to the node with the given peer id through the
-    /// given endpoint.
-    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint);
-
-    /// Indicates the behaviour that we disconnected from the node with the given peer id. The
-    /// endpoint is the one we used to be connected to.
-    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint);
+    /// Indicates the behaviour that we opened a new connection to the node with the given peer id
+    /// through the given endpoint.
+    ///
+    /// The swarm can hold several connections to the same node at the same time, and this method
+    /// is called once for each of them.
+    fn inject_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint);
 
-    /// Indicates the behaviour that we replace the connection from the node with another.
-    fn inject_replaced(&mut self, peer_id: PeerId, closed_endpoint: ConnectedPoint, new_endpoint: ConnectedPoint) {
-        self.inject_disconnected(&peer_id, closed_endpoint);
-        self.inject_connected(peer_id, new_endpoint);
-    }
+    /// Indicates the behaviour that a connection to the node with the given peer id has been
+    /// closed. The endpoint is the one this connection used to be connected to, and `reason`
+    /// tells why the connection has been closed.
+    ///
+    /// Other connections to the same node may still be open.
+    fn inject_disconnected(&mut self, peer_id: &PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint, reason: DisconnectReason<'_>);
 
-    /// Indicates the behaviour that the node with the given peer id has generated an event for
-    /// us.
+    /// Indicates the behaviour that the handler of a connection to the node with the given peer id
+    /// has generated an event for us.
     ///
     /// > **Note**: This method is only called for events generated by the protocols handler.
     fn inject_node_event(
         &mut self,
         peer_id: PeerId,
+        connection_id: ConnectionId,
         event: <<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent
     );
 
@@ -80,6 +82,22 @@ pub trait NetworkBehaviour {
     fn inject_dial_failure(&mut self, _peer_id: &PeerId) {
     }
 
+    /// Indicates to the behaviour that an incoming connection has been refused because it would
+    /// have exceeded one of the connection limits of the swarm.
+    ///
+    /// The ID of the remote is passed if the connection was refused after it has been
+    /// established. Dialing attempts that are refused are instead reported through
+    /// `inject_addr_reach_failure` and `inject_dial_failure`.
+    fn inject_connection_refused(&mut self, _peer_id: Option<&PeerId>, _endpoint: &ConnectedPoint, _error: &ConnectionLimitError) {
+    }
+
+    /// Indicates to the behaviour that an inbound substream opened by a peer has been reset
+    /// because it would have exceeded one of the substream limits of the swarm.
+    ///
+    /// This can be used to lower the score of peers that open too many substreams.
+    fn inject_substream_refused(&mut self, _peer_id: &PeerId, _error: &SubstreamLimitError) {
+    }
+
     /// Indicates to the behaviour that we have started listening on a new multiaddr.
     fn inject_new_listen_addr(&mut self, _addr: &Multiaddr) {
     }
@@ -89,6 +107,28 @@ pub trait NetworkBehaviour {
     fn inject_expired_listen_addr(&mut self, _addr: &Multiaddr) {
     }
 
+    /// Indicates to the behaviour that a listener failed with an error and will be restarted.
+    ///
+    /// The addresses it was listening on have already been reported as expired through
+    /// `inject_expired_listen_addr`.
+    fn inject_listener_error(&mut self, _id: ListenerId, _error: &dyn error::Error) {
+    }
+
+    /// Indicates to the behaviour that a listener closed, either gracefully or with an error,
+    /// and will not be restarted.
+    ///
+    /// The addresses it was listening on have already been reported as expired through
+    /// `inject_expired_listen_addr`.
+    fn inject_listener_closed(&mut self, _id: ListenerId, _reason: Result<(), &dyn error::Error>) {
+    }
+
+    /// Indicates to the behaviour that the swarm is shutting down.
+    ///
+    /// The listeners have already been removed. From now on, dialing requests are refused and
+    /// the connections are closed as soon as their handlers are done with their pending work.
+    fn inject_closing(&mut self) {
+    }
+
     /// Indicates to the behaviour that we have discovered a new external address for us.
     fn inject_new_external_addr(&mut self, _addr: &Multiaddr) {
     }
@@ -137,6 +177,9 @@ pub enum NetworkBehaviourAction<TInEvent, TOutEvent> {
     /// If the `Swarm` is connected to the peer, the message is delivered to the remote's
     /// protocol handler. If there is no connection to the peer, the message is ignored.
     /// To ensure delivery, the `NetworkBehaviour` must keep track of connected peers.
+    ///
+    /// If there are several connections to the peer, the message is delivered to the handler of
+    /// the oldest one. Use `SendEventToConnection` in order to pick the connection.
     SendEvent {
         /// The peer to which to send the message.
         peer_id: PeerId,
@@ -144,6 +187,32 @@ pub enum NetworkBehaviourAction<TInEvent, TOutEvent> {
         event: TInEvent,
     },
 
+    /// Instructs the `Swarm` to send a message to the handler of a specific connection to a
+    /// peer.
+    ///
+    /// If the connection has been closed in the meantime, the message is ignored.
+    SendEventToConnection {
+        /// The peer to which to send the message.
+        peer_id: PeerId,
+        /// The connection whose handler receives the message.
+        connection_id: ConnectionId,
+        /// The message to send.
+        event: TInEvent,
+    },
+
+    /// Instructs the `Swarm` to close the connections to a peer.
+    ///
+    /// If `connection_id` is `Some`, only this connection is closed and the other connections
+    /// to the peer are kept open. The connections are closed without waiting for their handlers,
+    /// and [`NetworkBehaviour::inject_disconnected`] is invoked for each of them. Has no effect
+    /// if we are not connected to the peer.
+    DisconnectPeer {
+        /// The peer to disconnect from.
+        peer_id: PeerId,
+        /// The connection to close, or `None` to close all the connections to the peer.
+        connection_id: Option<ConnectionId>,
+    },
+
     /// Informs the `Swarm` about a multi-address observed by a remote for
     /// the local node.
     ///
@@ -155,3 +224,25 @@ pub enum NetworkBehaviourAction<TInEvent, TOutEvent> {
         address: Multiaddr,
     },
 }
+
+/// Reason why a connection has been closed, as passed to
+/// [`NetworkBehaviour::inject_disconnected`].
+#[derive(Debug, Copy, Clone)]
+pub enum DisconnectReason<'a> {
+    /// The handler of the connection no longer required the connection to be kept alive.
+    KeepAliveTimeout,
+    /// The handler of the connection produced an error.
+    Handler(&'a (dyn error::Error + 'static)),
+    /// The remote closed the connection.
+    RemoteClosed,
+    /// The stream muxer of the connection produced an error.
+    Muxer(&'a io::Error),
+    /// The peer has been banned with `Swarm::ban_peer_id`.
+    Banned,
+    /// A behaviour closed the connection with [`NetworkBehaviourAction::DisconnectPeer`].
+    Behaviour,
+    /// The swarm is shutting down.
+    Closing,
+    /// The connection would have exceeded the limits of the `ResourceManager` of the swarm.
+    ResourceLimit(&'a ResourceLimitError),
+}
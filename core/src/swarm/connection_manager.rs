@@ -0,0 +1,415 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Behaviour that keeps the number of connections between a low and a high watermark.
+//!
+//! Behaviours and the application tag peers with weighted values through a
+//! [`ConnectionManagerHandle`], for example to indicate that a peer is in a routing table or in a
+//! pubsub mesh. The value of a peer is the sum of the values of its tags.
+//!
+//! When the number of connections exceeds the high watermark, the [`ConnectionManager`] closes
+//! the connections of the lowest-value peers until the low watermark is reached. Connections that
+//! have been established for less than the grace period and connections to protected peers are
+//! never closed.
+
+use crate::{
+    Multiaddr, PeerId,
+    protocols_handler::{DummyProtocolsHandler, ProtocolsHandler},
+    swarm::{ConnectedPoint, ConnectionId, DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, PollParameters},
+};
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::prelude::*;
+use log::debug;
+use parking_lot::Mutex;
+use std::{collections::VecDeque, marker::PhantomData, sync::Arc, time::Duration};
+use tokio_io::{AsyncRead, AsyncWrite};
+use void::Void;
+use wasm_timer::{Delay, Instant};
+
+/// Configuration of a [`ConnectionManager`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionManagerConfig {
+    low_watermark: usize,
+    high_watermark: usize,
+    grace_period: Duration,
+}
+
+impl ConnectionManagerConfig {
+    /// Creates a configuration with the default values.
+    pub fn new() -> Self {
+        ConnectionManagerConfig::default()
+    }
+
+    /// Sets the number of connections that trimming brings the total down to.
+    pub fn with_low_watermark(mut self, low_watermark: usize) -> Self {
+        self.low_watermark = low_watermark;
+        self
+    }
+
+    /// Sets the number of connections above which connections are trimmed.
+    pub fn with_high_watermark(mut self, high_watermark: usize) -> Self {
+        self.high_watermark = high_watermark;
+        self
+    }
+
+    /// Sets how long new connections are exempt from trimming.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+}
+
+impl Default for ConnectionManagerConfig {
+    fn default() -> Self {
+        ConnectionManagerConfig {
+            low_watermark: 160,
+            high_watermark: 192,
+            grace_period: Duration::from_secs(20),
+        }
+    }
+}
+
+/// Tags and protections of the peers, shared between a `ConnectionManager` and its handles.
+#[derive(Debug, Default)]
+struct PeerTags {
+    /// Value of each tag of each peer.
+    tags: FnvHashMap<PeerId, FnvHashMap<String, i32>>,
+    /// Tags under which each protected peer has been protected.
+    protected: FnvHashMap<PeerId, FnvHashSet<String>>,
+}
+
+impl PeerTags {
+    fn value(&self, peer_id: &PeerId) -> i64 {
+        self.tags.get(peer_id).map_or(0, |tags| tags.values().map(|v| i64::from(*v)).sum())
+    }
+
+    fn is_protected(&self, peer_id: &PeerId) -> bool {
+        self.protected.contains_key(peer_id)
+    }
+}
+
+/// Handle to tag and protect peers of a [`ConnectionManager`].
+///
+/// Cloning a handle is cheap, and all the clones act on the same manager. Behaviours can hold a
+/// handle in order to tag the peers they care about.
+#[derive(Debug, Clone)]
+pub struct ConnectionManagerHandle {
+    inner: Arc<Mutex<PeerTags>>,
+}
+
+impl ConnectionManagerHandle {
+    /// Sets the value of a tag of a peer, replacing the previous value if any.
+    ///
+    /// The tags of a peer are forgotten once we are no longer connected to it.
+    pub fn tag_peer(&self, peer_id: &PeerId, tag: impl Into<String>, value: i32) {
+        self.inner.lock().tags.entry(peer_id.clone()).or_default().insert(tag.into(), value);
+    }
+
+    /// Removes a tag of a peer.
+    pub fn untag_peer(&self, peer_id: &PeerId, tag: &str) {
+        let mut inner = self.inner.lock();
+        let now_empty = match inner.tags.get_mut(peer_id) {
+            Some(tags) => {
+                tags.remove(tag);
+                tags.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            inner.tags.remove(peer_id);
+        }
+    }
+
+    /// Returns the value of a tag of a peer, if the peer has this tag.
+    pub fn tag_value(&self, peer_id: &PeerId, tag: &str) -> Option<i32> {
+        self.inner.lock().tags.get(peer_id).and_then(|tags| tags.get(tag).cloned())
+    }
+
+    /// Returns the value of a peer, which is the sum of the values of its tags.
+    pub fn peer_value(&self, peer_id: &PeerId) -> i64 {
+        self.inner.lock().value(peer_id)
+    }
+
+    /// Prevents the connections to a peer from being trimmed, until `unprotect` is called with
+    /// the same tag.
+    ///
+    /// Protections are kept after we disconnect from the peer.
+    pub fn protect(&self, peer_id: &PeerId, tag: impl Into<String>) {
+        self.inner.lock().protected.entry(peer_id.clone()).or_default().insert(tag.into());
+    }
+
+    /// Removes a protection of a peer. Returns true if the peer is still protected under another
+    /// tag.
+    pub fn unprotect(&self, peer_id: &PeerId, tag: &str) -> bool {
+        let mut inner = self.inner.lock();
+        let still_protected = match inner.protected.get_mut(peer_id) {
+            Some(tags) => {
+                tags.remove(tag);
+                !tags.is_empty()
+            }
+            None => return false,
+        };
+        if !still_protected {
+            inner.protected.remove(peer_id);
+        }
+        still_protected
+    }
+
+    /// Returns true if the connections to a peer can't be trimmed.
+    pub fn is_protected(&self, peer_id: &PeerId) -> bool {
+        self.inner.lock().is_protected(peer_id)
+    }
+}
+
+/// Network behaviour that trims the connections to the lowest-value peers when there are too
+/// many of them. See the module-level documentation.
+pub struct ConnectionManager<TSubstream> {
+    config: ConnectionManagerConfig,
+    /// Tags and protections of the peers.
+    tags: Arc<Mutex<PeerTags>>,
+    /// Open connections, with their remote and when they have been established.
+    connections: FnvHashMap<ConnectionId, (PeerId, Instant)>,
+    /// If true, the connections must be trimmed on the next poll.
+    trim_needed: bool,
+    /// When to check the connections again after their grace period.
+    next_check: Option<Delay>,
+    /// Connections to close.
+    pending_disconnects: VecDeque<(PeerId, ConnectionId)>,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> ConnectionManager<TSubstream> {
+    /// Creates a new `ConnectionManager`.
+    pub fn new(config: ConnectionManagerConfig) -> Self {
+        ConnectionManager {
+            config,
+            tags: Arc::new(Mutex::new(PeerTags::default())),
+            connections: FnvHashMap::default(),
+            trim_needed: false,
+            next_check: None,
+            pending_disconnects: VecDeque::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns a handle to tag and protect peers.
+    pub fn handle(&self) -> ConnectionManagerHandle {
+        ConnectionManagerHandle { inner: self.tags.clone() }
+    }
+
+    /// Returns the number of open connections, not counting those being trimmed.
+    pub fn num_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Closes the connections to the lowest-value peers until the low watermark is reached.
+    ///
+    /// If not enough connections can be closed because of the grace period, schedules another
+    /// attempt for when it elapses for the oldest of the remaining ones.
+    fn trim(&mut self, now: Instant) {
+        self.trim_needed = false;
+        if self.connections.len() <= self.config.high_watermark {
+            return;
+        }
+
+        let tags = self.tags.lock();
+        let mut next_check = None;
+        let mut candidates = Vec::new();
+        for (connection_id, (peer_id, established)) in &self.connections {
+            if tags.is_protected(peer_id) {
+                continue;
+            }
+            let grace_end = *established + self.config.grace_period;
+            if grace_end > now {
+                next_check = Some(next_check.map_or(grace_end, |c: Instant| c.min(grace_end)));
+                continue;
+            }
+            candidates.push((tags.value(peer_id), *established, *connection_id));
+        }
+        drop(tags);
+
+        // Lowest values first, and the most recent connection first between peers of equal value.
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let target = self.config.low_watermark.min(self.config.high_watermark);
+        let to_close = self.connections.len().saturating_sub(target);
+        for (value, _, connection_id) in candidates.into_iter().take(to_close) {
+            if let Some((peer_id, _)) = self.connections.remove(&connection_id) {
+                debug!("Trimming connection {:?} to {:?} of value {}", connection_id, peer_id, value);
+                self.pending_disconnects.push_back((peer_id, connection_id));
+            }
+        }
+
+        self.next_check = if self.connections.len() > self.config.high_watermark {
+            next_check.map(Delay::new)
+        } else {
+            None
+        };
+    }
+
+    /// Returns the next connection to close, trimming the connections first if needed.
+    ///
+    /// Trimming may schedule a new check, which is polled before returning `NotReady` so that
+    /// the current task gets woken up once it elapses.
+    fn poll_disconnect(&mut self) -> Async<(PeerId, ConnectionId)> {
+        loop {
+            if self.trim_needed {
+                self.trim(Instant::now());
+            }
+
+            if let Some(disconnect) = self.pending_disconnects.pop_front() {
+                return Async::Ready(disconnect);
+            }
+
+            match self.next_check.as_mut().map(|delay| delay.poll()) {
+                None | Some(Ok(Async::NotReady)) => return Async::NotReady,
+                Some(Ok(Async::Ready(()))) | Some(Err(_)) => {
+                    self.next_check = None;
+                    self.trim_needed = true;
+                }
+            }
+        }
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for ConnectionManager<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite
+{
+    type ProtocolsHandler = DummyProtocolsHandler<TSubstream>;
+    type OutEvent = Void;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        DummyProtocolsHandler::default()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, _: ConnectedPoint) {
+        self.connections.insert(connection_id, (peer_id, Instant::now()));
+        if self.connections.len() > self.config.high_watermark {
+            self.trim_needed = true;
+        }
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, connection_id: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {
+        self.connections.remove(&connection_id);
+        self.pending_disconnects.retain(|(_, id)| *id != connection_id);
+        if !self.connections.values().any(|(p, _)| p == peer_id) {
+            self.tags.lock().tags.remove(peer_id);
+        }
+    }
+
+    fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, event: Void) {
+        void::unreachable(event)
+    }
+
+    fn poll(&mut self, _: &mut PollParameters<'_>) -> Async<NetworkBehaviourAction<<Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Self::OutEvent>> {
+        self.poll_disconnect().map(|(peer_id, connection_id)| NetworkBehaviourAction::DisconnectPeer {
+            peer_id,
+            connection_id: Some(connection_id),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::collection::ConnectionId;
+    use futures::future;
+    use std::io::Cursor;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn connect(manager: &mut ConnectionManager<Cursor<Vec<u8>>>, id: usize) -> PeerId {
+        let peer_id = PeerId::random();
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+        manager.inject_connected(peer_id.clone(), ConnectionId::from_raw(id), endpoint);
+        peer_id
+    }
+
+    #[test]
+    fn lowest_value_connections_are_trimmed() {
+        let config = ConnectionManagerConfig::new()
+            .with_low_watermark(2)
+            .with_high_watermark(3)
+            .with_grace_period(Duration::from_secs(0));
+        let mut manager = ConnectionManager::new(config);
+        let handle = manager.handle();
+
+        let peers = (0..4).map(|id| connect(&mut manager, id)).collect::<Vec<_>>();
+        handle.tag_peer(&peers[0], "kad", 5);
+        handle.tag_peer(&peers[1], "mesh", 10);
+        handle.tag_peer(&peers[2], "kad", 1);
+        handle.protect(&peers[3], "app");
+        assert!(manager.trim_needed);
+
+        manager.trim(Instant::now() + Duration::from_secs(1));
+        let trimmed = manager.pending_disconnects.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+        assert_eq!(trimmed, vec![peers[2].clone(), peers[0].clone()]);
+        assert_eq!(manager.num_connections(), 2);
+    }
+
+    #[test]
+    fn new_connections_are_kept_during_grace_period() {
+        let config = ConnectionManagerConfig::new()
+            .with_low_watermark(0)
+            .with_high_watermark(1)
+            .with_grace_period(Duration::from_secs(60));
+        let mut manager = ConnectionManager::new(config);
+        let handle = manager.handle();
+        let peer = connect(&mut manager, 0);
+        connect(&mut manager, 1);
+        handle.tag_peer(&peer, "kad", 1);
+
+        manager.trim(Instant::now());
+        assert!(manager.pending_disconnects.is_empty());
+        assert!(manager.next_check.is_some());
+
+        manager.inject_disconnected(&peer, ConnectionId::from_raw(0), ConnectedPoint::Dialer {
+            address: "/memory/1".parse().unwrap()
+        }, DisconnectReason::RemoteClosed);
+        assert_eq!(handle.tag_value(&peer, "kad"), None);
+    }
+
+    #[test]
+    fn connections_are_trimmed_once_grace_period_elapses() {
+        let config = ConnectionManagerConfig::new()
+            .with_low_watermark(0)
+            .with_high_watermark(1)
+            .with_grace_period(Duration::from_millis(100));
+        let mut manager = ConnectionManager::new(config);
+        connect(&mut manager, 0);
+        connect(&mut manager, 1);
+
+        let start = Instant::now();
+        let disconnect = future::poll_fn(move || -> Poll<_, ()> {
+            Ok(manager.poll_disconnect())
+        });
+        let timeout = tokio::timer::Delay::new(std::time::Instant::now() + Duration::from_secs(5));
+        let mut runtime = Runtime::new().unwrap();
+        match runtime.block_on(disconnect.select2(timeout)) {
+            Ok(future::Either::A(_)) => {}
+            _ => panic!("The connections should have been trimmed"),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Admission control of the connections of a `Swarm`.
+
+use crate::{Multiaddr, PeerId, nodes::raw_swarm::ConnectedPoint};
+use std::{error, fmt};
+
+/// Decides which connections a `Swarm` opens and accepts.
+///
+/// The gater is consulted at several points of the lifetime of a connection, so that unwanted
+/// connections are rejected as early, and therefore as cheaply, as possible. Every method returns
+/// `true` to let the connection through and defaults to doing so.
+///
+/// `intercept_dial`, `intercept_accept` and `intercept_upgraded` are called by the `Swarm` when
+/// the gater is passed to `SwarmBuilder::connection_gater`. `intercept_secured` runs in the
+/// middle of the upgrade of the connection, and is therefore called by the transport. Use
+/// `Transport::gate_secured` after the security upgrade to enable it.
+pub trait ConnectionGater: Send + Sync {
+    /// Called before dialing an address. The ID of the peer we are trying to reach is passed if
+    /// known.
+    fn intercept_dial(&self, _peer_id: Option<&PeerId>, _addr: &Multiaddr) -> bool {
+        true
+    }
+
+    /// Called when a listener receives a new connection, before any upgrade is applied to it.
+    fn intercept_accept(&self, _listen_addr: &Multiaddr, _send_back_addr: &Multiaddr) -> bool {
+        true
+    }
+
+    /// Called once the security upgrade has authenticated the remote, before the muxer is
+    /// negotiated.
+    fn intercept_secured(&self, _peer_id: &PeerId, _endpoint: &ConnectedPoint) -> bool {
+        true
+    }
+
+    /// Called once the connection is fully upgraded, before the behaviour is notified of it.
+    fn intercept_upgraded(&self, _peer_id: &PeerId, _endpoint: &ConnectedPoint) -> bool {
+        true
+    }
+}
+
+/// Error produced when a `ConnectionGater` rejects a connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectionDenied;
+
+impl fmt::Display for ConnectionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Connection denied by the connection gater")
+    }
+}
+
+impl error::Error for ConnectionDenied {}
@@ -0,0 +1,320 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Limits on the number of connections and substreams of a `Swarm`.
+
+use crate::resource::ResourceLimitError;
+use fnv::FnvHashMap;
+use std::{error, fmt};
+
+/// Limits on the number of connections a `Swarm` opens and accepts.
+///
+/// Every limit defaults to `None`, which means that there is no limit. Connections that would
+/// exceed a limit are refused with a [`ConnectionLimitError`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    max_pending_incoming: Option<u32>,
+    max_pending_outgoing: Option<u32>,
+    max_established_incoming: Option<u32>,
+    max_established_outgoing: Option<u32>,
+    max_established_per_peer: Option<u32>,
+}
+
+impl ConnectionLimits {
+    /// Sets the maximum number of incoming connections that are still being negotiated.
+    pub fn with_max_pending_incoming(mut self, limit: Option<u32>) -> Self {
+        self.max_pending_incoming = limit;
+        self
+    }
+
+    /// Sets the maximum number of dialing attempts in progress.
+    pub fn with_max_pending_outgoing(mut self, limit: Option<u32>) -> Self {
+        self.max_pending_outgoing = limit;
+        self
+    }
+
+    /// Sets the maximum number of established incoming connections.
+    pub fn with_max_established_incoming(mut self, limit: Option<u32>) -> Self {
+        self.max_established_incoming = limit;
+        self
+    }
+
+    /// Sets the maximum number of established outgoing connections.
+    pub fn with_max_established_outgoing(mut self, limit: Option<u32>) -> Self {
+        self.max_established_outgoing = limit;
+        self
+    }
+
+    /// Sets the maximum number of established connections with a single peer.
+    pub fn with_max_established_per_peer(mut self, limit: Option<u32>) -> Self {
+        self.max_established_per_peer = limit;
+        self
+    }
+
+    /// Checks whether a new incoming connection can be negotiated, given the number of incoming
+    /// connections currently being negotiated.
+    pub(crate) fn check_pending_incoming(&self, current: impl FnOnce() -> usize) -> Result<(), ConnectionLimitError> {
+        check(self.max_pending_incoming, current, |limit| ConnectionLimitError::PendingIncoming { limit })
+    }
+
+    /// Checks whether a new dialing attempt can start, given the number of attempts in progress.
+    pub(crate) fn check_pending_outgoing(&self, current: impl FnOnce() -> usize) -> Result<(), ConnectionLimitError> {
+        check(self.max_pending_outgoing, current, |limit| ConnectionLimitError::PendingOutgoing { limit })
+    }
+
+    /// Checks whether a new incoming connection can be established, given the number of
+    /// established incoming connections, not counting the new one.
+    pub(crate) fn check_established_incoming(&self, current: impl FnOnce() -> usize) -> Result<(), ConnectionLimitError> {
+        check(self.max_established_incoming, current, |limit| ConnectionLimitError::EstablishedIncoming { limit })
+    }
+
+    /// Checks whether a new outgoing connection can be established, given the number of
+    /// established outgoing connections, not counting the new one.
+    pub(crate) fn check_established_outgoing(&self, current: impl FnOnce() -> usize) -> Result<(), ConnectionLimitError> {
+        check(self.max_established_outgoing, current, |limit| ConnectionLimitError::EstablishedOutgoing { limit })
+    }
+
+    /// Checks whether a new connection with a peer can be established, given the number of
+    /// connections established with this peer, not counting the new one.
+    pub(crate) fn check_established_per_peer(&self, current: impl FnOnce() -> usize) -> Result<(), ConnectionLimitError> {
+        check(self.max_established_per_peer, current, |limit| ConnectionLimitError::EstablishedPerPeer { limit })
+    }
+}
+
+/// Checks `limit` against the current number of connections or substreams, which is only
+/// computed if there is a limit.
+fn check<E>(limit: Option<u32>, current: impl FnOnce() -> usize, err: impl FnOnce(u32) -> E)
+    -> Result<(), E>
+{
+    match limit {
+        Some(limit) if current() >= limit as usize => Err(err(limit)),
+        _ => Ok(()),
+    }
+}
+
+/// A connection has been refused because it would have exceeded one of the
+/// [`ConnectionLimits`] of the swarm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionLimitError {
+    /// Too many incoming connections are being negotiated.
+    PendingIncoming { limit: u32 },
+    /// Too many dialing attempts are in progress.
+    PendingOutgoing { limit: u32 },
+    /// Too many incoming connections are established.
+    EstablishedIncoming { limit: u32 },
+    /// Too many outgoing connections are established.
+    EstablishedOutgoing { limit: u32 },
+    /// Too many connections are established with this peer.
+    EstablishedPerPeer { limit: u32 },
+}
+
+impl fmt::Display for ConnectionLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionLimitError::PendingIncoming { limit } =>
+                write!(f, "Reached the limit of {} pending incoming connections", limit),
+            ConnectionLimitError::PendingOutgoing { limit } =>
+                write!(f, "Reached the limit of {} pending outgoing connections", limit),
+            ConnectionLimitError::EstablishedIncoming { limit } =>
+                write!(f, "Reached the limit of {} established incoming connections", limit),
+            ConnectionLimitError::EstablishedOutgoing { limit } =>
+                write!(f, "Reached the limit of {} established outgoing connections", limit),
+            ConnectionLimitError::EstablishedPerPeer { limit } =>
+                write!(f, "Reached the limit of {} established connections with the peer", limit),
+        }
+    }
+}
+
+impl error::Error for ConnectionLimitError {}
+
+/// Limits on the number of inbound substreams that remotes can open on each connection of a
+/// `Swarm`.
+///
+/// A substream is negotiating from the moment the remote opens it until the protocol handshake
+/// is over, and active from the moment a protocol has been agreed on until it is closed. The
+/// per-protocol limits only apply once a protocol has been agreed on.
+///
+/// Every limit defaults to `None`, which means that there is no limit. Substreams that would
+/// exceed a limit are reset and reported with a [`SubstreamLimitError`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InboundSubstreamLimits {
+    max_negotiating: Option<u32>,
+    max_active: Option<u32>,
+    max_negotiating_per_protocol: Option<u32>,
+    max_active_per_protocol: Option<u32>,
+    /// Limits of individual protocols, overriding `max_negotiating_per_protocol`.
+    protocol_max_negotiating: FnvHashMap<Vec<u8>, Option<u32>>,
+    /// Limits of individual protocols, overriding `max_active_per_protocol`.
+    protocol_max_active: FnvHashMap<Vec<u8>, Option<u32>>,
+}
+
+impl InboundSubstreamLimits {
+    /// Sets the maximum number of inbound substreams of a connection that are being negotiated.
+    pub fn with_max_negotiating(mut self, limit: Option<u32>) -> Self {
+        self.max_negotiating = limit;
+        self
+    }
+
+    /// Sets the maximum number of active inbound substreams of a connection.
+    pub fn with_max_active(mut self, limit: Option<u32>) -> Self {
+        self.max_active = limit;
+        self
+    }
+
+    /// Sets the maximum number of inbound substreams of a connection that are being negotiated
+    /// for any given protocol.
+    pub fn with_max_negotiating_per_protocol(mut self, limit: Option<u32>) -> Self {
+        self.max_negotiating_per_protocol = limit;
+        self
+    }
+
+    /// Sets the maximum number of active inbound substreams of a connection for any given
+    /// protocol.
+    pub fn with_max_active_per_protocol(mut self, limit: Option<u32>) -> Self {
+        self.max_active_per_protocol = limit;
+        self
+    }
+
+    /// Sets the maximum number of inbound substreams of a connection that are being negotiated
+    /// for the given protocol, instead of the one set with `with_max_negotiating_per_protocol`.
+    pub fn with_protocol_max_negotiating(mut self, protocol: impl Into<Vec<u8>>, limit: Option<u32>) -> Self {
+        self.protocol_max_negotiating.insert(protocol.into(), limit);
+        self
+    }
+
+    /// Sets the maximum number of active inbound substreams of a connection for the given
+    /// protocol, instead of the one set with `with_max_active_per_protocol`.
+    pub fn with_protocol_max_active(mut self, protocol: impl Into<Vec<u8>>, limit: Option<u32>) -> Self {
+        self.protocol_max_active.insert(protocol.into(), limit);
+        self
+    }
+
+    /// Checks whether a new inbound substream can be negotiated, given the number of inbound
+    /// substreams currently being negotiated.
+    pub(crate) fn check_negotiating(&self, current: impl FnOnce() -> usize) -> Result<(), SubstreamLimitError> {
+        check(self.max_negotiating, current, |limit| SubstreamLimitError::Negotiating { limit })
+    }
+
+    /// Checks whether a new inbound substream can become active, given the number of active
+    /// inbound substreams.
+    pub(crate) fn check_active(&self, current: impl FnOnce() -> usize) -> Result<(), SubstreamLimitError> {
+        check(self.max_active, current, |limit| SubstreamLimitError::Active { limit })
+    }
+
+    /// Checks whether a new inbound substream can be negotiated for `protocol`, given the number
+    /// of inbound substreams currently being negotiated for it.
+    pub(crate) fn check_protocol_negotiating(&self, protocol: &[u8], current: impl FnOnce() -> usize)
+        -> Result<(), SubstreamLimitError>
+    {
+        let limit = self.protocol_max_negotiating.get(protocol)
+            .cloned()
+            .unwrap_or(self.max_negotiating_per_protocol);
+        check(limit, current, |limit| SubstreamLimitError::ProtocolNegotiating { protocol: protocol.to_vec(), limit })
+    }
+
+    /// Checks whether a new inbound substream can become active for `protocol`, given the number
+    /// of active inbound substreams for it.
+    pub(crate) fn check_protocol_active(&self, protocol: &[u8], current: impl FnOnce() -> usize)
+        -> Result<(), SubstreamLimitError>
+    {
+        let limit = self.protocol_max_active.get(protocol)
+            .cloned()
+            .unwrap_or(self.max_active_per_protocol);
+        check(limit, current, |limit| SubstreamLimitError::ProtocolActive { protocol: protocol.to_vec(), limit })
+    }
+}
+
+/// An inbound substream has been reset because it would have exceeded one of the
+/// [`InboundSubstreamLimits`] of the swarm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubstreamLimitError {
+    /// Too many inbound substreams are being negotiated on the connection.
+    Negotiating { limit: u32 },
+    /// Too many inbound substreams are active on the connection.
+    Active { limit: u32 },
+    /// Too many inbound substreams are being negotiated for this protocol on the connection.
+    ProtocolNegotiating { protocol: Vec<u8>, limit: u32 },
+    /// Too many inbound substreams are active for this protocol on the connection.
+    ProtocolActive { protocol: Vec<u8>, limit: u32 },
+    /// The substream would have exceeded the limits of the `ResourceManager` of the swarm.
+    Resource(ResourceLimitError),
+}
+
+impl fmt::Display for SubstreamLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubstreamLimitError::Negotiating { limit } =>
+                write!(f, "Reached the limit of {} negotiating inbound substreams", limit),
+            SubstreamLimitError::Active { limit } =>
+                write!(f, "Reached the limit of {} active inbound substreams", limit),
+            SubstreamLimitError::ProtocolNegotiating { protocol, limit } =>
+                write!(f, "Reached the limit of {} negotiating inbound substreams for {}",
+                    limit, String::from_utf8_lossy(protocol)),
+            SubstreamLimitError::ProtocolActive { protocol, limit } =>
+                write!(f, "Reached the limit of {} active inbound substreams for {}",
+                    limit, String::from_utf8_lossy(protocol)),
+            SubstreamLimitError::Resource(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for SubstreamLimitError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SubstreamLimitError::Resource(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionLimitError, ConnectionLimits, InboundSubstreamLimits, SubstreamLimitError};
+
+    #[test]
+    fn limits_are_checked() {
+        let limits = ConnectionLimits::default()
+            .with_max_pending_outgoing(Some(2))
+            .with_max_established_per_peer(Some(1));
+
+        assert!(limits.check_pending_outgoing(|| 1).is_ok());
+        assert_eq!(limits.check_pending_outgoing(|| 2), Err(ConnectionLimitError::PendingOutgoing { limit: 2 }));
+        assert_eq!(limits.check_established_per_peer(|| 1), Err(ConnectionLimitError::EstablishedPerPeer { limit: 1 }));
+        assert!(limits.check_established_incoming(|| panic!("no limit to check")).is_ok());
+    }
+
+    #[test]
+    fn protocol_limits_override_the_default() {
+        let limits = InboundSubstreamLimits::default()
+            .with_max_active_per_protocol(Some(2))
+            .with_protocol_max_active(&b"/kad/1"[..], Some(8))
+            .with_protocol_max_negotiating(&b"/id/1"[..], Some(1));
+
+        assert!(limits.check_protocol_active(b"/kad/1", || 2).is_ok());
+        assert_eq!(
+            limits.check_protocol_active(b"/ping/1", || 2),
+            Err(SubstreamLimitError::ProtocolActive { protocol: b"/ping/1".to_vec(), limit: 2 })
+        );
+        assert!(limits.check_protocol_active(b"/id/1", || 2).is_err());
+        assert!(limits.check_protocol_negotiating(b"/id/1", || 1).is_err());
+        assert!(limits.check_protocol_negotiating(b"/kad/1", || 100).is_ok());
+        assert!(limits.check_negotiating(|| panic!("no limit to check")).is_ok());
+    }
+}
@@ -0,0 +1,542 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Information about the peers of the network, shared between all the behaviours of a `Swarm`.
+//!
+//! The [`PeerStore`] is owned by the `Swarm`. Behaviours can read from and write to it through
+//! the [`PollParameters`](crate::swarm::PollParameters) passed to their `poll` method, and the
+//! `Swarm` consults it in addition to `NetworkBehaviour::addresses_of_peer` when dialing a peer.
+//!
+//! By default the content of the store is lost when the process stops. A [`PeerStoreBackend`],
+//! such as the [`FilePeerStoreBackend`], can be attached to the store with
+//! [`PeerStore::with_backend`] in order to load the store from persistent storage and to
+//! periodically save it back.
+
+#[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
+mod file;
+
+#[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
+pub use self::file::FilePeerStoreBackend;
+
+use crate::{Multiaddr, PeerId, PublicKey};
+use fnv::FnvHashMap;
+use futures::prelude::*;
+use log::warn;
+use smallvec::SmallVec;
+use std::{fmt, io, time::Duration};
+use wasm_timer::{Instant, Interval};
+
+/// Persistent storage for the content of a [`PeerStore`].
+pub trait PeerStoreBackend: Send {
+    /// Loads the content of the store that was last saved.
+    fn load(&mut self) -> Result<PeerStore, io::Error>;
+
+    /// Saves the content of the store, replacing what was saved before.
+    fn save(&mut self, store: &PeerStore) -> Result<(), io::Error>;
+}
+
+/// Where an address stored in the [`PeerStore`] comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddressSource {
+    /// We successfully connected to the peer using this address.
+    Connection,
+    /// The peer itself reported that it is listening on this address, for example through the
+    /// identify protocol.
+    SelfReported,
+    /// The address has been found by a discovery mechanism, such as mDNS or Kademlia.
+    Discovery,
+    /// The address has been added by the user.
+    Manual,
+}
+
+impl AddressSource {
+    /// Returns how trustworthy the source is. When an address is reported by several sources,
+    /// the one with the highest priority is kept.
+    fn priority(self) -> u8 {
+        match self {
+            AddressSource::Discovery => 0,
+            AddressSource::SelfReported => 1,
+            AddressSource::Connection => 2,
+            AddressSource::Manual => 3,
+        }
+    }
+}
+
+/// An address of a peer, with its provenance and expiration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressRecord {
+    /// The address itself.
+    pub addr: Multiaddr,
+    /// Where the address comes from.
+    pub source: AddressSource,
+    /// When the address stops being valid. `None` if the address never expires.
+    pub expires: Option<Instant>,
+}
+
+impl AddressRecord {
+    /// Returns true if the address is expired at the given instant.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires.map(|e| e <= now).unwrap_or(false)
+    }
+}
+
+/// Everything we know about a peer.
+#[derive(Debug, Clone, Default)]
+pub struct PeerRecord {
+    addresses: SmallVec<[AddressRecord; 4]>,
+    public_key: Option<PublicKey>,
+    protocols: Vec<String>,
+    agent_version: Option<String>,
+    latency: Option<Duration>,
+}
+
+impl PeerRecord {
+    /// Returns the addresses of the peer, including the expired ones that haven't been removed
+    /// yet.
+    pub fn address_records(&self) -> impl ExactSizeIterator<Item = &AddressRecord> {
+        self.addresses.iter()
+    }
+
+    /// Returns the public key of the peer, if known.
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+
+    /// Returns the protocols the peer supports, as reported on the wire.
+    pub fn protocols(&self) -> impl ExactSizeIterator<Item = &str> {
+        self.protocols.iter().map(|p| p.as_str())
+    }
+
+    /// Returns the agent version of the peer, if known.
+    pub fn agent_version(&self) -> Option<&str> {
+        self.agent_version.as_ref().map(|v| v.as_str())
+    }
+
+    /// Returns the last measured round-trip time to the peer, if known.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Returns true if we don't know anything about the peer.
+    fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.public_key.is_none() && self.protocols.is_empty()
+            && self.agent_version.is_none() && self.latency.is_none()
+    }
+}
+
+/// How often the expired addresses are removed from a [`PeerStore`] owned by a `Swarm`.
+const REMOVE_EXPIRED_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stores information about the peers of the network.
+#[derive(Default)]
+pub struct PeerStore {
+    peers: FnvHashMap<PeerId, PeerRecord>,
+    /// Where the content of the store is saved, if anywhere.
+    backend: Option<Persistence>,
+    /// Fires when the expired addresses should be removed. Created on the first call to `poll`.
+    remove_expired_interval: Option<Interval>,
+}
+
+/// How a `PeerStore` is persisted.
+struct Persistence {
+    backend: Box<dyn PeerStoreBackend>,
+    /// Fires when the store should be saved if it has been modified.
+    flush_interval: Interval,
+    /// True if the store has been modified since it was last saved.
+    dirty: bool,
+}
+
+impl PeerStore {
+    /// Creates a new empty `PeerStore`.
+    pub fn new() -> Self {
+        PeerStore::default()
+    }
+
+    /// Loads a `PeerStore` from the given backend, and saves it back every `flush_interval` if
+    /// it has been modified in the meantime.
+    ///
+    /// The store is also saved when it is dropped.
+    pub fn with_backend<B>(mut backend: B, flush_interval: Duration) -> Result<Self, io::Error>
+    where
+        B: PeerStoreBackend + 'static
+    {
+        let mut store = backend.load()?;
+        store.remove_expired();
+        store.backend = Some(Persistence {
+            backend: Box::new(backend),
+            flush_interval: Interval::new_interval(flush_interval),
+            dirty: false,
+        });
+        Ok(store)
+    }
+
+    /// Saves the store to its backend, if it has one and if it has been modified since it was
+    /// last saved.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        let mut persistence = match self.backend.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let result = if persistence.dirty {
+            persistence.backend.save(self)
+        } else {
+            Ok(())
+        };
+        if result.is_ok() {
+            persistence.dirty = false;
+        }
+
+        self.backend = Some(persistence);
+        result
+    }
+
+    /// Removes the expired addresses and saves the store when their respective intervals have
+    /// elapsed. Must be called regularly, which the `Swarm` does.
+    pub(crate) fn poll(&mut self) {
+        let interval = self.remove_expired_interval
+            .get_or_insert_with(|| Interval::new_interval(REMOVE_EXPIRED_INTERVAL));
+        let remove_expired = match interval.poll() {
+            Ok(Async::Ready(_)) => true,
+            Ok(Async::NotReady) => false,
+            Err(err) => {
+                warn!("Peer store expiration timer errored: {:?}", err);
+                false
+            },
+        };
+        if remove_expired {
+            self.remove_expired();
+        }
+
+        let ready = match self.backend {
+            Some(ref mut p) => match p.flush_interval.poll() {
+                Ok(Async::Ready(_)) => true,
+                Ok(Async::NotReady) => false,
+                Err(err) => {
+                    warn!("Peer store flush timer errored: {:?}", err);
+                    false
+                },
+            },
+            None => false,
+        };
+
+        if ready {
+            if let Err(err) = self.flush() {
+                warn!("Failed to save the peer store: {:?}", err);
+            }
+        }
+    }
+
+    /// Marks the store as modified since it was last saved.
+    fn mark_dirty(&mut self) {
+        if let Some(ref mut p) = self.backend {
+            p.dirty = true;
+        }
+    }
+
+    /// Adds an address for the given peer.
+    ///
+    /// If `ttl` is `None`, the address never expires. If the address is already known, it keeps
+    /// the source with the highest priority, `Manual` being the highest followed by `Connection`,
+    /// `SelfReported` and `Discovery`, and it expires at the latest of the two expirations.
+    pub fn add_address(&mut self, peer_id: &PeerId, addr: Multiaddr, source: AddressSource, ttl: Option<Duration>) {
+        let now = Instant::now();
+        let expires = ttl.map(|ttl| now + ttl);
+        self.mark_dirty();
+        let record = self.peers.entry(peer_id.clone()).or_default();
+        record.addresses.retain(|a| !a.is_expired(now));
+
+        if let Some(existing) = record.addresses.iter_mut().find(|a| a.addr == addr) {
+            if source.priority() > existing.source.priority() {
+                existing.source = source;
+            }
+            existing.expires = match (existing.expires, expires) {
+                (Some(a), Some(b)) => Some(std::cmp::max(a, b)),
+                _ => None,
+            };
+            return;
+        }
+
+        record.addresses.push(AddressRecord { addr, source, expires });
+    }
+
+    /// Records that we are connected to a peer through an address we dialed.
+    ///
+    /// Addresses added by the user and addresses that never expire are left untouched. Other
+    /// addresses become permanent connection addresses until `disconnected_from` is called.
+    pub(crate) fn connected_to(&mut self, peer_id: &PeerId, addr: &Multiaddr) {
+        let keep = self.peers.get(peer_id)
+            .and_then(|r| r.addresses.iter().find(|a| a.addr == *addr))
+            .map(|a| a.source == AddressSource::Manual || a.expires.is_none())
+            .unwrap_or(false);
+        if !keep {
+            self.add_address(peer_id, addr.clone(), AddressSource::Connection, None);
+        }
+    }
+
+    /// Records that a connection to a peer through an address we dialed has closed.
+    ///
+    /// Only addresses whose source is `Connection` get the given time-to-live, including those
+    /// that have been reported by a lower-priority source while we were connected.
+    pub(crate) fn disconnected_from(&mut self, peer_id: &PeerId, addr: &Multiaddr, ttl: Duration) {
+        let is_connection = self.peers.get(peer_id)
+            .and_then(|r| r.addresses.iter().find(|a| a.addr == *addr))
+            .map(|a| a.source == AddressSource::Connection)
+            .unwrap_or(false);
+        if is_connection {
+            self.set_address_ttl(peer_id, addr, Some(ttl));
+        }
+    }
+
+    /// Changes the time-to-live of an address of a peer, regardless of its current expiration.
+    ///
+    /// Returns false if the address is unknown.
+    pub fn set_address_ttl(&mut self, peer_id: &PeerId, addr: &Multiaddr, ttl: Option<Duration>) -> bool {
+        let now = Instant::now();
+        self.mark_dirty();
+        let existing = self.peers.get_mut(peer_id)
+            .and_then(|r| r.addresses.iter_mut().find(|a| a.addr == *addr));
+        match existing {
+            Some(existing) => {
+                existing.expires = ttl.map(|ttl| now + ttl);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes an address of a peer. Returns true if the address was known.
+    pub fn remove_address(&mut self, peer_id: &PeerId, addr: &Multiaddr) -> bool {
+        self.mark_dirty();
+        let removed = match self.peers.get_mut(peer_id) {
+            Some(record) => {
+                let len = record.addresses.len();
+                record.addresses.retain(|a| a.addr != *addr);
+                record.addresses.len() != len
+            },
+            None => false,
+        };
+        self.remove_if_empty(peer_id);
+        removed
+    }
+
+    /// Returns the addresses of a peer that haven't expired.
+    pub fn addresses<'a>(&'a self, peer_id: &PeerId) -> impl Iterator<Item = &'a Multiaddr> + 'a {
+        let now = Instant::now();
+        self.peers.get(peer_id)
+            .into_iter()
+            .flat_map(|r| r.addresses.iter())
+            .filter(move |a| !a.is_expired(now))
+            .map(|a| &a.addr)
+    }
+
+    /// Sets the public key of a peer.
+    pub fn set_public_key(&mut self, peer_id: &PeerId, key: PublicKey) {
+        self.mark_dirty();
+        self.peers.entry(peer_id.clone()).or_default().public_key = Some(key);
+    }
+
+    /// Returns the public key of a peer, if known.
+    pub fn public_key(&self, peer_id: &PeerId) -> Option<&PublicKey> {
+        self.peers.get(peer_id).and_then(|r| r.public_key())
+    }
+
+    /// Replaces the list of protocols a peer supports.
+    pub fn set_protocols(&mut self, peer_id: &PeerId, protocols: impl IntoIterator<Item = String>) {
+        self.mark_dirty();
+        self.peers.entry(peer_id.clone()).or_default().protocols = protocols.into_iter().collect();
+    }
+
+    /// Returns true if the peer is known to support the given protocol.
+    pub fn supports_protocol(&self, peer_id: &PeerId, protocol: &str) -> bool {
+        self.peers.get(peer_id)
+            .map(|r| r.protocols.iter().any(|p| p == protocol))
+            .unwrap_or(false)
+    }
+
+    /// Sets the agent version of a peer.
+    pub fn set_agent_version(&mut self, peer_id: &PeerId, agent_version: String) {
+        self.mark_dirty();
+        self.peers.entry(peer_id.clone()).or_default().agent_version = Some(agent_version);
+    }
+
+    /// Records the latest round-trip time measured to a peer.
+    pub fn set_latency(&mut self, peer_id: &PeerId, latency: Duration) {
+        self.peers.entry(peer_id.clone()).or_default().latency = Some(latency);
+    }
+
+    /// Returns everything we know about a peer.
+    pub fn peer(&self, peer_id: &PeerId) -> Option<&PeerRecord> {
+        self.peers.get(peer_id)
+    }
+
+    /// Returns the list of peers we know something about.
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &PeerRecord)> {
+        self.peers.iter()
+    }
+
+    /// Forgets everything about a peer.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> Option<PeerRecord> {
+        self.mark_dirty();
+        self.peers.remove(peer_id)
+    }
+
+    /// Removes all the expired addresses, and the peers we no longer know anything about.
+    pub fn remove_expired(&mut self) {
+        let now = Instant::now();
+        let mut removed = false;
+        for record in self.peers.values_mut() {
+            let len = record.addresses.len();
+            record.addresses.retain(|a| !a.is_expired(now));
+            removed |= record.addresses.len() != len;
+        }
+        self.peers.retain(|_, r| !r.is_empty());
+        if removed {
+            self.mark_dirty();
+        }
+    }
+
+    fn remove_if_empty(&mut self, peer_id: &PeerId) {
+        if self.peers.get(peer_id).map(|r| r.is_empty()).unwrap_or(false) {
+            self.peers.remove(peer_id);
+        }
+    }
+}
+
+impl fmt::Debug for PeerStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerStore")
+            .field("peers", &self.peers)
+            .field("persistent", &self.backend.is_some())
+            .finish()
+    }
+}
+
+impl Drop for PeerStore {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Failed to save the peer store: {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PeerId;
+    use super::{AddressSource, PeerStore};
+    use std::time::Duration;
+
+    #[test]
+    fn addresses_expire() {
+        let peer = PeerId::random();
+        let mut store = PeerStore::new();
+        let permanent: crate::Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let expiring: crate::Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+
+        store.add_address(&peer, permanent.clone(), AddressSource::Manual, None);
+        store.add_address(&peer, expiring.clone(), AddressSource::Discovery, Some(Duration::from_secs(0)));
+        assert_eq!(store.addresses(&peer).collect::<Vec<_>>(), vec![&permanent]);
+
+        store.remove_expired();
+        assert_eq!(store.peer(&peer).unwrap().address_records().count(), 1);
+        assert!(store.remove_address(&peer, &permanent));
+        assert!(store.peer(&peer).is_none());
+    }
+
+    #[test]
+    fn re_adding_address_extends_expiration() {
+        let peer = PeerId::random();
+        let mut store = PeerStore::new();
+        let addr: crate::Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+
+        store.add_address(&peer, addr.clone(), AddressSource::Discovery, Some(Duration::from_secs(60)));
+        store.add_address(&peer, addr.clone(), AddressSource::Connection, Some(Duration::from_secs(0)));
+        let records = store.peer(&peer).unwrap().address_records().collect::<Vec<_>>();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].source, AddressSource::Connection);
+        assert_eq!(store.addresses(&peer).count(), 1);
+
+        assert!(store.set_address_ttl(&peer, &addr, Some(Duration::from_secs(0))));
+        assert_eq!(store.addresses(&peer).count(), 0);
+    }
+
+    #[test]
+    fn connections_dont_override_manual_addresses() {
+        let peer = PeerId::random();
+        let mut store = PeerStore::new();
+        let manual: crate::Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let discovered: crate::Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+
+        store.add_address(&peer, manual.clone(), AddressSource::Manual, Some(Duration::from_secs(600)));
+        store.add_address(&peer, discovered.clone(), AddressSource::Discovery, Some(Duration::from_secs(600)));
+        let before = store.peer(&peer).unwrap().address_records().cloned().collect::<Vec<_>>();
+
+        store.connected_to(&peer, &manual);
+        store.connected_to(&peer, &discovered);
+        store.disconnected_from(&peer, &manual, Duration::from_secs(0));
+        store.disconnected_from(&peer, &discovered, Duration::from_secs(0));
+
+        let records = store.peer(&peer).unwrap().address_records().collect::<Vec<_>>();
+        assert_eq!(records[0], &before[0]);
+        assert_eq!(records[1].source, AddressSource::Connection);
+        assert_eq!(store.addresses(&peer).collect::<Vec<_>>(), vec![&manual]);
+    }
+
+    #[test]
+    fn reported_addresses_keep_higher_priority_source() {
+        let peer = PeerId::random();
+        let mut store = PeerStore::new();
+        let manual: crate::Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let connected: crate::Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+
+        store.add_address(&peer, manual.clone(), AddressSource::Manual, None);
+        store.add_address(&peer, manual.clone(), AddressSource::SelfReported, Some(Duration::from_secs(60)));
+        store.add_address(&peer, manual.clone(), AddressSource::Discovery, Some(Duration::from_secs(60)));
+
+        store.connected_to(&peer, &connected);
+        store.add_address(&peer, connected.clone(), AddressSource::SelfReported, Some(Duration::from_secs(60)));
+
+        let records = store.peer(&peer).unwrap().address_records().collect::<Vec<_>>();
+        assert_eq!(records[0].source, AddressSource::Manual);
+        assert_eq!(records[0].expires, None);
+        assert_eq!(records[1].source, AddressSource::Connection);
+        assert_eq!(records[1].expires, None);
+
+        // Once the connection drops, the address is no longer permanent.
+        store.disconnected_from(&peer, &connected, Duration::from_secs(0));
+        assert_eq!(store.addresses(&peer).collect::<Vec<_>>(), vec![&manual]);
+    }
+
+    #[test]
+    fn peer_metadata() {
+        let peer = PeerId::random();
+        let mut store = PeerStore::new();
+        store.set_protocols(&peer, vec!["/ipfs/ping/1.0.0".to_owned()]);
+        store.set_agent_version(&peer, "rust-libp2p".to_owned());
+        store.set_latency(&peer, Duration::from_millis(5));
+
+        assert!(store.supports_protocol(&peer, "/ipfs/ping/1.0.0"));
+        assert!(!store.supports_protocol(&peer, "/ipfs/id/1.0.0"));
+        let record = store.peer(&peer).unwrap();
+        assert_eq!(record.agent_version(), Some("rust-libp2p"));
+        assert_eq!(record.latency(), Some(Duration::from_millis(5)));
+        assert!(record.public_key().is_none());
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Peer store backend that saves the store in a local file.
+//!
+//! The file is a text file made of one entry per line. Each peer starts with a `peer` line and is
+//! followed by the information we have about it:
+//!
+//! ```text
+//! peer QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N
+//! key 4XTTMEKvE5ezyZ8RPuDnNK6AwCprHX6zGrYkFKwFjF9kG89HL
+//! agent rust-libp2p/1.0.0
+//! protocol /ipfs/ping/1.0.0
+//! addr discovery 1559692800 /ip4/192.168.1.2/tcp/4001
+//! addr manual never /ip4/1.2.3.4/tcp/4001
+//! ```
+//!
+//! The expiration of addresses is written as a number of seconds since the UNIX epoch, so that
+//! TTLs are honoured across restarts.
+
+use crate::{Multiaddr, PeerId, PublicKey};
+use super::{AddressSource, PeerStore, PeerStoreBackend};
+use std::{fs, io, io::BufRead, io::Write, path::Path, path::PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wasm_timer::Instant;
+
+/// First line of the file, identifying its format.
+const HEADER: &str = "# libp2p peer store v1";
+
+/// [`PeerStoreBackend`] that saves the store in a local file.
+///
+/// Writes are atomic: the content is first written to a temporary file next to the target,
+/// which is then renamed. On Unix, the directory is synced afterwards so that the rename itself
+/// survives a crash.
+#[derive(Debug, Clone)]
+pub struct FilePeerStoreBackend {
+    path: PathBuf,
+}
+
+impl FilePeerStoreBackend {
+    /// Creates a backend that saves the store in the file at `path`. The file doesn't need to
+    /// exist.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FilePeerStoreBackend { path: path.into() }
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Returns the path of the temporary file written before being renamed.
+    fn tmp_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        name.push(".tmp");
+        self.path.with_file_name(name)
+    }
+}
+
+impl PeerStoreBackend for FilePeerStoreBackend {
+    fn load(&mut self) -> Result<PeerStore, io::Error> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(PeerStore::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut store = PeerStore::new();
+        let mut current: Option<PeerId> = None;
+        // Protocols of `current`, set on the store at once when we reach the next peer.
+        let mut protocols = Vec::new();
+        for (num, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+                format!("invalid peer store entry at line {}", num + 1));
+            let mut parts = line.splitn(2, ' ');
+            let (kind, value) = match (parts.next(), parts.next()) {
+                (Some(kind), Some(value)) => (kind, value),
+                _ => return Err(invalid()),
+            };
+
+            if kind == "peer" {
+                if let Some(peer_id) = current.take() {
+                    set_protocols(&mut store, &peer_id, &mut protocols);
+                }
+                current = Some(value.parse().map_err(|_| invalid())?);
+                continue;
+            }
+
+            let peer_id = current.as_ref().ok_or_else(invalid)?;
+            match kind {
+                "key" => {
+                    let bytes = bs58::decode(value).into_vec().map_err(|_| invalid())?;
+                    let key = PublicKey::from_protobuf_encoding(&bytes).map_err(|_| invalid())?;
+                    store.set_public_key(peer_id, key);
+                },
+                "agent" => store.set_agent_version(peer_id, value.to_owned()),
+                "protocol" => protocols.push(value.to_owned()),
+                "addr" => {
+                    let mut parts = value.splitn(3, ' ');
+                    let (source, expires, addr) = match (parts.next(), parts.next(), parts.next()) {
+                        (Some(s), Some(e), Some(a)) => (s, e, a),
+                        _ => return Err(invalid()),
+                    };
+                    let source = parse_source(source).ok_or_else(invalid)?;
+                    let addr: Multiaddr = addr.parse().map_err(|_| invalid())?;
+                    let ttl = if expires == "never" {
+                        None
+                    } else {
+                        let secs: u64 = expires.parse().map_err(|_| invalid())?;
+                        let expires = UNIX_EPOCH + Duration::from_secs(secs);
+                        match expires.duration_since(SystemTime::now()) {
+                            Ok(ttl) => Some(ttl),
+                            // The address expired while we were not running.
+                            Err(_) => continue,
+                        }
+                    };
+                    store.add_address(peer_id, addr, source, ttl);
+                },
+                // Ignore entries written by future versions.
+                _ => {},
+            }
+        }
+
+        if let Some(peer_id) = current {
+            set_protocols(&mut store, &peer_id, &mut protocols);
+        }
+        Ok(store)
+    }
+
+    fn save(&mut self, store: &PeerStore) -> Result<(), io::Error> {
+        let tmp_path = self.tmp_path();
+        let mut file = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        writeln!(file, "{}", HEADER)?;
+
+        let now = Instant::now();
+        let system_now = SystemTime::now();
+        for (peer_id, record) in store.peers() {
+            writeln!(file, "peer {}", peer_id.to_base58())?;
+            if let Some(key) = record.public_key() {
+                let encoded = bs58::encode(key.clone().into_protobuf_encoding()).into_string();
+                writeln!(file, "key {}", encoded)?;
+            }
+            if let Some(agent) = record.agent_version().filter(|a| is_single_line(a)) {
+                writeln!(file, "agent {}", agent)?;
+            }
+            for protocol in record.protocols().filter(|p| is_single_line(p)) {
+                writeln!(file, "protocol {}", protocol)?;
+            }
+            for addr in record.address_records().filter(|a| !a.is_expired(now)) {
+                let expires = match addr.expires {
+                    Some(expires) => {
+                        let at = system_now + (expires - now);
+                        let secs = at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                        secs.to_string()
+                    },
+                    None => "never".to_owned(),
+                };
+                writeln!(file, "addr {} {} {}", source_name(addr.source), expires, addr.addr)?;
+            }
+        }
+
+        let file = file.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        sync_parent_dir(&self.path)
+    }
+}
+
+/// Adds the `protocols` read from the file to those of the peer, and empties `protocols`.
+fn set_protocols(store: &mut PeerStore, peer_id: &PeerId, protocols: &mut Vec<String>) {
+    if protocols.is_empty() {
+        return;
+    }
+    // A peer normally only has one section in the file, in which case it has no protocols yet.
+    let mut all = store.peer(peer_id).map(|r| r.protocols.clone()).unwrap_or_default();
+    all.append(protocols);
+    store.set_protocols(peer_id, all);
+}
+
+/// Syncs the directory containing `path`, which makes a rename to `path` durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened, and therefore synced, on this platform.
+#[cfg(not(unix))]
+fn sync_parent_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns true if the value can be written on a line of the file.
+fn is_single_line(value: &str) -> bool {
+    !value.contains('\n') && !value.contains('\r')
+}
+
+fn source_name(source: AddressSource) -> &'static str {
+    match source {
+        AddressSource::Connection => "connection",
+        AddressSource::SelfReported => "self-reported",
+        AddressSource::Discovery => "discovery",
+        AddressSource::Manual => "manual",
+    }
+}
+
+fn parse_source(name: &str) -> Option<AddressSource> {
+    match name {
+        "connection" => Some(AddressSource::Connection),
+        "self-reported" => Some(AddressSource::SelfReported),
+        "discovery" => Some(AddressSource::Discovery),
+        "manual" => Some(AddressSource::Manual),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{identity, PeerId};
+    use crate::swarm::peer_store::{AddressSource, PeerStore, PeerStoreBackend};
+    use super::FilePeerStoreBackend;
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("libp2p-peer-store-{}", rand::random::<u64>()));
+        let mut backend = FilePeerStoreBackend::new(&path);
+        assert_eq!(backend.load().unwrap().peers().count(), 0);
+
+        let key = identity::Keypair::generate_ed25519().public();
+        let peer = PeerId::from_public_key(key.clone());
+        let permanent: crate::Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+        let expiring: crate::Multiaddr = "/ip4/1.2.3.4/tcp/2".parse().unwrap();
+        let expired: crate::Multiaddr = "/ip4/1.2.3.4/tcp/3".parse().unwrap();
+
+        let mut store = PeerStore::new();
+        store.set_public_key(&peer, key.clone());
+        store.set_agent_version(&peer, "rust-libp2p".to_owned());
+        store.set_protocols(&peer, vec!["/ipfs/ping/1.0.0".to_owned(), "/ipfs/id/1.0.0".to_owned()]);
+        store.add_address(&peer, permanent.clone(), AddressSource::Manual, None);
+        store.add_address(&peer, expiring.clone(), AddressSource::Discovery, Some(Duration::from_secs(3600)));
+        store.add_address(&peer, expired, AddressSource::Discovery, Some(Duration::from_secs(0)));
+        backend.save(&store).unwrap();
+
+        let loaded = backend.load().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let record = loaded.peer(&peer).unwrap();
+        assert_eq!(record.public_key(), Some(&key));
+        assert_eq!(record.agent_version(), Some("rust-libp2p"));
+        assert_eq!(record.protocols().collect::<Vec<_>>(), vec!["/ipfs/ping/1.0.0", "/ipfs/id/1.0.0"]);
+        let addrs = record.address_records().collect::<Vec<_>>();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[0].addr, permanent);
+        assert_eq!(addrs[0].source, AddressSource::Manual);
+        assert!(addrs[0].expires.is_none());
+        assert_eq!(addrs[1].addr, expiring);
+        assert!(addrs[1].expires.is_some());
+    }
+
+    #[test]
+    fn store_saved_on_drop() {
+        let path = std::env::temp_dir().join(format!("libp2p-peer-store-{}", rand::random::<u64>()));
+        let peer = PeerId::random();
+        let addr: crate::Multiaddr = "/ip4/1.2.3.4/tcp/1".parse().unwrap();
+
+        {
+            let backend = FilePeerStoreBackend::new(&path);
+            let mut store = PeerStore::with_backend(backend, Duration::from_secs(60)).unwrap();
+            store.add_address(&peer, addr.clone(), AddressSource::Manual, None);
+        }
+
+        let store = PeerStore::with_backend(FilePeerStoreBackend::new(&path), Duration::from_secs(60)).unwrap();
+        assert_eq!(store.addresses(&peer).collect::<Vec<_>>(), vec![&addr]);
+        drop(store);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -22,19 +22,28 @@ use crate::{
     Transport, Multiaddr, PeerId, InboundUpgrade, OutboundUpgrade, UpgradeInfo, ProtocolName,
     muxing::StreamMuxer,
     nodes::{
-        collection::ConnectionInfo,
-        handled_node::NodeHandler,
+        collection::{ConnectionId, ConnectionInfo},
+        handled_node::{HandledNodeError, NodeHandler},
+        listeners::{ListenerId, ListenerRestartPolicy},
         node::Substream,
-        raw_swarm::{self, RawSwarm, RawSwarmEvent}
+        raw_swarm::{self, ConnectedPoint, RawSwarm, RawSwarmEvent}
     },
     protocols_handler::{NodeHandlerWrapperBuilder, NodeHandlerWrapper, NodeHandlerWrapperError, IntoProtocolsHandler, ProtocolsHandler},
-    swarm::{NetworkBehaviour, NetworkBehaviourAction, registry::{Addresses, AddressIter}},
+    protocols_handler::{SupportedProtocols, supported::PeersProtocols},
+    bandwidth::ProtocolBandwidth,
+    resource::{ResourceLimitError, ResourceManager},
+    swarm::{DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, registry::{Addresses, AddressIter}},
+    swarm::peer_store::PeerStore,
+    swarm::{ConnectionDenied, ConnectionGater, ConnectionLimitError, ConnectionLimits},
+    swarm::{InboundSubstreamLimits, SubstreamLimitError},
+    swarm::backoff::{DialBackoff, DialBackoffState},
     transport::TransportError,
 };
-use futures::prelude::*;
+use futures::{prelude::*, sync::mpsc};
 use smallvec::SmallVec;
-use std::{error, fmt, io, ops::{Deref, DerefMut}};
-use std::collections::HashSet;
+use std::{error, fmt, io, ops::{Deref, DerefMut}, sync::Arc, time::Duration};
+use std::collections::{HashSet, VecDeque};
+use wasm_timer::{Delay, Instant};
 
 /// Contains the state of the network, plus the way it should behave.
 pub type Swarm<TTransport, TBehaviour> = ExpandedSwarm<
@@ -77,8 +86,89 @@ where
 
     /// List of nodes for which we deny any incoming connection.
     banned_peers: HashSet<PeerId>,
+
+    /// Information about the peers of the network, shared between the behaviours.
+    peer_store: PeerStore,
+
+    /// State shared with the handlers of the connections.
+    handlers_shared: HandlersShared,
+
+    /// Limits on the number of connections.
+    limits: ConnectionLimits,
+
+    /// Receiver of the inbound substreams refused by the handlers.
+    refused_substreams: mpsc::UnboundedReceiver<(PeerId, SubstreamLimitError)>,
+
+    /// If `Some`, backoff of the peers and addresses that failed to be dialed.
+    dial_backoff: Option<DialBackoffState>,
+
+    /// If `Some`, decides which connections are opened and accepted.
+    gater: Option<Arc<dyn ConnectionGater>>,
+
+    /// Whether the swarm is shutting down.
+    close_state: CloseState,
+
+    /// Events generated outside of `poll_swarm_event`, waiting to be reported by it.
+    queued_events: VecDeque<QueuedEvent<THandlerErr>>,
+}
+
+/// Event generated outside of `poll_swarm_event`, for example because of a method call, and
+/// reported by the next call to `poll_swarm_event`.
+enum QueuedEvent<THandlerErr> {
+    /// A connection has been closed by the swarm itself.
+    ConnectionClosed(PeerId, ConnectionId, ConnectedPoint, ConnectionClosedReason<THandlerErr>),
+    /// Dialing a peer has been refused because it is in backoff.
+    DialBackoff(PeerId, Instant),
+}
+
+/// State of a `Swarm` shared with the handlers of its connections.
+struct HandlersShared {
+    /// Protocols that the connected peers support or not, updated by the handlers of their
+    /// connections.
+    peers_protocols: PeersProtocols,
+    /// Limits on the number of inbound substreams of each connection.
+    substream_limits: Arc<InboundSubstreamLimits>,
+    /// Where the handlers report the inbound substreams they refused.
+    refused_substreams: mpsc::UnboundedSender<(PeerId, SubstreamLimitError)>,
+    /// If `Some`, where the handlers reserve their connection and its substreams.
+    resource_manager: Option<ResourceManager>,
+    /// If `Some`, where the handlers count the traffic of their substreams.
+    bandwidth: Option<ProtocolBandwidth>,
+}
+
+impl HandlersShared {
+    /// Builds the handler of a new connection.
+    fn builder<THandler>(&self, handler: THandler) -> NodeHandlerWrapperBuilder<THandler>
+    where
+        THandler: IntoProtocolsHandler
+    {
+        let builder = handler.into_node_handler_builder()
+            .with_peers_protocols(self.peers_protocols.clone())
+            .with_substream_limits(self.substream_limits.clone(), self.refused_substreams.clone());
+        let builder = match &self.resource_manager {
+            Some(manager) => builder.with_resource_manager(manager.clone()),
+            None => builder,
+        };
+        match &self.bandwidth {
+            Some(bandwidth) => builder.with_bandwidth(bandwidth.clone()),
+            None => builder,
+        }
+    }
+}
+
+/// Shutdown state of a `Swarm`.
+enum CloseState {
+    /// The swarm is running.
+    Open,
+    /// `close` has been called. The connections are forcibly closed when the `Delay` elapses.
+    Closing(Delay),
+    /// The swarm has been shut down.
+    Closed,
 }
 
+/// How long the address of a peer we dialed remains in the peer store after we disconnect.
+const DISCONNECTED_ADDRESS_TTL: Duration = Duration::from_secs(60 * 60);
+
 impl<TTransport, TBehaviour, TInEvent, TOutEvent, THandler, THandlerErr> Deref for
     ExpandedSwarm<TTransport, TBehaviour, TInEvent, TOutEvent, THandler, THandlerErr>
 where
@@ -146,28 +236,95 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
 
     /// Starts listening on the given address.
     ///
-    /// Returns an error if the address is not supported.
-    pub fn listen_on(me: &mut Self, addr: Multiaddr) -> Result<(), TransportError<TTransport::Error>> {
+    /// Returns an error if the address is not supported. On success, returns the ID of the new
+    /// listener, which can be passed to `remove_listener` to stop listening.
+    pub fn listen_on(me: &mut Self, addr: Multiaddr) -> Result<ListenerId, TransportError<TTransport::Error>> {
         me.raw_swarm.listen_on(addr)
     }
 
+    /// Stops the listener with the given ID.
+    ///
+    /// The addresses the listener was listening on are reported to the behaviour through
+    /// `inject_expired_listen_addr`. Returns `Err(())` if no listener with this ID exists.
+    pub fn remove_listener(me: &mut Self, id: ListenerId) -> Result<(), ()> {
+        for addr in me.raw_swarm.remove_listener(id)? {
+            me.listened_addrs.retain(|a| a != &addr);
+            me.behaviour.inject_expired_listen_addr(&addr);
+        }
+        Ok(())
+    }
+
     /// Tries to dial the given address.
     ///
-    /// Returns an error if the address is not supported.
-    pub fn dial_addr(me: &mut Self, addr: Multiaddr) -> Result<(), TransportError<TTransport::Error>> {
-        let handler = me.behaviour.new_handler();
-        me.raw_swarm.dial(addr, handler.into_node_handler_builder())
+    /// Returns an error if the swarm is shutting down, if the address is not supported, if the
+    /// address is in backoff after previous failures, if the connection gater denies it, or if
+    /// dialing would exceed one of the connection limits. In the latter case, the behaviour is
+    /// also notified through `inject_addr_reach_failure`.
+    pub fn dial_addr(me: &mut Self, addr: Multiaddr) -> Result<(), DialError<TTransport::Error>> {
+        if Self::is_closing(me) {
+            return Err(DialError::Closing);
+        }
+
+        if let Some(until) = Self::address_backoff(me, &addr) {
+            return Err(DialError::Backoff { until });
+        }
+
+        if !Self::gater_allows_dial(me, None, &addr) {
+            return Err(DialError::Denied(ConnectionDenied));
+        }
+
+        if let Err(err) = Self::check_dial_limits(me) {
+            me.behaviour.inject_addr_reach_failure(None, &addr, &err);
+            return Err(DialError::ConnectionLimit(err));
+        }
+
+        let handler = me.handlers_shared.builder(me.behaviour.new_handler());
+        me.raw_swarm.dial(addr, handler)
+            .map_err(DialError::Transport)
     }
 
-    /// Tries to reach the given peer using the elements in the topology.
+    /// Tries to reach the given peer using the elements in the topology and the addresses in the
+    /// peer store.
     ///
     /// Has no effect if we are already connected to that peer, or if no address is known for the
-    /// peer.
+    /// peer. Addresses that are in backoff after previous failures or that the connection gater
+    /// denies are skipped. If the peer itself is in backoff, or if the swarm is shutting down, the
+    /// behaviour is immediately notified through `inject_dial_failure`. In the former case,
+    /// `poll_swarm_event` also produces a `SwarmEvent::DialBackoff`, and behaviours can check
+    /// `PollParameters::peer_backoff` beforehand.
     pub fn dial(me: &mut Self, peer_id: PeerId) {
-        let addrs = me.behaviour.addresses_of_peer(&peer_id);
+        if Self::is_closing(me) {
+            me.behaviour.inject_dial_failure(&peer_id);
+            return;
+        }
+
+        let mut addrs = me.behaviour.addresses_of_peer(&peer_id);
+        for addr in me.peer_store.addresses(&peer_id) {
+            if !addrs.contains(addr) {
+                addrs.push(addr.clone());
+            }
+        }
+        let peer_backoff = Self::peer_backoff(me, &peer_id);
+        if peer_backoff.is_none() {
+            addrs.retain(|addr| Self::address_backoff(me, addr).is_none());
+        }
+        addrs.retain(|addr| Self::gater_allows_dial(me, Some(&peer_id), addr));
+        let limits_check = Self::check_dial_limits(me);
         match me.raw_swarm.peer(peer_id.clone()) {
+            raw_swarm::Peer::NotConnected(_) if peer_backoff.is_some() => {
+                let until = peer_backoff.expect("checked by the match guard; QED");
+                me.behaviour.inject_dial_failure(&peer_id);
+                me.queued_events.push_back(QueuedEvent::DialBackoff(peer_id, until));
+            },
+            raw_swarm::Peer::NotConnected(_) if limits_check.is_err() => {
+                let err = limits_check.expect_err("checked by the match guard; QED");
+                for addr in &addrs {
+                    me.behaviour.inject_addr_reach_failure(Some(&peer_id), addr, &err);
+                }
+                me.behaviour.inject_dial_failure(&peer_id);
+            },
             raw_swarm::Peer::NotConnected(peer) => {
-                let handler = me.behaviour.new_handler().into_node_handler_builder();
+                let handler = me.handlers_shared.builder(me.behaviour.new_handler());
                 if peer.connect_iter(addrs, handler).is_err() {
                     me.behaviour.inject_dial_failure(&peer_id);
                 }
@@ -179,6 +336,60 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
         }
     }
 
+    /// Starts shutting the swarm down.
+    ///
+    /// The listeners are removed, the pending dialing attempts are interrupted and the behaviour
+    /// is notified through `inject_closing`. The handlers of the existing connections are then
+    /// given until `timeout` to finish their pending work, after which their connection is
+    /// closed. Once all the connections are closed, and their muxers too if no executor is
+    /// available to close them in the background, `poll_swarm_event` produces
+    /// `SwarmEvent::Closed` and the `Stream` ends. The muxers that haven't finished closing by
+    /// `timeout` are dropped.
+    ///
+    /// Has no effect if the swarm is already shutting down.
+    pub fn close(me: &mut Self, timeout: Duration) {
+        if Self::is_closing(me) {
+            return;
+        }
+        me.close_state = CloseState::Closing(Delay::new(Instant::now() + timeout));
+
+        let listeners = me.raw_swarm.listener_ids().collect::<Vec<_>>();
+        for id in listeners {
+            let _ = Self::remove_listener(me, id);
+        }
+
+        let pending = me.raw_swarm.pending_connection_peers().cloned().collect::<Vec<_>>();
+        for peer_id in pending {
+            if let Some(attempt) = me.raw_swarm.peer(peer_id.clone()).into_pending_connect() {
+                attempt.interrupt();
+                me.behaviour.inject_dial_failure(&peer_id);
+            }
+        }
+
+        me.raw_swarm.close_gracefully();
+        me.behaviour.inject_closing();
+    }
+
+    /// Returns true if `close` has been called.
+    pub fn is_closing(me: &Self) -> bool {
+        match me.close_state {
+            CloseState::Open => false,
+            CloseState::Closing(_) | CloseState::Closed => true,
+        }
+    }
+
+    /// Returns the time until which dialing the given peer is refused, if it is in backoff after
+    /// previous failures.
+    pub fn peer_backoff(me: &Self, peer_id: &PeerId) -> Option<Instant> {
+        me.dial_backoff.as_ref().and_then(|b| b.peer_backoff(peer_id, Instant::now()))
+    }
+
+    /// Returns the time until which dialing the given address is refused, if it is in backoff
+    /// after previous failures.
+    pub fn address_backoff(me: &Self, addr: &Multiaddr) -> Option<Instant> {
+        me.dial_backoff.as_ref().and_then(|b| b.address_backoff(addr, Instant::now()))
+    }
+
     /// Returns an iterator that produces the list of addresses we're listening on.
     pub fn listeners(me: &Self) -> impl Iterator<Item = &Multiaddr> {
         me.raw_swarm.listen_addrs()
@@ -209,141 +420,369 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
     /// This function has no effect is the peer is already banned.
     pub fn ban_peer_id(me: &mut Self, peer_id: PeerId) {
         me.banned_peers.insert(peer_id.clone());
-        if let Some(c) = me.raw_swarm.peer(peer_id).into_connected() {
-            c.close();
-        }
+        Self::disconnect(me, &peer_id, None, || ConnectionClosedReason::Banned);
     }
 
     /// Unbans a peer.
     pub fn unban_peer_id(me: &mut Self, peer_id: PeerId) {
         me.banned_peers.remove(&peer_id);
     }
-}
 
-impl<TTransport, TBehaviour, TMuxer, TInEvent, TOutEvent, THandler, THandlerErr> Stream for
-    ExpandedSwarm<TTransport, TBehaviour, TInEvent, TOutEvent, THandler, THandlerErr>
-where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
-      TMuxer: StreamMuxer + Send + Sync + 'static,
-      <TMuxer as StreamMuxer>::OutboundSubstream: Send + 'static,
-      <TMuxer as StreamMuxer>::Substream: Send + 'static,
-      TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
-      TTransport::Error: Send + 'static,
-      TTransport::Listener: Send + 'static,
-      TTransport::ListenerUpgrade: Send + 'static,
-      TTransport::Dial: Send + 'static,
-      THandlerErr: error::Error,
-      THandler: IntoProtocolsHandler + Send + 'static,
-      <THandler as IntoProtocolsHandler>::Handler: ProtocolsHandler<InEvent = TInEvent, OutEvent = TOutEvent, Substream = Substream<TMuxer>, Error = THandlerErr> + Send + 'static,
-      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent: Send + 'static,
-      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent: Send + 'static,
-      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::Error: Send + 'static,
-      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
-      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol: InboundUpgrade<Substream<TMuxer>> + Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::Info: Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
-      <<<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
-      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol: OutboundUpgrade<Substream<TMuxer>> + Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::Info: Send + 'static,
-      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
-      <<<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
-      <NodeHandlerWrapper<<THandler as IntoProtocolsHandler>::Handler> as NodeHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
-{
-    type Item = TBehaviour::OutEvent;
-    type Error = io::Error;
+    /// Returns true if the connection gater, if any, allows dialing the address.
+    fn gater_allows_dial(me: &Self, peer_id: Option<&PeerId>, addr: &Multiaddr) -> bool {
+        me.gater.as_ref().map_or(true, |g| g.intercept_dial(peer_id, addr))
+    }
+
+    /// Checks whether starting a new dialing attempt would exceed a connection limit.
+    fn check_dial_limits(me: &Self) -> Result<(), ConnectionLimitError> {
+        me.limits.check_pending_outgoing(|| {
+            me.raw_swarm.pending_connection_peers().count() + me.raw_swarm.unknown_dials().count()
+        })?;
+        me.limits.check_established_outgoing(|| {
+            me.raw_swarm.connected_points().filter(|(_, e)| e.is_dialer()).count()
+        })
+    }
+
+    /// Checks whether accepting a new incoming connection would exceed a connection limit.
+    fn check_incoming_limits(me: &Self) -> Result<(), ConnectionLimitError> {
+        me.limits.check_pending_incoming(|| me.raw_swarm.incoming_negotiated().count())?;
+        me.limits.check_established_incoming(|| {
+            me.raw_swarm.connected_points().filter(|(_, e)| e.is_listener()).count()
+        })
+    }
+
+    /// Checks whether the connection that has just been established with `peer_id` through
+    /// `endpoint` exceeds a connection limit.
+    fn check_established_limits(me: &Self, peer_id: &PeerId, endpoint: &ConnectedPoint)
+        -> Result<(), ConnectionLimitError>
+    {
+        // The new connection is already part of `connected_points`, hence the subtractions.
+        if endpoint.is_dialer() {
+            me.limits.check_established_outgoing(|| {
+                me.raw_swarm.connected_points().filter(|(_, e)| e.is_dialer()).count().saturating_sub(1)
+            })?;
+        } else {
+            me.limits.check_established_incoming(|| {
+                me.raw_swarm.connected_points().filter(|(_, e)| e.is_listener()).count().saturating_sub(1)
+            })?;
+        }
+        me.limits.check_established_per_peer(|| {
+            me.raw_swarm.connected_points().filter(|(p, _)| *p == peer_id).count().saturating_sub(1)
+        })
+    }
+
+    /// Returns the peer store of the swarm.
+    pub fn peer_store(me: &Self) -> &PeerStore {
+        &me.peer_store
+    }
+
+    /// Returns the peer store of the swarm, for example in order to add addresses of peers.
+    pub fn peer_store_mut(me: &mut Self) -> &mut PeerStore {
+        &mut me.peer_store
+    }
+
+    /// Returns the protocols that a connected peer has been seen to support or to refuse.
+    ///
+    /// Returns `None` if we are not connected to the peer or if no protocol has been negotiated
+    /// with it yet.
+    pub fn peer_supported_protocols(me: &Self, peer_id: &PeerId) -> Option<SupportedProtocols> {
+        me.handlers_shared.peers_protocols.get(peer_id)
+    }
+
+    /// Returns the `ResourceManager` of the swarm, if any.
+    pub fn resource_manager(me: &Self) -> Option<&ResourceManager> {
+        me.handlers_shared.resource_manager.as_ref()
+    }
+
+    /// Closes the given connection to a peer, or all of them if `connection_id` is `None`,
+    /// notifies the behaviour through `inject_disconnected`, and queues a
+    /// `SwarmEvent::ConnectionClosed` for each of them.
+    fn disconnect<F>(me: &mut Self, peer_id: &PeerId, connection_id: Option<ConnectionId>, reason: F)
+    where
+        F: Fn() -> ConnectionClosedReason<THandlerErr>,
+    {
+        let peer = match me.raw_swarm.peer(peer_id.clone()).into_connected() {
+            Some(peer) => peer,
+            None => return,
+        };
+        let closed = peer.connections()
+            .filter(|(id, _)| connection_id.map_or(true, |c| c == *id))
+            .map(|(id, endpoint)| (id, endpoint.clone()))
+            .collect::<Vec<_>>();
+        match connection_id {
+            Some(connection_id) => { peer.close_connection(connection_id); },
+            None => peer.close(),
+        }
+        for (connection_id, endpoint) in closed {
+            if let ConnectedPoint::Dialer { address } = &endpoint {
+                me.peer_store.disconnected_from(peer_id, address, DISCONNECTED_ADDRESS_TTL);
+            }
+            let reason = reason();
+            me.behaviour.inject_disconnected(peer_id, connection_id, endpoint.clone(), reason.as_disconnect_reason());
+            me.queued_events.push_back(QueuedEvent::ConnectionClosed(peer_id.clone(), connection_id, endpoint, reason));
+        }
+        Swarm::remove_peer_state_if_disconnected(me, peer_id);
+    }
+
+    /// Forgets what the handlers learned about the peer, and its bandwidth counters, once its
+    /// last connection is gone.
+    fn remove_peer_state_if_disconnected(me: &mut Self, peer_id: &PeerId) {
+        if me.raw_swarm.peer(peer_id.clone()).into_connected().is_some() {
+            return;
+        }
+        me.handlers_shared.peers_protocols.remove(peer_id);
+        if let Some(bandwidth) = &me.handlers_shared.bandwidth {
+            bandwidth.remove_peer(peer_id);
+        }
+    }
+
+    /// Drives the shutdown of the swarm, if any. Returns `SwarmEvent::Closed` once it is over.
+    fn poll_close(me: &mut Self) -> Option<SwarmEvent<TBehaviour::OutEvent, THandlerErr, TTransport::Error>> {
+        let deadline_elapsed = match &mut me.close_state {
+            CloseState::Open => return None,
+            CloseState::Closed => return Some(Swarm::next_queued_event(me).unwrap_or(SwarmEvent::Closed)),
+            CloseState::Closing(deadline) => match deadline.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(())) | Err(_) => true,
+            },
+        };
+
+        // Without an executor, the muxers of the closed connections are closed by tasks that
+        // only make progress when the `RawSwarm` is polled, so we wait for them as well.
+        if !deadline_elapsed && (me.raw_swarm.connected_peers().next().is_some() || me.raw_swarm.has_local_tasks()) {
+            return None;
+        }
+
+        // Forcibly close the connections whose handlers didn't finish in time.
+        let peers = me.raw_swarm.connected_peers().cloned().collect::<Vec<_>>();
+        for peer_id in peers {
+            Swarm::disconnect(me, &peer_id, None, || ConnectionClosedReason::Closing);
+        }
+
+        me.close_state = CloseState::Closed;
+        Some(Swarm::next_queued_event(me).unwrap_or(SwarmEvent::Closed))
+    }
+
+    /// Pops the next event generated outside of `poll_swarm_event`.
+    fn next_queued_event(me: &mut Self) -> Option<SwarmEvent<TBehaviour::OutEvent, THandlerErr, TTransport::Error>> {
+        me.queued_events.pop_front().map(|event| match event {
+            QueuedEvent::ConnectionClosed(peer_id, connection_id, endpoint, reason) =>
+                SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, reason },
+            QueuedEvent::DialBackoff(peer_id, until) =>
+                SwarmEvent::DialBackoff { peer_id, until },
+        })
+    }
+
+    /// Polls the swarm for events.
+    ///
+    /// Contrary to polling the `Swarm` as a `Stream`, which only produces the events generated
+    /// by the behaviour, this also produces the events about the connections and listeners of
+    /// the swarm. The behaviour has already been notified of these events when they are returned.
+    pub fn poll_swarm_event(me: &mut Self) -> Async<SwarmEvent<TBehaviour::OutEvent, THandlerErr, TTransport::Error>> {
+        me.peer_store.poll();
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
         loop {
+            if let Some(event) = Swarm::poll_close(me) {
+                return Async::Ready(event);
+            }
+
+            if let Some(event) = Swarm::next_queued_event(me) {
+                return Async::Ready(event);
+            }
+
+            if let Ok(Async::Ready(Some((peer_id, error)))) = me.refused_substreams.poll() {
+                me.behaviour.inject_substream_refused(&peer_id, &error);
+                return Async::Ready(SwarmEvent::SubstreamRefused { peer_id, error });
+            }
+
             let mut raw_swarm_not_ready = false;
+            let incoming_limits_check = Swarm::check_incoming_limits(me);
 
-            match self.raw_swarm.poll() {
+            match me.raw_swarm.poll() {
                 Async::NotReady => raw_swarm_not_ready = true,
-                Async::Ready(RawSwarmEvent::NodeEvent { conn_info, event }) => {
-                    self.behaviour.inject_node_event(conn_info.peer_id().clone(), event);
+                Async::Ready(RawSwarmEvent::NodeEvent { conn_info, connection_id, event }) => {
+                    me.behaviour.inject_node_event(conn_info.peer_id().clone(), connection_id, event);
                 },
-                Async::Ready(RawSwarmEvent::Connected { conn_info, endpoint }) => {
-                    if self.banned_peers.contains(conn_info.peer_id()) {
-                        self.raw_swarm.peer(conn_info.peer_id().clone())
+                Async::Ready(RawSwarmEvent::Connected { conn_info, connection_id, endpoint }) => {
+                    let peer_id = conn_info.peer_id().clone();
+                    if me.banned_peers.contains(&peer_id) || Swarm::is_closing(me) {
+                        me.raw_swarm.peer(peer_id)
+                            .into_connected()
+                            .expect("the RawSwarm just notified us that we were connected; QED")
+                            .close_connection(connection_id);
+                    } else if me.gater.as_ref().map_or(false, |g| !g.intercept_upgraded(&peer_id, &endpoint)) {
+                        me.raw_swarm.peer(peer_id.clone())
                             .into_connected()
                             .expect("the RawSwarm just notified us that we were connected; QED")
-                            .close();
+                            .close_connection(connection_id);
+                        if let ConnectedPoint::Dialer { address } = &endpoint {
+                            me.behaviour.inject_addr_reach_failure(Some(&peer_id), address, &ConnectionDenied);
+                            me.behaviour.inject_dial_failure(&peer_id);
+                        }
+                        return Async::Ready(SwarmEvent::ConnectionDenied { peer_id: Some(peer_id), endpoint });
+                    } else if let Err(error) = Swarm::check_established_limits(me, &peer_id, &endpoint) {
+                        me.raw_swarm.peer(peer_id.clone())
+                            .into_connected()
+                            .expect("the RawSwarm just notified us that we were connected; QED")
+                            .close_connection(connection_id);
+                        if let ConnectedPoint::Dialer { address } = &endpoint {
+                            me.behaviour.inject_addr_reach_failure(Some(&peer_id), address, &error);
+                            me.behaviour.inject_dial_failure(&peer_id);
+                        } else {
+                            me.behaviour.inject_connection_refused(Some(&peer_id), &endpoint, &error);
+                        }
+                        return Async::Ready(SwarmEvent::ConnectionRefused { peer_id: Some(peer_id), endpoint, error });
                     } else {
-                        self.behaviour.inject_connected(conn_info.peer_id().clone(), endpoint);
+                        if let ConnectedPoint::Dialer { address } = &endpoint {
+                            me.peer_store.connected_to(&peer_id, address);
+                        }
+                        if let Some(backoff) = me.dial_backoff.as_mut() {
+                            backoff.connected(&peer_id, match &endpoint {
+                                ConnectedPoint::Dialer { address } => Some(address),
+                                ConnectedPoint::Listener { .. } => None,
+                            });
+                        }
+                        me.behaviour.inject_connected(peer_id.clone(), connection_id, endpoint.clone());
+                        return Async::Ready(SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint });
                     }
                 },
-                Async::Ready(RawSwarmEvent::NodeClosed { conn_info, endpoint, .. }) => {
-                    self.behaviour.inject_disconnected(conn_info.peer_id(), endpoint);
-                },
-                Async::Ready(RawSwarmEvent::Replaced { new_info, closed_endpoint, endpoint, .. }) => {
-                    self.behaviour.inject_replaced(new_info.peer_id().clone(), closed_endpoint, endpoint);
+                Async::Ready(RawSwarmEvent::NodeClosed { conn_info, connection_id, endpoint, error }) => {
+                    let peer_id = conn_info.peer_id().clone();
+                    if let ConnectedPoint::Dialer { address } = &endpoint {
+                        me.peer_store.disconnected_from(&peer_id, address, DISCONNECTED_ADDRESS_TTL);
+                    }
+                    let reason = ConnectionClosedReason::from(error);
+                    Swarm::remove_peer_state_if_disconnected(me, &peer_id);
+                    me.behaviour.inject_disconnected(&peer_id, connection_id, endpoint.clone(), reason.as_disconnect_reason());
+                    return Async::Ready(SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, reason });
                 },
                 Async::Ready(RawSwarmEvent::IncomingConnection(incoming)) => {
-                    let handler = self.behaviour.new_handler();
-                    incoming.accept(handler.into_node_handler_builder());
+                    let endpoint = incoming.to_connected_point();
+                    if me.gater.as_ref().map_or(false, |g| !g.intercept_accept(incoming.listen_addr(), incoming.send_back_addr())) {
+                        // Dropping the incoming connection closes it before it is upgraded.
+                        return Async::Ready(SwarmEvent::ConnectionDenied { peer_id: None, endpoint });
+                    }
+                    match incoming_limits_check {
+                        Ok(()) => {
+                            let handler = me.handlers_shared.builder(me.behaviour.new_handler());
+                            incoming.accept(handler);
+                            if let ConnectedPoint::Listener { listen_addr, send_back_addr } = endpoint {
+                                return Async::Ready(SwarmEvent::IncomingConnection { listen_addr, send_back_addr });
+                            }
+                        },
+                        Err(error) => {
+                            // Dropping the incoming connection closes it.
+                            me.behaviour.inject_connection_refused(None, &endpoint, &error);
+                            return Async::Ready(SwarmEvent::ConnectionRefused { peer_id: None, endpoint, error });
+                        },
+                    }
                 },
-                Async::Ready(RawSwarmEvent::NewListenerAddress { listen_addr }) => {
-                    if !self.listened_addrs.contains(&listen_addr) {
-                        self.listened_addrs.push(listen_addr.clone())
+                Async::Ready(RawSwarmEvent::NewListenerAddress { listener_id, listen_addr }) => {
+                    if !me.listened_addrs.contains(&listen_addr) {
+                        me.listened_addrs.push(listen_addr.clone())
                     }
-                    self.behaviour.inject_new_listen_addr(&listen_addr);
+                    me.behaviour.inject_new_listen_addr(&listen_addr);
+                    return Async::Ready(SwarmEvent::NewListenAddr { listener_id, address: listen_addr });
                 }
-                Async::Ready(RawSwarmEvent::ExpiredListenerAddress { listen_addr }) => {
-                    self.listened_addrs.retain(|a| a != &listen_addr);
-                    self.behaviour.inject_expired_listen_addr(&listen_addr);
+                Async::Ready(RawSwarmEvent::ExpiredListenerAddress { listener_id, listen_addr }) => {
+                    me.listened_addrs.retain(|a| a != &listen_addr);
+                    me.behaviour.inject_expired_listen_addr(&listen_addr);
+                    return Async::Ready(SwarmEvent::ExpiredListenAddr { listener_id, address: listen_addr });
                 }
-                Async::Ready(RawSwarmEvent::ListenerClosed { .. }) => {},
-                Async::Ready(RawSwarmEvent::IncomingConnectionError { .. }) => {},
+                Async::Ready(RawSwarmEvent::ListenerClosed { listener_id, addresses, reason }) => {
+                    for addr in &addresses {
+                        me.listened_addrs.retain(|a| a != addr);
+                        me.behaviour.inject_expired_listen_addr(addr);
+                    }
+                    me.behaviour.inject_listener_closed(listener_id, reason.as_ref().map(|_| ()).map_err(|err| err as &dyn error::Error));
+                    return Async::Ready(SwarmEvent::ListenerClosed { listener_id, addresses, reason });
+                },
+                Async::Ready(RawSwarmEvent::ListenerError { listener_id, addresses, error }) => {
+                    for addr in &addresses {
+                        me.listened_addrs.retain(|a| a != addr);
+                        me.behaviour.inject_expired_listen_addr(addr);
+                    }
+                    me.behaviour.inject_listener_error(listener_id, &error);
+                    return Async::Ready(SwarmEvent::ListenerError { listener_id, addresses, error });
+                },
+                Async::Ready(RawSwarmEvent::IncomingConnectionError { listen_addr, send_back_addr, error }) => {
+                    return Async::Ready(SwarmEvent::IncomingConnectionError { listen_addr, send_back_addr, error });
+                },
                 Async::Ready(RawSwarmEvent::DialError { peer_id, multiaddr, error, new_state }) => {
-                    self.behaviour.inject_addr_reach_failure(Some(&peer_id), &multiaddr, &error);
+                    me.behaviour.inject_addr_reach_failure(Some(&peer_id), &multiaddr, &error);
+                    if let Some(backoff) = me.dial_backoff.as_mut() {
+                        let now = Instant::now();
+                        backoff.address_failed(&multiaddr, now);
+                        if let raw_swarm::PeerState::NotConnected = new_state {
+                            backoff.peer_failed(&peer_id, now);
+                        }
+                    }
+                    let attempts_remaining = match new_state {
+                        raw_swarm::PeerState::Dialing { num_pending_addresses } => num_pending_addresses.get() as u32,
+                        _ => 0,
+                    };
                     if let raw_swarm::PeerState::NotConnected = new_state {
-                        self.behaviour.inject_dial_failure(&peer_id);
+                        me.behaviour.inject_dial_failure(&peer_id);
                     }
+                    return Async::Ready(SwarmEvent::UnreachableAddr { peer_id, address: multiaddr, error, attempts_remaining });
                 },
                 Async::Ready(RawSwarmEvent::UnknownPeerDialError { multiaddr, error, .. }) => {
-                    self.behaviour.inject_addr_reach_failure(None, &multiaddr, &error);
+                    me.behaviour.inject_addr_reach_failure(None, &multiaddr, &error);
+                    if let Some(backoff) = me.dial_backoff.as_mut() {
+                        backoff.address_failed(&multiaddr, Instant::now());
+                    }
+                    return Async::Ready(SwarmEvent::UnknownPeerUnreachableAddr { address: multiaddr, error });
                 },
             }
 
             let behaviour_poll = {
                 let mut parameters = PollParameters {
-                    local_peer_id: &mut self.raw_swarm.local_peer_id(),
-                    supported_protocols: &self.supported_protocols,
-                    listened_addrs: &self.listened_addrs,
-                    external_addrs: self.external_addrs.iter()
+                    local_peer_id: &mut me.raw_swarm.local_peer_id(),
+                    supported_protocols: &me.supported_protocols,
+                    listened_addrs: &me.listened_addrs,
+                    external_addrs: me.external_addrs.iter(),
+                    peer_store: &mut me.peer_store,
+                    peers_protocols: &me.handlers_shared.peers_protocols,
+                    dial_backoff: me.dial_backoff.as_ref(),
                 };
-                self.behaviour.poll(&mut parameters)
+                me.behaviour.poll(&mut parameters)
             };
 
             match behaviour_poll {
-                Async::NotReady if raw_swarm_not_ready => return Ok(Async::NotReady),
+                Async::NotReady if raw_swarm_not_ready => return Async::NotReady,
                 Async::NotReady => (),
                 Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
-                    return Ok(Async::Ready(Some(event)))
+                    return Async::Ready(SwarmEvent::Behaviour(event))
                 },
                 Async::Ready(NetworkBehaviourAction::DialAddress { address }) => {
-                    let _ = Swarm::dial_addr(self, address);
+                    let _ = Swarm::dial_addr(me, address);
                 },
                 Async::Ready(NetworkBehaviourAction::DialPeer { peer_id }) => {
-                    if self.banned_peers.contains(&peer_id) {
-                        self.behaviour.inject_dial_failure(&peer_id);
+                    if me.banned_peers.contains(&peer_id) {
+                        me.behaviour.inject_dial_failure(&peer_id);
                     } else {
-                        Swarm::dial(self, peer_id);
+                        Swarm::dial(me, peer_id);
                     }
                 },
                 Async::Ready(NetworkBehaviourAction::SendEvent { peer_id, event }) => {
-                    if let Some(mut peer) = self.raw_swarm.peer(peer_id).into_connected() {
+                    if let Some(mut peer) = me.raw_swarm.peer(peer_id).into_connected() {
                         peer.send_event(event);
                     }
                 },
+                Async::Ready(NetworkBehaviourAction::SendEventToConnection { peer_id, connection_id, event }) => {
+                    if let Some(mut peer) = me.raw_swarm.peer(peer_id).into_connected() {
+                        let _ = peer.send_event_to(connection_id, event);
+                    }
+                },
+                Async::Ready(NetworkBehaviourAction::DisconnectPeer { peer_id, connection_id }) => {
+                    Swarm::disconnect(me, &peer_id, connection_id, || ConnectionClosedReason::Behaviour);
+                },
                 Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address }) => {
-                    for addr in self.raw_swarm.nat_traversal(&address) {
-                        if self.external_addrs.iter().all(|a| *a != addr) {
-                            self.behaviour.inject_new_external_addr(&addr);
+                    for addr in me.raw_swarm.nat_traversal(&address) {
+                        if me.external_addrs.iter().all(|a| *a != addr) {
+                            me.behaviour.inject_new_external_addr(&addr);
                         }
-                        self.external_addrs.add(addr)
+                        me.external_addrs.add(addr)
                     }
                 },
             }
@@ -351,13 +790,397 @@ where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
     }
 }
 
+impl<TTransport, TBehaviour, TMuxer, TInEvent, TOutEvent, THandler, THandlerErr> Stream for
+    ExpandedSwarm<TTransport, TBehaviour, TInEvent, TOutEvent, THandler, THandlerErr>
+where TBehaviour: NetworkBehaviour<ProtocolsHandler = THandler>,
+      TMuxer: StreamMuxer + Send + Sync + 'static,
+      <TMuxer as StreamMuxer>::OutboundSubstream: Send + 'static,
+      <TMuxer as StreamMuxer>::Substream: Send + 'static,
+      TTransport: Transport<Output = (PeerId, TMuxer)> + Clone,
+      TTransport::Error: Send + 'static,
+      TTransport::Listener: Send + 'static,
+      TTransport::ListenerUpgrade: Send + 'static,
+      TTransport::Dial: Send + 'static,
+      THandlerErr: error::Error,
+      THandler: IntoProtocolsHandler + Send + 'static,
+      <THandler as IntoProtocolsHandler>::Handler: ProtocolsHandler<InEvent = TInEvent, OutEvent = TOutEvent, Substream = Substream<TMuxer>, Error = THandlerErr> + Send + 'static,
+      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InEvent: Send + 'static,
+      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent: Send + 'static,
+      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::Error: Send + 'static,
+      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol: InboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as InboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::InboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol: OutboundUpgrade<Substream<TMuxer>> + Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Future: Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as OutboundUpgrade<Substream<TMuxer>>>::Error: fmt::Debug + Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::Info: Send + 'static,
+      <<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter: Send + 'static,
+      <<<<THandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutboundProtocol as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send + 'static,
+      <NodeHandlerWrapper<<THandler as IntoProtocolsHandler>::Handler> as NodeHandler>::OutboundOpenInfo: Send + 'static, // TODO: shouldn't be necessary
+{
+    type Item = TBehaviour::OutEvent;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        loop {
+            match ExpandedSwarm::poll_swarm_event(self) {
+                Async::Ready(SwarmEvent::Behaviour(event)) => return Ok(Async::Ready(Some(event))),
+                Async::Ready(SwarmEvent::Closed) => return Ok(Async::Ready(None)),
+                Async::Ready(_) => {},
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Event generated by the `Swarm`, as returned by `poll_swarm_event`.
+///
+/// Apart from `Behaviour`, these events are informational. The behaviour has already been
+/// notified of them through the relevant `NetworkBehaviour` methods.
+pub enum SwarmEvent<TBvEv, THandlerErr, TTransErr> {
+    /// Event generated by the `NetworkBehaviour`.
+    Behaviour(TBvEv),
+    /// A connection to the given peer has been opened.
+    ConnectionEstablished {
+        /// Identity of the peer we are connected to.
+        peer_id: PeerId,
+        /// Identifier of the new connection.
+        connection_id: ConnectionId,
+        /// Endpoint of the connection.
+        endpoint: ConnectedPoint,
+    },
+    /// A connection with the given peer has been closed.
+    ConnectionClosed {
+        /// Identity of the peer we were connected to.
+        peer_id: PeerId,
+        /// Identifier of the connection that closed.
+        connection_id: ConnectionId,
+        /// Endpoint of the connection.
+        endpoint: ConnectedPoint,
+        /// The reason why the connection closed, which is also what the behaviour has been
+        /// passed.
+        reason: ConnectionClosedReason<THandlerErr>,
+    },
+    /// A connection has been refused because it would have exceeded one of the connection
+    /// limits of the swarm.
+    ConnectionRefused {
+        /// Identity of the remote, if the connection had already been established.
+        peer_id: Option<PeerId>,
+        /// Endpoint of the refused connection.
+        endpoint: ConnectedPoint,
+        /// The limit that would have been exceeded.
+        error: ConnectionLimitError,
+    },
+    /// A connection has been denied by the `ConnectionGater`.
+    ///
+    /// The ID of the remote is passed if the connection was denied after it has been fully
+    /// upgraded. Incoming connections denied before their upgrade are reported without it.
+    ConnectionDenied {
+        /// Identity of the remote, if known.
+        peer_id: Option<PeerId>,
+        /// Endpoint of the connection.
+        endpoint: ConnectedPoint,
+    },
+    /// An inbound substream opened by a peer has been reset because it would have exceeded one of
+    /// the substream limits of the swarm.
+    SubstreamRefused {
+        /// Identity of the peer that opened the substream.
+        peer_id: PeerId,
+        /// The limit that would have been exceeded.
+        error: SubstreamLimitError,
+    },
+    /// A new connection arrived on a listener and is being negotiated.
+    IncomingConnection {
+        /// Address of the listener that received the connection.
+        listen_addr: Multiaddr,
+        /// Address used to send back data to the remote.
+        send_back_addr: Multiaddr,
+    },
+    /// An error happened while negotiating an incoming connection.
+    IncomingConnectionError {
+        /// Address of the listener that received the connection.
+        listen_addr: Multiaddr,
+        /// Address used to send back data to the remote.
+        send_back_addr: Multiaddr,
+        /// The error that happened.
+        error: raw_swarm::IncomingError<TTransErr>,
+    },
+    /// Failed to reach an address of a peer we were trying to dial.
+    UnreachableAddr {
+        /// Identity of the peer we were trying to dial.
+        peer_id: PeerId,
+        /// The address that could not be reached.
+        address: Multiaddr,
+        /// The error that happened.
+        error: raw_swarm::RawSwarmReachError<TTransErr, PeerId>,
+        /// Number of addresses of the peer that are still being tried.
+        attempts_remaining: u32,
+    },
+    /// Dialing a peer has been refused because previous attempts failed and it is in backoff.
+    DialBackoff {
+        /// Identity of the peer that was not dialed.
+        peer_id: PeerId,
+        /// Dialing the peer is refused until then.
+        until: Instant,
+    },
+    /// Failed to reach an address that was dialed without a known peer.
+    UnknownPeerUnreachableAddr {
+        /// The address that could not be reached.
+        address: Multiaddr,
+        /// The error that happened.
+        error: raw_swarm::UnknownPeerDialErr<TTransErr>,
+    },
+    /// One of the listeners is now listening on a new address.
+    NewListenAddr {
+        /// The listener that is listening on the new address.
+        listener_id: ListenerId,
+        /// The new address.
+        address: Multiaddr,
+    },
+    /// One of the listeners is no longer listening on an address.
+    ExpiredListenAddr {
+        /// The listener that is no longer listening on the address.
+        listener_id: ListenerId,
+        /// The expired address.
+        address: Multiaddr,
+    },
+    /// One of the listeners closed and will not be restarted.
+    ListenerClosed {
+        /// The listener that closed.
+        listener_id: ListenerId,
+        /// The addresses the listener was listening on, which are now expired.
+        addresses: Vec<Multiaddr>,
+        /// The error that happened. `Ok` if gracefully closed.
+        reason: Result<(), TTransErr>,
+    },
+    /// One of the listeners failed and will be restarted.
+    ListenerError {
+        /// The listener that failed.
+        listener_id: ListenerId,
+        /// The addresses the listener was listening on, which are now expired.
+        addresses: Vec<Multiaddr>,
+        /// The error that happened.
+        error: TTransErr,
+    },
+    /// The swarm has been shut down after a call to `close`. Polling the swarm again produces
+    /// this event again.
+    Closed,
+}
+
+impl<TBvEv, THandlerErr, TTransErr> fmt::Debug for SwarmEvent<TBvEv, THandlerErr, TTransErr>
+where
+    TBvEv: fmt::Debug,
+    THandlerErr: fmt::Debug,
+    TTransErr: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwarmEvent::Behaviour(event) => f.debug_tuple("Behaviour").field(event).finish(),
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint } => f
+                .debug_struct("ConnectionEstablished")
+                .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
+                .field("endpoint", endpoint)
+                .finish(),
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, endpoint, reason } => f
+                .debug_struct("ConnectionClosed")
+                .field("peer_id", peer_id)
+                .field("connection_id", connection_id)
+                .field("endpoint", endpoint)
+                .field("reason", reason)
+                .finish(),
+            SwarmEvent::ConnectionRefused { peer_id, endpoint, error } => f
+                .debug_struct("ConnectionRefused")
+                .field("peer_id", peer_id)
+                .field("endpoint", endpoint)
+                .field("error", error)
+                .finish(),
+            SwarmEvent::ConnectionDenied { peer_id, endpoint } => f
+                .debug_struct("ConnectionDenied")
+                .field("peer_id", peer_id)
+                .field("endpoint", endpoint)
+                .finish(),
+            SwarmEvent::SubstreamRefused { peer_id, error } => f
+                .debug_struct("SubstreamRefused")
+                .field("peer_id", peer_id)
+                .field("error", error)
+                .finish(),
+            SwarmEvent::IncomingConnection { listen_addr, send_back_addr } => f
+                .debug_struct("IncomingConnection")
+                .field("listen_addr", listen_addr)
+                .field("send_back_addr", send_back_addr)
+                .finish(),
+            SwarmEvent::IncomingConnectionError { listen_addr, send_back_addr, error } => f
+                .debug_struct("IncomingConnectionError")
+                .field("listen_addr", listen_addr)
+                .field("send_back_addr", send_back_addr)
+                .field("error", error)
+                .finish(),
+            SwarmEvent::UnreachableAddr { peer_id, address, error, attempts_remaining } => f
+                .debug_struct("UnreachableAddr")
+                .field("peer_id", peer_id)
+                .field("address", address)
+                .field("error", error)
+                .field("attempts_remaining", attempts_remaining)
+                .finish(),
+            SwarmEvent::DialBackoff { peer_id, until } => f
+                .debug_struct("DialBackoff")
+                .field("peer_id", peer_id)
+                .field("until", until)
+                .finish(),
+            SwarmEvent::UnknownPeerUnreachableAddr { address, error } => f
+                .debug_struct("UnknownPeerUnreachableAddr")
+                .field("address", address)
+                .field("error", error)
+                .finish(),
+            SwarmEvent::NewListenAddr { listener_id, address } => f
+                .debug_struct("NewListenAddr")
+                .field("listener_id", listener_id)
+                .field("address", address)
+                .finish(),
+            SwarmEvent::ExpiredListenAddr { listener_id, address } => f
+                .debug_struct("ExpiredListenAddr")
+                .field("listener_id", listener_id)
+                .field("address", address)
+                .finish(),
+            SwarmEvent::ListenerClosed { listener_id, addresses, reason } => f
+                .debug_struct("ListenerClosed")
+                .field("listener_id", listener_id)
+                .field("addresses", addresses)
+                .field("reason", reason)
+                .finish(),
+            SwarmEvent::ListenerError { listener_id, addresses, error } => f
+                .debug_struct("ListenerError")
+                .field("listener_id", listener_id)
+                .field("addresses", addresses)
+                .field("error", error)
+                .finish(),
+            SwarmEvent::Closed => f.write_str("Closed"),
+        }
+    }
+}
+
+/// Reason why a connection has been closed, as reported by `SwarmEvent::ConnectionClosed`.
+///
+/// This is the owned counterpart of the `DisconnectReason` passed to
+/// `NetworkBehaviour::inject_disconnected`.
+#[derive(Debug)]
+pub enum ConnectionClosedReason<THandlerErr> {
+    /// The handler of the connection no longer required the connection to be kept alive.
+    KeepAliveTimeout,
+    /// The handler of the connection produced an error.
+    Handler(THandlerErr),
+    /// The remote closed the connection.
+    RemoteClosed,
+    /// The stream muxer of the connection produced an error.
+    Muxer(io::Error),
+    /// The peer has been banned with `Swarm::ban_peer_id`.
+    Banned,
+    /// A behaviour closed the connection with `NetworkBehaviourAction::DisconnectPeer`.
+    Behaviour,
+    /// The swarm is shutting down.
+    Closing,
+    /// The connection would have exceeded the limits of the `ResourceManager` of the swarm.
+    ResourceLimit(ResourceLimitError),
+}
+
+impl<THandlerErr> ConnectionClosedReason<THandlerErr>
+where
+    THandlerErr: error::Error + 'static,
+{
+    /// Returns the reason as passed to `NetworkBehaviour::inject_disconnected`.
+    pub fn as_disconnect_reason(&self) -> DisconnectReason<'_> {
+        match self {
+            ConnectionClosedReason::KeepAliveTimeout => DisconnectReason::KeepAliveTimeout,
+            ConnectionClosedReason::Handler(err) => DisconnectReason::Handler(err),
+            ConnectionClosedReason::RemoteClosed => DisconnectReason::RemoteClosed,
+            ConnectionClosedReason::Muxer(err) => DisconnectReason::Muxer(err),
+            ConnectionClosedReason::Banned => DisconnectReason::Banned,
+            ConnectionClosedReason::Behaviour => DisconnectReason::Behaviour,
+            ConnectionClosedReason::Closing => DisconnectReason::Closing,
+            ConnectionClosedReason::ResourceLimit(err) => DisconnectReason::ResourceLimit(err),
+        }
+    }
+}
+
+impl<THandlerErr> From<HandledNodeError<NodeHandlerWrapperError<THandlerErr>>> for ConnectionClosedReason<THandlerErr> {
+    fn from(error: HandledNodeError<NodeHandlerWrapperError<THandlerErr>>) -> Self {
+        match error {
+            HandledNodeError::Node(ref err) if err.kind() == io::ErrorKind::BrokenPipe =>
+                ConnectionClosedReason::RemoteClosed,
+            HandledNodeError::Node(err) => ConnectionClosedReason::Muxer(err),
+            HandledNodeError::Handler(NodeHandlerWrapperError::UselessTimeout) =>
+                ConnectionClosedReason::KeepAliveTimeout,
+            HandledNodeError::Handler(NodeHandlerWrapperError::Closed) =>
+                ConnectionClosedReason::Closing,
+            HandledNodeError::Handler(NodeHandlerWrapperError::ResourceLimit(err)) =>
+                ConnectionClosedReason::ResourceLimit(err),
+            HandledNodeError::Handler(NodeHandlerWrapperError::Handler(err)) =>
+                ConnectionClosedReason::Handler(err),
+        }
+    }
+}
+
+/// Error that can happen when dialing an address.
+#[derive(Debug)]
+pub enum DialError<TErr> {
+    /// Dialing would have exceeded one of the connection limits of the swarm.
+    ConnectionLimit(ConnectionLimitError),
+    /// The address failed to be dialed recently and is in backoff.
+    Backoff {
+        /// Dialing the address is refused until then.
+        until: Instant,
+    },
+    /// The swarm is shutting down.
+    Closing,
+    /// The connection gater denied dialing the address.
+    Denied(ConnectionDenied),
+    /// The transport failed to dial the address.
+    Transport(TransportError<TErr>),
+}
+
+impl<TErr> fmt::Display for DialError<TErr>
+where TErr: fmt::Display
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialError::ConnectionLimit(err) => write!(f, "Dial refused: {}", err),
+            DialError::Backoff { .. } => write!(f, "Dial refused: the address is in backoff"),
+            DialError::Closing => write!(f, "Dial refused: the swarm is shutting down"),
+            DialError::Denied(err) => write!(f, "Dial refused: {}", err),
+            DialError::Transport(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl<TErr> error::Error for DialError<TErr>
+where TErr: error::Error + 'static
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DialError::ConnectionLimit(err) => Some(err),
+            DialError::Backoff { .. } => None,
+            DialError::Closing => None,
+            DialError::Denied(err) => Some(err),
+            DialError::Transport(err) => Some(err),
+        }
+    }
+}
+
 /// Parameters passed to `poll()`, that the `NetworkBehaviour` has access to.
 // TODO: #[derive(Debug)]
 pub struct PollParameters<'a: 'a> {
     local_peer_id: &'a PeerId,
     supported_protocols: &'a [Vec<u8>],
     listened_addrs: &'a [Multiaddr],
-    external_addrs: AddressIter<'a>
+    external_addrs: AddressIter<'a>,
+    peer_store: &'a mut PeerStore,
+    peers_protocols: &'a PeersProtocols,
+    dial_backoff: Option<&'a DialBackoffState>,
 }
 
 impl<'a> PollParameters<'a> {
@@ -385,10 +1208,45 @@ impl<'a> PollParameters<'a> {
     pub fn local_peer_id(&self) -> &PeerId {
         self.local_peer_id
     }
+
+    /// Returns the peer store shared between all the behaviours of the swarm.
+    pub fn peer_store(&self) -> &PeerStore {
+        self.peer_store
+    }
+
+    /// Returns the peer store shared between all the behaviours of the swarm, in order to record
+    /// information about peers.
+    pub fn peer_store_mut(&mut self) -> &mut PeerStore {
+        self.peer_store
+    }
+
+    /// Returns the protocols that a connected peer has been seen to support or to refuse.
+    ///
+    /// Returns `None` if we are not connected to the peer or if no protocol has been negotiated
+    /// with it yet.
+    pub fn peer_supported_protocols(&self, peer_id: &PeerId) -> Option<SupportedProtocols> {
+        self.peers_protocols.get(peer_id)
+    }
+
+    /// Returns the time until which dialing the given peer is refused, if it is in backoff after
+    /// previous failures.
+    ///
+    /// A `NetworkBehaviourAction::DialPeer` for such a peer immediately fails.
+    pub fn peer_backoff(&self, peer_id: &PeerId) -> Option<Instant> {
+        self.dial_backoff.and_then(|b| b.peer_backoff(peer_id, Instant::now()))
+    }
 }
 
 pub struct SwarmBuilder<TTransport, TBehaviour> {
     incoming_limit: Option<u32>,
+    listener_restart_policy: Option<ListenerRestartPolicy>,
+    dial_backoff: Option<DialBackoff>,
+    gater: Option<Arc<dyn ConnectionGater>>,
+    limits: ConnectionLimits,
+    substream_limits: InboundSubstreamLimits,
+    resource_manager: Option<ResourceManager>,
+    bandwidth: Option<ProtocolBandwidth>,
+    peer_store: PeerStore,
     local_peer_id: PeerId,
     transport: TTransport,
     behaviour: TBehaviour,
@@ -428,6 +1286,14 @@ where TBehaviour: NetworkBehaviour,
     pub fn new(transport: TTransport, behaviour: TBehaviour, local_peer_id: PeerId) -> Self {
         SwarmBuilder {
             incoming_limit: None,
+            listener_restart_policy: None,
+            dial_backoff: None,
+            gater: None,
+            limits: ConnectionLimits::default(),
+            substream_limits: InboundSubstreamLimits::default(),
+            resource_manager: None,
+            bandwidth: None,
+            peer_store: PeerStore::new(),
             local_peer_id,
             transport,
             behaviour,
@@ -439,6 +1305,82 @@ where TBehaviour: NetworkBehaviour,
         self
     }
 
+    /// Sets the limits on the number of connections of the swarm.
+    ///
+    /// Contrary to `incoming_limit`, which stops accepting connections from the listeners until
+    /// the number of incoming connections being negotiated decreases, connections that exceed
+    /// these limits are refused and reported to the behaviour.
+    pub fn connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the limits on the number of inbound substreams of each connection.
+    ///
+    /// Substreams that exceed these limits are reset and reported to the behaviour.
+    pub fn substream_limits(mut self, limits: InboundSubstreamLimits) -> Self {
+        self.substream_limits = limits;
+        self
+    }
+
+    /// Reserves every established connection and its substreams in the given `ResourceManager`.
+    ///
+    /// Connections that can't be reserved are closed, inbound substreams that can't be reserved
+    /// are reset and reported to the behaviour, and outbound substreams that can't be reserved
+    /// fail with `ProtocolsHandlerUpgrErr::ResourceLimit`.
+    pub fn resource_manager(mut self, manager: ResourceManager) -> Self {
+        self.resource_manager = Some(manager);
+        self
+    }
+
+    /// Counts the bytes that go through the substreams of every connection in `bandwidth`, per
+    /// peer and per negotiated protocol.
+    pub fn bandwidth(mut self, bandwidth: ProtocolBandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Restarts listeners that fail with an error according to the given policy, instead of
+    /// closing them.
+    ///
+    /// Failures are reported to the behaviour through `inject_listener_error`, and listeners that
+    /// are closed for good through `inject_listener_closed`.
+    pub fn listener_restart_policy(mut self, policy: ListenerRestartPolicy) -> Self {
+        self.listener_restart_policy = Some(policy);
+        self
+    }
+
+    /// Enables the backoff of the peers and addresses that failed to be dialed.
+    ///
+    /// While in backoff, dialing an address with `dial_addr` is refused with
+    /// `DialError::Backoff`, and dialing a peer skips the addresses in backoff. Dialing a peer
+    /// that is itself in backoff is immediately reported as a failure through
+    /// `inject_dial_failure`. Disabled by default.
+    pub fn dial_backoff(mut self, backoff: DialBackoff) -> Self {
+        self.dial_backoff = Some(backoff);
+        self
+    }
+
+    /// Sets the `ConnectionGater` that decides which connections the swarm opens and accepts.
+    ///
+    /// To also reject connections right after the security upgrade, pass the same gater to
+    /// `Transport::gate_secured` when building the transport.
+    pub fn connection_gater<G>(mut self, gater: Arc<G>) -> Self
+    where
+        G: ConnectionGater + 'static,
+    {
+        self.gater = Some(gater);
+        self
+    }
+
+    /// Sets the peer store the swarm starts with. Defaults to an empty store.
+    ///
+    /// If the store has a backend, the swarm periodically saves it.
+    pub fn peer_store(mut self, peer_store: PeerStore) -> Self {
+        self.peer_store = peer_store;
+        self
+    }
+
     pub fn build(mut self) -> Swarm<TTransport, TBehaviour> {
         let supported_protocols = self.behaviour
             .new_handler()
@@ -448,7 +1390,9 @@ where TBehaviour: NetworkBehaviour,
             .map(|info| info.protocol_name().to_vec())
             .collect();
 
-        let raw_swarm = RawSwarm::new_with_incoming_limit(self.transport, self.local_peer_id, self.incoming_limit);
+        let mut raw_swarm = RawSwarm::new_with_incoming_limit(self.transport, self.local_peer_id, self.incoming_limit);
+        raw_swarm.set_listener_restart_policy(self.listener_restart_policy);
+        let (refused_substreams_tx, refused_substreams_rx) = mpsc::unbounded();
 
         ExpandedSwarm {
             raw_swarm,
@@ -457,22 +1401,58 @@ where TBehaviour: NetworkBehaviour,
             listened_addrs: SmallVec::new(),
             external_addrs: Addresses::default(),
             banned_peers: HashSet::new(),
+            peer_store: self.peer_store,
+            handlers_shared: HandlersShared {
+                peers_protocols: PeersProtocols::default(),
+                substream_limits: Arc::new(self.substream_limits),
+                refused_substreams: refused_substreams_tx,
+                resource_manager: self.resource_manager,
+                bandwidth: self.bandwidth,
+            },
+            limits: self.limits,
+            refused_substreams: refused_substreams_rx,
+            dial_backoff: self.dial_backoff.map(DialBackoffState::new),
+            gater: self.gater,
+            close_state: CloseState::Open,
+            queued_events: VecDeque::new(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{identity, PeerId, PublicKey};
-    use crate::protocols_handler::{DummyProtocolsHandler, ProtocolsHandler};
-    use crate::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters, SwarmBuilder};
-    use crate::tests::dummy_transport::DummyTransport;
-    use futures::prelude::*;
+    use crate::{bandwidth::ProtocolBandwidth, identity, PeerId, PublicKey};
+    use crate::protocols_handler::{DummyProtocolsHandler, KeepAlive, OneShotHandler, ProtocolsHandler, ProtocolsHandlerEvent, ProtocolsHandlerUpgrErr, SubstreamProtocol};
+    use crate::swarm::{ConnectedPoint, ConnectionClosedReason, ConnectionGater, ConnectionId, ConnectionLimitError, ConnectionLimits, DialBackoff, DialError, DisconnectReason};
+    use crate::swarm::{AddressSource, ExpandedSwarm, NetworkBehaviour, NetworkBehaviourAction, PollParameters, SwarmBuilder, SwarmEvent};
+    use crate::tests::{dummy_muxer::{DummyConnectionState, DummyMuxer}, dummy_transport::{DummyTransport, ListenerState}};
+    use crate::transport::ListenerEvent;
+    use crate::upgrade::DeniedUpgrade;
+    use futures::{executor::{self, Notify}, future, prelude::*};
     use multiaddr::Multiaddr;
-    use std::marker::PhantomData;
+    use std::{marker::PhantomData, sync::Arc, time::{Duration, Instant}};
+    use tokio_executor::park::ParkThread;
     use tokio_io::{AsyncRead, AsyncWrite};
     use void::Void;
 
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+        fn notify(&self, _: usize) {}
+    }
+
+    /// Runs `future` to completion on the current thread, turning `timer` while it isn't ready.
+    fn run_with_timer<F: Future>(timer: &mut wasm_timer::Timer<ParkThread>, future: F) -> Result<F::Item, F::Error> {
+        let mut future = executor::spawn(future);
+        let notify = Arc::new(NoopNotify);
+        loop {
+            if let Async::Ready(item) = future.poll_future_notify(&notify, 0)? {
+                return Ok(item);
+            }
+            timer.turn(Some(Duration::from_millis(10))).unwrap();
+        }
+    }
+
     #[derive(Clone)]
     struct DummyBehaviour<TSubstream> {
         marker: PhantomData<TSubstream>,
@@ -495,11 +1475,11 @@ mod tests {
             Vec::new()
         }
 
-        fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {}
+        fn inject_connected(&mut self, _: PeerId, _: ConnectionId, _: ConnectedPoint) {}
 
-        fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+        fn inject_disconnected(&mut self, _: &PeerId, _: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {}
 
-        fn inject_node_event(&mut self, _: PeerId,
+        fn inject_node_event(&mut self, _: PeerId, _: ConnectionId,
             _: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent) {}
 
         fn poll(&mut self, _: &mut PollParameters<'_>) ->
@@ -511,6 +1491,88 @@ mod tests {
 
     }
 
+    /// Behaviour whose connections are kept alive for a minute, unless the swarm is closed.
+    struct IdleBehaviour<TSubstream> {
+        marker: PhantomData<TSubstream>,
+    }
+
+    impl<TSubstream> NetworkBehaviour for IdleBehaviour<TSubstream>
+        where TSubstream: AsyncRead + AsyncWrite
+    {
+        type ProtocolsHandler = IdleHandler<TSubstream>;
+        type OutEvent = Void;
+
+        fn new_handler(&mut self) -> Self::ProtocolsHandler {
+            IdleHandler {
+                keep_alive: KeepAlive::Until(Instant::now() + Duration::from_secs(60)),
+                marker: PhantomData,
+            }
+        }
+
+        fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+            Vec::new()
+        }
+
+        fn inject_connected(&mut self, _: PeerId, _: ConnectionId, _: ConnectedPoint) {}
+
+        fn inject_disconnected(&mut self, _: &PeerId, _: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {}
+
+        fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, event: Void) {
+            void::unreachable(event)
+        }
+
+        fn poll(&mut self, _: &mut PollParameters<'_>) ->
+            Async<NetworkBehaviourAction<Void, Self::OutEvent>>
+        {
+            Async::NotReady
+        }
+    }
+
+    struct IdleHandler<TSubstream> {
+        keep_alive: KeepAlive,
+        marker: PhantomData<TSubstream>,
+    }
+
+    impl<TSubstream> ProtocolsHandler for IdleHandler<TSubstream>
+        where TSubstream: AsyncRead + AsyncWrite
+    {
+        type InEvent = Void;
+        type OutEvent = Void;
+        type Error = Void;
+        type Substream = TSubstream;
+        type InboundProtocol = DeniedUpgrade;
+        type OutboundProtocol = DeniedUpgrade;
+        type OutboundOpenInfo = Void;
+
+        fn listen_protocol(&self) -> SubstreamProtocol<DeniedUpgrade> {
+            SubstreamProtocol::new(DeniedUpgrade)
+        }
+
+        fn inject_fully_negotiated_inbound(&mut self, out: Void) {
+            void::unreachable(out)
+        }
+
+        fn inject_fully_negotiated_outbound(&mut self, out: Void, _: Void) {
+            void::unreachable(out)
+        }
+
+        fn inject_event(&mut self, event: Void) {
+            void::unreachable(event)
+        }
+
+        fn inject_dial_upgrade_error(&mut self, info: Void, _: ProtocolsHandlerUpgrErr<Void>) {
+            void::unreachable(info)
+        }
+
+        fn connection_keep_alive(&self) -> KeepAlive {
+            self.keep_alive
+        }
+
+        fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<DeniedUpgrade, Void, Void>, Void> {
+            Ok(Async::NotReady)
+        }
+    }
+
     fn get_random_id() -> PublicKey {
         identity::Keypair::generate_ed25519().public()
     }
@@ -533,4 +1595,358 @@ mod tests {
         let swarm = SwarmBuilder::new(transport, behaviour, id.into()).build();
         assert!(swarm.raw_swarm.incoming_limit().is_none())
     }
+
+    /// Behaviour whose connections are kept alive, so that they keep counting against the
+    /// connection limits.
+    struct KeepAliveBehaviour<TSubstream> {
+        marker: PhantomData<TSubstream>,
+    }
+
+    impl<TSubstream> NetworkBehaviour for KeepAliveBehaviour<TSubstream>
+        where TSubstream: AsyncRead + AsyncWrite
+    {
+        type ProtocolsHandler = OneShotHandler<TSubstream, DeniedUpgrade, DeniedUpgrade, Void>;
+        type OutEvent = Void;
+
+        fn new_handler(&mut self) -> Self::ProtocolsHandler {
+            OneShotHandler::new(SubstreamProtocol::new(DeniedUpgrade), Duration::from_secs(60))
+        }
+
+        fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+            Vec::new()
+        }
+
+        fn inject_connected(&mut self, _: PeerId, _: ConnectionId, _: ConnectedPoint) {}
+
+        fn inject_disconnected(&mut self, _: &PeerId, _: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {}
+
+        fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, event: Void) {
+            void::unreachable(event)
+        }
+
+        fn poll(&mut self, _: &mut PollParameters<'_>) ->
+            Async<NetworkBehaviourAction<DeniedUpgrade, Self::OutEvent>>
+        {
+            Async::NotReady
+        }
+    }
+
+    #[test]
+    fn dial_refused_over_pending_outgoing_limit() {
+        let id = get_random_id();
+        let transport = DummyTransport::new();
+        let behaviour = DummyBehaviour{marker: PhantomData};
+        let limits = ConnectionLimits::default().with_max_pending_outgoing(Some(0));
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into())
+            .connection_limits(limits).build();
+        match ExpandedSwarm::dial_addr(&mut swarm, "/memory/1".parse().unwrap()) {
+            Err(DialError::ConnectionLimit(ConnectionLimitError::PendingOutgoing { limit: 0 })) => {},
+            _ => panic!("dial should have been refused"),
+        }
+    }
+
+    #[test]
+    fn max_established_incoming_counts_connections_of_the_same_peer() {
+        let id = get_random_id();
+        let remote = PeerId::random();
+        let upgrade = ListenerEvent::Upgrade {
+            upgrade: (remote.clone(), DummyMuxer::new()),
+            listen_addr: "/memory/1".parse().unwrap(),
+            remote_addr: "/memory/2".parse().unwrap(),
+        };
+        let mut transport = DummyTransport::new();
+        let new_address = ListenerEvent::NewAddress("/memory/1".parse().unwrap());
+        transport.set_initial_listener_state(ListenerState::Events(vec![new_address, upgrade.clone(), upgrade]));
+        let behaviour = KeepAliveBehaviour{marker: PhantomData};
+        let limits = ConnectionLimits::default().with_max_established_incoming(Some(1));
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into())
+            .connection_limits(limits).build();
+        ExpandedSwarm::listen_on(&mut swarm, "/memory/1".parse().unwrap()).unwrap();
+
+        let mut established = 0;
+        loop {
+            let event = future::poll_fn(|| -> Poll<_, ()> {
+                Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+            }).wait().unwrap();
+            match event {
+                SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                    assert_eq!(peer_id, remote);
+                    established += 1;
+                    assert_eq!(established, 1, "the second connection should have been refused");
+                },
+                SwarmEvent::ConnectionRefused { peer_id, error, .. } => {
+                    assert_eq!(peer_id, Some(remote));
+                    assert_eq!(error, ConnectionLimitError::EstablishedIncoming { limit: 1 });
+                    break;
+                },
+                _ => {},
+            }
+        }
+        assert_eq!(established, 1);
+    }
+
+    #[test]
+    fn disconnect_forgets_peer_state() {
+        let id = get_random_id();
+        let remote = PeerId::random();
+        let upgrade = ListenerEvent::Upgrade {
+            upgrade: (remote.clone(), DummyMuxer::new()),
+            listen_addr: "/memory/1".parse().unwrap(),
+            remote_addr: "/memory/2".parse().unwrap(),
+        };
+        let mut transport = DummyTransport::new();
+        let new_address = ListenerEvent::NewAddress("/memory/1".parse().unwrap());
+        transport.set_initial_listener_state(ListenerState::Events(vec![new_address, upgrade]));
+        let behaviour = KeepAliveBehaviour{marker: PhantomData};
+        let bandwidth = ProtocolBandwidth::new(Duration::from_secs(1));
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into())
+            .bandwidth(bandwidth.clone()).build();
+        ExpandedSwarm::listen_on(&mut swarm, "/memory/1".parse().unwrap()).unwrap();
+
+        loop {
+            let event = future::poll_fn(|| -> Poll<_, ()> {
+                Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+            }).wait().unwrap();
+            if let SwarmEvent::ConnectionEstablished { .. } = event {
+                break;
+            }
+        }
+
+        swarm.handlers_shared.peers_protocols.update(&remote, |p| p.add_supported(b"/a".to_vec()));
+        bandwidth.substream_traffic(&remote, b"/a");
+        assert!(bandwidth.peer(&remote).is_some());
+        ExpandedSwarm::disconnect(&mut swarm, &remote, None, || ConnectionClosedReason::Behaviour);
+        assert!(swarm.handlers_shared.peers_protocols.get(&remote).is_none());
+        assert!(bandwidth.peer(&remote).is_none());
+    }
+
+    #[test]
+    fn banning_reports_connection_closed() {
+        let id = get_random_id();
+        let remote = PeerId::random();
+        let upgrade = ListenerEvent::Upgrade {
+            upgrade: (remote.clone(), DummyMuxer::new()),
+            listen_addr: "/memory/1".parse().unwrap(),
+            remote_addr: "/memory/2".parse().unwrap(),
+        };
+        let mut transport = DummyTransport::new();
+        let new_address = ListenerEvent::NewAddress("/memory/1".parse().unwrap());
+        transport.set_initial_listener_state(ListenerState::Events(vec![new_address, upgrade]));
+        let behaviour = KeepAliveBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into()).build();
+        ExpandedSwarm::listen_on(&mut swarm, "/memory/1".parse().unwrap()).unwrap();
+
+        let established = loop {
+            let event = future::poll_fn(|| -> Poll<_, ()> {
+                Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+            }).wait().unwrap();
+            if let SwarmEvent::ConnectionEstablished { connection_id, .. } = event {
+                break connection_id;
+            }
+        };
+
+        ExpandedSwarm::ban_peer_id(&mut swarm, remote.clone());
+        let event = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+        match event {
+            SwarmEvent::ConnectionClosed { peer_id, connection_id, reason: ConnectionClosedReason::Banned, .. } => {
+                assert_eq!(peer_id, remote);
+                assert_eq!(connection_id, established);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn dial_denied_by_gater() {
+        struct DenyAll;
+        impl ConnectionGater for DenyAll {
+            fn intercept_dial(&self, _: Option<&PeerId>, _: &Multiaddr) -> bool {
+                false
+            }
+        }
+
+        let id = get_random_id();
+        let transport = DummyTransport::new();
+        let behaviour = DummyBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into())
+            .connection_gater(Arc::new(DenyAll)).build();
+        match ExpandedSwarm::dial_addr(&mut swarm, "/memory/1".parse().unwrap()) {
+            Err(DialError::Denied(_)) => {},
+            _ => panic!("dial should have been denied"),
+        }
+    }
+
+    #[test]
+    fn failed_address_is_in_backoff() {
+        let id = get_random_id();
+        let mut transport = DummyTransport::new();
+        transport.make_dial_fail();
+        let behaviour = DummyBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into())
+            .dial_backoff(DialBackoff::default()).build();
+        let addr: Multiaddr = "/memory/1".parse().unwrap();
+
+        ExpandedSwarm::dial_addr(&mut swarm, addr.clone()).unwrap();
+        assert!(ExpandedSwarm::address_backoff(&swarm, &addr).is_none());
+        let event = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+        match event {
+            SwarmEvent::UnknownPeerUnreachableAddr { address, .. } => assert_eq!(address, addr),
+            _ => panic!("expected the dial to fail"),
+        }
+
+        let until = ExpandedSwarm::address_backoff(&swarm, &addr).expect("address in backoff");
+        match ExpandedSwarm::dial_addr(&mut swarm, addr) {
+            Err(DialError::Backoff { until: u }) => assert_eq!(u, until),
+            _ => panic!("dial should have been refused"),
+        }
+    }
+
+    #[test]
+    fn dialing_peer_in_backoff_is_reported() {
+        let id = get_random_id();
+        let mut transport = DummyTransport::new();
+        transport.make_dial_fail();
+        let behaviour = DummyBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into())
+            .dial_backoff(DialBackoff::default()).build();
+        let peer = PeerId::random();
+        ExpandedSwarm::peer_store_mut(&mut swarm)
+            .add_address(&peer, "/memory/1".parse().unwrap(), AddressSource::Manual, None);
+
+        ExpandedSwarm::dial(&mut swarm, peer.clone());
+        let event = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+        match event {
+            SwarmEvent::UnreachableAddr { peer_id, attempts_remaining: 0, .. } => assert_eq!(peer_id, peer),
+            event => panic!("expected the dial to fail: {:?}", event),
+        }
+
+        let until = ExpandedSwarm::peer_backoff(&swarm, &peer).expect("peer in backoff");
+        ExpandedSwarm::dial(&mut swarm, peer.clone());
+        let event = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+        match event {
+            SwarmEvent::DialBackoff { peer_id, until: u } => {
+                assert_eq!(peer_id, peer);
+                assert_eq!(u, until);
+            },
+            event => panic!("expected the dial to be refused: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn poll_swarm_event_reports_listen_addrs() {
+        let id = get_random_id();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+        let mut transport = DummyTransport::new();
+        transport.set_initial_listener_state(ListenerState::Events(vec![ListenerEvent::NewAddress(addr.clone())]));
+        let behaviour = DummyBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into()).build();
+        let listener = ExpandedSwarm::listen_on(&mut swarm, "/memory/0".parse().unwrap()).unwrap();
+
+        let event = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+        match event {
+            SwarmEvent::NewListenAddr { listener_id, address } => {
+                assert_eq!(listener_id, listener);
+                assert_eq!(address, addr);
+            },
+            _ => panic!("expected a new listen address"),
+        }
+        assert_eq!(ExpandedSwarm::listeners(&swarm).collect::<Vec<_>>(), vec![&addr]);
+    }
+
+    #[test]
+    fn close_removes_listeners_and_ends_the_stream() {
+        let id = get_random_id();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+        let mut transport = DummyTransport::new();
+        transport.set_initial_listener_state(ListenerState::Events(vec![ListenerEvent::NewAddress(addr.clone())]));
+        let behaviour = DummyBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into()).build();
+        ExpandedSwarm::listen_on(&mut swarm, "/memory/0".parse().unwrap()).unwrap();
+        let _ = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+
+        ExpandedSwarm::close(&mut swarm, Duration::from_secs(5));
+        assert!(ExpandedSwarm::is_closing(&swarm));
+        assert_eq!(ExpandedSwarm::listeners(&swarm).count(), 0);
+        match ExpandedSwarm::dial_addr(&mut swarm, addr) {
+            Err(DialError::Closing) => {},
+            _ => panic!("expected dialing to be refused"),
+        }
+
+        let event = future::poll_fn(|| -> Poll<_, ()> {
+            Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+        }).wait().unwrap();
+        match event {
+            SwarmEvent::Closed => {},
+            _ => panic!("expected the swarm to be closed"),
+        }
+        assert!(swarm.wait().next().is_none());
+    }
+
+    #[test]
+    fn close_waits_for_the_muxers_to_close() {
+        let id = get_random_id();
+        let remote = PeerId::random();
+        let mut muxer = DummyMuxer::new();
+        muxer.set_close_state(DummyConnectionState::Pending);
+        let upgrade = ListenerEvent::Upgrade {
+            upgrade: (remote.clone(), muxer),
+            listen_addr: "/memory/1".parse().unwrap(),
+            remote_addr: "/memory/2".parse().unwrap(),
+        };
+        let mut transport = DummyTransport::new();
+        let new_address = ListenerEvent::NewAddress("/memory/1".parse().unwrap());
+        transport.set_initial_listener_state(ListenerState::Events(vec![new_address, upgrade]));
+        let behaviour = IdleBehaviour{marker: PhantomData};
+        let mut swarm = SwarmBuilder::new(transport, behaviour, id.into()).build();
+        ExpandedSwarm::listen_on(&mut swarm, "/memory/1".parse().unwrap()).unwrap();
+
+        // Without an executor, the node tasks are polled by the swarm itself. The deadline of
+        // `close` needs a timer, which we drive manually.
+        let mut timer = wasm_timer::Timer::default();
+        let handle = timer.handle();
+        let mut enter = tokio_executor::enter().unwrap();
+        let timeout = Duration::from_millis(200);
+        let started = wasm_timer::with_default(&handle, &mut enter, |_| {
+            loop {
+                let event = run_with_timer(&mut timer, future::poll_fn(|| -> Poll<_, ()> {
+                    Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+                })).unwrap();
+                if let SwarmEvent::ConnectionEstablished { .. } = event {
+                    break;
+                }
+            }
+
+            let started = Instant::now();
+            ExpandedSwarm::close(&mut swarm, timeout);
+            match run_with_timer(&mut timer, future::poll_fn(|| -> Poll<_, ()> {
+                Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+            })).unwrap() {
+                SwarmEvent::ConnectionClosed { reason: ConnectionClosedReason::Closing, .. } => {},
+                event => panic!("unexpected event: {:?}", event),
+            }
+            assert!(started.elapsed() < timeout);
+            loop {
+                let event = run_with_timer(&mut timer, future::poll_fn(|| -> Poll<_, ()> {
+                    Ok(ExpandedSwarm::poll_swarm_event(&mut swarm))
+                })).unwrap();
+                if let SwarmEvent::Closed = event {
+                    break started;
+                }
+            }
+        });
+        // The muxer never finishes closing, hence the swarm waits until the deadline.
+        assert!(started.elapsed() >= timeout);
+    }
 }
@@ -28,7 +28,7 @@ use crate::{
         ProtocolsHandlerUpgrErr,
         IntoProtocolsHandler
     },
-    swarm::{NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess},
+    swarm::{ConnectionId, ConnectionLimitError, DisconnectReason, ListenerId, NetworkBehaviour, NetworkBehaviourAction, NetworkBehaviourEventProcess, SubstreamLimitError},
     upgrade::{InboundUpgrade, OutboundUpgrade, DeniedUpgrade, EitherUpgrade},
     PeerId, Multiaddr, nodes::ConnectedPoint, swarm::PollParameters,
 };
@@ -65,31 +65,26 @@ where
         self.inner.as_mut().map(|b| b.addresses_of_peer(peer_id)).unwrap_or_else(Vec::new)
     }
 
-    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+    fn inject_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint) {
         if let Some(inner) = self.inner.as_mut() {
-            inner.inject_connected(peer_id, endpoint)
+            inner.inject_connected(peer_id, connection_id, endpoint)
         }
     }
 
-    fn inject_disconnected(&mut self, peer_id: &PeerId, endpoint: ConnectedPoint) {
+    fn inject_disconnected(&mut self, peer_id: &PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint, reason: DisconnectReason<'_>) {
         if let Some(inner) = self.inner.as_mut() {
-            inner.inject_disconnected(peer_id, endpoint)
-        }
-    }
-
-    fn inject_replaced(&mut self, peer_id: PeerId, closed_endpoint: ConnectedPoint, new_endpoint: ConnectedPoint) {
-        if let Some(inner) = self.inner.as_mut() {
-            inner.inject_replaced(peer_id, closed_endpoint, new_endpoint)
+            inner.inject_disconnected(peer_id, connection_id, endpoint, reason)
         }
     }
 
     fn inject_node_event(
         &mut self,
         peer_id: PeerId,
+        connection_id: ConnectionId,
         event: <<Self::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent
     ) {
         if let Some(inner) = self.inner.as_mut() {
-            inner.inject_node_event(peer_id, event);
+            inner.inject_node_event(peer_id, connection_id, event);
         }
     }
 
@@ -105,6 +100,18 @@ where
         }
     }
 
+    fn inject_connection_refused(&mut self, peer_id: Option<&PeerId>, endpoint: &ConnectedPoint, error: &ConnectionLimitError) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.inject_connection_refused(peer_id, endpoint, error)
+        }
+    }
+
+    fn inject_substream_refused(&mut self, peer_id: &PeerId, error: &SubstreamLimitError) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.inject_substream_refused(peer_id, error)
+        }
+    }
+
     fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
         if let Some(inner) = self.inner.as_mut() {
             inner.inject_new_listen_addr(addr)
@@ -117,6 +124,24 @@ where
         }
     }
 
+    fn inject_listener_error(&mut self, id: ListenerId, error: &dyn error::Error) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.inject_listener_error(id, error)
+        }
+    }
+
+    fn inject_listener_closed(&mut self, id: ListenerId, reason: Result<(), &dyn error::Error>) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.inject_listener_closed(id, reason)
+        }
+    }
+
+    fn inject_closing(&mut self) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.inject_closing()
+        }
+    }
+
     fn inject_new_external_addr(&mut self, addr: &Multiaddr) {
         if let Some(inner) = self.inner.as_mut() {
             inner.inject_new_external_addr(addr)
@@ -51,6 +51,7 @@ struct DummyConnection {
 pub struct DummyMuxer{
     in_connection: DummyConnection,
     out_connection: DummyConnection,
+    close: DummyConnection,
 }
 
 impl DummyMuxer {
@@ -64,6 +65,9 @@ impl DummyMuxer {
             out_connection: DummyConnection {
                 state: DummyConnectionState::Pending,
             },
+            close: DummyConnection {
+                state: DummyConnectionState::Opened,
+            },
         }
     }
     /// Set the muxer state inbound "connection" state
@@ -74,6 +78,10 @@ impl DummyMuxer {
     pub fn set_outbound_connection_state(&mut self, state: DummyConnectionState) {
         self.out_connection.state = state
     }
+    /// Set the state of closing the muxer. `Pending` means that closing never finishes.
+    pub fn set_close_state(&mut self, state: DummyConnectionState) {
+        self.close.state = state
+    }
 }
 
 impl StreamMuxer for DummyMuxer {
@@ -114,7 +122,10 @@ impl StreamMuxer for DummyMuxer {
     fn destroy_substream(&self, _: Self::Substream) {}
     fn is_remote_acknowledged(&self) -> bool { true }
     fn close(&self) -> Poll<(), IoError> {
-        Ok(Async::Ready(()))
+        match self.close.state {
+            DummyConnectionState::Pending => Ok(Async::NotReady),
+            DummyConnectionState::Opened => Ok(Async::Ready(())),
+        }
     }
     fn flush_all(&self) -> Poll<(), IoError> {
         Ok(Async::Ready(()))
@@ -0,0 +1,182 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{
+    PeerId,
+    either::EitherError,
+    nodes::raw_swarm::ConnectedPoint,
+    swarm::{ConnectionDenied, ConnectionGater},
+    transport::{Transport, TransportError, ListenerEvent}
+};
+use futures::{prelude::*, try_ready};
+use multiaddr::Multiaddr;
+use std::{fmt, sync::Arc};
+
+/// See `Transport::gate_secured`.
+pub struct GateSecured<T, G: ?Sized> { transport: T, gater: Arc<G> }
+
+impl<T, G: ?Sized> GateSecured<T, G> {
+    pub(crate) fn new(transport: T, gater: Arc<G>) -> Self {
+        GateSecured { transport, gater }
+    }
+}
+
+impl<T: Clone, G: ?Sized> Clone for GateSecured<T, G> {
+    fn clone(&self) -> Self {
+        GateSecured { transport: self.transport.clone(), gater: self.gater.clone() }
+    }
+}
+
+impl<T: fmt::Debug, G: ?Sized> fmt::Debug for GateSecured<T, G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GateSecured").field("transport", &self.transport).finish()
+    }
+}
+
+impl<T, G, O> Transport for GateSecured<T, G>
+where
+    T: Transport<Output = (PeerId, O)>,
+    G: ConnectionGater + ?Sized,
+{
+    type Output = (PeerId, O);
+    type Error = EitherError<T::Error, ConnectionDenied>;
+    type Listener = GateSecuredStream<T::Listener, G>;
+    type ListenerUpgrade = GateSecuredFuture<T::ListenerUpgrade, G>;
+    type Dial = GateSecuredFuture<T::Dial, G>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        let stream = self.transport.listen_on(addr).map_err(|err| err.map(EitherError::A))?;
+        Ok(GateSecuredStream { stream, gater: self.gater })
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let future = self.transport.dial(addr.clone()).map_err(|err| err.map(EitherError::A))?;
+        let endpoint = ConnectedPoint::Dialer { address: addr };
+        Ok(GateSecuredFuture { inner: future, gater: self.gater, endpoint })
+    }
+}
+
+/// Custom `Stream` to avoid boxing.
+///
+/// Applies the gater to every incoming connection.
+pub struct GateSecuredStream<T, G: ?Sized> { stream: T, gater: Arc<G> }
+
+impl<T, G, X> Stream for GateSecuredStream<T, G>
+where
+    T: Stream<Item = ListenerEvent<X>>,
+    G: ?Sized,
+{
+    type Item = ListenerEvent<GateSecuredFuture<X, G>>;
+    type Error = EitherError<T::Error, ConnectionDenied>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.stream.poll().map_err(EitherError::A)? {
+            Async::Ready(Some(event)) => {
+                let event = match event {
+                    ListenerEvent::Upgrade { upgrade, listen_addr, remote_addr } => {
+                        let endpoint = ConnectedPoint::Listener {
+                            listen_addr: listen_addr.clone(),
+                            send_back_addr: remote_addr.clone()
+                        };
+                        ListenerEvent::Upgrade {
+                            upgrade: GateSecuredFuture {
+                                inner: upgrade,
+                                gater: self.gater.clone(),
+                                endpoint
+                            },
+                            listen_addr,
+                            remote_addr
+                        }
+                    }
+                    ListenerEvent::NewAddress(a) => ListenerEvent::NewAddress(a),
+                    ListenerEvent::AddressExpired(a) => ListenerEvent::AddressExpired(a)
+                };
+                Ok(Async::Ready(Some(event)))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Custom `Future` to avoid boxing.
+///
+/// Rejects the connection produced by the inner future if the gater denies it.
+pub struct GateSecuredFuture<T, G: ?Sized> {
+    inner: T,
+    gater: Arc<G>,
+    endpoint: ConnectedPoint
+}
+
+impl<T, G, O> Future for GateSecuredFuture<T, G>
+where
+    T: Future<Item = (PeerId, O)>,
+    G: ConnectionGater + ?Sized,
+{
+    type Item = (PeerId, O);
+    type Error = EitherError<T::Error, ConnectionDenied>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (peer_id, output) = try_ready!(self.inner.poll().map_err(EitherError::A));
+        if self.gater.intercept_secured(&peer_id, &self.endpoint) {
+            Ok(Async::Ready((peer_id, output)))
+        } else {
+            Err(EitherError::B(ConnectionDenied))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GateSecuredFuture;
+    use crate::{PeerId, nodes::raw_swarm::ConnectedPoint, swarm::ConnectionGater};
+    use futures::{future, prelude::*};
+    use std::sync::Arc;
+
+    struct DenyPeer(PeerId);
+
+    impl ConnectionGater for DenyPeer {
+        fn intercept_secured(&self, peer_id: &PeerId, _: &ConnectedPoint) -> bool {
+            *peer_id != self.0
+        }
+    }
+
+    #[test]
+    fn denied_peer_is_rejected() {
+        let denied = PeerId::random();
+        let gater = Arc::new(DenyPeer(denied.clone()));
+        let endpoint = ConnectedPoint::Dialer { address: "/memory/1".parse().unwrap() };
+
+        let allowed = PeerId::random();
+        let future = GateSecuredFuture {
+            inner: future::ok::<_, ()>((allowed.clone(), ())),
+            gater: gater.clone(),
+            endpoint: endpoint.clone(),
+        };
+        assert_eq!(future.wait().ok().map(|(peer_id, ())| peer_id), Some(allowed));
+
+        let future = GateSecuredFuture {
+            inner: future::ok::<_, ()>((denied, ())),
+            gater,
+            endpoint,
+        };
+        assert!(future.wait().is_err());
+    }
+}
@@ -25,10 +25,10 @@
 //! any desired protocols. The rest of the module defines combinators for
 //! modifying a transport through composition with other transports or protocol upgrades.
 
-use crate::{InboundUpgrade, OutboundUpgrade, nodes::raw_swarm::ConnectedPoint};
+use crate::{InboundUpgrade, OutboundUpgrade, nodes::raw_swarm::ConnectedPoint, swarm::ConnectionGater};
 use futures::prelude::*;
 use multiaddr::Multiaddr;
-use std::{error, fmt};
+use std::{error, fmt, sync::Arc};
 use std::time::Duration;
 use tokio_io::{AsyncRead, AsyncWrite};
 
@@ -36,6 +36,7 @@ pub mod and_then;
 pub mod boxed;
 pub mod choice;
 pub mod dummy;
+pub mod gate;
 pub mod map;
 pub mod map_err;
 pub mod memory;
@@ -199,6 +200,20 @@ pub trait Transport {
         and_then::AndThen::new(self, upgrade)
     }
 
+    /// Lets the given `ConnectionGater` reject connections once the remote has been
+    /// authenticated, through `ConnectionGater::intercept_secured`.
+    ///
+    /// This is meant to be applied right after the security upgrade, once the output of the
+    /// transport contains the ID of the remote, so that the muxer isn't negotiated on rejected
+    /// connections.
+    fn gate_secured<G>(self, gater: Arc<G>) -> gate::GateSecured<Self, G>
+    where
+        Self: Sized,
+        G: ConnectionGater + ?Sized,
+    {
+        gate::GateSecured::new(self, gater)
+    }
+
     /// Adds a timeout to the connection setup (including upgrades) for all inbound
     /// and outbound connection attempts.
     fn with_timeout(self, timeout: Duration) -> timeout::TransportTimeout<Self>
@@ -59,6 +59,8 @@ enum WriteOneInner<TSocket, TData> {
     WriteLen(io::WriteAll<TSocket, io::Window<[u8; 10]>>, TData),
     /// We need to write the actual data to the socket.
     Write(io::WriteAll<TSocket, TData>),
+    /// We need to flush the socket, as shutting it down doesn't necessarily flush it.
+    Flush(io::Flush<TSocket>),
     /// We need to shut down the socket.
     Shutdown(io::Shutdown<TSocket>),
     /// A problem happened during the processing.
@@ -100,12 +102,20 @@ where
                 },
                 WriteOneInner::Write(mut inner) => match inner.poll()? {
                     Async::Ready((socket, _)) => {
-                        *self = WriteOneInner::Shutdown(tokio_io::io::shutdown(socket));
+                        *self = WriteOneInner::Flush(io::flush(socket));
                     }
                     Async::NotReady => {
                         *self = WriteOneInner::Write(inner);
                     }
                 },
+                WriteOneInner::Flush(mut inner) => match inner.poll()? {
+                    Async::Ready(socket) => {
+                        *self = WriteOneInner::Shutdown(tokio_io::io::shutdown(socket));
+                    }
+                    Async::NotReady => {
+                        *self = WriteOneInner::Flush(inner);
+                    }
+                },
                 WriteOneInner::Shutdown(ref mut inner) => {
                     let socket = try_ready!(inner.poll());
                     return Ok(Async::Ready(socket));
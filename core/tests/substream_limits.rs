@@ -0,0 +1,129 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use futures::{future, prelude::*};
+use libp2p_core::{
+    Multiaddr,
+    PeerId,
+    Swarm,
+    identity,
+    muxing::StreamMuxer,
+    swarm::{InboundSubstreamLimits, SwarmBuilder},
+    upgrade::{self, OutboundUpgradeExt, InboundUpgradeExt},
+    transport::Transport
+};
+use libp2p_ping::{Ping, PingConfig, PingEvent, PingFailure};
+use libp2p_mplex::MplexConfig;
+use libp2p_secio::SecioConfig;
+use libp2p_tcp::TcpConfig;
+use std::{fmt, io, num::NonZeroU32, time::Duration, sync::mpsc::sync_channel};
+use tokio::runtime::Runtime;
+
+#[test]
+fn refused_substream_fails_on_the_remote() {
+    // The listener refuses every inbound substream. The pings of the dialer must fail as soon as
+    // the substream is reset, not after the ping timeout.
+    let cfg = PingConfig::new()
+        .with_keep_alive(true)
+        .with_timeout(Duration::from_secs(30))
+        .with_max_failures(NonZeroU32::new(2).unwrap());
+
+    let (peer1_id, trans) = mk_transport();
+    let limits = InboundSubstreamLimits::default().with_max_active(Some(0));
+    let mut swarm1 = SwarmBuilder::new(trans, Ping::new(cfg.clone()), peer1_id)
+        .substream_limits(limits)
+        .build();
+
+    let (peer2_id, trans) = mk_transport();
+    let mut swarm2 = Swarm::new(trans, Ping::new(cfg), peer2_id);
+
+    let (tx, rx) = sync_channel::<Multiaddr>(1);
+
+    let addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+    let mut listening = false;
+    Swarm::listen_on(&mut swarm1, addr).unwrap();
+    let peer1 = future::poll_fn(move || -> Poll<(), ()> {
+        loop {
+            match swarm1.poll().expect("Error while polling swarm") {
+                Async::Ready(Some(_)) => {},
+                _ => {
+                    if !listening {
+                        for l in Swarm::listeners(&swarm1) {
+                            tx.send(l.clone()).unwrap();
+                            listening = true;
+                        }
+                    }
+                    return Ok(Async::NotReady)
+                }
+            }
+        }
+    });
+
+    let mut dialing = false;
+    let peer2 = future::poll_fn(move || -> Result<_, ()> {
+        loop {
+            match swarm2.poll().expect("Error while polling swarm") {
+                Async::Ready(Some(PingEvent { result: Err(failure), .. })) =>
+                    return Ok(Async::Ready(failure)),
+                Async::Ready(Some(_)) => {},
+                _ => {
+                    if !dialing {
+                        Swarm::dial_addr(&mut swarm2, rx.recv().unwrap()).unwrap();
+                        dialing = true;
+                    }
+                    return Ok(Async::NotReady)
+                }
+            }
+        }
+    });
+
+    let timeout = tokio::timer::Delay::new(std::time::Instant::now() + Duration::from_secs(10));
+    let result = peer1.select2(peer2).select2(timeout).map_err(|_| panic!());
+    match Runtime::new().unwrap().block_on(result).unwrap() {
+        future::Either::A((future::Either::B((PingFailure::Other { .. }, _)), _)) => {},
+        future::Either::A((future::Either::B((PingFailure::Timeout, _)), _)) =>
+            panic!("The ping should have failed before timing out"),
+        future::Either::A((future::Either::A(_), _)) => unreachable!(),
+        future::Either::B(_) => panic!("The ping should have failed"),
+    }
+}
+
+fn mk_transport() -> (PeerId, impl Transport<
+    Output = (PeerId, impl StreamMuxer<Substream = impl Send, OutboundSubstream = impl Send, Error = impl Into<io::Error>>),
+    Listener = impl Send,
+    ListenerUpgrade = impl Send,
+    Dial = impl Send,
+    Error = impl fmt::Debug
+> + Clone) {
+    let id_keys = identity::Keypair::generate_ed25519();
+    let peer_id = id_keys.public().into_peer_id();
+    let transport = TcpConfig::new()
+        .nodelay(true)
+        .with_upgrade(SecioConfig::new(id_keys))
+        .and_then(move |out, endpoint| {
+            let peer_id = out.remote_key.into_peer_id();
+            let peer_id2 = peer_id.clone();
+            let upgrade = MplexConfig::default()
+                .map_outbound(move |muxer| (peer_id, muxer))
+                .map_inbound(move |muxer| (peer_id2, muxer));
+            upgrade::apply(out.stream, upgrade, endpoint)
+        });
+    (peer_id, transport)
+}
@@ -56,6 +56,11 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
     let into_proto_select_ident = quote!{::libp2p::core::protocols_handler::IntoProtocolsHandlerSelect};
     let peer_id = quote!{::libp2p::core::PeerId};
     let connected_point = quote!{::libp2p::core::swarm::ConnectedPoint};
+    let connection_id = quote!{::libp2p::core::swarm::ConnectionId};
+    let disconnect_reason = quote!{::libp2p::core::swarm::DisconnectReason};
+    let connection_limit_error = quote!{::libp2p::core::swarm::ConnectionLimitError};
+    let substream_limit_error = quote!{::libp2p::core::swarm::SubstreamLimitError};
+    let listener_id = quote!{::libp2p::core::swarm::ListenerId};
 
     // Name of the type parameter that represents the substream.
     let substream_generic = {
@@ -154,13 +159,13 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
 
             Some(if field_n == num_fields - 1 {
                 match field.ident {
-                    Some(ref i) => quote!{ self.#i.inject_connected(peer_id, endpoint); },
-                    None => quote!{ self.#field_n.inject_connected(peer_id, endpoint); },
+                    Some(ref i) => quote!{ self.#i.inject_connected(peer_id, connection_id, endpoint); },
+                    None => quote!{ self.#field_n.inject_connected(peer_id, connection_id, endpoint); },
                 }
             } else {
                 match field.ident {
-                    Some(ref i) => quote!{ self.#i.inject_connected(peer_id.clone(), endpoint.clone()); },
-                    None => quote!{ self.#field_n.inject_connected(peer_id.clone(), endpoint.clone()); },
+                    Some(ref i) => quote!{ self.#i.inject_connected(peer_id.clone(), connection_id, endpoint.clone()); },
+                    None => quote!{ self.#field_n.inject_connected(peer_id.clone(), connection_id, endpoint.clone()); },
                 }
             })
         })
@@ -176,68 +181,70 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
 
             Some(if field_n == num_fields - 1 {
                 match field.ident {
-                    Some(ref i) => quote!{ self.#i.inject_disconnected(peer_id, endpoint); },
-                    None => quote!{ self.#field_n.inject_disconnected(peer_id, endpoint); },
+                    Some(ref i) => quote!{ self.#i.inject_disconnected(peer_id, connection_id, endpoint, reason); },
+                    None => quote!{ self.#field_n.inject_disconnected(peer_id, connection_id, endpoint, reason); },
                 }
             } else {
                 match field.ident {
-                    Some(ref i) => quote!{ self.#i.inject_disconnected(peer_id, endpoint.clone()); },
-                    None => quote!{ self.#field_n.inject_disconnected(peer_id, endpoint.clone()); },
+                    Some(ref i) => quote!{ self.#i.inject_disconnected(peer_id, connection_id, endpoint.clone(), reason); },
+                    None => quote!{ self.#field_n.inject_disconnected(peer_id, connection_id, endpoint.clone(), reason); },
                 }
             })
         })
     };
 
-    // Build the list of statements to put in the body of `inject_replaced()`.
-    let inject_replaced_stmts = {
-        let num_fields = data_struct.fields.iter().filter(|f| !is_ignored(f)).count();
+    // Build the list of statements to put in the body of `inject_addr_reach_failure()`.
+    let inject_addr_reach_failure_stmts = {
         data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
             if is_ignored(&field) {
                 return None;
             }
 
-            Some(if field_n == num_fields - 1 {
-                match field.ident {
-                    Some(ref i) => quote!{ self.#i.inject_replaced(peer_id, closed_endpoint, new_endpoint); },
-                    None => quote!{ self.#field_n.inject_replaced(peer_id, closed_endpoint, new_endpoint); },
-                }
-            } else {
-                match field.ident {
-                    Some(ref i) => quote!{
-                        self.#i.inject_replaced(peer_id.clone(), closed_endpoint.clone(), new_endpoint.clone());
-                    },
-                    None => quote!{
-                        self.#field_n.inject_replaced(peer_id.clone(), closed_endpoint.clone(), new_endpoint.clone());
-                    },
-                }
+            Some(match field.ident {
+                Some(ref i) => quote!{ self.#i.inject_addr_reach_failure(peer_id, addr, error); },
+                None => quote!{ self.#field_n.inject_addr_reach_failure(peer_id, addr, error); },
             })
         })
     };
 
-    // Build the list of statements to put in the body of `inject_addr_reach_failure()`.
-    let inject_addr_reach_failure_stmts = {
+    // Build the list of statements to put in the body of `inject_dial_failure()`.
+    let inject_dial_failure_stmts = {
         data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
             if is_ignored(&field) {
                 return None;
             }
 
             Some(match field.ident {
-                Some(ref i) => quote!{ self.#i.inject_addr_reach_failure(peer_id, addr, error); },
-                None => quote!{ self.#field_n.inject_addr_reach_failure(peer_id, addr, error); },
+                Some(ref i) => quote!{ self.#i.inject_dial_failure(peer_id); },
+                None => quote!{ self.#field_n.inject_dial_failure(peer_id); },
             })
         })
     };
 
-    // Build the list of statements to put in the body of `inject_dial_failure()`.
-    let inject_dial_failure_stmts = {
+    // Build the list of statements to put in the body of `inject_connection_refused()`.
+    let inject_connection_refused_stmts = {
         data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
             if is_ignored(&field) {
                 return None;
             }
 
             Some(match field.ident {
-                Some(ref i) => quote!{ self.#i.inject_dial_failure(peer_id); },
-                None => quote!{ self.#field_n.inject_dial_failure(peer_id); },
+                Some(ref i) => quote!{ self.#i.inject_connection_refused(peer_id, endpoint, error); },
+                None => quote!{ self.#field_n.inject_connection_refused(peer_id, endpoint, error); },
+            })
+        })
+    };
+
+    // Build the list of statements to put in the body of `inject_substream_refused()`.
+    let inject_substream_refused_stmts = {
+        data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
+            if is_ignored(&field) {
+                return None;
+            }
+
+            Some(match field.ident {
+                Some(ref i) => quote!{ self.#i.inject_substream_refused(peer_id, error); },
+                None => quote!{ self.#field_n.inject_substream_refused(peer_id, error); },
             })
         })
     };
@@ -270,6 +277,48 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
         })
     };
 
+    // Build the list of statements to put in the body of `inject_listener_error()`.
+    let inject_listener_error_stmts = {
+        data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
+            if is_ignored(&field) {
+                return None;
+            }
+
+            Some(match field.ident {
+                Some(ref i) => quote!{ self.#i.inject_listener_error(id, error); },
+                None => quote!{ self.#field_n.inject_listener_error(id, error); },
+            })
+        })
+    };
+
+    // Build the list of statements to put in the body of `inject_listener_closed()`.
+    let inject_listener_closed_stmts = {
+        data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
+            if is_ignored(&field) {
+                return None;
+            }
+
+            Some(match field.ident {
+                Some(ref i) => quote!{ self.#i.inject_listener_closed(id, reason); },
+                None => quote!{ self.#field_n.inject_listener_closed(id, reason); },
+            })
+        })
+    };
+
+    // Build the list of statements to put in the body of `inject_closing()`.
+    let inject_closing_stmts = {
+        data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
+            if is_ignored(&field) {
+                return None;
+            }
+
+            Some(match field.ident {
+                Some(ref i) => quote!{ self.#i.inject_closing(); },
+                None => quote!{ self.#field_n.inject_closing(); },
+            })
+        })
+    };
+
     // Build the list of statements to put in the body of `inject_new_external_addr()`.
     let inject_new_external_addr_stmts = {
         data_struct.fields.iter().enumerate().filter_map(move |(field_n, field)| {
@@ -300,8 +349,8 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
         }
 
         Some(match field.ident {
-            Some(ref i) => quote!{ #elem => self.#i.inject_node_event(peer_id, ev) },
-            None => quote!{ #elem => self.#field_n.inject_node_event(peer_id, ev) },
+            Some(ref i) => quote!{ #elem => self.#i.inject_node_event(peer_id, connection_id, ev) },
+            None => quote!{ #elem => self.#field_n.inject_node_event(peer_id, connection_id, ev) },
         })
     });
 
@@ -407,6 +456,16 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
                             event: #wrapped_event,
                         });
                     }
+                    Async::Ready(#network_behaviour_action::SendEventToConnection { peer_id, connection_id, event }) => {
+                        return Async::Ready(#network_behaviour_action::SendEventToConnection {
+                            peer_id,
+                            connection_id,
+                            event: #wrapped_event,
+                        });
+                    }
+                    Async::Ready(#network_behaviour_action::DisconnectPeer { peer_id, connection_id }) => {
+                        return Async::Ready(#network_behaviour_action::DisconnectPeer { peer_id, connection_id });
+                    }
                     Async::Ready(#network_behaviour_action::ReportObservedAddr { address }) => {
                         return Async::Ready(#network_behaviour_action::ReportObservedAddr { address });
                     }
@@ -435,18 +494,14 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
                 out
             }
 
-            fn inject_connected(&mut self, peer_id: #peer_id, endpoint: #connected_point) {
+            fn inject_connected(&mut self, peer_id: #peer_id, connection_id: #connection_id, endpoint: #connected_point) {
                 #(#inject_connected_stmts);*
             }
 
-            fn inject_disconnected(&mut self, peer_id: &#peer_id, endpoint: #connected_point) {
+            fn inject_disconnected(&mut self, peer_id: &#peer_id, connection_id: #connection_id, endpoint: #connected_point, reason: #disconnect_reason<'_>) {
                 #(#inject_disconnected_stmts);*
             }
 
-            fn inject_replaced(&mut self, peer_id: #peer_id, closed_endpoint: #connected_point, new_endpoint: #connected_point) {
-                #(#inject_replaced_stmts);*
-            }
-
             fn inject_addr_reach_failure(&mut self, peer_id: Option<&#peer_id>, addr: &#multiaddr, error: &dyn std::error::Error) {
                 #(#inject_addr_reach_failure_stmts);*
             }
@@ -455,6 +510,14 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
                 #(#inject_dial_failure_stmts);*
             }
 
+            fn inject_connection_refused(&mut self, peer_id: Option<&#peer_id>, endpoint: &#connected_point, error: &#connection_limit_error) {
+                #(#inject_connection_refused_stmts);*
+            }
+
+            fn inject_substream_refused(&mut self, peer_id: &#peer_id, error: &#substream_limit_error) {
+                #(#inject_substream_refused_stmts);*
+            }
+
             fn inject_new_listen_addr(&mut self, addr: &#multiaddr) {
                 #(#inject_new_listen_addr_stmts);*
             }
@@ -463,6 +526,18 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
                 #(#inject_expired_listen_addr_stmts);*
             }
 
+            fn inject_listener_error(&mut self, id: #listener_id, error: &dyn std::error::Error) {
+                #(#inject_listener_error_stmts);*
+            }
+
+            fn inject_listener_closed(&mut self, id: #listener_id, reason: std::result::Result<(), &dyn std::error::Error>) {
+                #(#inject_listener_closed_stmts);*
+            }
+
+            fn inject_closing(&mut self) {
+                #(#inject_closing_stmts);*
+            }
+
             fn inject_new_external_addr(&mut self, addr: &#multiaddr) {
                 #(#inject_new_external_addr_stmts);*
             }
@@ -470,6 +545,7 @@ fn build_struct(ast: &DeriveInput, data_struct: &DataStruct) -> TokenStream {
             fn inject_node_event(
                 &mut self,
                 peer_id: #peer_id,
+                connection_id: #connection_id,
                 event: <<Self::ProtocolsHandler as #into_protocols_handler>::Handler as #protocols_handler>::OutEvent
             ) {
                 match event {
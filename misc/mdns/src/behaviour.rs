@@ -18,22 +18,25 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::service::{MdnsService, MdnsPacket};
+use crate::dns;
+use crate::service::{MdnsService, MdnsPacket, MdnsResponseError};
+use crate::socket::MdnsSocket;
 use futures::prelude::*;
 use log::warn;
 use libp2p_core::protocols_handler::{DummyProtocolsHandler, ProtocolsHandler};
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{AddressSource, ConnectedPoint, ConnectionId, DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{address_translation, Multiaddr, PeerId, multiaddr::Protocol};
 use smallvec::SmallVec;
 use std::{cmp, fmt, io, iter, marker::PhantomData, time::Duration};
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_udp::UdpSocket;
 use wasm_timer::{Delay, Instant};
 
 /// A `NetworkBehaviour` for mDNS. Automatically discovers peers on the local network and adds
 /// them to the topology.
-pub struct Mdns<TSubstream> {
+pub struct Mdns<TSubstream, TSocket = UdpSocket> {
     /// The inner service.
-    service: MdnsService,
+    service: MdnsService<TSocket>,
 
     /// List of nodes that we have discovered, the address, and when their TTL expires.
     ///
@@ -46,6 +49,9 @@ pub struct Mdns<TSubstream> {
     /// `None` if `discovered_nodes` is empty.
     closest_expiration: Option<Delay>,
 
+    /// `key=value` attributes sent alongside our addresses when responding to queries.
+    attributes: Vec<(String, String)>,
+
     /// Marker to pin the generic.
     marker: PhantomData<TSubstream>,
 }
@@ -53,12 +59,52 @@ pub struct Mdns<TSubstream> {
 impl<TSubstream> Mdns<TSubstream> {
     /// Builds a new `Mdns` behaviour.
     pub fn new() -> io::Result<Mdns<TSubstream>> {
-        Ok(Mdns {
-            service: MdnsService::new()?,
+        Ok(Mdns::with_service(MdnsService::new()?))
+    }
+}
+
+impl<TSubstream, TSocket> Mdns<TSubstream, TSocket> {
+    /// Builds a new `Mdns` behaviour on top of the given service.
+    ///
+    /// This makes it possible to use sockets other than UDP, such as a `MemorySocket`.
+    pub fn with_service(service: MdnsService<TSocket>) -> Mdns<TSubstream, TSocket> {
+        Mdns {
+            service,
             discovered_nodes: SmallVec::new(),
             closest_expiration: None,
+            attributes: Vec::new(),
             marker: PhantomData,
-        })
+        }
+    }
+
+    /// Sets a `key=value` attribute to announce to the other nodes of the local network, for
+    /// example our agent version or role. Replaces the previous value for this key, if any.
+    ///
+    /// Remote nodes receive the attributes in `MdnsEvent::Discovered`.
+    ///
+    /// Returns an error if the attribute can't be sent in a TXT record.
+    pub fn set_attribute(&mut self, key: impl Into<String>, value: impl Into<String>)
+        -> Result<(), MdnsResponseError>
+    {
+        let (key, value) = (key.into(), value.into());
+        dns::check_txt_attribute(&key, &value)?;
+        if let Some((_, cur_value)) = self.attributes.iter_mut().find(|(k, _)| *k == key) {
+            *cur_value = value;
+        } else {
+            self.attributes.push((key, value));
+        }
+        Ok(())
+    }
+
+    /// Stops announcing the attribute with the given key. Returns its value, if any.
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        let pos = self.attributes.iter().position(|(k, _)| k == key)?;
+        Some(self.attributes.remove(pos).1)
+    }
+
+    /// Returns the list of attributes we announce.
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attributes.iter().map(|(k, v)| (k.as_str(), v.as_str()))
     }
 
     /// Returns true if the given `PeerId` is in the list of nodes discovered through mDNS.
@@ -82,7 +128,23 @@ pub enum MdnsEvent {
 
 /// Iterator that produces the list of addresses that have been discovered.
 pub struct DiscoveredAddrsIter {
-    inner: smallvec::IntoIter<[(PeerId, Multiaddr); 4]>
+    inner: smallvec::IntoIter<[(PeerId, Multiaddr); 4]>,
+    /// Attributes announced by each of the discovered peers.
+    attributes: Vec<(PeerId, Vec<(String, String)>)>,
+}
+
+impl DiscoveredAddrsIter {
+    /// Returns the `key=value` attributes that the given discovered peer has announced.
+    ///
+    /// Returns an empty list if the peer hasn't announced any attribute, or isn't part of this
+    /// event.
+    pub fn attributes(&self, peer_id: &PeerId) -> &[(String, String)] {
+        self.attributes
+            .iter()
+            .find(|(p, _)| p == peer_id)
+            .map(|(_, attrs)| &attrs[..])
+            .unwrap_or(&[])
+    }
 }
 
 impl Iterator for DiscoveredAddrsIter {
@@ -138,9 +200,10 @@ impl fmt::Debug for ExpiredAddrsIter {
     }
 }
 
-impl<TSubstream> NetworkBehaviour for Mdns<TSubstream>
+impl<TSubstream, TSocket> NetworkBehaviour for Mdns<TSubstream, TSocket>
 where
     TSubstream: AsyncRead + AsyncWrite,
+    TSocket: MdnsSocket,
 {
     type ProtocolsHandler = DummyProtocolsHandler<TSubstream>;
     type OutEvent = MdnsEvent;
@@ -158,13 +221,14 @@ where
             .collect()
     }
 
-    fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {}
+    fn inject_connected(&mut self, _: PeerId, _: ConnectionId, _: ConnectedPoint) {}
 
-    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {}
 
     fn inject_node_event(
         &mut self,
         _: PeerId,
+        _: ConnectionId,
         _ev: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
         void::unreachable(_ev)
@@ -204,19 +268,29 @@ where
         }
 
         // Polling the mDNS service, and obtain the list of nodes discovered this round.
-        let discovered = loop {
+        let (discovered, attributes) = loop {
             let event = match self.service.poll() {
                 Async::Ready(ev) => ev,
                 Async::NotReady => return Async::NotReady,
             };
 
             match event {
-                MdnsPacket::Query(query) => {
-                    let _ = query.respond(
+                MdnsPacket::Query(mut query) => {
+                    let addresses = params.listened_addresses().cloned().collect::<Vec<_>>();
+                    let ttl = Duration::from_secs(5 * 60);
+                    let result = query.push_response(
                         params.local_peer_id().clone(),
-                        params.listened_addresses().cloned(),
-                        Duration::from_secs(5 * 60)
+                        addresses.clone(),
+                        self.attributes.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+                        ttl
                     );
+                    // If the attributes don't fit, we still advertise our addresses.
+                    if let Err(err) = result {
+                        warn!("Responding to mDNS query without TXT attributes: {}", err);
+                        if let Err(err) = query.respond(params.local_peer_id().clone(), addresses, ttl) {
+                            warn!("Failed to respond to mDNS query: {}", err);
+                        }
+                    }
                 },
                 MdnsPacket::Response(response) => {
                     // We replace the IP address with the address we observe the
@@ -228,11 +302,17 @@ where
                         .collect();
 
                     let mut discovered: SmallVec<[_; 4]> = SmallVec::new();
+                    let mut attributes = Vec::new();
                     for peer in response.discovered_peers() {
                         if peer.id() == params.local_peer_id() {
                             continue;
                         }
 
+                        let peer_attributes = peer.attributes().collect::<Vec<_>>();
+                        if !peer_attributes.is_empty() {
+                            attributes.push((peer.id().clone(), peer_attributes));
+                        }
+
                         let new_expiration = Instant::now() + peer.ttl();
 
                         let mut addrs = Vec::new();
@@ -244,6 +324,8 @@ where
                         }
 
                         for addr in addrs {
+                            params.peer_store_mut().add_address(peer.id(), addr.clone(), AddressSource::Discovery, Some(peer.ttl()));
+
                             if let Some((_, _, cur_expires)) = self.discovered_nodes.iter_mut()
                                 .find(|(p, a, _)| p == peer.id() && *a == addr)
                             {
@@ -256,7 +338,7 @@ where
                         }
                     }
 
-                    break discovered;
+                    break (discovered, attributes);
                 },
                 MdnsPacket::ServiceDiscovery(disc) => {
                     disc.respond(Duration::from_secs(5 * 60));
@@ -272,15 +354,78 @@ where
             .map(Delay::new);
         Async::Ready(NetworkBehaviourAction::GenerateEvent(MdnsEvent::Discovered(DiscoveredAddrsIter {
             inner: discovered.into_iter(),
+            attributes,
         })))
     }
 }
 
-impl<TSubstream> fmt::Debug for Mdns<TSubstream> {
+impl<TSubstream, TSocket> fmt::Debug for Mdns<TSubstream, TSocket> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("Mdns")
             .field("service", &self.service)
+            .field("attributes", &self.attributes)
             .finish()
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mdns, MdnsEvent, MdnsService, MemoryMulticastBus};
+    use futures::{future, prelude::*};
+    use libp2p_core::{
+        muxing::SingletonMuxer,
+        transport::MemoryTransport,
+        PeerId, Swarm, Transport
+    };
+    use std::{collections::HashSet, net::SocketAddr};
+
+    #[test]
+    fn memory_nodes_discover_each_other() {
+        let bus = MemoryMulticastBus::new(SocketAddr::from(([224, 0, 0, 251], 5353)));
+
+        let mut swarms = (0..3)
+            .map(|_| {
+                let transport = MemoryTransport
+                    .map(|socket, endpoint| {
+                        (PeerId::random(), SingletonMuxer::new(socket, endpoint.to_endpoint()))
+                    });
+                // Each service sends a single query, and doesn't rely on a timer to do so.
+                let mut service = MdnsService::with_sockets(
+                    bus.join(),
+                    bus.bind(),
+                    bus.multicast_addr(),
+                    None,
+                );
+                service.send_query();
+                let mut swarm = Swarm::new(transport, Mdns::with_service(service), PeerId::random());
+                Swarm::listen_on(&mut swarm, "/memory/0".parse().unwrap()).unwrap();
+                swarm
+            })
+            .collect::<Vec<_>>();
+
+        let peer_ids = swarms.iter().map(Swarm::local_peer_id).cloned().collect::<Vec<_>>();
+        let mut discovered = vec![HashSet::new(); swarms.len()];
+
+        // The packets are delivered by the bus as soon as they are sent, so a few rounds of polling
+        // are enough for every node to discover the others.
+        future::lazy(move || -> Result<(), ()> {
+            for _ in 0..10 {
+                for (n, swarm) in swarms.iter_mut().enumerate() {
+                    while let Async::Ready(Some(event)) = swarm.poll().unwrap() {
+                        if let MdnsEvent::Discovered(list) = event {
+                            for (peer_id, _) in list {
+                                assert_ne!(peer_id, peer_ids[n]);
+                                assert!(peer_ids.contains(&peer_id));
+                                discovered[n].insert(peer_id);
+                            }
+                        }
+                    }
+                }
+            }
+
+            assert!(discovered.iter().all(|d| d.len() == peer_ids.len() - 1));
+            Ok(())
+        }).wait().unwrap();
+    }
+}
@@ -79,20 +79,28 @@ pub fn build_query() -> Vec<u8> {
     out
 }
 
+/// Prefix of the TXT strings that contain an address.
+const DNSADDR_PREFIX: &[u8] = b"dnsaddr=";
+
 /// Builds the response to the DNS query.
 ///
-/// If there are more than 2^16-1 addresses, ignores the rest.
-pub fn build_query_response(
+/// Each element of `attributes` is sent as an additional `key=value` TXT string alongside the
+/// addresses.
+///
+/// If there are more than 2^16-1 addresses and attributes, ignores the rest.
+pub fn build_query_response<'a>(
     id: u16,
     peer_id: PeerId,
     addresses: impl ExactSizeIterator<Item = Multiaddr>,
+    attributes: impl ExactSizeIterator<Item = (&'a str, &'a str)>,
     ttl: Duration,
 ) -> Result<Vec<u8>, MdnsResponseError> {
     // Convert the TTL into seconds.
     let ttl = duration_to_secs(ttl);
 
-    // Add a limit to 2^16-1 addresses, as the protocol limits to this number.
+    // Add a limit to 2^16-1 records, as the protocol limits to this number.
     let addresses = addresses.take(65535);
+    let attributes = attributes.take(65535 - addresses.len());
 
     // This capacity was determined empirically and is a reasonable upper limit.
     let mut out = Vec::with_capacity(320);
@@ -104,7 +112,7 @@ pub fn build_query_response(
     append_u16(&mut out, 0x0);
     append_u16(&mut out, 0x1);
     append_u16(&mut out, 0x0);
-    append_u16(&mut out, addresses.len() as u16);
+    append_u16(&mut out, (addresses.len() + attributes.len()) as u16);
 
     // Our single answer.
     // The name.
@@ -139,6 +147,15 @@ pub fn build_query_response(
         append_txt_record(&mut out, &peer_id_bytes, ttl, Some(&txt_to_send_bytes[..]))?;
     }
 
+    // The TXT records for the attributes.
+    for (key, value) in attributes {
+        check_txt_attribute(key, value)?;
+        let txt_to_send = format!("{}={}", key, value);
+        let mut txt_to_send_bytes = Vec::with_capacity(txt_to_send.len());
+        append_character_string(&mut txt_to_send_bytes, txt_to_send.as_bytes())?;
+        append_txt_record(&mut out, &peer_id_bytes, ttl, Some(&txt_to_send_bytes[..]))?;
+    }
+
     // The DNS specs specify that the maximum allowed size is 9000 bytes.
     if out.len() > 9000 {
         return Err(MdnsResponseError::ResponseTooLong);
@@ -147,6 +164,53 @@ pub fn build_query_response(
     Ok(out)
 }
 
+/// Checks whether a `key=value` pair can be sent as a TXT attribute.
+///
+/// The key must be non-empty, must not contain `=`, and must not be `dnsaddr`, which is reserved
+/// for addresses. Both the key and the value must be ASCII.
+pub fn check_txt_attribute(key: &str, value: &str) -> Result<(), MdnsResponseError> {
+    if key.is_empty() || key.contains('=') || key == "dnsaddr" {
+        return Err(MdnsResponseError::InvalidTxtAttributeKey);
+    }
+    if !key.is_ascii() || !value.is_ascii() {
+        return Err(MdnsResponseError::NonAsciiTxtAttribute);
+    }
+    // One byte is used for the `=` separator.
+    if key.len() + value.len() + 1 > u8::max_value() as usize {
+        return Err(MdnsResponseError::TxtRecordTooLong);
+    }
+    Ok(())
+}
+
+/// Parses a TXT string sent by a remote.
+///
+/// Returns `None` if the string isn't valid.
+pub fn parse_txt_string(txt: &[u8]) -> Option<TxtString> {
+    let txt = decode_character_string(txt).ok()?;
+    if txt.starts_with(DNSADDR_PREFIX) {
+        let addr = str::from_utf8(&txt[DNSADDR_PREFIX.len()..]).ok()?;
+        return addr.parse().ok().map(TxtString::Address);
+    }
+
+    let txt = str::from_utf8(&txt).ok()?;
+    let mut iter = txt.splitn(2, '=');
+    let key = iter.next()?;
+    let value = iter.next()?;
+    if key.is_empty() {
+        return None;
+    }
+    Some(TxtString::Attribute(key.to_owned(), value.to_owned()))
+}
+
+/// Content of a TXT string sent by a remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxtString {
+    /// A `dnsaddr=` string containing one of the addresses of the peer.
+    Address(Multiaddr),
+    /// A `key=value` attribute.
+    Attribute(String, String),
+}
+
 /// Builds the response to the DNS query.
 pub fn build_service_discovery_response(id: u16, ttl: Duration) -> Vec<u8> {
     // Convert the TTL into seconds.
@@ -313,6 +377,8 @@ fn append_txt_record<'a>(
 pub enum MdnsResponseError {
     TxtRecordTooLong,
     NonAsciiMultiaddr,
+    NonAsciiTxtAttribute,
+    InvalidTxtAttributeKey,
     ResponseTooLong,
 }
 
@@ -326,6 +392,12 @@ impl fmt::Display for MdnsResponseError {
                 f,
                 "A multiaddr contains non-ASCII characters when serializd"
             ),
+            MdnsResponseError::NonAsciiTxtAttribute => {
+                write!(f, "A TXT attribute contains non-ASCII characters")
+            }
+            MdnsResponseError::InvalidTxtAttributeKey => {
+                write!(f, "A TXT attribute has an empty, reserved or invalid key")
+            }
             MdnsResponseError::ResponseTooLong => write!(f, "DNS response is too long"),
         }
     }
@@ -336,8 +408,8 @@ impl error::Error for MdnsResponseError {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use dns_parser::Packet;
-    use libp2p_core::identity;
+    use dns_parser::{Packet, RData};
+    use libp2p_core::{identity, multiaddr::Protocol};
     use std::time::Duration;
 
     #[test]
@@ -355,12 +427,79 @@ mod tests {
             0xf8f8,
             my_peer_id,
             vec![addr1, addr2].into_iter(),
+            Vec::new().into_iter(),
             Duration::from_secs(60),
         )
         .unwrap();
         assert!(Packet::parse(&query).is_ok());
     }
 
+    #[test]
+    fn build_query_response_ipv6_correct() {
+        let my_peer_id = identity::Keypair::generate_ed25519().public().into_peer_id();
+        let addr1: Multiaddr = "/ip6/fe80::1/tcp/5000".parse().unwrap();
+        let addr2: Multiaddr = "/ip6/2001:db8::8a2e:370:7334/udp/10000".parse().unwrap();
+        let query = build_query_response(
+            0xf8f8,
+            my_peer_id.clone(),
+            vec![addr1.clone(), addr2.clone()].into_iter(),
+            Vec::new().into_iter(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+
+        let packet = Packet::parse(&query).unwrap();
+        assert_eq!(packet.additional.len(), 2);
+        let txts = packet.additional.iter()
+            .filter_map(|record| match record.data {
+                RData::TXT(ref txt) => Some(txt.iter().map(|s| s.to_vec()).collect::<Vec<_>>()),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        for addr in &[addr1, addr2] {
+            let expected = format!("dnsaddr={}/p2p/{}", addr, my_peer_id.to_base58());
+            assert!(txts.iter().any(|txt| txt == expected.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn build_query_response_with_attributes() {
+        let my_peer_id = identity::Keypair::generate_ed25519().public().into_peer_id();
+        let addr: Multiaddr = "/ip4/1.2.3.4/tcp/5000".parse().unwrap();
+        let query = build_query_response(
+            0xf8f8,
+            my_peer_id.clone(),
+            vec![addr.clone()].into_iter(),
+            vec![("agent", "rust-libp2p/0.8 (linux)"), ("role", "relay")].into_iter(),
+            Duration::from_secs(60),
+        )
+        .unwrap();
+
+        let packet = Packet::parse(&query).unwrap();
+        assert_eq!(packet.additional.len(), 3);
+        let txts = packet.additional.iter()
+            .filter_map(|record| match record.data {
+                RData::TXT(ref txt) => Some(txt.iter().filter_map(parse_txt_string).collect::<Vec<_>>()),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        assert!(txts.contains(&TxtString::Address(addr.with(Protocol::P2p(my_peer_id.clone().into())))));
+        assert!(txts.contains(&TxtString::Attribute("agent".into(), "rust-libp2p/0.8 (linux)".into())));
+        assert!(txts.contains(&TxtString::Attribute("role".into(), "relay".into())));
+    }
+
+    #[test]
+    fn invalid_txt_attributes() {
+        assert_eq!(check_txt_attribute("", "foo"), Err(MdnsResponseError::InvalidTxtAttributeKey));
+        assert_eq!(check_txt_attribute("a=b", "foo"), Err(MdnsResponseError::InvalidTxtAttributeKey));
+        assert_eq!(check_txt_attribute("dnsaddr", "foo"), Err(MdnsResponseError::InvalidTxtAttributeKey));
+        assert_eq!(check_txt_attribute("k\u{e9}y", "foo"), Err(MdnsResponseError::NonAsciiTxtAttribute));
+        assert_eq!(check_txt_attribute("key", &"a".repeat(300)), Err(MdnsResponseError::TxtRecordTooLong));
+        assert_eq!(check_txt_attribute("key", "value=with=equals"), Ok(()));
+    }
+
     #[test]
     fn build_service_discovery_response_correct() {
         let query = build_service_discovery_response(0x1234, Duration::from_secs(120));
@@ -29,6 +29,9 @@
 //! This crate provides the `Mdns` struct which implements the `NetworkBehaviour` trait. This
 //! struct will automatically discover other libp2p nodes on the local network.
 //!
+//! The `socket` module makes it possible to run the discovery on top of a `MemoryMulticastBus`
+//! instead of UDP, so that multiple nodes in the same process can discover each other in tests.
+//!
 
 /// Hardcoded name of the mDNS service. Part of the mDNS libp2p specifications.
 const SERVICE_NAME: &[u8] = b"_p2p._udp.local";
@@ -37,8 +40,10 @@ const META_QUERY_SERVICE: &[u8] = b"_services._dns-sd._udp.local";
 
 pub use self::behaviour::{Mdns, MdnsEvent};
 pub use self::service::MdnsService;
+pub use self::socket::{MdnsSocket, MemoryMulticastBus, MemorySocket};
 
 mod behaviour;
 mod dns;
 
 pub mod service;
+pub mod socket;
@@ -18,18 +18,26 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::{SERVICE_NAME, META_QUERY_SERVICE, dns};
+use crate::{SERVICE_NAME, META_QUERY_SERVICE, dns::{self, TxtString}, socket::MdnsSocket};
 use dns_parser::{Packet, RData};
 use futures::{prelude::*, task};
 use libp2p_core::{Multiaddr, PeerId};
+use log::debug;
 use multiaddr::Protocol;
-use std::{fmt, io, net::Ipv4Addr, net::SocketAddr, str, time::Duration};
+use std::{fmt, io, iter, net::Ipv4Addr, net::Ipv6Addr, net::SocketAddr, time::Duration};
 use tokio_reactor::Handle;
 use wasm_timer::{Instant, Interval};
 use tokio_udp::UdpSocket;
 
 pub use dns::MdnsResponseError;
 
+/// IPv4 multicast address used by mDNS, as defined in RFC 6762.
+const IPV4_MDNS_MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// IPv6 link-local multicast address used by mDNS, as defined in RFC 6762.
+const IPV6_MDNS_MULTICAST_ADDRESS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+/// Port used by mDNS.
+const MDNS_PORT: u16 = 5353;
+
 /// A running service that discovers libp2p peers and responds to other libp2p peers' queries on
 /// the local network.
 ///
@@ -49,6 +57,9 @@ pub use dns::MdnsResponseError;
 /// When you receive an `MdnsResponse`, use the provided methods to query the information received
 /// in the response.
 ///
+/// By default the service uses UDP sockets. Use `with_sockets` to make it run on top of any other
+/// implementation of `MdnsSocket`, such as a `MemorySocket`.
+///
 /// # Example
 ///
 /// ```rust
@@ -91,26 +102,44 @@ pub use dns::MdnsResponseError;
 ///     }
 /// }).for_each(|_| Ok(()));
 /// # }
-pub struct MdnsService {
-    /// Main socket for listening.
-    socket: UdpSocket,
-    /// Socket for sending queries on the network.
-    query_socket: UdpSocket,
-    /// Interval for sending queries.
-    query_interval: Interval,
-    /// Whether we send queries on the network at all.
+pub struct MdnsService<TSocket = UdpSocket> {
+    /// Sockets bound to the IPv4 multicast group, or `None` if IPv4 isn't available.
+    ipv4: Option<MulticastSockets<TSocket>>,
+    /// Sockets bound to the IPv6 multicast group, or `None` if IPv6 isn't available.
+    ipv6: Option<MulticastSockets<TSocket>>,
+    /// Interval for sending queries, or `None` if queries are only sent through `send_query`.
+    query_interval: Option<Interval>,
+    /// Whether the `query_interval` sends queries on the network.
     /// Note that we still need to have an interval for querying, as we need to wake up the socket
-    /// regularly to recover from errors. Otherwise we could simply use an `Option<Interval>`.
+    /// regularly to recover from errors.
     silent: bool,
-    /// Buffer used for receiving data from the main socket.
+    /// Buffer used for receiving data from the main sockets.
     recv_buffer: [u8; 2048],
+}
+
+/// Sockets and pending buffers for one IP version.
+struct MulticastSockets<TSocket> {
+    /// Main socket for listening.
+    socket: TSocket,
+    /// Socket for sending queries on the network.
+    query_socket: TSocket,
+    /// Multicast address and port that packets are sent to.
+    multicast_addr: SocketAddr,
+    /// Indices of the interfaces that every packet is sent on, one after the other. If empty, the
+    /// packets are sent once on the interface picked by the operating system.
+    interfaces: Vec<u32>,
     /// Buffers pending to send on the main socket.
     send_buffers: Vec<Vec<u8>>,
+    /// Position in `interfaces` of the next interface to send the first of `send_buffers` on.
+    send_interface: usize,
     /// Buffers pending to send on the query socket.
     query_send_buffers: Vec<Vec<u8>>,
+    /// Position in `interfaces` of the next interface to send the first of `query_send_buffers`
+    /// on.
+    query_send_interface: usize,
 }
 
-impl MdnsService {
+impl MdnsService<UdpSocket> {
     /// Starts a new mDNS service.
     #[inline]
     pub fn new() -> io::Result<MdnsService> {
@@ -124,36 +153,81 @@ impl MdnsService {
     }
 
     /// Starts a new mDNS service.
+    ///
+    /// Both an IPv4 and an IPv6 socket are opened. Only fails if neither of them could be set up.
     fn new_inner(silent: bool) -> io::Result<MdnsService> {
-        let socket = {
-            #[cfg(unix)]
-            fn platform_specific(s: &net2::UdpBuilder) -> io::Result<()> {
-                net2::unix::UnixUdpBuilderExt::reuse_port(s, true)?;
-                Ok(())
+        let (ipv4, ipv6) = match (MulticastSockets::new_v4(), MulticastSockets::new_v6()) {
+            (Ok(ipv4), Ok(ipv6)) => (Some(ipv4), Some(ipv6)),
+            (Ok(ipv4), Err(err)) => {
+                debug!("Failed to start IPv6 mDNS: {:?}", err);
+                (Some(ipv4), None)
+            }
+            (Err(err), Ok(ipv6)) => {
+                debug!("Failed to start IPv4 mDNS: {:?}", err);
+                (None, Some(ipv6))
             }
-            #[cfg(not(unix))]
-            fn platform_specific(_: &net2::UdpBuilder) -> io::Result<()> { Ok(()) }
-            let builder = net2::UdpBuilder::new_v4()?;
-            builder.reuse_address(true)?;
-            platform_specific(&builder)?;
-            builder.bind(("0.0.0.0", 5353))?
+            (Err(err), Err(_)) => return Err(err),
         };
 
-        let socket = UdpSocket::from_std(socket, &Handle::default())?;
-        socket.set_multicast_loop_v4(true)?;
-        socket.set_multicast_ttl_v4(255)?;
-        // TODO: correct interfaces?
-        socket.join_multicast_v4(&From::from([224, 0, 0, 251]), &Ipv4Addr::UNSPECIFIED)?;
-
         Ok(MdnsService {
-            socket,
-            query_socket: UdpSocket::bind(&From::from(([0, 0, 0, 0], 0)))?,
-            query_interval: Interval::new(Instant::now(), Duration::from_secs(20)),
+            ipv4,
+            ipv6,
+            query_interval: Some(Interval::new(Instant::now(), Duration::from_secs(20))),
             silent,
             recv_buffer: [0; 2048],
+        })
+    }
+}
+
+impl<TSocket> MdnsService<TSocket>
+where
+    TSocket: MdnsSocket,
+{
+    /// Builds a new mDNS service on top of the given sockets.
+    ///
+    /// `socket` must receive the packets sent to `multicast_addr`, and is used to send responses.
+    /// `query_socket` is used to send a query every `query_interval`. If `query_interval` is
+    /// `None`, queries are only sent when `send_query` is called and the service doesn't need a
+    /// timer, which makes it possible to control it entirely from tests.
+    pub fn with_sockets(
+        socket: TSocket,
+        query_socket: TSocket,
+        multicast_addr: SocketAddr,
+        query_interval: Option<Duration>,
+    ) -> MdnsService<TSocket> {
+        let sockets = MulticastSockets {
+            socket,
+            query_socket,
+            multicast_addr,
+            interfaces: Vec::new(),
             send_buffers: Vec::new(),
+            send_interface: 0,
             query_send_buffers: Vec::new(),
-        })
+            query_send_interface: 0,
+        };
+
+        let (ipv4, ipv6) = if multicast_addr.is_ipv4() {
+            (Some(sockets), None)
+        } else {
+            (None, Some(sockets))
+        };
+
+        MdnsService {
+            ipv4,
+            ipv6,
+            query_interval: query_interval.map(|period| Interval::new(Instant::now(), period)),
+            silent: false,
+            recv_buffer: [0; 2048],
+        }
+    }
+
+    /// Queues a query for the nodes of the local network, in addition to the ones sent
+    /// periodically. The query is sent the next time the service is polled.
+    pub fn send_query(&mut self) {
+        let query = dns::build_query();
+        for sockets in self.ipv4.iter_mut().chain(self.ipv6.iter_mut()) {
+            sockets.query_send_buffers.push(query.clone());
+        }
     }
 
     /// Polls the service for packets.
@@ -161,133 +235,287 @@ impl MdnsService {
         // Send a query every time `query_interval` fires.
         // Note that we don't use a loop here—it is pretty unlikely that we need it, and there is
         // no point in sending multiple requests in a row.
-        match self.query_interval.poll() {
-            Ok(Async::Ready(_)) => {
+        match self.query_interval.as_mut().map(Stream::poll) {
+            Some(Ok(Async::Ready(_))) => {
                 if !self.silent {
-                    let query = dns::build_query();
-                    self.query_send_buffers.push(query.to_vec());
+                    self.send_query();
                 }
             }
-            Ok(Async::NotReady) => (),
+            Some(Ok(Async::NotReady)) | None => (),
             _ => unreachable!("A wasm_timer::Interval never errors"), // TODO: is that true?
         };
 
-        // Flush the send buffer of the main socket.
-        while !self.send_buffers.is_empty() {
-            let to_send = self.send_buffers.remove(0);
-            match self
-                .socket
-                .poll_send_to(&to_send, &From::from(([224, 0, 0, 251], 5353)))
-            {
-                Ok(Async::Ready(bytes_written)) => {
-                    debug_assert_eq!(bytes_written, to_send.len());
-                }
-                Ok(Async::NotReady) => {
-                    self.send_buffers.insert(0, to_send);
-                    break;
-                }
-                Err(_) => {
-                    // Errors are non-fatal because they can happen for example if we lose
-                    // connection to the network.
-                    self.send_buffers.clear();
-                    break;
-                }
-            }
+        // Flush the send buffers. This has to be after the push to `query_send_buffers`.
+        for sockets in self.ipv4.iter_mut().chain(self.ipv6.iter_mut()) {
+            sockets.flush();
         }
 
-        // Flush the query send buffer.
-        // This has to be after the push to `query_send_buffers`.
-        while !self.query_send_buffers.is_empty() {
-            let to_send = self.query_send_buffers.remove(0);
-            match self
-                .query_socket
-                .poll_send_to(&to_send, &From::from(([224, 0, 0, 251], 5353)))
-            {
-                Ok(Async::Ready(bytes_written)) => {
-                    debug_assert_eq!(bytes_written, to_send.len());
-                }
-                Ok(Async::NotReady) => {
-                    self.query_send_buffers.insert(0, to_send);
-                    break;
-                }
+        // Check for any incoming packet, first on the IPv4 socket and then on the IPv6 one.
+        let recv_buffer = &mut self.recv_buffer;
+        let received = self.ipv4.iter_mut().chain(self.ipv6.iter_mut())
+            .filter_map(|sockets| match sockets.socket.poll_recv_from(recv_buffer) {
+                Ok(Async::Ready((len, from))) => Some((len, from, &mut sockets.send_buffers)),
+                Ok(Async::NotReady) => None,
                 Err(_) => {
-                    // Errors are non-fatal because they can happen for example if we lose
-                    // connection to the network.
-                    self.query_send_buffers.clear();
-                    break;
+                    // Error are non-fatal and can happen if we get disconnected from example.
+                    // The query interval will wake up the task at some point so that we can try
+                    // again.
+                    None
                 }
-            }
-        }
-
-        // Check for any incoming packet.
-        match self.socket.poll_recv_from(&mut self.recv_buffer) {
-            Ok(Async::Ready((len, from))) => {
-                match Packet::parse(&self.recv_buffer[..len]) {
-                    Ok(packet) => {
-                        if packet.header.query {
-                            if packet
-                                .questions
-                                .iter()
-                                .any(|q| q.qname.to_string().as_bytes() == SERVICE_NAME)
-                            {
-                                return Async::Ready(MdnsPacket::Query(MdnsQuery {
+            })
+            .next();
+
+        if let Some((len, from, send_buffers)) = received {
+            match Packet::parse(&self.recv_buffer[..len]) {
+                Ok(packet) => {
+                    if packet.header.query {
+                        if packet
+                            .questions
+                            .iter()
+                            .any(|q| q.qname.to_string().as_bytes() == SERVICE_NAME)
+                        {
+                            return Async::Ready(MdnsPacket::Query(MdnsQuery {
+                                from,
+                                query_id: packet.header.id,
+                                send_buffers,
+                            }));
+                        } else if packet
+                            .questions
+                            .iter()
+                            .any(|q| q.qname.to_string().as_bytes() == META_QUERY_SERVICE)
+                        {
+                            // TODO: what if multiple questions, one with SERVICE_NAME and one with META_QUERY_SERVICE?
+                            return Async::Ready(MdnsPacket::ServiceDiscovery(
+                                MdnsServiceDiscovery {
                                     from,
                                     query_id: packet.header.id,
-                                    send_buffers: &mut self.send_buffers,
-                                }));
-                            } else if packet
-                                .questions
-                                .iter()
-                                .any(|q| q.qname.to_string().as_bytes() == META_QUERY_SERVICE)
-                            {
-                                // TODO: what if multiple questions, one with SERVICE_NAME and one with META_QUERY_SERVICE?
-                                return Async::Ready(MdnsPacket::ServiceDiscovery(
-                                    MdnsServiceDiscovery {
-                                        from,
-                                        query_id: packet.header.id,
-                                        send_buffers: &mut self.send_buffers,
-                                    },
-                                ));
-                            } else {
-                                // Note that ideally we would use a loop instead. However as of the
-                                // writing of this code non-lexical lifetimes haven't been merged
-                                // yet, and I can't manage to write this code without having borrow
-                                // issues.
-                                task::current().notify();
-                                return Async::NotReady;
-                            }
+                                    send_buffers,
+                                },
+                            ));
                         } else {
-                            return Async::Ready(MdnsPacket::Response(MdnsResponse {
-                                packet,
-                                from,
-                            }));
+                            // Note that ideally we would use a loop instead. However as of the
+                            // writing of this code non-lexical lifetimes haven't been merged
+                            // yet, and I can't manage to write this code without having borrow
+                            // issues.
+                            task::current().notify();
+                            return Async::NotReady;
                         }
-                    }
-                    Err(_) => {
-                        // Ignore errors while parsing the packet. We need to poll again for the
-                        // next packet.
-                        // Note that ideally we would use a loop instead. However as of the writing
-                        // of this code non-lexical lifetimes haven't been merged yet, and I can't
-                        // manage to write this code without having borrow issues.
-                        task::current().notify();
-                        return Async::NotReady;
+                    } else {
+                        return Async::Ready(MdnsPacket::Response(MdnsResponse {
+                            packet,
+                            from,
+                        }));
                     }
                 }
+                Err(_) => {
+                    // Ignore errors while parsing the packet. We need to poll again for the
+                    // next packet.
+                    // Note that ideally we would use a loop instead. However as of the writing
+                    // of this code non-lexical lifetimes haven't been merged yet, and I can't
+                    // manage to write this code without having borrow issues.
+                    task::current().notify();
+                    return Async::NotReady;
+                }
             }
-            Ok(Async::NotReady) => (),
-            Err(_) => {
-                // Error are non-fatal and can happen if we get disconnected from example.
-                // The query interval will wake up the task at some point so that we can try again.
+        }
+
+        Async::NotReady
+    }
+}
+
+impl MulticastSockets<UdpSocket> {
+    /// Opens the IPv4 sockets and joins the IPv4 mDNS multicast group.
+    fn new_v4() -> io::Result<MulticastSockets<UdpSocket>> {
+        let builder = net2::UdpBuilder::new_v4()?;
+        builder.reuse_address(true)?;
+        reuse_port(&builder)?;
+        let socket = builder.bind((Ipv4Addr::UNSPECIFIED, MDNS_PORT))?;
+
+        let socket = UdpSocket::from_std(socket, &Handle::default())?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+        // TODO: correct interfaces?
+        socket.join_multicast_v4(&IPV4_MDNS_MULTICAST_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+
+        Ok(MulticastSockets {
+            socket,
+            query_socket: UdpSocket::bind(&From::from((Ipv4Addr::UNSPECIFIED, 0)))?,
+            multicast_addr: From::from((IPV4_MDNS_MULTICAST_ADDRESS, MDNS_PORT)),
+            interfaces: Vec::new(),
+            send_buffers: Vec::new(),
+            send_interface: 0,
+            query_send_buffers: Vec::new(),
+            query_send_interface: 0,
+        })
+    }
+
+    /// Opens the IPv6 sockets and joins the IPv6 mDNS multicast group on every interface that
+    /// has an IPv6 address. Since the group is link-local, the packets are then sent on each of
+    /// these interfaces.
+    fn new_v6() -> io::Result<MulticastSockets<UdpSocket>> {
+        let builder = net2::UdpBuilder::new_v6()?;
+        builder.only_v6(true)?;
+        builder.reuse_address(true)?;
+        reuse_port(&builder)?;
+        let socket = builder.bind((Ipv6Addr::UNSPECIFIED, MDNS_PORT))?;
+
+        let socket = UdpSocket::from_std(socket, &Handle::default())?;
+        socket.set_multicast_loop_v6(true)?;
+
+        // Joining the group can fail on some interfaces, for example if they don't support
+        // multicast. We only report an error if we couldn't join on any of them.
+        let mut last_error = None;
+        let mut interfaces = Vec::new();
+        for interface in ipv6_multicast_interfaces() {
+            match socket.join_multicast_v6(&IPV6_MDNS_MULTICAST_ADDRESS, interface) {
+                Ok(()) => interfaces.push(interface),
+                Err(err) => {
+                    debug!("Failed to join IPv6 mDNS group on interface {}: {:?}", interface, err);
+                    last_error = Some(err);
+                }
             }
+        }
+        if interfaces.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::Other, "No interface to join the IPv6 mDNS group on")
+            }));
+        }
+
+        let query_socket = {
+            let builder = net2::UdpBuilder::new_v6()?;
+            builder.only_v6(true)?;
+            let socket = builder.bind((Ipv6Addr::UNSPECIFIED, 0))?;
+            UdpSocket::from_std(socket, &Handle::default())?
         };
 
-        Async::NotReady
+        Ok(MulticastSockets {
+            socket,
+            query_socket,
+            multicast_addr: From::from((IPV6_MDNS_MULTICAST_ADDRESS, MDNS_PORT)),
+            interfaces,
+            send_buffers: Vec::new(),
+            send_interface: 0,
+            query_send_buffers: Vec::new(),
+            query_send_interface: 0,
+        })
     }
 }
 
-impl fmt::Debug for MdnsService {
+impl<TSocket> MulticastSockets<TSocket>
+where
+    TSocket: MdnsSocket,
+{
+    /// Sends as much of the pending buffers as possible on the network.
+    fn flush(&mut self) {
+        let multicast_addr = self.multicast_addr;
+        flush_buffers(&mut self.socket, &mut self.send_buffers, &mut self.send_interface,
+            &self.interfaces, &multicast_addr);
+        flush_buffers(&mut self.query_socket, &mut self.query_send_buffers,
+            &mut self.query_send_interface, &self.interfaces, &multicast_addr);
+    }
+}
+
+/// Sends the content of `buffers` on `socket` to `target`, once on each of `interfaces`, until
+/// either the socket isn't ready or the buffers are empty.
+///
+/// `next_interface` is the position in `interfaces` of the next interface to send the first
+/// buffer on, and is updated as the buffer is sent.
+fn flush_buffers(
+    socket: &mut impl MdnsSocket,
+    buffers: &mut Vec<Vec<u8>>,
+    next_interface: &mut usize,
+    interfaces: &[u32],
+    target: &SocketAddr,
+) {
+    while !buffers.is_empty() {
+        if let Some(&interface) = interfaces.get(*next_interface) {
+            if let Err(err) = socket.set_multicast_if_v6(interface) {
+                debug!("Failed to select interface {} to send mDNS packets: {:?}", interface, err);
+            }
+        }
+
+        match socket.poll_send_to(&buffers[0], target) {
+            Ok(Async::Ready(bytes_written)) => {
+                debug_assert_eq!(bytes_written, buffers[0].len());
+            }
+            Ok(Async::NotReady) => break,
+            Err(_) if interfaces.is_empty() => {
+                // Errors are non-fatal because they can happen for example if we lose
+                // connection to the network.
+                buffers.clear();
+                *next_interface = 0;
+                break;
+            }
+            Err(err) => {
+                // The other interfaces may still be usable, so we only skip this one.
+                debug!("Failed to send mDNS packet on interface {}: {:?}", interfaces[*next_interface], err);
+            }
+        }
+
+        *next_interface += 1;
+        if *next_interface >= interfaces.len() {
+            *next_interface = 0;
+            buffers.remove(0);
+        }
+    }
+}
+
+/// Allows multiple processes on the same machine to bind the mDNS port.
+#[cfg(unix)]
+fn reuse_port(builder: &net2::UdpBuilder) -> io::Result<()> {
+    net2::unix::UnixUdpBuilderExt::reuse_port(builder, true)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reuse_port(_: &net2::UdpBuilder) -> io::Result<()> {
+    Ok(())
+}
+
+/// Returns the indices of the interfaces on which to join the IPv6 multicast group.
+///
+/// These are the non-loopback interfaces that have an IPv6 address. If they can't be determined,
+/// returns the index `0`, which lets the operating system pick a default interface.
+#[cfg(unix)]
+fn ipv6_multicast_interfaces() -> Vec<u32> {
+    let interfaces = match get_if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            debug!("Failed to list the network interfaces: {:?}", err);
+            return vec![0];
+        }
+    };
+
+    let mut indices = interfaces
+        .into_iter()
+        .filter(|iface| !iface.is_loopback() && iface.ip().is_ipv6())
+        .filter_map(|iface| {
+            let name = std::ffi::CString::new(iface.name).ok()?;
+            match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+                0 => None,
+                index => Some(index),
+            }
+        })
+        .collect::<Vec<_>>();
+    indices.sort();
+    indices.dedup();
+
+    if indices.is_empty() {
+        indices.push(0);
+    }
+    indices
+}
+
+#[cfg(not(unix))]
+fn ipv6_multicast_interfaces() -> Vec<u32> {
+    vec![0]
+}
+
+impl<TSocket> fmt::Debug for MdnsService<TSocket> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_struct("MdnsService")
+            .field("ipv4", &self.ipv4.is_some())
+            .field("ipv6", &self.ipv6.is_some())
             .field("silent", &self.silent)
             .finish()
     }
@@ -333,8 +561,50 @@ impl<'a> MdnsQuery<'a> {
         TAddresses: IntoIterator<Item = Multiaddr>,
         TAddresses::IntoIter: ExactSizeIterator,
     {
-        let response =
-            dns::build_query_response(self.query_id, peer_id, addresses.into_iter(), ttl)?;
+        self.respond_with_attributes(peer_id, addresses, iter::empty(), ttl)
+    }
+
+    /// Same as `respond`, but also sends the given `key=value` attributes as TXT strings.
+    ///
+    /// The remote can retrieve them with `MdnsPeer::attributes`.
+    pub fn respond_with_attributes<'b, TAddresses, TAttributes>(
+        mut self,
+        peer_id: PeerId,
+        addresses: TAddresses,
+        attributes: TAttributes,
+        ttl: Duration,
+    ) -> Result<(), MdnsResponseError>
+    where
+        TAddresses: IntoIterator<Item = Multiaddr>,
+        TAddresses::IntoIter: ExactSizeIterator,
+        TAttributes: IntoIterator<Item = (&'b str, &'b str)>,
+        TAttributes::IntoIter: ExactSizeIterator,
+    {
+        self.push_response(peer_id, addresses, attributes, ttl)
+    }
+
+    /// Same as `respond_with_attributes`, but doesn't consume the query, so that another
+    /// response can be attempted if this one fails.
+    pub(crate) fn push_response<'b, TAddresses, TAttributes>(
+        &mut self,
+        peer_id: PeerId,
+        addresses: TAddresses,
+        attributes: TAttributes,
+        ttl: Duration,
+    ) -> Result<(), MdnsResponseError>
+    where
+        TAddresses: IntoIterator<Item = Multiaddr>,
+        TAddresses::IntoIter: ExactSizeIterator,
+        TAttributes: IntoIterator<Item = (&'b str, &'b str)>,
+        TAttributes::IntoIter: ExactSizeIterator,
+    {
+        let response = dns::build_query_response(
+            self.query_id,
+            peer_id,
+            addresses.into_iter(),
+            attributes.into_iter(),
+            ttl,
+        )?;
         self.send_buffers.push(response);
         Ok(())
     }
@@ -485,6 +755,31 @@ impl<'a> MdnsPeer<'a> {
     /// Filters out invalid addresses.
     pub fn addresses<'b>(&'b self) -> impl Iterator<Item = Multiaddr> + 'b {
         let my_peer_id = &self.peer_id;
+        self.txt_strings().filter_map(move |txt| {
+            let mut addr = match txt {
+                TxtString::Address(addr) => addr,
+                TxtString::Attribute(..) => return None,
+            };
+            match addr.pop() {
+                Some(Protocol::P2p(ref peer_id)) if peer_id == my_peer_id => (),
+                _ => return None,
+            };
+            Some(addr)
+        })
+    }
+
+    /// Returns the `key=value` attributes the peer has attached to its response.
+    ///
+    /// Filters out invalid attributes.
+    pub fn attributes<'b>(&'b self) -> impl Iterator<Item = (String, String)> + 'b {
+        self.txt_strings().filter_map(|txt| match txt {
+            TxtString::Attribute(key, value) => Some((key, value)),
+            TxtString::Address(_) => None,
+        })
+    }
+
+    /// Returns the TXT strings of the records that concern this peer.
+    fn txt_strings<'b>(&'b self) -> impl Iterator<Item = TxtString> + 'b {
         let record_value = &self.record_value;
         self.packet
             .additional
@@ -501,29 +796,8 @@ impl<'a> MdnsPeer<'a> {
                 }
             })
             .flat_map(|txt| txt.iter())
-            .filter_map(move |txt| {
-                // TODO: wrong, txt can be multiple character strings
-                let addr = match dns::decode_character_string(txt) {
-                    Ok(a) => a,
-                    Err(_) => return None,
-                };
-                if !addr.starts_with(b"dnsaddr=") {
-                    return None;
-                }
-                let addr = match str::from_utf8(&addr[8..]) {
-                    Ok(a) => a,
-                    Err(_) => return None,
-                };
-                let mut addr = match addr.parse::<Multiaddr>() {
-                    Ok(a) => a,
-                    Err(_) => return None,
-                };
-                match addr.pop() {
-                    Some(Protocol::P2p(ref peer_id)) if peer_id == my_peer_id => (),
-                    _ => return None,
-                };
-                Some(addr)
-            })
+            // TODO: wrong, txt can be multiple character strings
+            .filter_map(dns::parse_txt_string)
     }
 }
 
@@ -537,10 +811,63 @@ impl<'a> fmt::Debug for MdnsPeer<'a> {
 
 #[cfg(test)]
 mod tests {
-    use libp2p_core::PeerId;
-    use std::{io, time::Duration};
+    use dns_parser::Packet;
+    use libp2p_core::{Multiaddr, PeerId};
+    use std::{io, net::Ipv6Addr, net::SocketAddr, time::Duration};
     use tokio::{self, prelude::*};
-    use crate::service::{MdnsPacket, MdnsService};
+    use crate::dns;
+    use crate::service::{flush_buffers, MdnsPacket, MdnsResponse, MdnsService};
+    use crate::socket::MdnsSocket;
+
+    #[test]
+    fn parse_ipv6_response() {
+        let peer_id = PeerId::random();
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip6/fe80::1/tcp/4001".parse().unwrap(),
+            "/ip6/2001:db8::1/tcp/4001".parse().unwrap(),
+        ];
+        let response = dns::build_query_response(
+            0,
+            peer_id.clone(),
+            addrs.clone().into_iter(),
+            Vec::new().into_iter(),
+            Duration::from_secs(120),
+        ).unwrap();
+
+        let response = MdnsResponse {
+            packet: Packet::parse(&response).unwrap(),
+            from: SocketAddr::from((Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), 5353)),
+        };
+        let peers = response.discovered_peers().collect::<Vec<_>>();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].id(), &peer_id);
+        assert_eq!(peers[0].addresses().collect::<Vec<_>>(), addrs);
+    }
+
+    #[test]
+    fn parse_response_attributes() {
+        let peer_id = PeerId::random();
+        let addr: Multiaddr = "/ip4/192.168.1.2/tcp/4001".parse().unwrap();
+        let response = dns::build_query_response(
+            0,
+            peer_id.clone(),
+            vec![addr.clone()].into_iter(),
+            vec![("agent", "rust-libp2p"), ("role", "bootstrap")].into_iter(),
+            Duration::from_secs(120),
+        ).unwrap();
+
+        let response = MdnsResponse {
+            packet: Packet::parse(&response).unwrap(),
+            from: SocketAddr::from(([192, 168, 1, 2], 5353)),
+        };
+        let peers = response.discovered_peers().collect::<Vec<_>>();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].addresses().collect::<Vec<_>>(), vec![addr]);
+        assert_eq!(peers[0].attributes().collect::<Vec<_>>(), vec![
+            ("agent".to_owned(), "rust-libp2p".to_owned()),
+            ("role".to_owned(), "bootstrap".to_owned()),
+        ]);
+    }
 
     #[test]
     fn discover_ourselves() {
@@ -575,4 +902,40 @@ mod tests {
                 .for_each(|_| Ok(())),
         );
     }
+
+    #[test]
+    fn packets_are_sent_on_every_interface() {
+        /// Socket that records the packets sent on it, along with the selected interface.
+        #[derive(Default)]
+        struct RecordingSocket {
+            interface: u32,
+            sent: Vec<(u32, Vec<u8>)>,
+        }
+
+        impl MdnsSocket for RecordingSocket {
+            fn poll_send_to(&mut self, buf: &[u8], _: &SocketAddr) -> Poll<usize, io::Error> {
+                self.sent.push((self.interface, buf.to_vec()));
+                Ok(Async::Ready(buf.len()))
+            }
+
+            fn poll_recv_from(&mut self, _: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+                Ok(Async::NotReady)
+            }
+
+            fn set_multicast_if_v6(&mut self, interface: u32) -> io::Result<()> {
+                self.interface = interface;
+                Ok(())
+            }
+        }
+
+        let target = SocketAddr::from((Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb), 5353));
+        let mut socket = RecordingSocket::default();
+        let mut buffers = vec![vec![1], vec![2]];
+        let mut next_interface = 0;
+        flush_buffers(&mut socket, &mut buffers, &mut next_interface, &[2, 5], &target);
+
+        assert!(buffers.is_empty());
+        assert_eq!(next_interface, 0);
+        assert_eq!(socket.sent, vec![(2, vec![1]), (5, vec![1]), (2, vec![2]), (5, vec![2])]);
+    }
 }
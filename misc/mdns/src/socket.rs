@@ -0,0 +1,281 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Abstraction over the sockets used by the `MdnsService`.
+//!
+//! The `MdnsSocket` trait is implemented on `tokio_udp::UdpSocket`, which is what the service
+//! uses by default, and on `MemorySocket`. The latter is connected to a `MemoryMulticastBus` and
+//! lets multiple services living in the same process discover each other without touching the
+//! network, which is mostly useful for testing.
+
+use futures::{prelude::*, task};
+use std::{cmp, collections::VecDeque, fmt, io, net::Ipv4Addr, net::SocketAddr};
+use std::sync::{Arc, Mutex, Weak};
+use tokio_udp::UdpSocket;
+
+/// Socket that the `MdnsService` uses to send and receive packets.
+pub trait MdnsSocket {
+    /// Attempts to send `buf` to `target`. Returns the number of bytes written.
+    ///
+    /// On `NotReady`, the current task is notified once the socket can accept data again.
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error>;
+
+    /// Attempts to receive a packet and write it in `buf`. Returns the number of bytes written
+    /// and the sender of the packet.
+    ///
+    /// On `NotReady`, the current task is notified once a packet is available.
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error>;
+
+    /// Sets the index of the interface that the following IPv6 multicast packets are sent on.
+    /// The index `0` lets the operating system pick a default interface.
+    ///
+    /// The default implementation does nothing.
+    fn set_multicast_if_v6(&mut self, _interface: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MdnsSocket for UdpSocket {
+    #[inline]
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        UdpSocket::poll_send_to(self, buf, target)
+    }
+
+    #[inline]
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        UdpSocket::poll_recv_from(self, buf)
+    }
+
+    #[cfg(unix)]
+    fn set_multicast_if_v6(&mut self, interface: u32) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let interface = interface as libc::c_uint;
+        let ret = unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MULTICAST_IF,
+                &interface as *const libc::c_uint as *const libc::c_void,
+                std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// In-memory network on which `MemorySocket`s exchange packets.
+///
+/// Packets sent to the multicast address of the bus are delivered to all the sockets created
+/// with `join`, including the sender. Packets sent to any other address are delivered to the
+/// socket with that address, if any. Delivery is immediate and never fails.
+///
+/// Cloning the bus produces a handle to the same network.
+#[derive(Clone)]
+pub struct MemoryMulticastBus {
+    inner: Arc<Mutex<BusInner>>,
+}
+
+struct BusInner {
+    /// Address that packets are sent to in order to reach all the members.
+    multicast_addr: SocketAddr,
+    /// Sockets connected to the bus. The boolean is true if the socket is a member of the
+    /// multicast group.
+    sockets: Vec<(SocketAddr, bool, Weak<Mutex<SocketQueue>>)>,
+    /// Port to assign to the next socket.
+    next_port: u16,
+}
+
+/// Packets received by a `MemorySocket` and not processed yet.
+struct SocketQueue {
+    /// Received packets, with their sender.
+    packets: VecDeque<(Vec<u8>, SocketAddr)>,
+    /// Task to notify when a packet arrives.
+    task: Option<task::Task>,
+}
+
+impl MemoryMulticastBus {
+    /// Creates a new bus on which packets sent to `multicast_addr` are delivered to all the
+    /// members.
+    pub fn new(multicast_addr: SocketAddr) -> MemoryMulticastBus {
+        MemoryMulticastBus {
+            inner: Arc::new(Mutex::new(BusInner {
+                multicast_addr,
+                sockets: Vec::new(),
+                next_port: 1,
+            })),
+        }
+    }
+
+    /// Returns the address that packets are sent to in order to reach all the members.
+    pub fn multicast_addr(&self) -> SocketAddr {
+        self.inner.lock().expect("lock is never poisoned").multicast_addr
+    }
+
+    /// Creates a socket that is a member of the multicast group.
+    pub fn join(&self) -> MemorySocket {
+        self.new_socket(true)
+    }
+
+    /// Creates a socket that is not a member of the multicast group. It only receives the packets
+    /// sent directly to its address.
+    pub fn bind(&self) -> MemorySocket {
+        self.new_socket(false)
+    }
+
+    fn new_socket(&self, member: bool) -> MemorySocket {
+        let mut inner = self.inner.lock().expect("lock is never poisoned");
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, inner.next_port));
+        inner.next_port = inner.next_port.checked_add(1).expect("too many memory sockets");
+        let queue = Arc::new(Mutex::new(SocketQueue {
+            packets: VecDeque::new(),
+            task: None,
+        }));
+        inner.sockets.push((addr, member, Arc::downgrade(&queue)));
+        MemorySocket {
+            addr,
+            queue,
+            bus: self.clone(),
+        }
+    }
+
+    /// Delivers `packet` sent by `from` to the sockets that `target` designates.
+    fn deliver(&self, packet: &[u8], from: SocketAddr, target: &SocketAddr) {
+        let mut inner = self.inner.lock().expect("lock is never poisoned");
+        inner.sockets.retain(|(_, _, queue)| queue.upgrade().is_some());
+        let multicast = *target == inner.multicast_addr;
+        for (addr, member, queue) in inner.sockets.iter() {
+            if !(multicast && *member) && addr != target {
+                continue;
+            }
+            if let Some(queue) = queue.upgrade() {
+                let mut queue = queue.lock().expect("lock is never poisoned");
+                queue.packets.push_back((packet.to_vec(), from));
+                if let Some(task) = queue.task.take() {
+                    task.notify();
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MemoryMulticastBus {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().expect("lock is never poisoned");
+        fmt.debug_struct("MemoryMulticastBus")
+            .field("multicast_addr", &inner.multicast_addr)
+            .field("num_sockets", &inner.sockets.len())
+            .finish()
+    }
+}
+
+/// Socket connected to a `MemoryMulticastBus`.
+pub struct MemorySocket {
+    /// Address of this socket on the bus.
+    addr: SocketAddr,
+    /// Packets received by this socket.
+    queue: Arc<Mutex<SocketQueue>>,
+    /// The bus this socket is connected to.
+    bus: MemoryMulticastBus,
+}
+
+impl MemorySocket {
+    /// Returns the address of this socket on the bus.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl MdnsSocket for MemorySocket {
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        self.bus.deliver(buf, self.addr, target);
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        let mut queue = self.queue.lock().expect("lock is never poisoned");
+        match queue.packets.pop_front() {
+            Some((packet, from)) => {
+                // Similar to UDP, the part of the packet that doesn't fit in `buf` is discarded.
+                let len = cmp::min(packet.len(), buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+                Ok(Async::Ready((len, from)))
+            }
+            None => {
+                queue.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MemorySocket {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("MemorySocket")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MdnsSocket, MemoryMulticastBus};
+    use futures::{future, prelude::*};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn multicast_reaches_members_only() {
+        let multicast_addr = SocketAddr::from(([224, 0, 0, 251], 5353));
+        let bus = MemoryMulticastBus::new(multicast_addr);
+        let mut member1 = bus.join();
+        let mut member2 = bus.join();
+        let mut other = bus.bind();
+
+        future::lazy(move || {
+            let mut buf = [0; 16];
+            assert!(other.poll_send_to(b"hello", &multicast_addr).unwrap().is_ready());
+
+            for member in [&mut member1, &mut member2].iter_mut() {
+                match member.poll_recv_from(&mut buf).unwrap() {
+                    Async::Ready((len, from)) => {
+                        assert_eq!(&buf[..len], b"hello");
+                        assert_eq!(from, other.local_addr());
+                    }
+                    Async::NotReady => panic!("packet not delivered"),
+                }
+            }
+            assert!(other.poll_recv_from(&mut buf).unwrap().is_not_ready());
+
+            // Unicast only reaches the target.
+            let target = member2.local_addr();
+            assert!(member1.poll_send_to(b"direct", &target).unwrap().is_ready());
+            assert!(member1.poll_recv_from(&mut buf).unwrap().is_not_ready());
+            match member2.poll_recv_from(&mut buf).unwrap() {
+                Async::Ready((len, _)) => assert_eq!(&buf[..len], b"direct"),
+                Async::NotReady => panic!("packet not delivered"),
+            }
+            Ok::<_, ()>(())
+        }).wait().unwrap();
+    }
+}
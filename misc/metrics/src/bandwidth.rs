@@ -0,0 +1,68 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{Recorder, encoding::{Counter, Encoder, Family}};
+use libp2p_core::bandwidth::{BandwidthStats, ProtocolBandwidth};
+
+/// Metrics of the bandwidth used by the node.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    bytes: Family<Counter>,
+    protocol_bytes: Family<Counter>,
+}
+
+impl Metrics {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.family("libp2p_bandwidth_bytes",
+            "Number of bytes sent and received on all connections.", &self.bytes);
+        encoder.family("libp2p_bandwidth_protocol_bytes",
+            "Number of bytes sent and received on substreams, by protocol.", &self.protocol_bytes);
+    }
+}
+
+/// Records the totals of all the connections, for example from `BandwidthSnapshot::totals`.
+impl Recorder<BandwidthStats> for crate::Metrics {
+    fn record(&self, stats: &BandwidthStats) {
+        let mut metrics = self.inner.lock();
+        metrics.bandwidth.bytes
+            .get_or_create(&[("direction", "inbound")])
+            .set_total(stats.total_inbound());
+        metrics.bandwidth.bytes
+            .get_or_create(&[("direction", "outbound")])
+            .set_total(stats.total_outbound());
+    }
+}
+
+/// Records the totals of each protocol.
+impl Recorder<ProtocolBandwidth> for crate::Metrics {
+    fn record(&self, bandwidth: &ProtocolBandwidth) {
+        let protocols = bandwidth.protocols();
+        let mut metrics = self.inner.lock();
+        for (protocol, stats) in protocols {
+            let protocol = String::from_utf8_lossy(&protocol).into_owned();
+            metrics.bandwidth.protocol_bytes
+                .get_or_create(&[("protocol", &protocol), ("direction", "inbound")])
+                .set_total(stats.total_inbound());
+            metrics.bandwidth.protocol_bytes
+                .get_or_create(&[("protocol", &protocol), ("direction", "outbound")])
+                .set_total(stats.total_outbound());
+        }
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Metric types and their rendering in the OpenMetrics text format.
+
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// Labels identifying a metric within a [`Family`], as pairs of names and values.
+type Labels = Vec<(&'static str, String)>;
+
+/// Type of a metric, as it appears in the `# TYPE` line of its family.
+pub(crate) trait Metric {
+    /// Name of the type in the text format.
+    const TYPE: &'static str;
+
+    /// Writes the samples of the metric.
+    fn encode(&self, name: &str, labels: &[(&'static str, String)], out: &mut String);
+}
+
+/// Counter that only ever increases.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Counter {
+    value: u64,
+}
+
+impl Counter {
+    /// Increments the counter by one.
+    pub fn inc(&mut self) {
+        self.inc_by(1);
+    }
+
+    /// Increments the counter by the given value.
+    pub fn inc_by(&mut self, value: u64) {
+        self.value = self.value.saturating_add(value);
+    }
+
+    /// Sets the counter to a total tracked somewhere else. A total lower than the current value
+    /// is ignored, so that the counter never decreases.
+    pub fn set_total(&mut self, total: u64) {
+        if total > self.value {
+            self.value = total;
+        }
+    }
+}
+
+impl Metric for Counter {
+    const TYPE: &'static str = "counter";
+
+    fn encode(&self, name: &str, labels: &[(&'static str, String)], out: &mut String) {
+        write_sample(out, name, "_total", labels, None, &self.value.to_string());
+    }
+}
+
+/// Value that can go up and down.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Gauge {
+    value: i64,
+}
+
+impl Gauge {
+    /// Sets the value of the gauge.
+    pub fn set(&mut self, value: i64) {
+        self.value = value;
+    }
+
+    /// Increments the gauge by one.
+    pub fn inc(&mut self) {
+        self.value += 1;
+    }
+
+    /// Decrements the gauge by one.
+    pub fn dec(&mut self) {
+        self.value -= 1;
+    }
+}
+
+impl Metric for Gauge {
+    const TYPE: &'static str = "gauge";
+
+    fn encode(&self, name: &str, labels: &[(&'static str, String)], out: &mut String) {
+        write_sample(out, name, "", labels, None, &self.value.to_string());
+    }
+}
+
+/// Distribution of observed values over a fixed set of buckets.
+#[derive(Debug, Clone)]
+pub(crate) struct Histogram {
+    /// Upper bounds of the buckets, in increasing order. The `+Inf` bucket is implicit.
+    bounds: &'static [f64],
+    /// Number of observations of each bucket, not cumulated. Has one more element than `bounds`.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Creates an empty histogram with the given bucket upper bounds.
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Records a value.
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Records a duration, in seconds.
+    pub fn observe_duration(&mut self, duration: Duration) {
+        self.observe(duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9);
+    }
+}
+
+impl Metric for Histogram {
+    const TYPE: &'static str = "histogram";
+
+    fn encode(&self, name: &str, labels: &[(&'static str, String)], out: &mut String) {
+        let mut cumulated = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            cumulated += count;
+            let bound = bound.to_string();
+            write_sample(out, name, "_bucket", labels, Some(&bound), &cumulated.to_string());
+        }
+        write_sample(out, name, "_bucket", labels, Some("+Inf"), &self.count.to_string());
+        write_sample(out, name, "_sum", labels, None, &self.sum.to_string());
+        write_sample(out, name, "_count", labels, None, &self.count.to_string());
+    }
+}
+
+/// Set of metrics of the same name, distinguished by their labels.
+#[derive(Debug, Clone)]
+pub(crate) struct Family<M> {
+    metrics: BTreeMap<Labels, M>,
+    new_metric: fn() -> M,
+}
+
+impl<M: Default> Default for Family<M> {
+    fn default() -> Self {
+        Family::new(M::default)
+    }
+}
+
+impl<M> Family<M> {
+    /// Creates an empty family whose metrics are created with `new_metric`.
+    pub fn new(new_metric: fn() -> M) -> Self {
+        Family {
+            metrics: BTreeMap::new(),
+            new_metric,
+        }
+    }
+
+    /// Returns the metric with the given labels, creating it if necessary.
+    pub fn get_or_create(&mut self, labels: &[(&'static str, &str)]) -> &mut M {
+        let labels = labels.iter()
+            .map(|(name, value)| (*name, (*value).to_owned()))
+            .collect::<Labels>();
+        let new_metric = self.new_metric;
+        self.metrics.entry(labels).or_insert_with(new_metric)
+    }
+}
+
+/// Renders metric families in the OpenMetrics text format.
+pub(crate) struct Encoder {
+    out: String,
+}
+
+impl Encoder {
+    /// Creates an encoder with an empty output.
+    pub fn new() -> Self {
+        Encoder { out: String::new() }
+    }
+
+    /// Writes a metric family without labels.
+    pub fn metric<M: Metric>(&mut self, name: &str, help: &str, metric: &M) {
+        self.header::<M>(name, help);
+        metric.encode(name, &[], &mut self.out);
+    }
+
+    /// Writes a metric family with one sample per set of labels.
+    pub fn family<M: Metric>(&mut self, name: &str, help: &str, family: &Family<M>) {
+        self.header::<M>(name, help);
+        for (labels, metric) in &family.metrics {
+            metric.encode(name, labels, &mut self.out);
+        }
+    }
+
+    /// Terminates the exposition and returns it.
+    pub fn finish(mut self) -> String {
+        self.out.push_str("# EOF\n");
+        self.out
+    }
+
+    fn header<M: Metric>(&mut self, name: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, M::TYPE);
+    }
+}
+
+/// Writes a line with a sample. `le` is the upper bound of a histogram bucket, if any.
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[(&'static str, String)],
+    le: Option<&str>,
+    value: &str,
+) {
+    out.push_str(name);
+    out.push_str(suffix);
+    let le = le.map(|le| ("le", le));
+    let mut labels = labels.iter().map(|(n, v)| (*n, v.as_str())).chain(le).peekable();
+    if labels.peek().is_some() {
+        out.push('{');
+        for (i, (name, value)) in labels.enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            out.push_str(name);
+            out.push_str("=\"");
+            escape_label_value(value, out);
+            out.push('"');
+        }
+        out.push('}');
+    }
+    out.push(' ');
+    out.push_str(value);
+    out.push('\n');
+}
+
+fn escape_label_value(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_are_rendered() {
+        let mut counters = Family::<Counter>::default();
+        counters.get_or_create(&[("protocol", "/foo\"bar")]).inc_by(3);
+        counters.get_or_create(&[("protocol", "/baz")]).inc();
+
+        let mut histogram = Histogram::new(&[0.5, 1.0]);
+        histogram.observe(0.25);
+        histogram.observe(0.75);
+        histogram.observe(2.0);
+
+        let mut encoder = Encoder::new();
+        encoder.family("requests", "Number of requests.", &counters);
+        encoder.metric("latency_seconds", "Latency of the requests.", &histogram);
+
+        assert_eq!(encoder.finish(), "\
+# HELP requests Number of requests.
+# TYPE requests counter
+requests_total{protocol=\"/baz\"} 1
+requests_total{protocol=\"/foo\\\"bar\"} 3
+# HELP latency_seconds Latency of the requests.
+# TYPE latency_seconds histogram
+latency_seconds_bucket{le=\"0.5\"} 1
+latency_seconds_bucket{le=\"1\"} 2
+latency_seconds_bucket{le=\"+Inf\"} 3
+latency_seconds_sum 3
+latency_seconds_count 3
+# EOF
+");
+    }
+}
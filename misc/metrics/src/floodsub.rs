@@ -0,0 +1,48 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{Recorder, encoding::{Counter, Encoder}};
+use libp2p_floodsub::FloodsubEvent;
+
+/// Metrics of the floodsub messages.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    messages_received: Counter,
+    messages_forwarded: Counter,
+}
+
+impl Metrics {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.metric("libp2p_floodsub_messages_received",
+            "Number of floodsub messages received on a subscribed topic.", &self.messages_received);
+        encoder.metric("libp2p_floodsub_messages_forwarded",
+            "Number of floodsub messages propagated to other peers.", &self.messages_forwarded);
+    }
+}
+
+impl Recorder<FloodsubEvent> for crate::Metrics {
+    fn record(&self, event: &FloodsubEvent) {
+        match event {
+            FloodsubEvent::Message(_) => self.inner.lock().floodsub.messages_received.inc(),
+            FloodsubEvent::Forwarded { .. } => self.inner.lock().floodsub.messages_forwarded.inc(),
+            FloodsubEvent::Subscribed { .. } | FloodsubEvent::Unsubscribed { .. } => {}
+        }
+    }
+}
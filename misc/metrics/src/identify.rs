@@ -0,0 +1,55 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{Recorder, encoding::{Counter, Encoder, Family}};
+use libp2p_identify::IdentifyEvent;
+
+/// Metrics of the identify protocol.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    received: Counter,
+    errors: Counter,
+    sent: Family<Counter>,
+}
+
+impl Metrics {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.metric("libp2p_identify_received",
+            "Number of identification information received from remotes.", &self.received);
+        encoder.metric("libp2p_identify_errors",
+            "Number of remotes that failed to be identified.", &self.errors);
+        encoder.family("libp2p_identify_sent",
+            "Number of identification information sent back to remotes, by outcome.", &self.sent);
+    }
+}
+
+impl Recorder<IdentifyEvent> for crate::Metrics {
+    fn record(&self, event: &IdentifyEvent) {
+        let mut metrics = self.inner.lock();
+        match event {
+            IdentifyEvent::Identified { .. } => metrics.identify.received.inc(),
+            IdentifyEvent::Error { .. } => metrics.identify.errors.inc(),
+            IdentifyEvent::SendBack { result, .. } => {
+                let outcome = if result.is_ok() { "success" } else { "error" };
+                metrics.identify.sent.get_or_create(&[("outcome", outcome)]).inc();
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{Recorder, encoding::{Counter, Encoder, Family, Histogram}};
+use libp2p_kad::KademliaOut;
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the query durations, in seconds.
+const QUERY_DURATION_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 60.0];
+
+/// Metrics of the Kademlia queries.
+pub(crate) struct Metrics {
+    query_duration: Family<Histogram>,
+    query_results: Family<Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            query_duration: Family::new(|| Histogram::new(QUERY_DURATION_BUCKETS)),
+            query_results: Family::default(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.family("libp2p_kad_query_duration_seconds",
+            "Duration of the Kademlia queries.", &self.query_duration);
+        encoder.family("libp2p_kad_query_results",
+            "Number of finished Kademlia queries, by outcome.", &self.query_results);
+    }
+
+    fn record_query(&mut self, query: &'static str, found: bool, duration: Duration) {
+        let outcome = if found { "found" } else { "not_found" };
+        self.query_duration
+            .get_or_create(&[("query", query)])
+            .observe_duration(duration);
+        self.query_results
+            .get_or_create(&[("query", query), ("outcome", outcome)])
+            .inc();
+    }
+}
+
+impl Recorder<KademliaOut> for crate::Metrics {
+    fn record(&self, event: &KademliaOut) {
+        match event {
+            KademliaOut::FindNodeResult { key, closer_peers, duration } => {
+                let found = closer_peers.contains(key);
+                self.inner.lock().kad.record_query("find_node", found, *duration);
+            }
+            KademliaOut::GetProvidersResult { provider_peers, duration, .. } => {
+                let found = !provider_peers.is_empty();
+                self.inner.lock().kad.record_query("get_providers", found, *duration);
+            }
+            KademliaOut::Discovered { .. } | KademliaOut::KBucketAdded { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Metrics of the swarm and of the protocols, rendered in the OpenMetrics text format.
+//!
+//! A [`Metrics`] object holds counters and histograms shared by all the recorders of a node.
+//! They are updated in two ways:
+//!
+//! - [`SwarmMetrics`] is a [`NetworkBehaviour`](libp2p_core::swarm::NetworkBehaviour) that
+//!   records the connections opened and closed, the dial errors and the listen addresses. Add it
+//!   to the behaviour of the swarm next to the other behaviours.
+//! - The [`Recorder`] trait is implemented for the events of the Kademlia, floodsub, ping and
+//!   identify behaviours. Call [`Recorder::record`] from the `NetworkBehaviourEventProcess`
+//!   implementations of the behaviour. It is also implemented for
+//!   [`BandwidthStats`](libp2p_core::bandwidth::BandwidthStats), as returned by
+//!   `BandwidthSnapshot::totals`, and for
+//!   [`ProtocolBandwidth`](libp2p_core::bandwidth::ProtocolBandwidth).
+//!
+//! [`Metrics::encode`] then renders all the metrics, for example to answer the scrapes of a
+//! Prometheus server.
+//!
+//! ```
+//! use libp2p_metrics::{Metrics, Recorder};
+//! use libp2p_ping::{PingEvent, PingSuccess};
+//! use libp2p_core::PeerId;
+//! use std::time::Duration;
+//!
+//! let metrics = Metrics::new();
+//! metrics.record(&PingEvent {
+//!     peer: PeerId::random(),
+//!     result: Ok(PingSuccess::Ping { rtt: Duration::from_millis(30) }),
+//! });
+//! assert!(metrics.encode().contains("libp2p_ping_rtt_seconds_count 1"));
+//! ```
+
+mod bandwidth;
+mod encoding;
+mod floodsub;
+mod identify;
+mod kad;
+mod ping;
+mod swarm;
+
+pub use self::swarm::SwarmMetrics;
+
+use self::encoding::Encoder;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// Records the metrics derived from an event.
+pub trait Recorder<TEvent> {
+    /// Updates the metrics with the given event.
+    fn record(&self, event: &TEvent);
+}
+
+/// Metrics of a node.
+///
+/// Cloning a `Metrics` is cheap and returns a handle to the same metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Mutex<Families>>,
+}
+
+/// Metrics of the swarm and of each protocol.
+#[derive(Default)]
+struct Families {
+    swarm: swarm::Metrics,
+    kad: kad::Metrics,
+    floodsub: floodsub::Metrics,
+    ping: ping::Metrics,
+    identify: identify::Metrics,
+    bandwidth: bandwidth::Metrics,
+}
+
+impl Metrics {
+    /// Creates a new set of metrics, all at zero.
+    pub fn new() -> Self {
+        Metrics {
+            inner: Arc::new(Mutex::new(Families::default())),
+        }
+    }
+
+    /// Creates a behaviour that records the events of the swarm in these metrics.
+    pub fn swarm_behaviour<TSubstream>(&self) -> SwarmMetrics<TSubstream> {
+        SwarmMetrics::new(self.clone())
+    }
+
+    /// Renders all the metrics in the OpenMetrics text format.
+    pub fn encode(&self) -> String {
+        let families = self.inner.lock();
+        let mut encoder = Encoder::new();
+        families.swarm.encode(&mut encoder);
+        families.kad.encode(&mut encoder);
+        families.floodsub.encode(&mut encoder);
+        families.ping.encode(&mut encoder);
+        families.identify.encode(&mut encoder);
+        families.bandwidth.encode(&mut encoder);
+        encoder.finish()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_core::PeerId;
+    use libp2p_kad::KademliaOut;
+    use std::time::Duration;
+
+    #[test]
+    fn kademlia_queries_are_recorded() {
+        let metrics = Metrics::new();
+        let key = PeerId::random();
+        metrics.record(&KademliaOut::FindNodeResult {
+            key: key.clone(),
+            closer_peers: vec![PeerId::random(), key],
+            duration: Duration::from_millis(300),
+        });
+        metrics.record(&KademliaOut::FindNodeResult {
+            key: PeerId::random(),
+            closer_peers: Vec::new(),
+            duration: Duration::from_secs(30),
+        });
+
+        let text = metrics.encode();
+        assert!(text.contains("\nlibp2p_kad_query_results_total{query=\"find_node\",outcome=\"found\"} 1\n"));
+        assert!(text.contains("\nlibp2p_kad_query_results_total{query=\"find_node\",outcome=\"not_found\"} 1\n"));
+        assert!(text.contains("\nlibp2p_kad_query_duration_seconds_bucket{query=\"find_node\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("\nlibp2p_kad_query_duration_seconds_count{query=\"find_node\"} 2\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
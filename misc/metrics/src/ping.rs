@@ -0,0 +1,63 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::{Recorder, encoding::{Counter, Encoder, Family, Histogram}};
+use libp2p_ping::{PingEvent, PingFailure, PingSuccess};
+
+/// Upper bounds of the buckets of the round-trip times, in seconds.
+const RTT_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Metrics of the pings.
+pub(crate) struct Metrics {
+    rtt: Histogram,
+    failures: Family<Counter>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            rtt: Histogram::new(RTT_BUCKETS),
+            failures: Family::default(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.metric("libp2p_ping_rtt_seconds",
+            "Round-trip time of the outbound pings.", &self.rtt);
+        encoder.family("libp2p_ping_failures",
+            "Number of outbound pings that failed, by reason.", &self.failures);
+    }
+}
+
+impl Recorder<PingEvent> for crate::Metrics {
+    fn record(&self, event: &PingEvent) {
+        let mut metrics = self.inner.lock();
+        match &event.result {
+            Ok(PingSuccess::Ping { rtt }) => metrics.ping.rtt.observe_duration(*rtt),
+            Ok(PingSuccess::Pong) => {}
+            Err(PingFailure::Timeout) =>
+                metrics.ping.failures.get_or_create(&[("reason", "timeout")]).inc(),
+            Err(PingFailure::Other { .. }) =>
+                metrics.ping.failures.get_or_create(&[("reason", "other")]).inc(),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::encoding::{Counter, Encoder, Family, Gauge};
+use futures::prelude::*;
+use libp2p_core::{
+    Multiaddr, PeerId,
+    protocols_handler::{DummyProtocolsHandler, ProtocolsHandler},
+    swarm::{ConnectedPoint, ConnectionId, ConnectionLimitError, DisconnectReason},
+    swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters},
+};
+use std::{collections::HashSet, error, marker::PhantomData};
+use tokio_io::{AsyncRead, AsyncWrite};
+use void::Void;
+
+/// Metrics of the connections and of the listeners of the swarm.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    connections: Gauge,
+    connections_established: Family<Counter>,
+    connections_closed: Family<Counter>,
+    connections_refused: Family<Counter>,
+    unreachable_addresses: Family<Counter>,
+    dial_failures: Counter,
+    /// Addresses we are listening on. Kept so that expiring an address twice has no effect.
+    listen_addrs: HashSet<Multiaddr>,
+    listen_addresses: Gauge,
+}
+
+impl Metrics {
+    pub fn encode(&self, encoder: &mut Encoder) {
+        encoder.metric("libp2p_swarm_connections",
+            "Number of open connections.", &self.connections);
+        encoder.family("libp2p_swarm_connections_established",
+            "Number of connections established.", &self.connections_established);
+        encoder.family("libp2p_swarm_connections_closed",
+            "Number of connections closed, by reason.", &self.connections_closed);
+        encoder.family("libp2p_swarm_connections_refused",
+            "Number of connections refused because of the connection limits.", &self.connections_refused);
+        encoder.family("libp2p_swarm_unreachable_addresses",
+            "Number of addresses that failed to be dialed.", &self.unreachable_addresses);
+        encoder.metric("libp2p_swarm_dial_failures",
+            "Number of peers that failed to be dialed on all their addresses.", &self.dial_failures);
+        encoder.metric("libp2p_swarm_listen_addresses",
+            "Number of addresses the swarm is listening on.", &self.listen_addresses);
+    }
+
+    fn set_listen_addr(&mut self, addr: &Multiaddr, listening: bool) {
+        if listening {
+            self.listen_addrs.insert(addr.clone());
+        } else {
+            self.listen_addrs.remove(addr);
+        }
+        self.listen_addresses.set(self.listen_addrs.len() as i64);
+    }
+}
+
+/// Behaviour recording the events of the swarm in a [`Metrics`](crate::Metrics).
+///
+/// It neither opens substreams nor keeps connections alive.
+pub struct SwarmMetrics<TSubstream> {
+    metrics: crate::Metrics,
+    marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream> SwarmMetrics<TSubstream> {
+    /// Creates a behaviour that records in the given metrics.
+    pub fn new(metrics: crate::Metrics) -> Self {
+        SwarmMetrics {
+            metrics,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for SwarmMetrics<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite
+{
+    type ProtocolsHandler = DummyProtocolsHandler<TSubstream>;
+    type OutEvent = Void;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        DummyProtocolsHandler::default()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, _: PeerId, _: ConnectionId, endpoint: ConnectedPoint) {
+        let mut metrics = self.metrics.inner.lock();
+        metrics.swarm.connections.inc();
+        metrics.swarm.connections_established
+            .get_or_create(&[("direction", direction(&endpoint))])
+            .inc();
+    }
+
+    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectionId, endpoint: ConnectedPoint, reason: DisconnectReason<'_>) {
+        let mut metrics = self.metrics.inner.lock();
+        metrics.swarm.connections.dec();
+        metrics.swarm.connections_closed
+            .get_or_create(&[("direction", direction(&endpoint)), ("reason", disconnect_reason(&reason))])
+            .inc();
+    }
+
+    fn inject_node_event(&mut self, _: PeerId, _: ConnectionId, event: Void) {
+        void::unreachable(event)
+    }
+
+    fn inject_addr_reach_failure(&mut self, peer_id: Option<&PeerId>, _: &Multiaddr, _: &dyn error::Error) {
+        let peer = if peer_id.is_some() { "known" } else { "unknown" };
+        self.metrics.inner.lock().swarm.unreachable_addresses
+            .get_or_create(&[("peer", peer)])
+            .inc();
+    }
+
+    fn inject_dial_failure(&mut self, _: &PeerId) {
+        self.metrics.inner.lock().swarm.dial_failures.inc();
+    }
+
+    fn inject_connection_refused(&mut self, _: Option<&PeerId>, endpoint: &ConnectedPoint, _: &ConnectionLimitError) {
+        self.metrics.inner.lock().swarm.connections_refused
+            .get_or_create(&[("direction", direction(endpoint))])
+            .inc();
+    }
+
+    fn inject_new_listen_addr(&mut self, addr: &Multiaddr) {
+        self.metrics.inner.lock().swarm.set_listen_addr(addr, true);
+    }
+
+    fn inject_expired_listen_addr(&mut self, addr: &Multiaddr) {
+        self.metrics.inner.lock().swarm.set_listen_addr(addr, false);
+    }
+
+    fn poll(&mut self, _: &mut PollParameters<'_>) -> Async<NetworkBehaviourAction<<Self::ProtocolsHandler as ProtocolsHandler>::InEvent, Self::OutEvent>> {
+        Async::NotReady
+    }
+}
+
+fn direction(endpoint: &ConnectedPoint) -> &'static str {
+    match endpoint {
+        ConnectedPoint::Dialer { .. } => "outbound",
+        ConnectedPoint::Listener { .. } => "inbound",
+    }
+}
+
+fn disconnect_reason(reason: &DisconnectReason<'_>) -> &'static str {
+    match reason {
+        DisconnectReason::KeepAliveTimeout => "keep_alive_timeout",
+        DisconnectReason::Handler(_) => "handler",
+        DisconnectReason::RemoteClosed => "remote_closed",
+        DisconnectReason::Muxer(_) => "muxer",
+        DisconnectReason::Banned => "banned",
+        DisconnectReason::Behaviour => "behaviour",
+        DisconnectReason::Closing => "closing",
+        DisconnectReason::ResourceLimit(_) => "resource_limit",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn listen_addresses_are_counted_once() {
+        let metrics = crate::Metrics::new();
+        let mut behaviour: SwarmMetrics<Cursor<Vec<u8>>> = metrics.swarm_behaviour();
+        let first: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let second: Multiaddr = "/ip4/127.0.0.1/tcp/4002".parse().unwrap();
+
+        behaviour.inject_new_listen_addr(&first);
+        behaviour.inject_new_listen_addr(&second);
+        behaviour.inject_expired_listen_addr(&first);
+        behaviour.inject_expired_listen_addr(&first);
+        behaviour.inject_addr_reach_failure(None, &first, &std::io::Error::from(std::io::ErrorKind::Other));
+
+        let text = metrics.encode();
+        assert!(text.contains("\nlibp2p_swarm_listen_addresses 1\n"));
+        assert!(text.contains("\nlibp2p_swarm_unreachable_addresses_total{peer=\"unknown\"} 1\n"));
+    }
+}
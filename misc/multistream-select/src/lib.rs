@@ -74,22 +74,68 @@ mod tests;
 
 mod protocol;
 
-use futures::prelude::*;
-use std::io;
+use futures::{prelude::*, try_ready};
+use std::{any::Any, io};
 
 pub use self::dialer_select::{dialer_select_proto, DialerSelectFuture};
 pub use self::error::ProtocolChoiceError;
 pub use self::listener_select::{listener_select_proto, ListenerSelectFuture};
 
 /// A stream after it has been negotiated.
-pub struct Negotiated<TInner>(pub(crate) TInner);
+pub struct Negotiated<TInner> {
+    inner: TInner,
+    /// Values that live as long as the stream. See `attach`.
+    attached: Vec<Box<dyn Any + Send + Sync>>,
+    /// Notified of the bytes that go through the stream. See `observe`.
+    observers: Vec<Box<dyn TrafficObserver>>,
+}
+
+/// Observes the bytes read from and written to a `Negotiated` stream.
+pub trait TrafficObserver: Send + Sync {
+    /// Called when bytes have been read from the stream.
+    fn on_read(&self, num_bytes: usize);
+
+    /// Called when bytes have been written to the stream.
+    fn on_write(&self, num_bytes: usize);
+}
+
+impl<TInner> Negotiated<TInner> {
+    pub(crate) fn new(inner: TInner) -> Self {
+        Negotiated { inner, attached: Vec::new(), observers: Vec::new() }
+    }
+
+    /// Attaches a value to the stream, which is dropped at the same time as the stream.
+    ///
+    /// This can be used, for example, to keep track of the number of negotiated streams that
+    /// are still alive.
+    pub fn attach<T>(&mut self, value: T)
+    where
+        T: Send + Sync + 'static
+    {
+        self.attached.push(Box::new(value));
+    }
+
+    /// Notifies `observer` of every byte read from or written to the stream from now on.
+    ///
+    /// The observer is dropped at the same time as the stream.
+    pub fn observe<T>(&mut self, observer: T)
+    where
+        T: TrafficObserver + 'static
+    {
+        self.observers.push(Box::new(observer));
+    }
+}
 
 impl<TInner> io::Read for Negotiated<TInner>
 where
     TInner: io::Read
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.0.read(buf)
+        let num_bytes = self.inner.read(buf)?;
+        for observer in &self.observers {
+            observer.on_read(num_bytes);
+        }
+        Ok(num_bytes)
     }
 }
 
@@ -98,11 +144,15 @@ where
     TInner: tokio_io::AsyncRead
 {
     unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
-        self.0.prepare_uninitialized_buffer(buf)
+        self.inner.prepare_uninitialized_buffer(buf)
     }
 
     fn read_buf<B: bytes::BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
-        self.0.read_buf(buf)
+        let num_bytes = try_ready!(self.inner.read_buf(buf));
+        for observer in &self.observers {
+            observer.on_read(num_bytes);
+        }
+        Ok(Async::Ready(num_bytes))
     }
 }
 
@@ -111,11 +161,15 @@ where
     TInner: io::Write
 {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.0.write(buf)
+        let num_bytes = self.inner.write(buf)?;
+        for observer in &self.observers {
+            observer.on_write(num_bytes);
+        }
+        Ok(num_bytes)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.0.flush()
+        self.inner.flush()
     }
 }
 
@@ -124,6 +178,6 @@ where
     TInner: tokio_io::AsyncWrite
 {
     fn shutdown(&mut self) -> Poll<(), io::Error> {
-        self.0.shutdown()
+        self.inner.shutdown()
     }
 }
@@ -58,6 +58,15 @@ impl Elem {
         }
     }
 
+    /// Returns the number of bytes of data carried by this message.
+    #[inline]
+    pub fn data_len(&self) -> usize {
+        match self {
+            Elem::Data { data, .. } => data.len(),
+            _ => 0,
+        }
+    }
+
     /// Returns true if this message is `Close` or `Reset`.
     #[inline]
     pub fn is_close_or_reset_msg(&self) -> bool {
@@ -76,6 +85,16 @@ impl Elem {
             false
         }
     }
+
+    /// Returns true if this message is `Reset`.
+    #[inline]
+    pub fn is_reset_msg(&self) -> bool {
+        if let Elem::Reset { .. } = self {
+            true
+        } else {
+            false
+        }
+    }
 }
 
 pub struct Codec {
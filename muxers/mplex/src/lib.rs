@@ -20,13 +20,14 @@
 
 mod codec;
 
-use std::{cmp, iter, mem};
+use std::{cmp, collections::VecDeque, iter, mem};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::sync::{atomic::AtomicUsize, atomic::Ordering, Arc};
 use bytes::Bytes;
 use libp2p_core::{
     Endpoint,
     StreamMuxer,
+    resource::{ConnectionScope, MemoryScope, ResourceManager},
     upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, Negotiated}
 };
 use log::{debug, trace};
@@ -48,6 +49,8 @@ pub struct MplexConfig {
     /// When sending data, split it into frames whose maximum size is this value
     /// (max 1MByte, as per the Mplex spec).
     split_send_size: usize,
+    /// If `Some`, where to reserve the memory of the internal buffer.
+    resource_manager: Option<ResourceManager>,
 }
 
 impl MplexConfig {
@@ -93,19 +96,33 @@ impl MplexConfig {
         self
     }
 
+    /// Reserves the data held in the internal buffer in the transient scope of the given
+    /// `ResourceManager`, until the muxer is given the scope of its connection with
+    /// `StreamMuxer::set_resource_scope`. The swarm does so if it has a `ResourceManager`.
+    ///
+    /// If the memory can't be reserved, an error is generated and the connection closes.
+    pub fn resource_manager(&mut self, manager: ResourceManager) -> &mut Self {
+        self.resource_manager = Some(manager);
+        self
+    }
+
     #[inline]
     fn upgrade<C>(self, i: C) -> Multiplex<C>
     where
         C: AsyncRead + AsyncWrite
     {
         let max_buffer_len = self.max_buffer_len;
+        let memory = self.resource_manager.as_ref().map(ResourceManager::transient_memory);
         Multiplex {
             inner: Mutex::new(MultiplexInner {
                 error: Ok(()),
                 inner: executor::spawn(Framed::new(i, codec::Codec::new()).fuse()),
                 config: self,
                 buffer: Vec::with_capacity(cmp::min(max_buffer_len, 512)),
+                memory,
                 opened_substreams: Default::default(),
+                pending_resets: VecDeque::new(),
+                flush_resets: false,
                 next_outbound_stream_id: 0,
                 notifier_read: Arc::new(Notifier {
                     to_notify: Mutex::new(Default::default()),
@@ -128,6 +145,7 @@ impl Default for MplexConfig {
             max_buffer_len: 4096,
             max_buffer_behaviour: MaxBufferBehaviour::CloseAll,
             split_send_size: 1024,
+            resource_manager: None,
         }
     }
 }
@@ -195,11 +213,18 @@ struct MultiplexInner<C> {
     config: MplexConfig,
     // Buffer of elements pulled from the stream but not processed yet.
     buffer: Vec<codec::Elem>,
+    /// If `Some`, where the data held in `buffer` is reserved. Either in the transient scope or,
+    /// once it is known, in the scope of the connection.
+    memory: Option<MemoryScope>,
     // List of Ids of opened substreams. Used to filter out messages that don't belong to any
     // substream. Note that this is handled exclusively by `next_match`.
     // The `Endpoint` value denotes who initiated the substream from our point of view
     // (see note [StreamId]).
     opened_substreams: FnvHashSet<(u32, Endpoint)>,
+    /// `Reset` messages of the substreams that have been destroyed and that couldn't be sent yet.
+    pending_resets: VecDeque<codec::Elem>,
+    /// If true, `Reset` messages have been sent but not flushed yet.
+    flush_resets: bool,
     // Id of the next outgoing substream.
     next_outbound_stream_id: u32,
     /// List of tasks to notify when a read event happens on the underlying stream.
@@ -213,6 +238,55 @@ struct MultiplexInner<C> {
     is_acknowledged: bool,
 }
 
+impl<C> MultiplexInner<C> {
+    /// Removes the elements of the buffer for which `f` returns false, and releases their data.
+    fn retain_buffer(&mut self, mut f: impl FnMut(&codec::Elem) -> bool) {
+        let mut released = 0;
+        self.buffer.retain(|elem| {
+            let keep = f(elem);
+            if !keep {
+                released += elem.data_len();
+            }
+            keep
+        });
+        if let Some(memory) = &self.memory {
+            memory.release_memory(released);
+        }
+    }
+}
+
+impl<C> MultiplexInner<C>
+where C: AsyncRead + AsyncWrite
+{
+    /// Sends and flushes the pending `Reset` messages.
+    ///
+    /// Doesn't register the current task if `NotReady` is returned, so that it can be called from
+    /// outside of a task.
+    fn poll_send_resets(&mut self) -> Poll<(), IoError> {
+        if self.is_shutdown {
+            self.pending_resets.clear();
+            self.flush_resets = false;
+            return Ok(Async::Ready(()));
+        }
+
+        while let Some(elem) = self.pending_resets.pop_front() {
+            match self.inner.start_send_notify(elem, &self.notifier_write, 0)? {
+                AsyncSink::Ready => self.flush_resets = true,
+                AsyncSink::NotReady(elem) => {
+                    self.pending_resets.push_front(elem);
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+
+        if self.flush_resets {
+            try_ready!(self.inner.poll_flush_notify(&self.notifier_write, 0));
+            self.flush_resets = false;
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
 struct Notifier {
     /// List of tasks to notify.
     to_notify: Mutex<FnvHashMap<usize, task::Task>>,
@@ -256,13 +330,21 @@ where C: AsyncRead + AsyncWrite,
         return Err(IoError::new(err.kind(), err.to_string()));
     }
 
+    // Take the opportunity to send the `Reset` messages of the substreams destroyed earlier.
+    if let Async::NotReady = inner.poll_send_resets()? {
+        inner.notifier_write.to_notify.lock().insert(TASK_ID.with(|&t| t), task::current());
+    }
+
     if let Some((offset, out)) = inner.buffer.iter().enumerate().filter_map(|(n, v)| filter(v).map(|v| (n, v))).next() {
         // The buffer was full and no longer is, so let's notify everything.
         if inner.buffer.len() == inner.config.max_buffer_len {
             executor::Notify::notify(&*inner.notifier_read, 0);
         }
 
-        inner.buffer.remove(offset);
+        let elem = inner.buffer.remove(offset);
+        if let Some(memory) = &inner.memory {
+            memory.release_memory(elem.data_len());
+        }
         return Ok(Async::Ready(out));
     }
 
@@ -301,6 +383,7 @@ where C: AsyncRead + AsyncWrite,
         inner.is_acknowledged = true;
 
         // Handle substreams opening/closing.
+        let mut was_open = false;
         match elem {
             codec::Elem::Open { substream_id } => {
                 if !inner.opened_substreams.insert((substream_id, Endpoint::Listener)) {
@@ -308,7 +391,7 @@ where C: AsyncRead + AsyncWrite,
                 }
             }
             codec::Elem::Close { substream_id, endpoint, .. } | codec::Elem::Reset { substream_id, endpoint, .. } => {
-                inner.opened_substreams.remove(&(substream_id, !endpoint));
+                was_open = inner.opened_substreams.remove(&(substream_id, !endpoint));
             }
             _ => ()
         }
@@ -317,7 +400,17 @@ where C: AsyncRead + AsyncWrite,
             return Ok(Async::Ready(out));
         } else {
             let endpoint = elem.endpoint().unwrap_or(Endpoint::Dialer);
-            if inner.opened_substreams.contains(&(elem.substream_id(), !endpoint)) || elem.is_open_msg() {
+            // `Reset` messages are kept so that reading from the substream produces an error.
+            if inner.opened_substreams.contains(&(elem.substream_id(), !endpoint))
+                || elem.is_open_msg() || (elem.is_reset_msg() && was_open)
+            {
+                if let Some(memory) = &inner.memory {
+                    if let Err(err) = memory.reserve_memory(elem.data_len()) {
+                        debug!("Failed to reserve mplex buffer: {}", err);
+                        inner.error = Err(IoError::new(IoErrorKind::Other, err.to_string()));
+                        return Err(IoError::new(IoErrorKind::Other, err));
+                    }
+                }
                 inner.buffer.push(elem);
             } else if !elem.is_close_or_reset_msg() {
                 debug!("Ignored message {:?} because the substream wasn't open", elem);
@@ -425,7 +518,7 @@ where C: AsyncRead + AsyncWrite
                 },
                 Err(err) => {
                     debug!("Failed to open outbound substream {}", substream.num);
-                    inner.buffer.retain(|elem| {
+                    inner.retain_buffer(|elem| {
                         elem.substream_id() != substream.num || elem.endpoint() == Some(Endpoint::Dialer)
                     });
                     return Err(err)
@@ -485,12 +578,17 @@ where C: AsyncRead + AsyncWrite
                     codec::Elem::Data { substream_id, endpoint, data, .. }
                         if *substream_id == substream.num && *endpoint != substream.endpoint => // see note [StreamId]
                     {
-                        Some(Some(data.clone()))
+                        Some(Ok(Some(data.clone())))
                     }
                     codec::Elem::Close { substream_id, endpoint }
                         if *substream_id == substream.num && *endpoint != substream.endpoint => // see note [StreamId]
                     {
-                        Some(None)
+                        Some(Ok(None))
+                    }
+                    codec::Elem::Reset { substream_id, endpoint }
+                        if *substream_id == substream.num && *endpoint != substream.endpoint => // see note [StreamId]
+                    {
+                        Some(Err(IoErrorKind::ConnectionReset))
                     }
                     _ => None
                 }
@@ -499,11 +597,17 @@ where C: AsyncRead + AsyncWrite
             // We're in a loop, so all we need to do is set `substream.current_data` to the data we
             // just read and wait for the next iteration.
             match next_data_poll? {
-                Async::Ready(Some(data)) => substream.current_data = data,
-                Async::Ready(None) => {
+                Async::Ready(Ok(Some(data))) => substream.current_data = data,
+                Async::Ready(Ok(None)) => {
                     substream.remote_open = false;
                     return Ok(Async::Ready(0));
                 },
+                Async::Ready(Err(kind)) => {
+                    debug!("Substream {} has been reset by the remote", substream.num);
+                    substream.remote_open = false;
+                    substream.local_open = false;
+                    return Err(kind.into());
+                },
                 Async::NotReady => {
                     // There was no data packet in the buffer about this substream; maybe it's
                     // because it has been closed.
@@ -573,9 +677,24 @@ where C: AsyncRead + AsyncWrite
     }
 
     fn destroy_substream(&self, sub: Self::Substream) {
-        self.inner.lock().buffer.retain(|elem| {
+        let mut inner = self.inner.lock();
+        inner.retain_buffer(|elem| {
             elem.substream_id() != sub.num || elem.endpoint() == Some(sub.endpoint)
-        })
+        });
+
+        // Unless both sides have closed the substream, let the remote know that we're no longer
+        // reading from or writing to it.
+        let still_open = inner.opened_substreams.remove(&(sub.num, sub.endpoint));
+        if sub.local_open || still_open {
+            debug!("Resetting substream {}", sub.num);
+            inner.pending_resets.push_back(codec::Elem::Reset {
+                substream_id: sub.num,
+                endpoint: sub.endpoint,
+            });
+            if let Err(err) = inner.poll_send_resets() {
+                debug!("Failed to reset substream {}: {:?}", sub.num, err);
+            }
+        }
     }
 
     fn is_remote_acknowledged(&self) -> bool {
@@ -590,12 +709,26 @@ where C: AsyncRead + AsyncWrite
         Ok(Async::Ready(()))
     }
 
+    fn set_resource_scope(&self, scope: &ConnectionScope) {
+        let mut inner = self.inner.lock();
+        let memory = scope.memory();
+        let buffered = inner.buffer.iter().map(codec::Elem::data_len).sum();
+        if let Err(err) = memory.reserve_memory(buffered) {
+            debug!("Failed to reserve mplex buffer: {}", err);
+            inner.error = Err(IoError::new(IoErrorKind::Other, err.to_string()));
+            return;
+        }
+        // Replacing the transient scope, if any, releases the memory reserved in it.
+        inner.memory = Some(memory);
+    }
+
     #[inline]
     fn flush_all(&self) -> Poll<(), IoError> {
         let inner = &mut *self.inner.lock();
         if inner.is_shutdown {
             return Ok(Async::Ready(()))
         }
+        try_ready!(inner.poll_send_resets());
         inner.inner.poll_flush_notify(&inner.notifier_write, 0)
     }
 }
@@ -18,10 +18,14 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use libp2p_core::{muxing, Transport, transport::ListenerEvent};
+use libp2p_core::{
+    Endpoint, StreamMuxer, muxing, Transport,
+    resource::{ResourceManager, ResourceManagerConfig},
+    transport::ListenerEvent
+};
 use libp2p_tcp::TcpConfig;
-use futures::prelude::*;
-use std::sync::{Arc, mpsc};
+use futures::{future, prelude::*};
+use std::{io, sync::{Arc, mpsc}};
 use std::thread;
 use tokio::{
     codec::length_delimited::Builder,
@@ -154,3 +158,139 @@ fn client_to_server_inbound() {
     let _ = rt.block_on(future).unwrap();
     bg_thread.join().unwrap();
 }
+
+#[test]
+fn dropped_substream_is_reset() {
+    // The server drops the substream opened by the client, which must notice it when reading.
+
+    let (tx, rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    let bg_thread = thread::spawn(move || {
+        let transport =
+            TcpConfig::new().with_upgrade(libp2p_mplex::MplexConfig::new());
+
+        let mut listener = transport
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+
+        let addr = listener.by_ref().wait()
+            .next()
+            .expect("some event")
+            .expect("no error")
+            .into_new_address()
+            .expect("listen address");
+
+        tx.send(addr).unwrap();
+
+        let future = listener
+            .filter_map(ListenerEvent::into_upgrade)
+            .into_future()
+            .map_err(|(err, _)| panic!("{:?}", err))
+            .and_then(|(client, _)| client.unwrap().0)
+            .map_err(|err| panic!("{:?}", err))
+            .and_then(|client| {
+                let client = Arc::new(client);
+                muxing::inbound_from_ref_and_wrap(client.clone()).map(move |substream| (client, substream))
+            });
+
+        let mut rt = Runtime::new().unwrap();
+        let (client, substream) = rt.block_on(future).unwrap();
+        drop(substream);
+        // Keep the connection open until the client is done.
+        done_rx.recv().unwrap();
+        drop(client);
+    });
+
+    let transport = TcpConfig::new().with_upgrade(libp2p_mplex::MplexConfig::new());
+
+    let future = transport
+        .dial(rx.recv().unwrap())
+        .unwrap()
+        .map_err(|err| panic!("{:?}", err))
+        .and_then(|client| muxing::outbound_from_ref_and_wrap(Arc::new(client)))
+        .and_then(|server| tokio::io::read(server, vec![0; 16]));
+
+    let mut rt = Runtime::new().unwrap();
+    match rt.block_on(future) {
+        Err(ref err) if err.kind() == io::ErrorKind::ConnectionReset => {}
+        Err(err) => panic!("Unexpected error: {:?}", err),
+        Ok(_) => panic!("Reading from a reset substream should fail"),
+    }
+    done_tx.send(()).unwrap();
+    bg_thread.join().unwrap();
+}
+
+#[test]
+fn buffer_is_reserved_in_connection_scope() {
+    // The server sends data on a substream that the client doesn't read, which is buffered by the
+    // client's muxer in the scope of its connection.
+
+    let (tx, rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    let bg_thread = thread::spawn(move || {
+        let transport =
+            TcpConfig::new().with_upgrade(libp2p_mplex::MplexConfig::new());
+
+        let mut listener = transport
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+
+        let addr = listener.by_ref().wait()
+            .next()
+            .expect("some event")
+            .expect("no error")
+            .into_new_address()
+            .expect("listen address");
+
+        tx.send(addr).unwrap();
+
+        let future = listener
+            .filter_map(ListenerEvent::into_upgrade)
+            .into_future()
+            .map_err(|(err, _)| panic!("{:?}", err))
+            .and_then(|(client, _)| client.unwrap().0)
+            .map_err(|err| panic!("{:?}", err))
+            .and_then(|client| {
+                let client = Arc::new(client);
+                muxing::outbound_from_ref_and_wrap(client.clone()).map(move |substream| (client, substream))
+            })
+            .map(|(client, substream)| (client, Builder::new().new_write(substream)))
+            .and_then(|(client, substream)| substream.send("hello world".into()).map(move |s| (client, s)));
+
+        let mut rt = Runtime::new().unwrap();
+        let (_client, _substream) = rt.block_on(future).unwrap();
+        // Keep the connection open until the client is done.
+        done_rx.recv().unwrap();
+    });
+
+    let manager = ResourceManager::new(ResourceManagerConfig::new());
+    let transport = TcpConfig::new().with_upgrade({
+        let mut config = libp2p_mplex::MplexConfig::new();
+        config.resource_manager(manager.clone());
+        config
+    });
+    let mut rt = Runtime::new().unwrap();
+    let client = rt.block_on(transport.dial(rx.recv().unwrap()).unwrap()).unwrap();
+    let connection = manager.open_connection(Endpoint::Dialer).unwrap();
+    client.set_resource_scope(&connection);
+    let client = Arc::new(client);
+
+    let future = muxing::inbound_from_ref_and_wrap(client.clone())
+        .and_then(move |substream| future::poll_fn(move || {
+            // Keep polling the connection without reading from the substream.
+            if connection.usage().memory() >= "hello world".len() {
+                return Ok(Async::Ready(()));
+            }
+            match client.poll_inbound()? {
+                Async::Ready(_) => panic!("Unexpected substream"),
+                Async::NotReady => Ok(Async::NotReady),
+            }
+        }).map(move |()| drop(substream)));
+
+    rt.block_on(future).unwrap();
+    assert_eq!(manager.transient_usage().memory(), 0);
+    done_tx.send(()).unwrap();
+    bg_thread.join().unwrap();
+}
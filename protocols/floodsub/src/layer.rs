@@ -23,7 +23,7 @@ use crate::topic::{Topic, TopicHash};
 use cuckoofilter::CuckooFilter;
 use fnv::FnvHashSet;
 use futures::prelude::*;
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{protocols_handler::ProtocolsHandler, protocols_handler::OneShotHandler, Multiaddr, PeerId};
 use rand;
 use smallvec::SmallVec;
@@ -48,6 +48,10 @@ pub struct Floodsub<TSubstream> {
     //       opened substreams
     connected_peers: HashMap<PeerId, SmallVec<[TopicHash; 8]>>,
 
+    /// Open connections to each of the peers in `connected_peers`. Subscriptions are exchanged
+    /// when the first connection is opened, and the peer is forgotten when the last one closes.
+    peer_connections: HashMap<PeerId, SmallVec<[ConnectionId; 2]>>,
+
     // List of topics we're subscribed to. Necessary to filter out messages that we receive
     // erroneously.
     subscribed_topics: SmallVec<[Topic; 16]>,
@@ -68,6 +72,7 @@ impl<TSubstream> Floodsub<TSubstream> {
             local_peer_id,
             target_peers: FnvHashSet::default(),
             connected_peers: HashMap::new(),
+            peer_connections: HashMap::new(),
             subscribed_topics: SmallVec::new(),
             received: CuckooFilter::new(),
             marker: PhantomData,
@@ -221,7 +226,13 @@ where
         Vec::new()
     }
 
-    fn inject_connected(&mut self, id: PeerId, _: ConnectedPoint) {
+    fn inject_connected(&mut self, id: PeerId, connection_id: ConnectionId, _: ConnectedPoint) {
+        let connections = self.peer_connections.entry(id.clone()).or_insert_with(SmallVec::new);
+        connections.push(connection_id);
+        if connections.len() > 1 {
+            return;
+        }
+
         // We need to send our subscriptions to the newly-connected node.
         if self.target_peers.contains(&id) {
             for topic in self.subscribed_topics.iter() {
@@ -241,7 +252,15 @@ where
         self.connected_peers.insert(id.clone(), SmallVec::new());
     }
 
-    fn inject_disconnected(&mut self, id: &PeerId, _: ConnectedPoint) {
+    fn inject_disconnected(&mut self, id: &PeerId, connection_id: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {
+        if let Some(connections) = self.peer_connections.get_mut(id) {
+            connections.retain(|c| *c != connection_id);
+            if !connections.is_empty() {
+                return;
+            }
+        }
+        self.peer_connections.remove(id);
+
         let was_in = self.connected_peers.remove(id);
         debug_assert!(was_in.is_some());
 
@@ -255,6 +274,7 @@ where
     fn inject_node_event(
         &mut self,
         propagation_source: PeerId,
+        _: ConnectionId,
         event: InnerMessage,
     ) {
         // We ignore successful sends event.
@@ -307,6 +327,7 @@ where
             }
 
             // Propagate the message to everyone else who is subscribed to any of the topics.
+            let mut num_peers = 0;
             for (peer_id, subscr_topics) in self.connected_peers.iter() {
                 if peer_id == &propagation_source {
                     continue;
@@ -324,6 +345,14 @@ where
                         messages: vec![message.clone()],
                     }));
                 }
+                num_peers += 1;
+            }
+
+            if num_peers > 0 {
+                self.events.push_back(NetworkBehaviourAction::GenerateEvent(FloodsubEvent::Forwarded {
+                    propagation_source: propagation_source.clone(),
+                    num_peers,
+                }));
             }
         }
 
@@ -395,4 +424,12 @@ pub enum FloodsubEvent {
         /// The topic it has subscribed from.
         topic: TopicHash,
     },
+
+    /// A received message has been propagated to other peers subscribed to its topics.
+    Forwarded {
+        /// Remote that sent us the message.
+        propagation_source: PeerId,
+        /// Number of peers the message has been sent to.
+        num_peers: usize,
+    },
 }
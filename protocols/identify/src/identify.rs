@@ -23,13 +23,16 @@ use crate::periodic_id_handler::{PeriodicIdHandler, PeriodicIdHandlerEvent};
 use crate::protocol::{IdentifyInfo, IdentifySender, IdentifySenderFuture};
 use futures::prelude::*;
 use libp2p_core::protocols_handler::{ProtocolsHandler, ProtocolsHandlerSelect, ProtocolsHandlerUpgrErr};
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{AddressSource, ConnectedPoint, ConnectionId, DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{Multiaddr, PeerId, PublicKey, either::EitherOutput, upgrade::Negotiated};
 use smallvec::SmallVec;
-use std::{collections::HashMap, collections::VecDeque, io};
+use std::{collections::HashMap, collections::VecDeque, io, time::Duration};
 use tokio_io::{AsyncRead, AsyncWrite};
 use void::Void;
 
+/// How long the listening addresses reported by a remote remain in the peer store.
+const REPORTED_ADDRESS_TTL: Duration = Duration::from_secs(60 * 60);
+
 /// Network behaviour that automatically identifies nodes periodically, returns information
 /// about them, and answers identify queries from other nodes.
 pub struct Identify<TSubstream> {
@@ -39,8 +42,8 @@ pub struct Identify<TSubstream> {
     agent_version: String,
     /// The public key of the local node. To report on the wire.
     local_public_key: PublicKey,
-    /// For each peer we're connected to, the observed address to send back to it.
-    observed_addresses: HashMap<PeerId, Multiaddr>,
+    /// For each connection we have, the observed address to send back to the remote.
+    observed_addresses: HashMap<ConnectionId, Multiaddr>,
     /// List of senders to answer, with the observed multiaddr.
     to_answer: SmallVec<[(PeerId, IdentifySender<Negotiated<TSubstream>>, Multiaddr); 4]>,
     /// List of futures that send back information back to remotes.
@@ -79,22 +82,23 @@ where
         Vec::new()
     }
 
-    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+    fn inject_connected(&mut self, _: PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint) {
         let observed = match endpoint {
             ConnectedPoint::Dialer { address } => address,
             ConnectedPoint::Listener { send_back_addr, .. } => send_back_addr,
         };
 
-        self.observed_addresses.insert(peer_id, observed);
+        self.observed_addresses.insert(connection_id, observed);
     }
 
-    fn inject_disconnected(&mut self, peer_id: &PeerId, _: ConnectedPoint) {
-        self.observed_addresses.remove(peer_id);
+    fn inject_disconnected(&mut self, _: &PeerId, connection_id: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {
+        self.observed_addresses.remove(&connection_id);
     }
 
     fn inject_node_event(
         &mut self,
         peer_id: PeerId,
+        connection_id: ConnectionId,
         event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
         match event {
@@ -111,10 +115,10 @@ where
                     });
             }
             EitherOutput::First(sender) => {
-                let observed = self.observed_addresses.get(&peer_id)
-                    .expect("We only receive events from nodes we're connected to. We insert \
-                             into the hashmap when we connect to a node and remove only when we \
-                             disconnect; QED");
+                let observed = self.observed_addresses.get(&connection_id)
+                    .expect("We only receive events from open connections. We insert into the \
+                             hashmap when a connection is opened and remove only when it is \
+                             closed; QED");
                 self.to_answer.push((peer_id, sender, observed.clone()));
             }
             EitherOutput::Second(PeriodicIdHandlerEvent::IdentificationError(err)) => {
@@ -137,6 +141,15 @@ where
         >,
     > {
         if let Some(event) = self.events.pop_front() {
+            if let NetworkBehaviourAction::GenerateEvent(IdentifyEvent::Identified { peer_id, info, .. }) = &event {
+                let peer_store = params.peer_store_mut();
+                peer_store.set_public_key(peer_id, info.public_key.clone());
+                peer_store.set_protocols(peer_id, info.protocols.iter().cloned());
+                peer_store.set_agent_version(peer_id, info.agent_version.clone());
+                for addr in &info.listen_addrs {
+                    peer_store.add_address(peer_id, addr.clone(), AddressSource::SelfReported, Some(REPORTED_ADDRESS_TTL));
+                }
+            }
             return Async::Ready(event);
         }
 
@@ -25,7 +25,7 @@ use crate::protocol::{KadConnectionType, KadPeer};
 use crate::query::{QueryConfig, QueryState, QueryStatePollOut};
 use fnv::{FnvHashMap, FnvHashSet};
 use futures::{prelude::*, stream};
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{protocols_handler::ProtocolsHandler, Multiaddr, PeerId};
 use multihash::Multihash;
 use smallvec::SmallVec;
@@ -47,8 +47,8 @@ pub struct Kademlia<TSubstream> {
     /// is the list of accumulated providers for `GET_PROVIDERS` queries.
     active_queries: FnvHashMap<QueryId, QueryState<QueryInfo, PeerId>>,
 
-    /// List of peers the swarm is connected to.
-    connected_peers: FnvHashSet<PeerId>,
+    /// List of peers the swarm is connected to, with their open connections.
+    connected_peers: FnvHashMap<PeerId, SmallVec<[ConnectionId; 2]>>,
 
     /// Contains a list of peer IDs which we are not connected to, and an RPC query to send to them
     /// once they connect.
@@ -102,6 +102,8 @@ struct QueryInfo {
     inner: QueryInfoInner,
     /// Temporary addresses used when trying to reach nodes.
     untrusted_addresses: FnvHashMap<PeerId, SmallVec<[Multiaddr; 8]>>,
+    /// When the query was started.
+    started: Instant,
 }
 
 /// Additional information about the query.
@@ -329,6 +331,7 @@ impl<TSubstream> Kademlia<TSubstream> {
         let target = QueryInfo {
             inner: target,
             untrusted_addresses: Default::default(),
+            started: Instant::now(),
         };
 
         let target_key = kbucket::Key::new(target.clone());
@@ -442,7 +445,7 @@ impl<TSubstream> Kademlia<TSubstream> {
                     },
                     kbucket::InsertResult::Full => (),
                     kbucket::InsertResult::Pending { disconnected } => {
-                        debug_assert!(!self.connected_peers.contains(disconnected.preimage()));
+                        debug_assert!(!self.connected_peers.contains_key(disconnected.preimage()));
                         self.queued_events.push(NetworkBehaviourAction::DialPeer {
                             peer_id: disconnected.into_preimage(),
                         })
@@ -492,7 +495,19 @@ where
         out_list
     }
 
-    fn inject_connected(&mut self, id: PeerId, endpoint: ConnectedPoint) {
+    fn inject_connected(&mut self, id: PeerId, connection_id: ConnectionId, endpoint: ConnectedPoint) {
+        if let Some(connections) = self.connected_peers.get_mut(&id) {
+            connections.push(connection_id);
+            // An additional connection to a node we already know about. Only remember the
+            // address we dialed.
+            if let Some(addrs) = self.kbuckets.entry(&kbucket::Key::new(id)).value() {
+                if let ConnectedPoint::Dialer { address } = endpoint {
+                    addrs.insert(address);
+                }
+            }
+            return;
+        }
+
         if let Some(pos) = self.pending_rpcs.iter().position(|(p, _)| p == &id) {
             let (_, rpc) = self.pending_rpcs.remove(pos);
             self.queued_events.push(NetworkBehaviourAction::SendEvent {
@@ -507,7 +522,7 @@ where
         };
 
         self.connection_updated(id.clone(), address, NodeStatus::Connected);
-        self.connected_peers.insert(id);
+        self.connected_peers.insert(id, SmallVec::from_elem(connection_id, 1));
     }
 
     fn inject_addr_reach_failure(&mut self, peer_id: Option<&PeerId>, addr: &Multiaddr, _: &dyn error::Error) {
@@ -534,7 +549,28 @@ where
         }
     }
 
-    fn inject_disconnected(&mut self, id: &PeerId, _old_endpoint: ConnectedPoint) {
+    fn inject_disconnected(&mut self, id: &PeerId, connection_id: ConnectionId, _old_endpoint: ConnectedPoint, _: DisconnectReason<'_>) {
+        if let Some(connections) = self.connected_peers.get_mut(id) {
+            let was_oldest = connections.first() == Some(&connection_id);
+            connections.retain(|c| *c != connection_id);
+            if !connections.is_empty() {
+                if !was_oldest {
+                    return;
+                }
+                // Queries are sent on the oldest connection, so the requests that were in flight
+                // on the closed one are lost and need to be re-sent.
+                for (query_id, query) in self.active_queries.iter() {
+                    if query.is_waiting(id) {
+                        self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                            peer_id: id.clone(),
+                            event: query.target().to_rpc_request(*query_id),
+                        });
+                    }
+                }
+                return;
+            }
+        }
+
         for query in self.active_queries.values_mut() {
             query.inject_rpc_error(id);
         }
@@ -542,30 +578,13 @@ where
         self.connected_peers.remove(id);
     }
 
-    fn inject_replaced(&mut self, peer_id: PeerId, _old: ConnectedPoint, new_endpoint: ConnectedPoint) {
-        // We need to re-send the active queries.
-        for (query_id, query) in self.active_queries.iter() {
-            if query.is_waiting(&peer_id) {
-                self.queued_events.push(NetworkBehaviourAction::SendEvent {
-                    peer_id: peer_id.clone(),
-                    event: query.target().to_rpc_request(*query_id),
-                });
-            }
-        }
-
-        if let Some(addrs) = self.kbuckets.entry(&kbucket::Key::new(peer_id)).value() {
-            if let ConnectedPoint::Dialer { address } = new_endpoint {
-                addrs.insert(address);
-            }
-        }
-    }
-
-    fn inject_node_event(&mut self, source: PeerId, event: KademliaHandlerEvent<QueryId>) {
+    fn inject_node_event(&mut self, source: PeerId, connection_id: ConnectionId, event: KademliaHandlerEvent<QueryId>) {
         match event {
             KademliaHandlerEvent::FindNodeReq { key, request_id } => {
                 let closer_peers = self.find_closest(&kbucket::Key::new(key), &source);
-                self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                self.queued_events.push(NetworkBehaviourAction::SendEventToConnection {
                     peer_id: source,
+                    connection_id,
                     event: KademliaHandlerIn::FindNodeRes {
                         closer_peers,
                         request_id,
@@ -581,8 +600,9 @@ where
             KademliaHandlerEvent::GetProvidersReq { key, request_id } => {
                 let provider_peers = self.provider_peers(&key, &source);
                 let closer_peers = self.find_closest(&kbucket::Key::from(key), &source);
-                self.queued_events.push(NetworkBehaviourAction::SendEvent {
+                self.queued_events.push(NetworkBehaviourAction::SendEventToConnection {
                     peer_id: source,
+                    connection_id,
                     event: KademliaHandlerIn::GetProvidersRes {
                         closer_peers,
                         provider_peers,
@@ -691,7 +711,7 @@ where
                             query_target,
                         }) => {
                             let rpc = query_target.to_rpc_request(query_id);
-                            if self.connected_peers.contains(peer_id) {
+                            if self.connected_peers.contains_key(peer_id) {
                                 return Async::Ready(NetworkBehaviourAction::SendEvent {
                                     peer_id: peer_id.clone(),
                                     event: rpc,
@@ -718,6 +738,7 @@ where
                     .remove(&finished_query)
                     .expect("finished_query was gathered when iterating active_queries; QED.")
                     .into_target_and_closest_peers();
+                let duration = query_info.started.elapsed();
 
                 match query_info.inner {
                     QueryInfoInner::Initialization { .. } => {},
@@ -725,6 +746,7 @@ where
                         let event = KademliaOut::FindNodeResult {
                             key: target,
                             closer_peers: closer_peers.collect(),
+                            duration,
                         };
                         break Async::Ready(NetworkBehaviourAction::GenerateEvent(event));
                     },
@@ -733,6 +755,7 @@ where
                             key: target,
                             closer_peers: closer_peers.collect(),
                             provider_peers: pending_results,
+                            duration,
                         };
 
                         break Async::Ready(NetworkBehaviourAction::GenerateEvent(event));
@@ -791,6 +814,8 @@ pub enum KademliaOut {
         key: PeerId,
         /// List of peers ordered from closest to furthest away.
         closer_peers: Vec<PeerId>,
+        /// How long the query took.
+        duration: Duration,
     },
 
     /// Result of a `GET_PROVIDERS` iterative query.
@@ -801,6 +826,8 @@ pub enum KademliaOut {
         provider_peers: Vec<PeerId>,
         /// List of peers ordered from closest to furthest away.
         closer_peers: Vec<PeerId>,
+        /// How long the query took.
+        duration: Duration,
     },
 }
 
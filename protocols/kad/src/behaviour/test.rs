@@ -119,7 +119,7 @@ fn query_iter() {
                     loop {
                         match swarm.poll().unwrap() {
                             Async::Ready(Some(KademliaOut::FindNodeResult {
-                                key, closer_peers
+                                key, closer_peers, ..
                             })) => {
                                 assert_eq!(key, search_target);
                                 assert_eq!(swarm_ids[i], expected_swarm_id);
@@ -162,7 +162,7 @@ fn unresponsive_not_returned_direct() {
             for swarm in &mut swarms {
                 loop {
                     match swarm.poll().unwrap() {
-                        Async::Ready(Some(KademliaOut::FindNodeResult { key, closer_peers })) => {
+                        Async::Ready(Some(KademliaOut::FindNodeResult { key, closer_peers, .. })) => {
                             assert_eq!(key, search_target);
                             assert_eq!(closer_peers.len(), 0);
                             return Ok(Async::Ready(()));
@@ -207,7 +207,7 @@ fn unresponsive_not_returned_indirect() {
             for swarm in &mut swarms {
                 loop {
                     match swarm.poll().unwrap() {
-                        Async::Ready(Some(KademliaOut::FindNodeResult { key, closer_peers })) => {
+                        Async::Ready(Some(KademliaOut::FindNodeResult { key, closer_peers, .. })) => {
                             assert_eq!(key, search_target);
                             assert_eq!(closer_peers.len(), 1);
                             assert_eq!(closer_peers[0], first_peer_id);
@@ -32,7 +32,8 @@
 //! the connection will be closed.
 //!
 //! The `Ping` network behaviour produces [`PingEvent`]s, which may be consumed from the `Swarm`
-//! by an application, e.g. to collect statistics.
+//! by an application, e.g. to collect statistics. The latest round-trip time to each peer is
+//! also recorded in the peer store of the `Swarm`.
 //!
 //! > **Note**: The ping protocol does not keep otherwise idle connections alive,
 //! > it only adds an additional condition for terminating the connection, namely
@@ -48,7 +49,7 @@ pub use handler::{PingConfig, PingResult, PingSuccess, PingFailure};
 use handler::PingHandler;
 
 use futures::prelude::*;
-use libp2p_core::swarm::{ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
+use libp2p_core::swarm::{ConnectedPoint, ConnectionId, DisconnectReason, NetworkBehaviour, NetworkBehaviourAction, PollParameters};
 use libp2p_core::{Multiaddr, PeerId};
 use std::collections::VecDeque;
 use std::marker::PhantomData;
@@ -108,17 +109,20 @@ where
         Vec::new()
     }
 
-    fn inject_connected(&mut self, _: PeerId, _: ConnectedPoint) {}
+    fn inject_connected(&mut self, _: PeerId, _: ConnectionId, _: ConnectedPoint) {}
 
-    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectedPoint) {}
+    fn inject_disconnected(&mut self, _: &PeerId, _: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {}
 
-    fn inject_node_event(&mut self, peer: PeerId, result: PingResult) {
+    fn inject_node_event(&mut self, peer: PeerId, _: ConnectionId, result: PingResult) {
         self.events.push_front(PingEvent { peer, result })
     }
 
-    fn poll(&mut self, _: &mut PollParameters<'_>) -> Async<NetworkBehaviourAction<Void, PingEvent>>
+    fn poll(&mut self, params: &mut PollParameters<'_>) -> Async<NetworkBehaviourAction<Void, PingEvent>>
     {
         if let Some(e) = self.events.pop_back() {
+            if let Ok(PingSuccess::Ping { rtt }) = e.result {
+                params.peer_store_mut().set_latency(&e.peer, rtt);
+            }
             Async::Ready(NetworkBehaviourAction::GenerateEvent(e))
         } else {
             Async::NotReady
@@ -0,0 +1,55 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`RequestResponseCodec`] trait implemented by users of the [`RequestResponse`]
+//! behaviour.
+//!
+//! [`RequestResponse`]: crate::RequestResponse
+
+use libp2p_core::upgrade::ProtocolName;
+use std::io;
+
+/// A `RequestResponseCodec` defines the request and response types of a
+/// [`RequestResponse`](crate::RequestResponse) protocol and how these are
+/// encoded and decoded.
+///
+/// Every request and response is sent on a substream as a single message prefixed with its
+/// length. The framing is handled by the behaviour, the codec only converts between messages
+/// and bytes.
+pub trait RequestResponseCodec: Clone {
+    /// The type of protocol(s) or protocol versions being negotiated.
+    type Protocol: ProtocolName + Clone;
+    /// The type of inbound and outbound requests.
+    type Request;
+    /// The type of inbound and outbound responses.
+    type Response;
+
+    /// Encodes a request to be sent using the given protocol.
+    fn encode_request(&mut self, protocol: &Self::Protocol, request: Self::Request) -> Vec<u8>;
+
+    /// Decodes a request received using the given protocol.
+    fn decode_request(&mut self, protocol: &Self::Protocol, bytes: Vec<u8>) -> io::Result<Self::Request>;
+
+    /// Encodes a response to be sent using the given protocol.
+    fn encode_response(&mut self, protocol: &Self::Protocol, response: Self::Response) -> Vec<u8>;
+
+    /// Decodes a response received using the given protocol.
+    fn decode_response(&mut self, protocol: &Self::Protocol, bytes: Vec<u8>) -> io::Result<Self::Response>;
+}
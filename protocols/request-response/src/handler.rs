@@ -0,0 +1,309 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`ProtocolsHandler`] of the [`RequestResponse`](crate::RequestResponse) behaviour.
+
+use crate::RequestId;
+use crate::codec::RequestResponseCodec;
+use crate::protocol::{RequestProtocol, ResponseProtocol};
+use futures::{prelude::*, sync::oneshot};
+use libp2p_core::protocols_handler::{
+    KeepAlive,
+    ProtocolsHandler,
+    ProtocolsHandlerEvent,
+    ProtocolsHandlerUpgrErr,
+    SubstreamProtocol
+};
+use libp2p_core::upgrade::{self, Negotiated, ProtocolChoiceError, ReadOneError, UpgradeError};
+use smallvec::SmallVec;
+use std::{collections::VecDeque, io, sync::{Arc, atomic::{AtomicU64, Ordering}}, time::Duration};
+use tokio_io::{AsyncRead, AsyncWrite};
+use void::Void;
+use wasm_timer::{Delay, Instant};
+
+/// Event produced by the [`RequestResponseHandler`].
+pub enum RequestResponseHandlerEvent<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// A request has been received.
+    Request {
+        request_id: RequestId,
+        request: TCodec::Request,
+        sender: oneshot::Sender<TCodec::Response>,
+    },
+    /// A response to an outbound request has been received.
+    Response {
+        request_id: RequestId,
+        response: TCodec::Response,
+    },
+    /// The response to an inbound request has been sent.
+    ResponseSent(RequestId),
+    /// The response channel of an inbound request has been dropped without a response
+    /// being sent.
+    ResponseOmission(RequestId),
+    /// No response has been received in time for an outbound request.
+    OutboundTimeout(RequestId),
+    /// The remote supports none of the protocols of an outbound request.
+    OutboundUnsupportedProtocols(RequestId),
+    /// An outbound request failed.
+    OutboundError(RequestId, io::Error),
+    /// No response has been sent in time for an inbound request.
+    InboundTimeout(RequestId),
+    /// Sending the response to an inbound request failed.
+    InboundError(RequestId, io::Error),
+}
+
+/// State of an inbound request.
+enum InboundState<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// Waiting for the response to be passed to the response channel.
+    AwaitingResponse {
+        request_id: RequestId,
+        protocol: TCodec::Protocol,
+        socket: Negotiated<TSubstream>,
+        receiver: oneshot::Receiver<TCodec::Response>,
+        timeout: Delay,
+    },
+    /// Writing the response to the substream.
+    Sending {
+        request_id: RequestId,
+        write: upgrade::WriteOne<Negotiated<TSubstream>>,
+        timeout: Delay,
+    },
+}
+
+impl<TSubstream, TCodec> InboundState<TSubstream, TCodec>
+where
+    TSubstream: AsyncWrite,
+    TCodec: RequestResponseCodec,
+{
+    /// Makes progress on the inbound request. Returns the event to report once the
+    /// request has been dealt with, or the new state otherwise.
+    fn poll(self, codec: &mut TCodec) -> Result<Self, RequestResponseHandlerEvent<TCodec>> {
+        let mut state = self;
+        loop {
+            state = match state {
+                InboundState::AwaitingResponse { request_id, protocol, socket, mut receiver, mut timeout } => {
+                    match receiver.poll() {
+                        Ok(Async::Ready(response)) => {
+                            let bytes = codec.encode_response(&protocol, response);
+                            InboundState::Sending {
+                                request_id,
+                                write: upgrade::write_one(socket, bytes),
+                                timeout,
+                            }
+                        }
+                        Ok(Async::NotReady) => match timeout.poll() {
+                            Ok(Async::NotReady) => return Ok(InboundState::AwaitingResponse {
+                                request_id, protocol, socket, receiver, timeout
+                            }),
+                            Ok(Async::Ready(())) | Err(_) =>
+                                return Err(RequestResponseHandlerEvent::InboundTimeout(request_id)),
+                        },
+                        Err(oneshot::Canceled) =>
+                            return Err(RequestResponseHandlerEvent::ResponseOmission(request_id)),
+                    }
+                }
+                InboundState::Sending { request_id, mut write, mut timeout } => {
+                    match write.poll() {
+                        Ok(Async::Ready(())) =>
+                            return Err(RequestResponseHandlerEvent::ResponseSent(request_id)),
+                        Ok(Async::NotReady) => match timeout.poll() {
+                            Ok(Async::NotReady) =>
+                                return Ok(InboundState::Sending { request_id, write, timeout }),
+                            Ok(Async::Ready(())) | Err(_) =>
+                                return Err(RequestResponseHandlerEvent::InboundTimeout(request_id)),
+                        },
+                        Err(err) =>
+                            return Err(RequestResponseHandlerEvent::InboundError(request_id, err)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Protocol handler that sends the outbound requests of the local node and answers the
+/// inbound requests of the remote, each on a dedicated substream.
+pub struct RequestResponseHandler<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// The codec used to encode and decode the messages.
+    codec: TCodec,
+    /// The supported protocols, by order of preference.
+    protocols: SmallVec<[TCodec::Protocol; 2]>,
+    /// Maximum duration of a request, from the opening of the substream until the
+    /// response has been received or sent.
+    request_timeout: Duration,
+    /// How long to keep the connection alive once there are no pending requests.
+    keep_alive_timeout: Duration,
+    /// Maximum size in bytes of a message.
+    max_message_size: usize,
+    /// Current keep-alive state of the connection.
+    keep_alive: KeepAlive,
+    /// Source of the identifiers of inbound requests, shared with the behaviour.
+    next_request_id: Arc<AtomicU64>,
+    /// Outbound requests for which a substream hasn't been requested yet.
+    outbound: VecDeque<RequestProtocol<TCodec>>,
+    /// Number of outbound requests whose substream is being opened or negotiated.
+    pending_outbound: usize,
+    /// Inbound requests that haven't been answered yet.
+    inbound: Vec<InboundState<TSubstream, TCodec>>,
+    /// Events to return from `poll`.
+    pending_events: VecDeque<RequestResponseHandlerEvent<TCodec>>,
+}
+
+impl<TSubstream, TCodec> RequestResponseHandler<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    pub(crate) fn new(
+        codec: TCodec,
+        protocols: SmallVec<[TCodec::Protocol; 2]>,
+        request_timeout: Duration,
+        keep_alive_timeout: Duration,
+        max_message_size: usize,
+        next_request_id: Arc<AtomicU64>,
+    ) -> Self {
+        RequestResponseHandler {
+            codec,
+            protocols,
+            request_timeout,
+            keep_alive_timeout,
+            max_message_size,
+            keep_alive: KeepAlive::Until(Instant::now() + keep_alive_timeout),
+            next_request_id,
+            outbound: VecDeque::new(),
+            pending_outbound: 0,
+            inbound: Vec::new(),
+            pending_events: VecDeque::new(),
+        }
+    }
+}
+
+impl<TSubstream, TCodec> ProtocolsHandler for RequestResponseHandler<TSubstream, TCodec>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+    TCodec: RequestResponseCodec,
+{
+    type InEvent = RequestProtocol<TCodec>;
+    type OutEvent = RequestResponseHandlerEvent<TCodec>;
+    type Error = Void;
+    type Substream = TSubstream;
+    type InboundProtocol = ResponseProtocol<TCodec>;
+    type OutboundProtocol = RequestProtocol<TCodec>;
+    type OutboundOpenInfo = RequestId;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        let protocol = ResponseProtocol {
+            codec: self.codec.clone(),
+            protocols: self.protocols.clone(),
+            max_message_size: self.max_message_size,
+        };
+        SubstreamProtocol::new(protocol).with_timeout(self.request_timeout)
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        (request, protocol, socket): (TCodec::Request, TCodec::Protocol, Negotiated<TSubstream>)
+    ) {
+        let request_id = RequestId(self.next_request_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, receiver) = oneshot::channel();
+        self.inbound.push(InboundState::AwaitingResponse {
+            request_id,
+            protocol,
+            socket,
+            receiver,
+            timeout: Delay::new(Instant::now() + self.request_timeout),
+        });
+        self.pending_events.push_back(RequestResponseHandlerEvent::Request { request_id, request, sender });
+        self.keep_alive = KeepAlive::Yes;
+    }
+
+    fn inject_fully_negotiated_outbound(&mut self, response: TCodec::Response, request_id: RequestId) {
+        self.pending_outbound -= 1;
+        self.pending_events.push_back(RequestResponseHandlerEvent::Response { request_id, response });
+    }
+
+    fn inject_event(&mut self, request: RequestProtocol<TCodec>) {
+        self.outbound.push_back(request);
+        self.keep_alive = KeepAlive::Yes;
+    }
+
+    fn inject_dial_upgrade_error(&mut self, request_id: RequestId, error: ProtocolsHandlerUpgrErr<ReadOneError>) {
+        self.pending_outbound -= 1;
+        let event = match error {
+            ProtocolsHandlerUpgrErr::Timeout | ProtocolsHandlerUpgrErr::Timer =>
+                RequestResponseHandlerEvent::OutboundTimeout(request_id),
+            ProtocolsHandlerUpgrErr::ResourceLimit(err) =>
+                RequestResponseHandlerEvent::OutboundError(request_id, io::Error::new(io::ErrorKind::Other, err)),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(ProtocolChoiceError::NoProtocolFound)) =>
+                RequestResponseHandlerEvent::OutboundUnsupportedProtocols(request_id),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)) =>
+                RequestResponseHandlerEvent::OutboundError(request_id, io::Error::new(io::ErrorKind::Other, err)),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(ReadOneError::Io(err))) =>
+                RequestResponseHandlerEvent::OutboundError(request_id, err),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(err)) =>
+                RequestResponseHandlerEvent::OutboundError(request_id, io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        self.pending_events.push_back(event);
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.keep_alive
+    }
+
+    fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<RequestProtocol<TCodec>, RequestId, Self::OutEvent>, Void> {
+        for n in (0..self.inbound.len()).rev() {
+            let state = self.inbound.swap_remove(n);
+            match state.poll(&mut self.codec) {
+                Ok(state) => self.inbound.push(state),
+                Err(event) => self.pending_events.push_back(event),
+            }
+        }
+
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(event)));
+        }
+
+        if let Some(request) = self.outbound.pop_front() {
+            let info = request.request_id;
+            self.pending_outbound += 1;
+            return Ok(Async::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(request).with_timeout(self.request_timeout),
+                info,
+            }));
+        }
+
+        if self.pending_outbound == 0 && self.inbound.is_empty() {
+            if self.keep_alive.is_yes() {
+                self.keep_alive = KeepAlive::Until(Instant::now() + self.keep_alive_timeout);
+            }
+        } else {
+            self.keep_alive = KeepAlive::Yes;
+        }
+
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,584 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Generic request/response protocols.
+//!
+//! The [`RequestResponse`] struct implements the [`NetworkBehaviour`] trait for protocols in
+//! which a peer sends a single request on a new substream and the remote answers it with a
+//! single response on that same substream. The types of the requests and responses and the
+//! way they are encoded are defined by a [`RequestResponseCodec`], which is the only part
+//! to implement for a new protocol.
+//!
+//! Requests are sent with [`RequestResponse::send_request`], dialing the peer first if we are
+//! not connected to it. Inbound requests are reported as [`RequestResponseMessage::Request`]
+//! together with a [`ResponseChannel`] that must be passed to
+//! [`RequestResponse::send_response`] in order to answer them. Every request, inbound or
+//! outbound, must be completed within the configured request timeout.
+//!
+//! [`NetworkBehaviour`]: libp2p_core::swarm::NetworkBehaviour
+
+pub mod codec;
+pub mod handler;
+pub mod protocol;
+
+pub use codec::RequestResponseCodec;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{prelude::*, sync::oneshot};
+use handler::{RequestResponseHandler, RequestResponseHandlerEvent};
+use libp2p_core::swarm::{
+    ConnectedPoint,
+    ConnectionId,
+    DisconnectReason,
+    NetworkBehaviour,
+    NetworkBehaviourAction,
+    PollParameters
+};
+use libp2p_core::{Multiaddr, PeerId};
+use protocol::RequestProtocol;
+use smallvec::SmallVec;
+use std::{collections::VecDeque, error, fmt, io, marker::PhantomData, time::Duration};
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Identifier of an inbound or outbound request.
+///
+/// Identifiers are unique among all the requests of a [`RequestResponse`] behaviour.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Configuration of a [`RequestResponse`] behaviour.
+#[derive(Debug, Clone)]
+pub struct RequestResponseConfig {
+    request_timeout: Duration,
+    connection_keep_alive: Duration,
+    max_message_size: usize,
+}
+
+impl RequestResponseConfig {
+    /// Creates a new `RequestResponseConfig` with the following default settings:
+    ///
+    ///   * [`RequestResponseConfig::with_request_timeout`] 10s
+    ///   * [`RequestResponseConfig::with_connection_keep_alive`] 10s
+    ///   * [`RequestResponseConfig::with_max_message_size`] 1 MiB
+    pub fn new() -> Self {
+        RequestResponseConfig {
+            request_timeout: Duration::from_secs(10),
+            connection_keep_alive: Duration::from_secs(10),
+            max_message_size: 1024 * 1024,
+        }
+    }
+
+    /// Sets the maximum duration of a request, from the opening of its substream until the
+    /// response has been received or sent.
+    pub fn with_request_timeout(mut self, d: Duration) -> Self {
+        self.request_timeout = d;
+        self
+    }
+
+    /// Sets how long a connection is kept alive once it has no pending request.
+    pub fn with_connection_keep_alive(mut self, d: Duration) -> Self {
+        self.connection_keep_alive = d;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a request or response.
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+}
+
+impl Default for RequestResponseConfig {
+    fn default() -> Self {
+        RequestResponseConfig::new()
+    }
+}
+
+/// The channel through which the response to an inbound request is sent.
+///
+/// Must be passed to [`RequestResponse::send_response`]. Dropping the channel without
+/// sending a response produces an [`InboundFailure::ResponseOmission`].
+#[derive(Debug)]
+pub struct ResponseChannel<TResponse> {
+    request_id: RequestId,
+    peer: PeerId,
+    sender: oneshot::Sender<TResponse>,
+}
+
+impl<TResponse> ResponseChannel<TResponse> {
+    /// Returns the identifier of the request to answer.
+    pub fn request_id(&self) -> RequestId {
+        self.request_id
+    }
+
+    /// Returns the peer that sent the request.
+    pub fn peer(&self) -> &PeerId {
+        &self.peer
+    }
+
+    /// Returns `false` if the response can no longer be sent, for example because the
+    /// connection has been closed or the request timed out.
+    pub fn is_open(&self) -> bool {
+        !self.sender.is_canceled()
+    }
+}
+
+/// An inbound request or response.
+#[derive(Debug)]
+pub enum RequestResponseMessage<TRequest, TResponse> {
+    /// A request sent by the remote.
+    Request {
+        /// The identifier of the request.
+        request_id: RequestId,
+        /// The request.
+        request: TRequest,
+        /// The channel through which the response must be sent.
+        channel: ResponseChannel<TResponse>,
+    },
+    /// A response to a request sent with [`RequestResponse::send_request`].
+    Response {
+        /// The identifier returned by [`RequestResponse::send_request`].
+        request_id: RequestId,
+        /// The response.
+        response: TResponse,
+    },
+}
+
+/// Event generated by the [`RequestResponse`] network behaviour.
+#[derive(Debug)]
+pub enum RequestResponseEvent<TRequest, TResponse> {
+    /// An inbound request or response has been received.
+    Message {
+        /// The peer that sent the message.
+        peer: PeerId,
+        /// The message.
+        message: RequestResponseMessage<TRequest, TResponse>,
+    },
+    /// An outbound request failed.
+    OutboundFailure {
+        /// The peer to which the request was sent.
+        peer: PeerId,
+        /// The identifier returned by [`RequestResponse::send_request`].
+        request_id: RequestId,
+        /// Why the request failed.
+        error: OutboundFailure,
+    },
+    /// No response could be sent to an inbound request.
+    InboundFailure {
+        /// The peer that sent the request.
+        peer: PeerId,
+        /// The identifier of the request.
+        request_id: RequestId,
+        /// Why no response was sent.
+        error: InboundFailure,
+    },
+    /// The response to an inbound request has been sent.
+    ResponseSent {
+        /// The peer that sent the request.
+        peer: PeerId,
+        /// The identifier of the request.
+        request_id: RequestId,
+    },
+}
+
+/// Possible failures of an outbound request.
+#[derive(Debug)]
+pub enum OutboundFailure {
+    /// We are not connected to the peer and dialing it failed.
+    DialFailure,
+    /// No response has been received within the request timeout.
+    Timeout,
+    /// The connection has been closed before a response was received.
+    ConnectionClosed,
+    /// The remote supports none of the protocols of the request.
+    UnsupportedProtocols,
+    /// An error happened while sending the request or reading the response.
+    Io(io::Error),
+}
+
+impl fmt::Display for OutboundFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutboundFailure::DialFailure => write!(f, "Failed to dial the peer"),
+            OutboundFailure::Timeout => write!(f, "Timeout while waiting for a response"),
+            OutboundFailure::ConnectionClosed => write!(f, "Connection closed before a response was received"),
+            OutboundFailure::UnsupportedProtocols => write!(f, "The remote supports none of the requested protocols"),
+            OutboundFailure::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for OutboundFailure {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            OutboundFailure::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Possible failures of an inbound request.
+#[derive(Debug)]
+pub enum InboundFailure {
+    /// No response has been sent within the request timeout.
+    Timeout,
+    /// The connection has been closed before a response was sent.
+    ConnectionClosed,
+    /// The [`ResponseChannel`] has been dropped without a response being sent.
+    ResponseOmission,
+    /// An error happened while sending the response.
+    Io(io::Error),
+}
+
+impl fmt::Display for InboundFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InboundFailure::Timeout => write!(f, "Timeout while waiting for the response"),
+            InboundFailure::ConnectionClosed => write!(f, "Connection closed before the response was sent"),
+            InboundFailure::ResponseOmission => write!(f, "The response channel has been dropped"),
+            InboundFailure::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for InboundFailure {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            InboundFailure::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The requests in flight on a connection.
+struct Connection {
+    id: ConnectionId,
+    /// Outbound requests waiting for a response.
+    pending_outbound: FnvHashSet<RequestId>,
+    /// Inbound requests waiting for the response to be sent.
+    pending_inbound: FnvHashSet<RequestId>,
+}
+
+/// A request/response protocol for some message codec.
+///
+/// See the crate root documentation for more information.
+pub struct RequestResponse<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// The supported protocols, by order of preference.
+    protocols: SmallVec<[TCodec::Protocol; 2]>,
+    /// The codec used to encode and decode the messages.
+    codec: TCodec,
+    /// The configuration of the behaviour.
+    config: RequestResponseConfig,
+    /// Source of the identifiers of the requests, shared with the handlers.
+    next_request_id: Arc<AtomicU64>,
+    /// Actions to return from `poll`.
+    pending_events: VecDeque<NetworkBehaviourAction<
+        RequestProtocol<TCodec>,
+        RequestResponseEvent<TCodec::Request, TCodec::Response>
+    >>,
+    /// The open connections of every connected peer.
+    connected: FnvHashMap<PeerId, SmallVec<[Connection; 2]>>,
+    /// Addresses added with `add_address`.
+    addresses: FnvHashMap<PeerId, SmallVec<[Multiaddr; 6]>>,
+    /// Requests to peers we are dialing.
+    pending_requests: FnvHashMap<PeerId, SmallVec<[RequestProtocol<TCodec>; 4]>>,
+    _marker: PhantomData<TSubstream>,
+}
+
+impl<TSubstream, TCodec> RequestResponse<TSubstream, TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    /// Creates a new `RequestResponse` behaviour for the given protocols, by order of
+    /// preference, using the given codec and configuration.
+    pub fn new<I>(codec: TCodec, protocols: I, config: RequestResponseConfig) -> Self
+    where
+        I: IntoIterator<Item = TCodec::Protocol>,
+    {
+        RequestResponse {
+            protocols: protocols.into_iter().collect(),
+            codec,
+            config,
+            next_request_id: Arc::new(AtomicU64::new(1)),
+            pending_events: VecDeque::new(),
+            connected: FnvHashMap::default(),
+            addresses: FnvHashMap::default(),
+            pending_requests: FnvHashMap::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sends a request to a peer and returns the identifier of the request.
+    ///
+    /// If we are not connected to the peer, it is dialed first. The outcome of the request is
+    /// reported as a [`RequestResponseMessage::Response`] or a
+    /// [`RequestResponseEvent::OutboundFailure`] carrying the returned identifier.
+    pub fn send_request(&mut self, peer: &PeerId, request: TCodec::Request) -> RequestId {
+        let request_id = RequestId(self.next_request_id.fetch_add(1, Ordering::Relaxed));
+        let request = RequestProtocol {
+            codec: self.codec.clone(),
+            protocols: self.protocols.clone(),
+            request_id,
+            request,
+            max_message_size: self.config.max_message_size,
+        };
+
+        if let Some(request) = self.try_send_request(peer, request) {
+            if !self.pending_requests.contains_key(peer) {
+                self.pending_events.push_back(NetworkBehaviourAction::DialPeer { peer_id: peer.clone() });
+            }
+            self.pending_requests.entry(peer.clone()).or_default().push(request);
+        }
+
+        request_id
+    }
+
+    /// Sends the response to an inbound request through its channel.
+    ///
+    /// Returns the response back if it can no longer be sent, for example because the
+    /// connection has been closed or the request timed out.
+    pub fn send_response(&mut self, channel: ResponseChannel<TCodec::Response>, response: TCodec::Response)
+        -> Result<(), TCodec::Response>
+    {
+        channel.sender.send(response)
+    }
+
+    /// Adds an address through which the given peer can be dialed.
+    pub fn add_address(&mut self, peer: &PeerId, address: Multiaddr) {
+        let addresses = self.addresses.entry(peer.clone()).or_default();
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    /// Removes an address previously added with `add_address`.
+    pub fn remove_address(&mut self, peer: &PeerId, address: &Multiaddr) {
+        let mut last = false;
+        if let Some(addresses) = self.addresses.get_mut(peer) {
+            addresses.retain(|a| a != address);
+            last = addresses.is_empty();
+        }
+        if last {
+            self.addresses.remove(peer);
+        }
+    }
+
+    /// Returns `true` if we are connected to the given peer.
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.connected.contains_key(peer)
+    }
+
+    /// Hands the request over to one of the connections to the peer, or gives it back if
+    /// we are not connected to the peer.
+    fn try_send_request(&mut self, peer: &PeerId, request: RequestProtocol<TCodec>)
+        -> Option<RequestProtocol<TCodec>>
+    {
+        let connections = match self.connected.get_mut(peer) {
+            Some(connections) if !connections.is_empty() => connections,
+            _ => return Some(request),
+        };
+        // Spread the requests over the connections.
+        let index = (request.request_id.0 as usize) % connections.len();
+        let connection = &mut connections[index];
+        connection.pending_outbound.insert(request.request_id);
+        self.pending_events.push_back(NetworkBehaviourAction::SendEventToConnection {
+            peer_id: peer.clone(),
+            connection_id: connection.id,
+            event: request,
+        });
+        None
+    }
+
+    /// Returns the connection with the given identifier.
+    fn get_connection_mut(&mut self, peer: &PeerId, connection_id: ConnectionId) -> Option<&mut Connection> {
+        self.connected.get_mut(peer)
+            .and_then(|connections| connections.iter_mut().find(|c| c.id == connection_id))
+    }
+
+    /// Builds the event reporting a failed inbound request and forgets about the request.
+    fn inbound_failure(&mut self, peer: PeerId, connection_id: ConnectionId, request_id: RequestId, error: InboundFailure)
+        -> RequestResponseEvent<TCodec::Request, TCodec::Response>
+    {
+        if let Some(connection) = self.get_connection_mut(&peer, connection_id) {
+            connection.pending_inbound.remove(&request_id);
+        }
+        RequestResponseEvent::InboundFailure { peer, request_id, error }
+    }
+
+    /// Builds the event reporting a failed outbound request and forgets about the request.
+    fn outbound_failure(&mut self, peer: PeerId, connection_id: ConnectionId, request_id: RequestId, error: OutboundFailure)
+        -> RequestResponseEvent<TCodec::Request, TCodec::Response>
+    {
+        if let Some(connection) = self.get_connection_mut(&peer, connection_id) {
+            connection.pending_outbound.remove(&request_id);
+        }
+        RequestResponseEvent::OutboundFailure { peer, request_id, error }
+    }
+}
+
+impl<TSubstream, TCodec> NetworkBehaviour for RequestResponse<TSubstream, TCodec>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+    TCodec: RequestResponseCodec,
+{
+    type ProtocolsHandler = RequestResponseHandler<TSubstream, TCodec>;
+    type OutEvent = RequestResponseEvent<TCodec::Request, TCodec::Response>;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        RequestResponseHandler::new(
+            self.codec.clone(),
+            self.protocols.clone(),
+            self.config.request_timeout,
+            self.config.connection_keep_alive,
+            self.config.max_message_size,
+            self.next_request_id.clone(),
+        )
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.addresses.get(peer_id).map(|a| a.to_vec()).unwrap_or_default()
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, _: ConnectedPoint) {
+        self.connected.entry(peer_id.clone()).or_default().push(Connection {
+            id: connection_id,
+            pending_outbound: FnvHashSet::default(),
+            pending_inbound: FnvHashSet::default(),
+        });
+
+        if let Some(requests) = self.pending_requests.remove(&peer_id) {
+            for request in requests {
+                let _sent = self.try_send_request(&peer_id, request);
+                debug_assert!(_sent.is_none());
+            }
+        }
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, connection_id: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {
+        let connection = match self.connected.get_mut(peer_id) {
+            Some(connections) => {
+                let connection = connections.iter()
+                    .position(|c| c.id == connection_id)
+                    .map(|pos| connections.remove(pos));
+                if connections.is_empty() {
+                    self.connected.remove(peer_id);
+                }
+                connection
+            },
+            None => None,
+        };
+
+        if let Some(connection) = connection {
+            for request_id in connection.pending_outbound {
+                self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    RequestResponseEvent::OutboundFailure {
+                        peer: peer_id.clone(),
+                        request_id,
+                        error: OutboundFailure::ConnectionClosed,
+                    }
+                ));
+            }
+            for request_id in connection.pending_inbound {
+                self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    RequestResponseEvent::InboundFailure {
+                        peer: peer_id.clone(),
+                        request_id,
+                        error: InboundFailure::ConnectionClosed,
+                    }
+                ));
+            }
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        if let Some(requests) = self.pending_requests.remove(peer_id) {
+            for request in requests {
+                self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(
+                    RequestResponseEvent::OutboundFailure {
+                        peer: peer_id.clone(),
+                        request_id: request.request_id,
+                        error: OutboundFailure::DialFailure,
+                    }
+                ));
+            }
+        }
+    }
+
+    fn inject_node_event(&mut self, peer: PeerId, connection_id: ConnectionId, event: RequestResponseHandlerEvent<TCodec>) {
+        let event = match event {
+            RequestResponseHandlerEvent::Request { request_id, request, sender } => {
+                if let Some(connection) = self.get_connection_mut(&peer, connection_id) {
+                    connection.pending_inbound.insert(request_id);
+                }
+                let channel = ResponseChannel { request_id, peer: peer.clone(), sender };
+                let message = RequestResponseMessage::Request { request_id, request, channel };
+                RequestResponseEvent::Message { peer, message }
+            },
+            RequestResponseHandlerEvent::Response { request_id, response } => {
+                if let Some(connection) = self.get_connection_mut(&peer, connection_id) {
+                    connection.pending_outbound.remove(&request_id);
+                }
+                let message = RequestResponseMessage::Response { request_id, response };
+                RequestResponseEvent::Message { peer, message }
+            },
+            RequestResponseHandlerEvent::ResponseSent(request_id) => {
+                if let Some(connection) = self.get_connection_mut(&peer, connection_id) {
+                    connection.pending_inbound.remove(&request_id);
+                }
+                RequestResponseEvent::ResponseSent { peer, request_id }
+            },
+            RequestResponseHandlerEvent::ResponseOmission(request_id) =>
+                self.inbound_failure(peer, connection_id, request_id, InboundFailure::ResponseOmission),
+            RequestResponseHandlerEvent::InboundTimeout(request_id) =>
+                self.inbound_failure(peer, connection_id, request_id, InboundFailure::Timeout),
+            RequestResponseHandlerEvent::InboundError(request_id, err) =>
+                self.inbound_failure(peer, connection_id, request_id, InboundFailure::Io(err)),
+            RequestResponseHandlerEvent::OutboundTimeout(request_id) =>
+                self.outbound_failure(peer, connection_id, request_id, OutboundFailure::Timeout),
+            RequestResponseHandlerEvent::OutboundUnsupportedProtocols(request_id) =>
+                self.outbound_failure(peer, connection_id, request_id, OutboundFailure::UnsupportedProtocols),
+            RequestResponseHandlerEvent::OutboundError(request_id, err) =>
+                self.outbound_failure(peer, connection_id, request_id, OutboundFailure::Io(err)),
+        };
+
+        self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    fn poll(&mut self, _: &mut PollParameters<'_>) -> Async<NetworkBehaviourAction<
+        RequestProtocol<TCodec>,
+        RequestResponseEvent<TCodec::Request, TCodec::Response>
+    >> {
+        if let Some(event) = self.pending_events.pop_front() {
+            Async::Ready(event)
+        } else {
+            Async::NotReady
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The upgrades used by the [`RequestResponse`](crate::RequestResponse) behaviour.
+//!
+//! A request is sent on a new outbound substream and the response is read back on that same
+//! substream, after which the substream is closed. Both messages are prefixed with their
+//! length.
+
+use crate::RequestId;
+use crate::codec::RequestResponseCodec;
+use libp2p_core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, upgrade::{self, Negotiated, ReadOneError}};
+use smallvec::SmallVec;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Upgrade for inbound substreams. Reads a request and hands out the substream on which the
+/// response must be sent.
+pub struct ResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    pub(crate) codec: TCodec,
+    pub(crate) protocols: SmallVec<[TCodec::Protocol; 2]>,
+    pub(crate) max_message_size: usize,
+}
+
+impl<TCodec> UpgradeInfo for ResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    type Info = TCodec::Protocol;
+    type InfoIter = smallvec::IntoIter<[Self::Info; 2]>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.protocols.clone().into_iter()
+    }
+}
+
+impl<TCodec, TSocket> InboundUpgrade<TSocket> for ResponseProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+    TSocket: AsyncRead + AsyncWrite,
+{
+    type Output = (TCodec::Request, TCodec::Protocol, Negotiated<TSocket>);
+    type Error = ReadOneError;
+    type Future = upgrade::ReadRespond<
+        Negotiated<TSocket>,
+        (TCodec, TCodec::Protocol),
+        fn(Negotiated<TSocket>, Vec<u8>, (TCodec, TCodec::Protocol)) -> Result<Self::Output, ReadOneError>
+    >;
+
+    fn upgrade_inbound(self, socket: Negotiated<TSocket>, protocol: Self::Info) -> Self::Future {
+        upgrade::read_respond(socket, self.max_message_size, (self.codec, protocol), decode_request::<TCodec, TSocket>)
+    }
+}
+
+/// Decodes the request read from an inbound substream.
+fn decode_request<TCodec, TSocket>(socket: Negotiated<TSocket>, bytes: Vec<u8>, (mut codec, protocol): (TCodec, TCodec::Protocol))
+    -> Result<(TCodec::Request, TCodec::Protocol, Negotiated<TSocket>), ReadOneError>
+where
+    TCodec: RequestResponseCodec,
+{
+    let request = codec.decode_request(&protocol, bytes).map_err(ReadOneError::Io)?;
+    Ok((request, protocol, socket))
+}
+
+/// Upgrade for outbound substreams. Sends a request and reads the response.
+pub struct RequestProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    pub(crate) codec: TCodec,
+    pub(crate) protocols: SmallVec<[TCodec::Protocol; 2]>,
+    pub(crate) request_id: RequestId,
+    pub(crate) request: TCodec::Request,
+    pub(crate) max_message_size: usize,
+}
+
+impl<TCodec> UpgradeInfo for RequestProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+{
+    type Info = TCodec::Protocol;
+    type InfoIter = smallvec::IntoIter<[Self::Info; 2]>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.protocols.clone().into_iter()
+    }
+}
+
+impl<TCodec, TSocket> OutboundUpgrade<TSocket> for RequestProtocol<TCodec>
+where
+    TCodec: RequestResponseCodec,
+    TSocket: AsyncRead + AsyncWrite,
+{
+    type Output = TCodec::Response;
+    type Error = ReadOneError;
+    type Future = upgrade::RequestResponse<
+        Negotiated<TSocket>,
+        (TCodec, TCodec::Protocol),
+        fn(Vec<u8>, (TCodec, TCodec::Protocol)) -> Result<TCodec::Response, ReadOneError>
+    >;
+
+    fn upgrade_outbound(mut self, socket: Negotiated<TSocket>, protocol: Self::Info) -> Self::Future {
+        let bytes = self.codec.encode_request(&protocol, self.request);
+        upgrade::request_response(socket, bytes, self.max_message_size, (self.codec, protocol), decode_response::<TCodec>)
+    }
+}
+
+/// Decodes the response read from an outbound substream.
+fn decode_response<TCodec>(bytes: Vec<u8>, (mut codec, protocol): (TCodec, TCodec::Protocol))
+    -> Result<TCodec::Response, ReadOneError>
+where
+    TCodec: RequestResponseCodec,
+{
+    codec.decode_response(&protocol, bytes).map_err(ReadOneError::Io)
+}
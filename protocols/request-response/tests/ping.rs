@@ -0,0 +1,316 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Integration tests for the `RequestResponse` network behaviour.
+
+use libp2p_core::{
+    PeerId,
+    Swarm,
+    identity,
+    muxing::StreamMuxer,
+    upgrade::{self, OutboundUpgradeExt, InboundUpgradeExt, ProtocolName},
+    transport::Transport
+};
+use libp2p_mplex::MplexConfig;
+use libp2p_request_response::*;
+use libp2p_secio::SecioConfig;
+use libp2p_tcp::TcpConfig;
+use futures::{future, prelude::*};
+use std::{fmt, io, time::Duration};
+use tokio::runtime::Runtime;
+
+#[test]
+fn ping_protocol() {
+    let ping = Ping("ping".to_string().into_bytes());
+    let pong = Pong("pong".to_string().into_bytes());
+
+    let (peer1_id, trans) = mk_transport();
+    let protocols = Some(PingProtocol("/ping/1"));
+    let rr = RequestResponse::new(PingCodec, protocols.clone(), RequestResponseConfig::new());
+    let mut swarm1 = Swarm::new(trans, rr, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let rr = RequestResponse::new(PingCodec, protocols, RequestResponseConfig::new());
+    let mut swarm2 = Swarm::new(trans, rr, peer2_id.clone());
+
+    Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+    let mut request_id = None;
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        loop {
+            match swarm1.poll().expect("Error while polling swarm") {
+                Async::Ready(Some(RequestResponseEvent::Message {
+                    peer,
+                    message: RequestResponseMessage::Request { request, channel, .. }
+                })) => {
+                    assert_eq!(peer, peer2_id);
+                    assert_eq!(request, ping);
+                    swarm1.send_response(channel, pong.clone()).unwrap();
+                },
+                Async::Ready(Some(RequestResponseEvent::ResponseSent { peer, .. })) => {
+                    assert_eq!(peer, peer2_id);
+                },
+                Async::Ready(Some(e)) => panic!("Peer1: Unexpected event: {:?}", e),
+                Async::Ready(None) | Async::NotReady => break,
+            }
+        }
+
+        if request_id.is_none() {
+            if let Some(addr) = Swarm::listeners(&swarm1).next().cloned() {
+                swarm2.add_address(&peer1_id, addr);
+                request_id = Some(swarm2.send_request(&peer1_id, ping.clone()));
+            }
+        }
+
+        match swarm2.poll().expect("Error while polling swarm") {
+            Async::Ready(Some(RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { request_id: id, response }
+            })) => {
+                assert_eq!(peer, peer1_id);
+                assert_eq!(Some(id), request_id);
+                assert_eq!(response, pong);
+                Ok(Async::Ready(()))
+            },
+            Async::Ready(Some(e)) => panic!("Peer2: Unexpected event: {:?}", e),
+            Async::Ready(None) | Async::NotReady => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+#[test]
+fn unsupported_protocols() {
+    let (peer1_id, trans) = mk_transport();
+    let rr = RequestResponse::new(PingCodec, Some(PingProtocol("/ping/1")), RequestResponseConfig::new());
+    let mut swarm1 = Swarm::new(trans, rr, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let rr = RequestResponse::new(PingCodec, Some(PingProtocol("/ping/2")), RequestResponseConfig::new());
+    let mut swarm2 = Swarm::new(trans, rr, peer2_id);
+
+    Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+    let mut request_id = None;
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        if let Async::Ready(Some(e)) = swarm1.poll().expect("Error while polling swarm") {
+            panic!("Peer1: Unexpected event: {:?}", e)
+        }
+
+        if request_id.is_none() {
+            if let Some(addr) = Swarm::listeners(&swarm1).next().cloned() {
+                swarm2.add_address(&peer1_id, addr);
+                request_id = Some(swarm2.send_request(&peer1_id, Ping(Vec::new())));
+            }
+        }
+
+        match swarm2.poll().expect("Error while polling swarm") {
+            Async::Ready(Some(RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id: id,
+                error: OutboundFailure::UnsupportedProtocols
+            })) => {
+                assert_eq!(peer, peer1_id);
+                assert_eq!(Some(id), request_id);
+                Ok(Async::Ready(()))
+            },
+            Async::Ready(Some(e)) => panic!("Peer2: Unexpected event: {:?}", e),
+            Async::Ready(None) | Async::NotReady => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+#[test]
+fn dial_failure() {
+    let (peer_id, trans) = mk_transport();
+    let rr = RequestResponse::new(PingCodec, Some(PingProtocol("/ping/1")), RequestResponseConfig::new());
+    let mut swarm = Swarm::new(trans, rr, peer_id);
+
+    // We know no address of the remote.
+    let remote = PeerId::random();
+    let request_id = swarm.send_request(&remote, Ping(Vec::new()));
+
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        match swarm.poll().expect("Error while polling swarm") {
+            Async::Ready(Some(RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id: id,
+                error: OutboundFailure::DialFailure
+            })) => {
+                assert_eq!(peer, remote);
+                assert_eq!(id, request_id);
+                Ok(Async::Ready(()))
+            },
+            Async::Ready(Some(e)) => panic!("Unexpected event: {:?}", e),
+            Async::Ready(None) | Async::NotReady => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+#[test]
+fn dial_failure_on_unreachable_address() {
+    let (peer_id, trans) = mk_transport();
+    let rr = RequestResponse::new(PingCodec, Some(PingProtocol("/ping/1")), RequestResponseConfig::new());
+    let mut swarm = Swarm::new(trans, rr, peer_id);
+
+    // Bind a port and close it right away, so that nothing listens on it.
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let remote = PeerId::random();
+    swarm.add_address(&remote, format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap());
+    let request_id = swarm.send_request(&remote, Ping(Vec::new()));
+
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        match swarm.poll().expect("Error while polling swarm") {
+            Async::Ready(Some(RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id: id,
+                error: OutboundFailure::DialFailure
+            })) => {
+                assert_eq!(peer, remote);
+                assert_eq!(id, request_id);
+                Ok(Async::Ready(()))
+            },
+            Async::Ready(Some(e)) => panic!("Unexpected event: {:?}", e),
+            Async::Ready(None) | Async::NotReady => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+#[test]
+fn request_timeout() {
+    let (peer1_id, trans) = mk_transport();
+    let rr = RequestResponse::new(PingCodec, Some(PingProtocol("/ping/1")), RequestResponseConfig::new());
+    let mut swarm1 = Swarm::new(trans, rr, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let config = RequestResponseConfig::new().with_request_timeout(Duration::from_millis(200));
+    let rr = RequestResponse::new(PingCodec, Some(PingProtocol("/ping/1")), config);
+    let mut swarm2 = Swarm::new(trans, rr, peer2_id);
+
+    Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+    // The channels are kept without answering, which would otherwise be reported to the remote.
+    let mut channels = Vec::new();
+    let mut request_id = None;
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        loop {
+            match swarm1.poll().expect("Error while polling swarm") {
+                Async::Ready(Some(RequestResponseEvent::Message {
+                    message: RequestResponseMessage::Request { channel, .. }, ..
+                })) => channels.push(channel),
+                Async::Ready(Some(e)) => panic!("Peer1: Unexpected event: {:?}", e),
+                Async::Ready(None) | Async::NotReady => break,
+            }
+        }
+
+        if request_id.is_none() {
+            if let Some(addr) = Swarm::listeners(&swarm1).next().cloned() {
+                swarm2.add_address(&peer1_id, addr);
+                request_id = Some(swarm2.send_request(&peer1_id, Ping(Vec::new())));
+            }
+        }
+
+        match swarm2.poll().expect("Error while polling swarm") {
+            Async::Ready(Some(RequestResponseEvent::OutboundFailure {
+                peer,
+                request_id: id,
+                error: OutboundFailure::Timeout
+            })) => {
+                assert_eq!(peer, peer1_id);
+                assert_eq!(Some(id), request_id);
+                Ok(Async::Ready(()))
+            },
+            Async::Ready(Some(e)) => panic!("Peer2: Unexpected event: {:?}", e),
+            Async::Ready(None) | Async::NotReady => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+fn mk_transport() -> (PeerId, impl Transport<
+    Output = (PeerId, impl StreamMuxer<Substream = impl Send, OutboundSubstream = impl Send, Error = impl Into<io::Error>>),
+    Listener = impl Send,
+    ListenerUpgrade = impl Send,
+    Dial = impl Send,
+    Error = impl fmt::Debug
+> + Clone) {
+    let id_keys = identity::Keypair::generate_ed25519();
+    let peer_id = id_keys.public().into_peer_id();
+    let transport = TcpConfig::new()
+        .nodelay(true)
+        .with_upgrade(SecioConfig::new(id_keys))
+        .and_then(move |out, endpoint| {
+            let peer_id = out.remote_key.into_peer_id();
+            let peer_id2 = peer_id.clone();
+            let upgrade = MplexConfig::default()
+                .map_outbound(move |muxer| (peer_id, muxer))
+                .map_inbound(move |muxer| (peer_id2, muxer));
+            upgrade::apply(out.stream, upgrade, endpoint)
+        });
+    (peer_id, transport)
+}
+
+#[derive(Debug, Clone)]
+struct PingProtocol(&'static str);
+
+impl ProtocolName for PingProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+#[derive(Clone)]
+struct PingCodec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Ping(Vec<u8>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pong(Vec<u8>);
+
+impl RequestResponseCodec for PingCodec {
+    type Protocol = PingProtocol;
+    type Request = Ping;
+    type Response = Pong;
+
+    fn encode_request(&mut self, _: &PingProtocol, Ping(data): Ping) -> Vec<u8> {
+        data
+    }
+
+    fn decode_request(&mut self, _: &PingProtocol, bytes: Vec<u8>) -> io::Result<Ping> {
+        Ok(Ping(bytes))
+    }
+
+    fn encode_response(&mut self, _: &PingProtocol, Pong(data): Pong) -> Vec<u8> {
+        data
+    }
+
+    fn decode_response(&mut self, _: &PingProtocol, bytes: Vec<u8>) -> io::Result<Pong> {
+        Ok(Pong(bytes))
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The [`ProtocolsHandler`] of the [`Stream`](crate::Stream) behaviour.
+
+use crate::{NegotiatedStream, OpenStreamError, StreamProtocol};
+use crate::protocol::{AcceptUpgrade, OpenUpgrade};
+use futures::{prelude::*, sync::oneshot, task::AtomicTask};
+use libp2p_core::protocols_handler::{
+    KeepAlive,
+    ProtocolsHandler,
+    ProtocolsHandlerEvent,
+    ProtocolsHandlerUpgrErr,
+    SubstreamProtocol
+};
+use libp2p_core::upgrade::{Negotiated, ProtocolChoiceError, UpgradeError};
+use parking_lot::RwLock;
+use smallvec::SmallVec;
+use std::{collections::VecDeque, io, sync::Arc, time::Duration};
+use tokio_io::{AsyncRead, AsyncWrite};
+use void::Void;
+use wasm_timer::Instant;
+
+/// Request to open a substream, sent by the behaviour to the handler.
+pub struct OpenStreamRequest<TSubstream> {
+    pub(crate) protocol: StreamProtocol,
+    pub(crate) sender: oneshot::Sender<Result<NegotiatedStream<TSubstream>, OpenStreamError>>,
+}
+
+/// A substream opened by the remote, produced by the handler.
+pub struct InboundStream<TSubstream> {
+    /// The protocol negotiated on the substream.
+    pub protocol: StreamProtocol,
+    /// The substream.
+    pub stream: NegotiatedStream<TSubstream>,
+}
+
+/// Shared between a handler and the streams of its connection.
+///
+/// The number of strong references tells how many streams are still alive, and the task is
+/// notified whenever one of them is dropped.
+pub(crate) struct StreamTracker {
+    pub(crate) task: AtomicTask,
+}
+
+/// Protocol handler that accepts the inbound substreams of the registered protocols and
+/// opens the outbound substreams requested by the behaviour.
+///
+/// The connection is kept alive as long as one of the substreams is alive, and for an idle
+/// timeout afterwards.
+pub struct StreamHandler<TSubstream> {
+    /// The registered protocols, shared with the behaviour.
+    protocols: Arc<RwLock<SmallVec<[StreamProtocol; 4]>>>,
+    /// How long to keep the connection alive once there is no stream left.
+    idle_timeout: Duration,
+    /// Tracks the streams handed out for this connection.
+    tracker: Arc<StreamTracker>,
+    /// Outbound substreams that haven't been requested yet.
+    pending_open: VecDeque<OpenStreamRequest<TSubstream>>,
+    /// Number of outbound substreams being opened or negotiated.
+    pending_outbound: usize,
+    /// Inbound substreams to report.
+    pending_events: VecDeque<InboundStream<TSubstream>>,
+    /// Current keep-alive state of the connection.
+    keep_alive: KeepAlive,
+}
+
+impl<TSubstream> StreamHandler<TSubstream> {
+    pub(crate) fn new(protocols: Arc<RwLock<SmallVec<[StreamProtocol; 4]>>>, idle_timeout: Duration) -> Self {
+        StreamHandler {
+            protocols,
+            idle_timeout,
+            tracker: Arc::new(StreamTracker { task: AtomicTask::new() }),
+            pending_open: VecDeque::new(),
+            pending_outbound: 0,
+            pending_events: VecDeque::new(),
+            keep_alive: KeepAlive::Until(Instant::now() + idle_timeout),
+        }
+    }
+}
+
+impl<TSubstream> ProtocolsHandler for StreamHandler<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type InEvent = OpenStreamRequest<TSubstream>;
+    type OutEvent = InboundStream<TSubstream>;
+    type Error = Void;
+    type Substream = TSubstream;
+    type InboundProtocol = AcceptUpgrade;
+    type OutboundProtocol = OpenUpgrade;
+    type OutboundOpenInfo = oneshot::Sender<Result<NegotiatedStream<TSubstream>, OpenStreamError>>;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<AcceptUpgrade> {
+        SubstreamProtocol::new(AcceptUpgrade { protocols: self.protocols.read().clone() })
+    }
+
+    fn inject_fully_negotiated_inbound(&mut self, (socket, protocol): (Negotiated<TSubstream>, StreamProtocol)) {
+        let stream = NegotiatedStream::new(socket, self.tracker.clone());
+        self.pending_events.push_back(InboundStream { protocol, stream });
+        self.keep_alive = KeepAlive::Yes;
+    }
+
+    fn inject_fully_negotiated_outbound(&mut self, socket: Negotiated<TSubstream>, sender: Self::OutboundOpenInfo) {
+        self.pending_outbound -= 1;
+        let _ = sender.send(Ok(NegotiatedStream::new(socket, self.tracker.clone())));
+    }
+
+    fn inject_event(&mut self, request: OpenStreamRequest<TSubstream>) {
+        self.pending_open.push_back(request);
+        self.keep_alive = KeepAlive::Yes;
+    }
+
+    fn inject_dial_upgrade_error(&mut self, sender: Self::OutboundOpenInfo, error: ProtocolsHandlerUpgrErr<Void>) {
+        self.pending_outbound -= 1;
+        let error = match error {
+            ProtocolsHandlerUpgrErr::Timeout | ProtocolsHandlerUpgrErr::Timer => OpenStreamError::Timeout,
+            ProtocolsHandlerUpgrErr::ResourceLimit(err) =>
+                OpenStreamError::Io(io::Error::new(io::ErrorKind::Other, err)),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(ProtocolChoiceError::NoProtocolFound)) =>
+                OpenStreamError::UnsupportedProtocol,
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)) =>
+                OpenStreamError::Io(io::Error::new(io::ErrorKind::Other, err)),
+            ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(err)) => void::unreachable(err),
+        };
+        let _ = sender.send(Err(error));
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.keep_alive
+    }
+
+    fn poll(&mut self) -> Poll<ProtocolsHandlerEvent<OpenUpgrade, Self::OutboundOpenInfo, Self::OutEvent>, Void> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(event)));
+        }
+
+        while let Some(request) = self.pending_open.pop_front() {
+            // The requester is no longer interested in the substream.
+            if request.sender.is_canceled() {
+                continue;
+            }
+            self.pending_outbound += 1;
+            return Ok(Async::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(OpenUpgrade { protocol: request.protocol }),
+                info: request.sender,
+            }));
+        }
+
+        self.tracker.task.register();
+        if self.pending_outbound > 0 || Arc::strong_count(&self.tracker) > 1 {
+            self.keep_alive = KeepAlive::Yes;
+        } else if self.keep_alive.is_yes() {
+            self.keep_alive = KeepAlive::Until(Instant::now() + self.idle_timeout);
+        }
+
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Raw substreams for applications.
+//!
+//! The [`Stream`] struct implements the [`NetworkBehaviour`] trait for applications that
+//! want to run their own framing over substreams, for example to transfer files or to
+//! tunnel another protocol, without writing a [`ProtocolsHandler`].
+//!
+//! The application registers the names of its protocols with [`Stream::register`]. Every
+//! substream opened by a remote for one of these protocols is reported as a [`StreamEvent`].
+//! Substreams are opened towards a remote with [`Stream::open_stream`], which dials the peer
+//! if necessary and returns a future resolving to the substream.
+//!
+//! A connection is kept alive as long as one of its substreams is alive, and is closed after
+//! an idle timeout once all of them have been dropped.
+//!
+//! > **Note**: The behaviour doesn't know any address. Peers are dialed with the addresses
+//! > of the other behaviours and of the peer store of the `Swarm`.
+//!
+//! [`NetworkBehaviour`]: libp2p_core::swarm::NetworkBehaviour
+//! [`ProtocolsHandler`]: libp2p_core::protocols_handler::ProtocolsHandler
+
+pub mod handler;
+pub mod protocol;
+
+pub use protocol::StreamProtocol;
+
+use fnv::FnvHashMap;
+use futures::{prelude::*, sync::oneshot};
+use handler::{InboundStream, OpenStreamRequest, StreamHandler, StreamTracker};
+use libp2p_core::swarm::{
+    ConnectedPoint,
+    ConnectionId,
+    DisconnectReason,
+    NetworkBehaviour,
+    NetworkBehaviourAction,
+    PollParameters
+};
+use libp2p_core::{Multiaddr, PeerId, upgrade::Negotiated};
+use parking_lot::RwLock;
+use smallvec::SmallVec;
+use std::{collections::VecDeque, error, fmt, io, sync::Arc, time::Duration};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// A substream on which a protocol has been negotiated.
+///
+/// Keeps its connection alive until it is dropped.
+pub struct NegotiatedStream<TSubstream> {
+    inner: Negotiated<TSubstream>,
+    tracker: Arc<StreamTracker>,
+}
+
+impl<TSubstream> NegotiatedStream<TSubstream> {
+    fn new(inner: Negotiated<TSubstream>, tracker: Arc<StreamTracker>) -> Self {
+        NegotiatedStream { inner, tracker }
+    }
+}
+
+impl<TSubstream> Drop for NegotiatedStream<TSubstream> {
+    fn drop(&mut self) {
+        // Let the handler update the keep-alive of the connection.
+        self.tracker.task.notify();
+    }
+}
+
+impl<TSubstream> fmt::Debug for NegotiatedStream<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NegotiatedStream").finish()
+    }
+}
+
+impl<TSubstream> io::Read for NegotiatedStream<TSubstream>
+where
+    TSubstream: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<TSubstream> AsyncRead for NegotiatedStream<TSubstream>
+where
+    TSubstream: AsyncRead,
+{
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<TSubstream> io::Write for NegotiatedStream<TSubstream>
+where
+    TSubstream: io::Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<TSubstream> AsyncWrite for NegotiatedStream<TSubstream>
+where
+    TSubstream: AsyncWrite,
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+/// Future returned by [`Stream::open_stream`], resolving to the opened substream.
+#[must_use = "futures do nothing unless polled"]
+pub struct OpenStream<TSubstream> {
+    receiver: oneshot::Receiver<Result<NegotiatedStream<TSubstream>, OpenStreamError>>,
+}
+
+impl<TSubstream> Future for OpenStream<TSubstream> {
+    type Item = NegotiatedStream<TSubstream>;
+    type Error = OpenStreamError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Ok(stream))) => Ok(Async::Ready(stream)),
+            Ok(Async::Ready(Err(err))) => Err(err),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(oneshot::Canceled) => Err(OpenStreamError::ConnectionClosed),
+        }
+    }
+}
+
+/// Error while opening a substream with [`Stream::open_stream`].
+#[derive(Debug)]
+pub enum OpenStreamError {
+    /// We are not connected to the peer and dialing it failed.
+    DialFailure,
+    /// The connection has been closed before the substream was open.
+    ConnectionClosed,
+    /// The remote doesn't support the protocol.
+    UnsupportedProtocol,
+    /// The substream couldn't be opened and negotiated in time.
+    Timeout,
+    /// An error happened while negotiating the protocol.
+    Io(io::Error),
+}
+
+impl fmt::Display for OpenStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenStreamError::DialFailure => write!(f, "Failed to dial the peer"),
+            OpenStreamError::ConnectionClosed => write!(f, "Connection closed before the substream was open"),
+            OpenStreamError::UnsupportedProtocol => write!(f, "The remote doesn't support the protocol"),
+            OpenStreamError::Timeout => write!(f, "Timeout while opening the substream"),
+            OpenStreamError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for OpenStreamError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            OpenStreamError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Event generated by the [`Stream`] network behaviour when a remote opens a substream for
+/// one of the registered protocols.
+pub struct StreamEvent<TSubstream> {
+    /// The peer that opened the substream.
+    pub peer: PeerId,
+    /// The protocol negotiated on the substream.
+    pub protocol: StreamProtocol,
+    /// The substream.
+    pub stream: NegotiatedStream<TSubstream>,
+}
+
+impl<TSubstream> fmt::Debug for StreamEvent<TSubstream> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamEvent")
+            .field("peer", &self.peer)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+/// Network behaviour that hands raw substreams over to the application.
+///
+/// See the crate root documentation for more information.
+pub struct Stream<TSubstream> {
+    /// The registered protocols, shared with the handlers.
+    protocols: Arc<RwLock<SmallVec<[StreamProtocol; 4]>>>,
+    /// How long to keep a connection alive once it has no stream left.
+    idle_timeout: Duration,
+    /// The open connections of every connected peer.
+    connected: FnvHashMap<PeerId, SmallVec<[ConnectionId; 2]>>,
+    /// Substreams to open once we are connected to the peer.
+    pending_opens: FnvHashMap<PeerId, SmallVec<[OpenStreamRequest<TSubstream>; 4]>>,
+    /// Actions to return from `poll`.
+    pending_events: VecDeque<NetworkBehaviourAction<OpenStreamRequest<TSubstream>, StreamEvent<TSubstream>>>,
+}
+
+impl<TSubstream> Stream<TSubstream> {
+    /// Creates a new `Stream` network behaviour with no registered protocol.
+    pub fn new() -> Self {
+        Stream {
+            protocols: Arc::new(RwLock::new(SmallVec::new())),
+            idle_timeout: Duration::from_secs(10),
+            connected: FnvHashMap::default(),
+            pending_opens: FnvHashMap::default(),
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Sets how long a connection is kept alive once all its substreams have been dropped.
+    /// Defaults to 10 seconds.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Accepts the substreams opened by remotes for the given protocol from now on.
+    ///
+    /// Returns `false` if the protocol was already registered.
+    pub fn register(&mut self, protocol: impl Into<StreamProtocol>) -> bool {
+        let protocol = protocol.into();
+        let mut protocols = self.protocols.write();
+        if protocols.contains(&protocol) {
+            return false;
+        }
+        protocols.push(protocol);
+        true
+    }
+
+    /// Stops accepting the substreams opened by remotes for the given protocol. Substreams that
+    /// are already open are not affected.
+    ///
+    /// Returns `false` if the protocol wasn't registered.
+    pub fn unregister(&mut self, protocol: &StreamProtocol) -> bool {
+        let mut protocols = self.protocols.write();
+        let len = protocols.len();
+        protocols.retain(|p| p != protocol);
+        protocols.len() != len
+    }
+
+    /// Opens a substream to the given peer and negotiates the given protocol on it, dialing
+    /// the peer first if we are not connected to it.
+    ///
+    /// The protocol doesn't need to be registered. Dropping the returned future before it
+    /// resolves abandons the substream.
+    pub fn open_stream(&mut self, peer: &PeerId, protocol: impl Into<StreamProtocol>) -> OpenStream<TSubstream> {
+        let (sender, receiver) = oneshot::channel();
+        let request = OpenStreamRequest { protocol: protocol.into(), sender };
+
+        if let Some(connection_id) = self.connected.get(peer).and_then(|c| c.first()).cloned() {
+            self.pending_events.push_back(NetworkBehaviourAction::SendEventToConnection {
+                peer_id: peer.clone(),
+                connection_id,
+                event: request,
+            });
+        } else {
+            if !self.pending_opens.contains_key(peer) {
+                self.pending_events.push_back(NetworkBehaviourAction::DialPeer { peer_id: peer.clone() });
+            }
+            self.pending_opens.entry(peer.clone()).or_default().push(request);
+        }
+
+        OpenStream { receiver }
+    }
+}
+
+impl<TSubstream> Default for Stream<TSubstream> {
+    fn default() -> Self {
+        Stream::new()
+    }
+}
+
+impl<TSubstream> NetworkBehaviour for Stream<TSubstream>
+where
+    TSubstream: AsyncRead + AsyncWrite,
+{
+    type ProtocolsHandler = StreamHandler<TSubstream>;
+    type OutEvent = StreamEvent<TSubstream>;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        StreamHandler::new(self.protocols.clone(), self.idle_timeout)
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, peer_id: PeerId, connection_id: ConnectionId, _: ConnectedPoint) {
+        self.connected.entry(peer_id.clone()).or_default().push(connection_id);
+
+        if let Some(requests) = self.pending_opens.remove(&peer_id) {
+            for request in requests {
+                self.pending_events.push_back(NetworkBehaviourAction::SendEventToConnection {
+                    peer_id: peer_id.clone(),
+                    connection_id,
+                    event: request,
+                });
+            }
+        }
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId, connection_id: ConnectionId, _: ConnectedPoint, _: DisconnectReason<'_>) {
+        let mut last = false;
+        if let Some(connections) = self.connected.get_mut(peer_id) {
+            connections.retain(|c| *c != connection_id);
+            last = connections.is_empty();
+        }
+        if last {
+            self.connected.remove(peer_id);
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        if let Some(requests) = self.pending_opens.remove(peer_id) {
+            for request in requests {
+                let _ = request.sender.send(Err(OpenStreamError::DialFailure));
+            }
+        }
+    }
+
+    fn inject_node_event(&mut self, peer: PeerId, _: ConnectionId, event: InboundStream<TSubstream>) {
+        let InboundStream { protocol, stream } = event;
+        self.pending_events.push_back(NetworkBehaviourAction::GenerateEvent(StreamEvent { peer, protocol, stream }));
+    }
+
+    fn poll(&mut self, _: &mut PollParameters<'_>)
+        -> Async<NetworkBehaviourAction<OpenStreamRequest<TSubstream>, StreamEvent<TSubstream>>>
+    {
+        if let Some(event) = self.pending_events.pop_front() {
+            Async::Ready(event)
+        } else {
+            Async::NotReady
+        }
+    }
+}
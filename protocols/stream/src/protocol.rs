@@ -0,0 +1,113 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The upgrades used by the [`Stream`](crate::Stream) behaviour. They only negotiate the
+//! protocol and hand the substream over as is.
+
+use futures::future::{self, FutureResult};
+use libp2p_core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, upgrade::{Negotiated, ProtocolName}};
+use smallvec::SmallVec;
+use std::{borrow::Cow, fmt, iter};
+use void::Void;
+
+/// Name of a protocol that can be negotiated on a substream.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StreamProtocol(Cow<'static, str>);
+
+impl StreamProtocol {
+    /// Returns the name of the protocol.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ProtocolName for StreamProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl From<&'static str> for StreamProtocol {
+    fn from(name: &'static str) -> Self {
+        StreamProtocol(Cow::Borrowed(name))
+    }
+}
+
+impl From<String> for StreamProtocol {
+    fn from(name: String) -> Self {
+        StreamProtocol(Cow::Owned(name))
+    }
+}
+
+impl fmt::Display for StreamProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Upgrade for inbound substreams that accepts any of the registered protocols.
+#[derive(Debug, Clone)]
+pub struct AcceptUpgrade {
+    pub(crate) protocols: SmallVec<[StreamProtocol; 4]>,
+}
+
+impl UpgradeInfo for AcceptUpgrade {
+    type Info = StreamProtocol;
+    type InfoIter = smallvec::IntoIter<[StreamProtocol; 4]>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.protocols.clone().into_iter()
+    }
+}
+
+impl<TSocket> InboundUpgrade<TSocket> for AcceptUpgrade {
+    type Output = (Negotiated<TSocket>, StreamProtocol);
+    type Error = Void;
+    type Future = FutureResult<Self::Output, Void>;
+
+    fn upgrade_inbound(self, socket: Negotiated<TSocket>, protocol: StreamProtocol) -> Self::Future {
+        future::ok((socket, protocol))
+    }
+}
+
+/// Upgrade for outbound substreams that negotiates a single protocol.
+#[derive(Debug, Clone)]
+pub struct OpenUpgrade {
+    pub(crate) protocol: StreamProtocol,
+}
+
+impl UpgradeInfo for OpenUpgrade {
+    type Info = StreamProtocol;
+    type InfoIter = iter::Once<StreamProtocol>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(self.protocol.clone())
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for OpenUpgrade {
+    type Output = Negotiated<TSocket>;
+    type Error = Void;
+    type Future = FutureResult<Self::Output, Void>;
+
+    fn upgrade_outbound(self, socket: Negotiated<TSocket>, _: StreamProtocol) -> Self::Future {
+        future::ok(socket)
+    }
+}
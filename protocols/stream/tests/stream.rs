@@ -0,0 +1,162 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Integration tests for the `Stream` network behaviour.
+
+use libp2p_core::{
+    PeerId,
+    Swarm,
+    identity,
+    muxing::StreamMuxer,
+    swarm::AddressSource,
+    upgrade::{self, OutboundUpgradeExt, InboundUpgradeExt},
+    transport::Transport
+};
+use libp2p_mplex::MplexConfig;
+use libp2p_secio::SecioConfig;
+use libp2p_stream::{OpenStreamError, Stream, StreamEvent};
+use libp2p_tcp::TcpConfig;
+use futures::{future, Async, Future, Stream as _};
+use std::{fmt, io};
+use tokio::runtime::Runtime;
+
+#[test]
+fn open_and_accept_stream() {
+    let (peer1_id, trans) = mk_transport();
+    let mut behaviour = Stream::new();
+    assert!(behaviour.register("/echo/1.0.0"));
+    let mut swarm1 = Swarm::new(trans, behaviour, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let mut swarm2 = Swarm::new(trans, Stream::new(), peer2_id.clone());
+
+    Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+    let mut open = None;
+    let mut echo = None;
+    let mut exchange = None;
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        while let Async::Ready(Some(StreamEvent { peer, protocol, stream })) = swarm1.poll().expect("Error while polling swarm") {
+            assert_eq!(peer, peer2_id);
+            assert_eq!(protocol.as_str(), "/echo/1.0.0");
+            // Echo one message back.
+            echo = Some(upgrade::read_respond(stream, 1024, (), |stream, msg, ()| {
+                Ok::<_, upgrade::ReadOneError>(upgrade::write_one(stream, msg))
+            }).and_then(|write| write.map_err(upgrade::ReadOneError::Io)));
+        }
+
+        if let Some(fut) = echo.as_mut() {
+            if fut.poll().expect("Error while echoing").is_ready() {
+                echo = None;
+            }
+        }
+
+        if open.is_none() && exchange.is_none() {
+            if let Some(addr) = Swarm::listeners(&swarm1).next().cloned() {
+                Swarm::peer_store_mut(&mut swarm2).add_address(&peer1_id, addr, AddressSource::Manual, None);
+                open = Some(swarm2.open_stream(&peer1_id, "/echo/1.0.0"));
+            }
+        }
+
+        if let Async::Ready(Some(e)) = swarm2.poll().expect("Error while polling swarm") {
+            panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        if let Some(Async::Ready(stream)) = open.as_mut().map(|f| f.poll().expect("Error while opening")) {
+            open = None;
+            exchange = Some(upgrade::request_response(stream, b"hello".to_vec(), 1024, (), |msg, ()| {
+                Ok::<_, upgrade::ReadOneError>(msg)
+            }));
+        }
+
+        match exchange.as_mut().map(|f| f.poll().expect("Error during the exchange")) {
+            Some(Async::Ready(msg)) => {
+                assert_eq!(msg, b"hello");
+                Ok(Async::Ready(()))
+            },
+            _ => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+#[test]
+fn unsupported_protocol() {
+    let (peer1_id, trans) = mk_transport();
+    let mut behaviour = Stream::new();
+    behaviour.register("/echo/1.0.0");
+    let mut swarm1 = Swarm::new(trans, behaviour, peer1_id.clone());
+
+    let (peer2_id, trans) = mk_transport();
+    let mut swarm2 = Swarm::new(trans, Stream::new(), peer2_id);
+
+    Swarm::listen_on(&mut swarm1, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+
+    let mut open = None;
+    let test = future::poll_fn(move || -> Result<_, ()> {
+        if let Async::Ready(Some(e)) = swarm1.poll().expect("Error while polling swarm") {
+            panic!("Peer1: Unexpected event: {:?}", e)
+        }
+
+        if open.is_none() {
+            if let Some(addr) = Swarm::listeners(&swarm1).next().cloned() {
+                Swarm::peer_store_mut(&mut swarm2).add_address(&peer1_id, addr, AddressSource::Manual, None);
+                open = Some(swarm2.open_stream(&peer1_id, "/echo/2.0.0"));
+            }
+        }
+
+        if let Async::Ready(Some(e)) = swarm2.poll().expect("Error while polling swarm") {
+            panic!("Peer2: Unexpected event: {:?}", e)
+        }
+
+        match open.as_mut().map(|f| f.poll()) {
+            Some(Err(OpenStreamError::UnsupportedProtocol)) => Ok(Async::Ready(())),
+            Some(Err(e)) => panic!("Unexpected error: {:?}", e),
+            Some(Ok(Async::Ready(_))) => panic!("Stream unexpectedly opened"),
+            Some(Ok(Async::NotReady)) | None => Ok(Async::NotReady),
+        }
+    });
+
+    Runtime::new().unwrap().block_on(test).unwrap();
+}
+
+fn mk_transport() -> (PeerId, impl Transport<
+    Output = (PeerId, impl StreamMuxer<Substream = impl Send, OutboundSubstream = impl Send, Error = impl Into<io::Error>>),
+    Listener = impl Send,
+    ListenerUpgrade = impl Send,
+    Dial = impl Send,
+    Error = impl fmt::Debug
+> + Clone) {
+    let id_keys = identity::Keypair::generate_ed25519();
+    let peer_id = id_keys.public().into_peer_id();
+    let transport = TcpConfig::new()
+        .nodelay(true)
+        .with_upgrade(SecioConfig::new(id_keys))
+        .and_then(move |out, endpoint| {
+            let peer_id = out.remote_key.into_peer_id();
+            let peer_id2 = peer_id.clone();
+            let upgrade = MplexConfig::default()
+                .map_outbound(move |muxer| (peer_id, muxer))
+                .map_inbound(move |muxer| (peer_id2, muxer));
+            upgrade::apply(out.stream, upgrade, endpoint)
+        });
+    (peer_id, transport)
+}
@@ -18,13 +18,19 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::{Multiaddr, core::{Transport, transport::{ListenerEvent, TransportError}}};
+//! Measurement of the bandwidth used by a node.
+//!
+//! [`BandwidthLogging`] wraps around a transport and measures the bytes that go through all its
+//! connections. In order to break the traffic down by peer and by negotiated protocol, pass a
+//! [`ProtocolBandwidth`] to `SwarmBuilder::bandwidth` instead.
+
+use crate::{Multiaddr, core::{Transport, bandwidth::BandwidthCounters, transport::{ListenerEvent, TransportError}}};
 use futures::{prelude::*, try_ready};
-use lazy_static::lazy_static;
 use parking_lot::Mutex;
-use smallvec::{smallvec, SmallVec};
-use std::{cmp, io, io::Read, io::Write, sync::Arc, time::Duration};
-use wasm_timer::Instant;
+use std::{fmt, io, io::Read, io::Write, sync::{Arc, Weak}, time::Duration};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub use crate::core::bandwidth::{BandwidthStats, ProtocolBandwidth};
 
 /// Wraps around a `Transport` and logs the bandwidth that goes through all the opened connections.
 #[derive(Clone)]
@@ -37,14 +43,11 @@ impl<TInner> BandwidthLogging<TInner> {
     /// Creates a new `BandwidthLogging` around the transport.
     #[inline]
     pub fn new(inner: TInner, period: Duration) -> (Self, Arc<BandwidthSinks>) {
-        let mut period_seconds = cmp::min(period.as_secs(), 86400) as u32;
-        if period.subsec_nanos() > 0 {
-            period_seconds += 1;
-        }
-
         let sink = Arc::new(BandwidthSinks {
-            download: Mutex::new(BandwidthSink::new(period_seconds)),
-            upload: Mutex::new(BandwidthSink::new(period_seconds)),
+            inner: Mutex::new(SinksInner {
+                counters: BandwidthCounters::new(period),
+                connections: Vec::new(),
+            }),
         });
 
         let trans = BandwidthLogging {
@@ -75,9 +78,10 @@ where
 
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
         let sinks = self.sinks;
+        let remote_addr = addr.clone();
         self.inner
             .dial(addr)
-            .map(move |fut| BandwidthFuture { inner: fut, sinks })
+            .map(move |fut| BandwidthFuture { inner: fut, sinks, remote_addr })
     }
 }
 
@@ -101,9 +105,18 @@ where
             None => return Ok(Async::Ready(None))
         };
 
-        let event = event.map(|inner| {
-            BandwidthFuture { inner, sinks: self.sinks.clone() }
-        });
+        let event = match event {
+            ListenerEvent::Upgrade { upgrade, listen_addr, remote_addr } => {
+                let upgrade = BandwidthFuture {
+                    inner: upgrade,
+                    sinks: self.sinks.clone(),
+                    remote_addr: remote_addr.clone(),
+                };
+                ListenerEvent::Upgrade { upgrade, listen_addr, remote_addr }
+            }
+            ListenerEvent::NewAddress(a) => ListenerEvent::NewAddress(a),
+            ListenerEvent::AddressExpired(a) => ListenerEvent::AddressExpired(a),
+        };
 
         Ok(Async::Ready(Some(event)))
     }
@@ -114,6 +127,7 @@ where
 pub struct BandwidthFuture<TInner> {
     inner: TInner,
     sinks: Arc<BandwidthSinks>,
+    remote_addr: Multiaddr,
 }
 
 impl<TInner> Future for BandwidthFuture<TInner>
@@ -124,30 +138,202 @@ impl<TInner> Future for BandwidthFuture<TInner>
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let inner = try_ready!(self.inner.poll());
+        let counters = self.sinks.add_connection(self.remote_addr.clone());
         Ok(Async::Ready(BandwidthConnecLogging {
             inner,
             sinks: self.sinks.clone(),
+            counters,
         }))
     }
 }
 
-/// Allows obtaining the average bandwidth of the connections created from a `BandwidthLogging`.
+/// Allows obtaining the bandwidth of the connections created from a `BandwidthLogging`.
 pub struct BandwidthSinks {
-    download: Mutex<BandwidthSink>,
-    upload: Mutex<BandwidthSink>,
+    /// The counters of the connections are also only updated while this lock is held, so that
+    /// they are consistent with the totals.
+    inner: Mutex<SinksInner>,
+}
+
+struct SinksInner {
+    counters: BandwidthCounters,
+    /// Counters of the connections, which are dropped along with the connections.
+    connections: Vec<Weak<ConnectionCounters>>,
 }
 
 impl BandwidthSinks {
     /// Returns the average number of bytes that have been downloaded in the period.
     #[inline]
     pub fn average_download_per_sec(&self) -> u64 {
-        self.download.lock().get()
+        self.inner.lock().counters.stats().inbound_per_sec()
     }
 
     /// Returns the average number of bytes that have been uploaded in the period.
     #[inline]
     pub fn average_upload_per_sec(&self) -> u64 {
-        self.upload.lock().get()
+        self.inner.lock().counters.stats().outbound_per_sec()
+    }
+
+    /// Returns the number of bytes that have been downloaded since the transport was created.
+    #[inline]
+    pub fn total_inbound(&self) -> u64 {
+        self.inner.lock().counters.total_inbound()
+    }
+
+    /// Returns the number of bytes that have been uploaded since the transport was created.
+    #[inline]
+    pub fn total_outbound(&self) -> u64 {
+        self.inner.lock().counters.total_outbound()
+    }
+
+    /// Returns the totals, the average rates and the counters of each open connection, all
+    /// taken at the same instant.
+    ///
+    /// Contrary to calling the individual getters one after the other, no traffic can be
+    /// accounted for in between, which means that the values are consistent with each other.
+    pub fn snapshot(&self) -> BandwidthSnapshot {
+        let mut inner = self.inner.lock();
+        inner.connections.retain(|c| c.upgrade().is_some());
+        let connections = inner.connections.iter()
+            .filter_map(Weak::upgrade)
+            .map(|c| ConnectionStats {
+                remote_addr: c.remote_addr.clone(),
+                total_inbound: c.inbound.load(Ordering::Relaxed),
+                total_outbound: c.outbound.load(Ordering::Relaxed),
+            })
+            .collect();
+        BandwidthSnapshot {
+            totals: inner.counters.stats(),
+            connections,
+        }
+    }
+
+    /// Returns the bandwidth of each connection that is still open, from the oldest to the most
+    /// recent.
+    pub fn connections(&self) -> Vec<ConnectionBandwidth> {
+        let mut inner = self.inner.lock();
+        inner.connections.retain(|c| c.upgrade().is_some());
+        inner.connections.iter()
+            .filter_map(Weak::upgrade)
+            .map(|inner| ConnectionBandwidth { inner })
+            .collect()
+    }
+
+    fn add_connection(&self, remote_addr: Multiaddr) -> Arc<ConnectionCounters> {
+        let counters = Arc::new(ConnectionCounters {
+            remote_addr,
+            inbound: AtomicU64::new(0),
+            outbound: AtomicU64::new(0),
+        });
+        let mut inner = self.inner.lock();
+        inner.connections.retain(|c| c.upgrade().is_some());
+        inner.connections.push(Arc::downgrade(&counters));
+        counters
+    }
+
+    fn inject_read(&self, connection: &ConnectionCounters, num_bytes: usize) {
+        let mut inner = self.inner.lock();
+        connection.inbound.fetch_add(num_bytes as u64, Ordering::Relaxed);
+        inner.counters.inject_read(num_bytes);
+    }
+
+    fn inject_write(&self, connection: &ConnectionCounters, num_bytes: usize) {
+        let mut inner = self.inner.lock();
+        connection.outbound.fetch_add(num_bytes as u64, Ordering::Relaxed);
+        inner.counters.inject_write(num_bytes);
+    }
+}
+
+/// Bandwidth of the transport and of its open connections at a given instant, as returned by
+/// `BandwidthSinks::snapshot`.
+#[derive(Debug, Clone)]
+pub struct BandwidthSnapshot {
+    totals: BandwidthStats,
+    connections: Vec<ConnectionStats>,
+}
+
+impl BandwidthSnapshot {
+    /// Returns the totals and the average rates of all the connections, including the closed
+    /// ones.
+    pub fn totals(&self) -> &BandwidthStats {
+        &self.totals
+    }
+
+    /// Returns the counters of each connection that was open, from the oldest to the most recent.
+    pub fn connections(&self) -> &[ConnectionStats] {
+        &self.connections
+    }
+}
+
+/// Counters of a connection, as part of a `BandwidthSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionStats {
+    remote_addr: Multiaddr,
+    total_inbound: u64,
+    total_outbound: u64,
+}
+
+impl ConnectionStats {
+    /// Returns the address of the remote of the connection.
+    pub fn remote_addr(&self) -> &Multiaddr {
+        &self.remote_addr
+    }
+
+    /// Returns the number of bytes that had been downloaded through the connection.
+    #[inline]
+    pub fn total_inbound(&self) -> u64 {
+        self.total_inbound
+    }
+
+    /// Returns the number of bytes that had been uploaded through the connection.
+    #[inline]
+    pub fn total_outbound(&self) -> u64 {
+        self.total_outbound
+    }
+}
+
+/// Number of bytes that went through a connection, shared between the connection and the
+/// `BandwidthSinks`.
+struct ConnectionCounters {
+    remote_addr: Multiaddr,
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+/// Bandwidth of a single connection, as returned by `BandwidthSinks::connections`.
+///
+/// Cloning a `ConnectionBandwidth` is cheap, and the values keep being updated as long as the
+/// connection is open.
+#[derive(Clone)]
+pub struct ConnectionBandwidth {
+    inner: Arc<ConnectionCounters>,
+}
+
+impl ConnectionBandwidth {
+    /// Returns the address of the remote of the connection.
+    pub fn remote_addr(&self) -> &Multiaddr {
+        &self.inner.remote_addr
+    }
+
+    /// Returns the number of bytes that have been downloaded through the connection.
+    #[inline]
+    pub fn total_inbound(&self) -> u64 {
+        self.inner.inbound.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes that have been uploaded through the connection.
+    #[inline]
+    pub fn total_outbound(&self) -> u64 {
+        self.inner.outbound.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for ConnectionBandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionBandwidth")
+            .field("remote_addr", self.remote_addr())
+            .field("total_inbound", &self.total_inbound())
+            .field("total_outbound", &self.total_outbound())
+            .finish()
     }
 }
 
@@ -155,6 +341,36 @@ impl BandwidthSinks {
 pub struct BandwidthConnecLogging<TInner> {
     inner: TInner,
     sinks: Arc<BandwidthSinks>,
+    /// Number of bytes that went through this connection.
+    counters: Arc<ConnectionCounters>,
+}
+
+impl<TInner> BandwidthConnecLogging<TInner> {
+    /// Returns the number of bytes that have been downloaded through this connection.
+    #[inline]
+    pub fn total_inbound(&self) -> u64 {
+        self.counters.inbound.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes that have been uploaded through this connection.
+    #[inline]
+    pub fn total_outbound(&self) -> u64 {
+        self.counters.outbound.load(Ordering::Relaxed)
+    }
+
+    /// Returns a handle to the bandwidth of this connection, which remains readable once the
+    /// connection has been wrapped, for example by an encryption or a muxing upgrade.
+    pub fn bandwidth(&self) -> ConnectionBandwidth {
+        ConnectionBandwidth { inner: self.counters.clone() }
+    }
+
+    fn record_read(&mut self, num_bytes: usize) {
+        self.sinks.inject_read(&self.counters, num_bytes);
+    }
+
+    fn record_write(&mut self, num_bytes: usize) {
+        self.sinks.inject_write(&self.counters, num_bytes);
+    }
 }
 
 impl<TInner> Read for BandwidthConnecLogging<TInner>
@@ -163,7 +379,7 @@ impl<TInner> Read for BandwidthConnecLogging<TInner>
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let num_bytes = self.inner.read(buf)?;
-        self.sinks.download.lock().inject(num_bytes);
+        self.record_read(num_bytes);
         Ok(num_bytes)
     }
 }
@@ -176,7 +392,9 @@ impl<TInner> tokio_io::AsyncRead for BandwidthConnecLogging<TInner>
     }
 
     fn read_buf<B: bytes::BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
-        self.inner.read_buf(buf)
+        let num_bytes = try_ready!(self.inner.read_buf(buf));
+        self.record_read(num_bytes);
+        Ok(Async::Ready(num_bytes))
     }
 }
 
@@ -186,7 +404,7 @@ impl<TInner> Write for BandwidthConnecLogging<TInner>
     #[inline]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let num_bytes = self.inner.write(buf)?;
-        self.sinks.upload.lock().inject(num_bytes);
+        self.record_write(num_bytes);
         Ok(num_bytes)
     }
 
@@ -205,97 +423,63 @@ impl<TInner> tokio_io::AsyncWrite for BandwidthConnecLogging<TInner>
     }
 }
 
-/// Returns the number of seconds that have elapsed between an arbitrary EPOCH and now.
-#[inline]
-fn current_second() -> u32 {
-    lazy_static! {
-        static ref EPOCH: Instant = Instant::now();
-    }
-
-    EPOCH.elapsed().as_secs() as u32
-}
-
-/// Structure that calculates the average bandwidth over the last few seconds.
-///
-/// If you want to calculate for example both download and upload bandwidths, create two different
-/// objects.
-struct BandwidthSink {
-    /// Bytes sent over the past seconds. Contains `rolling_seconds + 1` elements, where
-    /// `rolling_seconds` is the value passed to `new`. Only the first `rolling_seconds` elements
-    /// are taken into account for the average, while the last element is the element to be
-    /// inserted later.
-    bytes: SmallVec<[u64; 8]>,
-    /// Number of seconds between `EPOCH` and the moment we have last updated `bytes`.
-    latest_update: u32,
-}
-
-impl BandwidthSink {
-    /// Initializes a `BandwidthSink`.
-    fn new(seconds: u32) -> Self {
-        BandwidthSink {
-            bytes: smallvec![0; seconds as usize + 1],
-            latest_update: current_second(),
-        }
-    }
-
-    /// Returns the number of bytes over the last few seconds. The number of seconds is the value
-    /// configured at initialization.
-    fn get(&mut self) -> u64 {
-        self.update();
-        let seconds = self.bytes.len() - 1;
-        self.bytes.iter()
-            .take(seconds)
-            .fold(0u64, |a, &b| a.saturating_add(b)) / seconds as u64
-    }
-
-    /// Notifies the `BandwidthSink` that a certain number of bytes have been transmitted at this
-    /// moment.
-    fn inject(&mut self, bytes: usize) {
-        self.update();
-        if let Some(last) = self.bytes.last_mut() {
-            *last = last.saturating_add(bytes as u64);
-        }
-    }
-
-    /// Updates the state of the `BandwidthSink` so that the last element of `bytes` contains the
-    /// current second.
-    fn update(&mut self) {
-        let current_second = current_second();
-        debug_assert!(current_second >= self.latest_update);
-        let num_iter = cmp::min(current_second - self.latest_update, self.bytes.len() as u32);
-        for _ in 0..num_iter {
-            self.bytes.remove(0);
-            self.bytes.push(0);
-        }
-
-        self.latest_update = current_second;
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use std::{thread, time::Duration};
     use super::*;
+    use crate::tcp::TcpConfig;
+    use tokio::runtime::current_thread::Runtime;
 
     #[test]
-    fn sink_works() {
-        let mut sink = BandwidthSink::new(5);
-        sink.inject(100);
-        thread::sleep(Duration::from_millis(1000));
-        assert_eq!(sink.get(), 20);
-        sink.inject(100);
-        thread::sleep(Duration::from_millis(1000));
-        assert_eq!(sink.get(), 40);
-        sink.inject(100);
-        thread::sleep(Duration::from_millis(1000));
-        assert_eq!(sink.get(), 60);
-        sink.inject(100);
-        thread::sleep(Duration::from_millis(1000));
-        assert_eq!(sink.get(), 80);
-        sink.inject(100);
-        thread::sleep(Duration::from_millis(1000));
-        assert_eq!(sink.get(), 100);
-        thread::sleep(Duration::from_millis(1000));
-        assert_eq!(sink.get(), 80);
+    fn traffic_is_counted_per_connection_and_in_total() {
+        let (transport, sinks) = BandwidthLogging::new(TcpConfig::new(), Duration::from_secs(1));
+
+        let mut listener = transport.clone().listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let addr = listener.by_ref().wait()
+            .next()
+            .expect("some event")
+            .expect("no error")
+            .into_new_address()
+            .expect("listen address");
+
+        let inbound = listener
+            .filter_map(ListenerEvent::into_upgrade)
+            .into_future()
+            .map_err(|(err, _)| err)
+            .and_then(|(upgrade, _)| upgrade.expect("listener ended").0)
+            .and_then(|socket| tokio_io::io::read_exact(socket, [0; 10]));
+        let outbound = transport.dial(addr.clone()).unwrap()
+            .and_then(|socket| tokio_io::io::write_all(socket, [1; 10]));
+
+        let mut runtime = Runtime::new().unwrap();
+        let ((listener_socket, _), (dialer_socket, _)) = runtime.block_on(inbound.join(outbound)).unwrap();
+
+        // The counters can be read through `BandwidthSinks` regardless of who holds the connections.
+        let connections = sinks.connections();
+        assert_eq!(connections.len(), 2);
+        let dialer = connections.iter().find(|c| c.remote_addr() == &addr).expect("dialer connection");
+        let listener = connections.iter().find(|c| c.remote_addr() != &addr).expect("listener connection");
+        assert_eq!((dialer.total_inbound(), dialer.total_outbound()), (0, 10));
+        assert_eq!((listener.total_inbound(), listener.total_outbound()), (10, 0));
+        assert_eq!(dialer_socket.bandwidth().total_outbound(), 10);
+
+        let snapshot = sinks.snapshot();
+        assert_eq!(snapshot.totals().total_inbound(), 10);
+        assert_eq!(snapshot.totals().total_outbound(), 10);
+        let counters = snapshot.connections().iter()
+            .map(|c| (c.remote_addr() == &addr, c.total_inbound(), c.total_outbound()))
+            .collect::<Vec<_>>();
+        assert_eq!(counters.len(), 2);
+        assert!(counters.contains(&(true, 0, 10)));
+        assert!(counters.contains(&(false, 10, 0)));
+        assert_eq!(sinks.total_inbound(), 10);
+        assert_eq!(sinks.total_outbound(), 10);
+
+        drop(connections);
+        drop(listener_socket);
+        drop(dialer_socket);
+        assert!(sinks.connections().is_empty());
+        let snapshot = sinks.snapshot();
+        assert!(snapshot.connections().is_empty());
+        assert_eq!(snapshot.totals().total_inbound(), 10);
     }
 }
@@ -179,6 +179,8 @@ pub use libp2p_mplex as mplex;
 #[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
 #[doc(inline)]
 pub use libp2p_mdns as mdns;
+#[doc(inline)]
+pub use libp2p_metrics as metrics;
 #[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
 #[doc(inline)]
 pub use libp2p_noise as noise;
@@ -189,7 +191,11 @@ pub use libp2p_plaintext as plaintext;
 #[doc(inline)]
 pub use libp2p_ratelimit as ratelimit;
 #[doc(inline)]
+pub use libp2p_request_response as request_response;
+#[doc(inline)]
 pub use libp2p_secio as secio;
+#[doc(inline)]
+pub use libp2p_stream as stream;
 #[cfg(not(any(target_os = "emscripten", target_os = "unknown")))]
 #[doc(inline)]
 pub use libp2p_tcp as tcp;